@@ -1,41 +1,43 @@
 use actix_web::{
     cookie::{time, Cookie},
-    web, HttpMessage, HttpRequest, HttpResponse,
+    web, HttpRequest, HttpResponse,
 };
+use auth_middleware::{guard::Guard, source::TokenSource, user::AuthenticatedUser};
 use database::{pgx::Postgresql, redis::RedisImpl};
+use errors::{
+    error::{Error, ErrorBody},
+    response::{Empty, Response},
+};
 use logger::log::Log;
 use security::{env::EnvImpl, hasher::Bcrypt, jwt::JwtImpl};
-use serde::{Deserialize, Serialize};
+use validation::extractor::ValidJson;
 
 use crate::{
-    middlewares::{self},
-    services::auth_service::{AuthService, AuthServiceImpl},
+    services::auth_service::{
+        AuthService, AuthServiceImpl, ChangePasswordData, SignInData, SignUpData, TokenData,
+    },
     utils,
 };
 
-#[derive(Serialize, Deserialize, Debug)]
-struct ResponseOk<T> {
-    pub data: Option<T>,
-    pub message: String,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-struct ResponseError {
-    pub message: String,
-}
-
-pub fn auth_controller(config: &mut web::ServiceConfig) {
-    let refresh_middleware = middlewares::refresh_middleware::Middleware {
-        roles: vec![utils::constants::REFRESH_TOKEN.to_string()],
-    };
-    let jwt_middleware = middlewares::jwt_middleware::Middleware {
-        roles: vec![utils::constants::AUTH_TOKEN.to_string()],
-    };
+pub fn auth_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let refresh_middleware = Guard::new(jwt.clone())
+        .sources(vec![
+            TokenSource::bearer("Authorization-refresh"),
+            TokenSource::cookie("refresh_token"),
+        ])
+        .kinds(&[utils::constants::REFRESH_TOKEN]);
+    let jwt_middleware = Guard::new(jwt.clone())
+        .sources(vec![
+            TokenSource::authorization(),
+            TokenSource::cookie("token"),
+        ])
+        .kinds(&[utils::constants::AUTH_TOKEN]);
     config.service(
         web::scope("/auth")
             .route("/signup", web::post().to(sign_up_handler))
             .route("/signin", web::post().to(sign_in_handler))
             .route("/signout", web::get().to(sign_out_handler))
+            .route("/password", web::post().to(change_password_handler))
             .service(
                 web::scope("/refresh-token")
                     .wrap(refresh_middleware)
@@ -49,135 +51,143 @@ pub fn auth_controller(config: &mut web::ServiceConfig) {
     );
 }
 
+#[utoipa::path(
+    post,
+    path = "/auth/signup",
+    tag = "auth",
+    request_body = SignUpData,
+    responses(
+        (status = 200, description = "Username of the new user", body = Response<String>),
+        (status = 409, description = "Username is already taken", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    )
+)]
 async fn sign_up_handler(
-    data: web::Json<crate::services::auth_service::SignUpData>,
+    data: ValidJson<SignUpData>,
     ctrl: web::Data<AuthServiceImpl<Postgresql, Bcrypt, JwtImpl<EnvImpl>, Log, RedisImpl>>,
-) -> HttpResponse {
-    match ctrl.sign_up(&data).await {
-        Ok(Some(user_id)) => HttpResponse::Ok().json(ResponseOk {
-            data: Some(user_id),
-            message: "Successfully signed up".to_string(),
-        }),
-        Ok(None) => HttpResponse::BadRequest().json(ResponseError {
-            message: "Failed to sign up, missing fields".to_string(),
-        }),
-        Err(err) => HttpResponse::InternalServerError().json(ResponseError {
-            message: format!("Error: {}", err),
-        }),
-    }
+) -> Result<HttpResponse, Error> {
+    let username = ctrl.sign_up(&data).await?;
+    Ok(HttpResponse::Ok().json(Response::new(username, "Successfully signed up")))
 }
 
+/// The `token` cookie, gone when the auth token in it runs out.
+fn token_cookie(token: String) -> Cookie<'static> {
+    Cookie::build("token", token)
+        .path("/")
+        .max_age(time::Duration::minutes(
+            utils::constants::AUTH_TOKEN_TTL_MINUTES,
+        ))
+        .http_only(true)
+        .finish()
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/signin",
+    tag = "auth",
+    request_body = SignInData,
+    responses(
+        (status = 200, description = "Tokens, also set as `token` and `refresh_token` cookies. The auth token is valid for 15 minutes, renew it with `/auth/refresh-token`", body = Response<TokenData>),
+        (status = 401, description = "Invalid username or password", body = ErrorBody),
+        (status = 403, description = "Account is suspended or needs a new password", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    )
+)]
 async fn sign_in_handler(
-    data: web::Json<crate::services::auth_service::SignInData>,
+    data: ValidJson<SignInData>,
     ctrl: web::Data<AuthServiceImpl<Postgresql, Bcrypt, JwtImpl<EnvImpl>, Log, RedisImpl>>,
-) -> HttpResponse {
+) -> Result<HttpResponse, Error> {
     // TODO: add secure cookie and strict same site
-    match ctrl.sign_in(&data).await {
-        Ok(Some(token)) => {
-            let cookie = Cookie::build("token", token.token.clone())
-                .path("/")
-                .max_age(time::Duration::hours(4))
-                .http_only(true)
-                .finish();
-            let refresh_cookie = Cookie::build("refresh_token", token.refresh_token.clone())
-                .path("/")
-                .http_only(true)
-                .max_age(time::Duration::weeks(4))
-                .finish();
-            HttpResponse::Ok()
-                .cookie(cookie)
-                .cookie(refresh_cookie)
-                .json(ResponseOk {
-                    data: Some(crate::services::auth_service::TokenData {
-                        token: token.token,
-                        refresh_token: token.refresh_token,
-                    }),
-                    message: "Successfully signed in".to_string(),
-                })
-        }
-        Ok(None) => HttpResponse::BadRequest().json(ResponseError {
-            message: "Failed to sign in, invalid credentials".to_string(),
-        }),
-        Err(err) => HttpResponse::InternalServerError().json(ResponseError {
-            message: format!("Error: {}", err),
-        }),
-    }
+    let token = ctrl.sign_in(&data).await?;
+    let cookie = token_cookie(token.token.clone());
+    let refresh_cookie = Cookie::build("refresh_token", token.refresh_token.clone())
+        .path("/")
+        .http_only(true)
+        .max_age(time::Duration::weeks(4))
+        .finish();
+    Ok(HttpResponse::Ok()
+        .cookie(cookie)
+        .cookie(refresh_cookie)
+        .json(Response::new(token, "Successfully signed in")))
 }
 
+#[utoipa::path(
+    get,
+    path = "/auth/signout",
+    tag = "auth",
+    responses(
+        (status = 200, description = "Both tokens are revoked. Other auth tokens of the session keep working until they run out, within 15 minutes", body = Response<Empty>),
+        (status = 400, description = "Token cookies are missing", body = ErrorBody),
+    ),
+    security(("cookie" = [], "refresh_cookie" = []))
+)]
 async fn sign_out_handler(
     ctrl: web::Data<AuthServiceImpl<Postgresql, Bcrypt, JwtImpl<EnvImpl>, Log, RedisImpl>>,
     req: HttpRequest,
-) -> HttpResponse {
-    let token = req.cookie("token");
-    let refresh_token = req.cookie("refresh_token");
-    if token.is_none() {
-        return HttpResponse::BadRequest().json(ResponseError {
-            message: "Token not found in request".to_string(),
-        });
-    }
-    if refresh_token.is_none() {
-        return HttpResponse::BadRequest().json(ResponseError {
-            message: "Refresh token not found in request".to_string(),
-        });
-    }
-    let token = token.unwrap().value().to_string();
-    let refresh_token = refresh_token.unwrap().value().to_string();
+) -> Result<HttpResponse, Error> {
+    let token = req
+        .cookie("token")
+        .ok_or_else(|| Error::BadRequest("Token not found in request".to_string()))?;
+    let refresh_token = req
+        .cookie("refresh_token")
+        .ok_or_else(|| Error::BadRequest("Refresh token not found in request".to_string()))?;
 
-    match ctrl.sign_out(&token, &refresh_token).await {
-        Ok(Some(_)) => HttpResponse::Ok().json(ResponseOk {
-            data: Some(()),
-            message: "Successfully signed out".to_string(),
-        }),
-        Ok(None) => HttpResponse::BadRequest().json(ResponseError {
-            message: "Failed to sign out".to_string(),
-        }),
-        Err(err) => HttpResponse::InternalServerError().json(ResponseError {
-            message: format!("Error: {}", err),
-        }),
-    };
+    ctrl.sign_out(token.value(), refresh_token.value()).await?;
+    Ok(HttpResponse::Ok().json(Response::new(Empty, "Successfully signed out")))
+}
 
-    HttpResponse::Ok().json(ResponseOk {
-        data: Some(()),
-        message: "Successfully signed out".to_string(),
-    })
+#[utoipa::path(
+    post,
+    path = "/auth/password",
+    tag = "auth",
+    request_body = ChangePasswordData,
+    responses(
+        (status = 200, description = "Password is changed and every session is revoked. Auth tokens already issued run out within 15 minutes", body = Response<Empty>),
+        (status = 400, description = "New password is the current one", body = ErrorBody),
+        (status = 401, description = "Invalid username or password", body = ErrorBody),
+        (status = 403, description = "Account is suspended", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    )
+)]
+async fn change_password_handler(
+    data: ValidJson<ChangePasswordData>,
+    ctrl: web::Data<AuthServiceImpl<Postgresql, Bcrypt, JwtImpl<EnvImpl>, Log, RedisImpl>>,
+) -> Result<HttpResponse, Error> {
+    ctrl.change_password(&data).await?;
+    Ok(HttpResponse::Ok().json(Response::new(Empty, "Successfully changed password")))
 }
 
+#[utoipa::path(
+    get,
+    path = "/auth/refresh-token",
+    tag = "auth",
+    responses(
+        (status = 200, description = "A new auth token, valid for 15 minutes, also set as the `token` cookie", body = Response<String>),
+        (status = 401, description = "Missing, invalid or revoked refresh token", body = ErrorBody),
+        (status = 403, description = "Account is suspended", body = ErrorBody),
+    ),
+    security(("refresh_bearer" = []), ("refresh_cookie" = []))
+)]
 async fn refresh_token_handler(
     ctrl: web::Data<AuthServiceImpl<Postgresql, Bcrypt, JwtImpl<EnvImpl>, Log, RedisImpl>>,
-    req: HttpRequest,
-) -> HttpResponse {
-    if let Some(refresh_token) = req.extensions().get::<String>() {
-        match ctrl.gain_new_token(refresh_token).await {
-            Ok(Some(new_token)) => HttpResponse::Ok().json(ResponseOk {
-                data: Some(new_token),
-                message: "Successfully refreshed token".to_string(),
-            }),
-            Ok(None) => HttpResponse::BadRequest().json(ResponseError {
-                message: "Failed to refresh token".to_string(),
-            }),
-            Err(err) => HttpResponse::InternalServerError().json(ResponseError {
-                message: format!("Error: {}", err),
-            }),
-        }
-    } else {
-        HttpResponse::BadRequest().json(ResponseError {
-            message: "Token not found in request".to_string(),
-        })
-    }
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let new_token = ctrl.gain_new_token(&user.token).await?;
+    Ok(HttpResponse::Ok()
+        .cookie(token_cookie(new_token.clone()))
+        .json(Response::new(new_token, "Successfully refreshed token")))
 }
 
-async fn get_token_handler(
-    _ctrl: web::Data<AuthServiceImpl<Postgresql, Bcrypt, JwtImpl<EnvImpl>, Log, RedisImpl>>,
-    req: HttpRequest,
-) -> HttpResponse {
-    if let Some(jwt_token) = req.extensions().get::<String>() {
-        HttpResponse::Ok().json(ResponseOk {
-            data: Some(jwt_token.clone()),
-            message: "Successfully got token".to_string(),
-        })
-    } else {
-        HttpResponse::BadRequest().json(ResponseError {
-            message: "Token not found in request".to_string(),
-        })
-    }
+#[utoipa::path(
+    get,
+    path = "/auth/token",
+    tag = "auth",
+    responses(
+        (status = 200, description = "The auth token the request was made with", body = Response<String>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_token_handler(user: AuthenticatedUser) -> HttpResponse {
+    HttpResponse::Ok().json(Response::new(user.token, "Successfully got token"))
 }
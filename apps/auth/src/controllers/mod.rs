@@ -1 +1,2 @@
 pub mod auth_controller;
+pub mod role_controller;
@@ -0,0 +1,185 @@
+use actix_web::{web, HttpResponse};
+use auth_middleware::{
+    guard::Guard,
+    rbac::{ROLES_READ, ROLES_WRITE},
+    source::TokenSource,
+};
+use database::pgx::Postgresql;
+use errors::{
+    error::{Error, ErrorBody},
+    response::{Empty, Response},
+};
+use logger::log::Log;
+use security::{env::EnvImpl, jwt::JwtImpl};
+use validation::extractor::ValidJson;
+
+use crate::{
+    services::role_service::{AssignRoleData, Role, RoleService, RoleServiceImpl},
+    utils,
+};
+
+pub fn role_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let sources = vec![TokenSource::authorization(), TokenSource::cookie("token")];
+    let read_middleware = Guard::new(jwt.clone())
+        .sources(sources.clone())
+        .kinds(&[utils::constants::AUTH_TOKEN])
+        .scopes(&[ROLES_READ]);
+    let write_middleware = Guard::new(jwt.clone())
+        .sources(sources)
+        .kinds(&[utils::constants::AUTH_TOKEN])
+        .scopes(&[ROLES_WRITE]);
+    config
+        .service(
+            web::scope("/auth/roles")
+                .wrap(read_middleware)
+                .route("", web::get().to(get_roles_handler))
+                .route("/users/{user_id}", web::get().to(get_user_roles_handler)),
+        )
+        .service(
+            web::scope("/auth/users/{user_id}/roles")
+                .wrap(write_middleware)
+                .route("", web::post().to(assign_role_handler))
+                .route("/{role}", web::delete().to(revoke_role_handler)),
+        );
+}
+
+#[utoipa::path(
+    get,
+    path = "/auth/roles",
+    tag = "roles",
+    responses(
+        (status = 200, description = "All roles with their permissions", body = Response<Vec<Role>>),
+        (status = 403, description = "Missing `roles:read` permission", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_roles_handler(
+    service: web::Data<RoleServiceImpl<Postgresql, Log>>,
+) -> Result<HttpResponse, Error> {
+    let roles = service.get_roles().await?;
+    Ok(HttpResponse::Ok().json(Response::new(roles, "Successfully got roles")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/auth/roles/users/{user_id}",
+    tag = "roles",
+    params(("user_id" = String, Path, description = "User id")),
+    responses(
+        (status = 200, description = "Roles assigned to the user", body = Response<Vec<Role>>),
+        (status = 403, description = "Missing `roles:read` permission", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_user_roles_handler(
+    service: web::Data<RoleServiceImpl<Postgresql, Log>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let user_id = path.into_inner();
+    let roles = service.get_user_roles(&user_id).await?;
+    Ok(HttpResponse::Ok().json(Response::new(roles, "Successfully got user roles")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/users/{user_id}/roles",
+    tag = "roles",
+    params(("user_id" = String, Path, description = "User id")),
+    request_body = AssignRoleData,
+    responses(
+        (status = 200, description = "Role is assigned", body = Response<Empty>),
+        (status = 403, description = "Missing `roles:write` permission", body = ErrorBody),
+        (status = 404, description = "User or role not found", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn assign_role_handler(
+    service: web::Data<RoleServiceImpl<Postgresql, Log>>,
+    path: web::Path<String>,
+    data: ValidJson<AssignRoleData>,
+) -> Result<HttpResponse, Error> {
+    let user_id = path.into_inner();
+    service.assign_role(&user_id, &data.role).await?;
+    Ok(HttpResponse::Ok().json(Response::new(Empty, "Successfully assigned role")))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/auth/users/{user_id}/roles/{role}",
+    tag = "roles",
+    params(
+        ("user_id" = String, Path, description = "User id"),
+        ("role" = String, Path, description = "Role name"),
+    ),
+    responses(
+        (status = 200, description = "Role is revoked", body = Response<Empty>),
+        (status = 403, description = "Missing `roles:write` permission", body = ErrorBody),
+        (status = 404, description = "Role assignment not found", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn revoke_role_handler(
+    service: web::Data<RoleServiceImpl<Postgresql, Log>>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (user_id, role) = path.into_inner();
+    service.revoke_role(&user_id, &role).await?;
+    Ok(HttpResponse::Ok().json(Response::new(Empty, "Successfully revoked role")))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::StatusCode,
+        test::{init_service, try_call_service, TestRequest},
+        App,
+    };
+    use auth_middleware::rbac::{ROLE_USER, USERS_READ};
+    use chrono::{Duration, Utc};
+    use security::jwt::{AdditionalClaims, Claims, Jwt};
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_only_admins_manage_roles() {
+        std::env::set_var("JWT_SECRET", "role-controller-test");
+        let jwt = JwtImpl::new(EnvImpl);
+        let token = jwt
+            .sign(&Claims {
+                exp: (Utc::now() + Duration::minutes(5)).timestamp() as usize,
+                iat: Utc::now().timestamp() as usize,
+                nbf: Utc::now().timestamp() as usize,
+                sub: "alice".to_string(),
+                jti: "jti".to_string(),
+                additional_claims: AdditionalClaims {
+                    user_id: "user-1".to_string(),
+                    kind: utils::constants::AUTH_TOKEN.to_string(),
+                    roles: vec![ROLE_USER.to_string()],
+                    scopes: vec![USERS_READ.to_string()],
+                },
+            })
+            .unwrap();
+        let app = init_service(App::new().configure(|config| role_controller(config, &jwt))).await;
+
+        let requests = [
+            TestRequest::get().uri("/auth/roles"),
+            TestRequest::post()
+                .uri("/auth/users/user-2/roles")
+                .set_json(AssignRoleData {
+                    role: "admin".to_string(),
+                }),
+            TestRequest::delete().uri("/auth/users/user-2/roles/moderator"),
+        ];
+        for request in requests {
+            let req = request
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request();
+            let res = try_call_service(&app, req).await;
+            assert_eq!(
+                res.err().map(|e| e.as_response_error().status_code()),
+                Some(StatusCode::FORBIDDEN)
+            );
+        }
+    }
+}
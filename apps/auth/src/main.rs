@@ -1,32 +1,48 @@
 use actix_web::{web, App, HttpServer};
-use controllers::auth_controller::auth_controller;
+use controllers::{auth_controller::auth_controller, role_controller::role_controller};
 use database::{pgx::Postgresql, redis::RedisImpl};
 use logger::log::Log;
 use security::{env::EnvImpl, hasher::Bcrypt, jwt::JwtImpl};
-use services::auth_service::AuthServiceImpl;
+use services::{auth_service::AuthServiceImpl, role_service::RoleServiceImpl};
 
 mod controllers;
-mod middlewares;
+mod openapi;
 mod services;
 mod utils;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let jwt = JwtImpl::new(EnvImpl::default());
-    let database = Postgresql::new(EnvImpl::default()).await;
-    let bcrypt = Bcrypt::default();
-    let logger = Log::default();
-    let redis = RedisImpl::new(EnvImpl::default());
+    let jwt = JwtImpl::new(EnvImpl);
+    let guard_jwt = jwt.clone();
+    let database = Postgresql::new(EnvImpl).await;
+    let bcrypt = Bcrypt;
+    let logger = Log;
+    let redis = RedisImpl::new(EnvImpl);
     let auth_service = AuthServiceImpl::new(database, bcrypt, jwt, logger, redis);
+    let role_service = RoleServiceImpl::new(Postgresql::new(EnvImpl).await, Log);
 
     // Share the auth service instance with all handlers using web::Data
     let auth_service_data = web::Data::new(auth_service);
+    let role_service_data = web::Data::new(role_service);
 
     //serve on 127.0.0.1:8080
     HttpServer::new(move || {
-        App::new()
+        let app = App::new()
             .app_data(auth_service_data.clone()) // Share the auth service with handlers
-            .configure(auth_controller) // Configure routes
+            .app_data(role_service_data.clone())
+            // Documentation routes live under /auth too, register them first
+            .route(
+                "/auth/openapi.json",
+                web::get().to(openapi::openapi_handler),
+            );
+        #[cfg(feature = "swagger-ui")]
+        let app = app.service(
+            utoipa_swagger_ui::SwaggerUi::new("/auth/swagger-ui/{_:.*}")
+                .config(utoipa_swagger_ui::Config::from("../openapi.json")),
+        );
+        // Role routes live under /auth, register them before the /auth scope
+        app.configure(|config| role_controller(config, &guard_jwt))
+            .configure(|config| auth_controller(config, &guard_jwt)) // Configure routes
     })
     .bind("0.0.0.0:8080")?
     .run()
@@ -1,2 +0,0 @@
-pub mod jwt_middleware;
-pub mod refresh_middleware;
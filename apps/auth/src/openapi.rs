@@ -0,0 +1,60 @@
+use actix_web::HttpResponse;
+use auth_middleware::openapi::SecurityAddon;
+use errors::error::{ErrorBody, FieldError};
+use utoipa::OpenApi;
+
+use crate::controllers::{auth_controller, role_controller};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Auth API"),
+    paths(
+        auth_controller::sign_up_handler,
+        auth_controller::sign_in_handler,
+        auth_controller::sign_out_handler,
+        auth_controller::change_password_handler,
+        auth_controller::refresh_token_handler,
+        auth_controller::get_token_handler,
+        role_controller::get_roles_handler,
+        role_controller::get_user_roles_handler,
+        role_controller::assign_role_handler,
+        role_controller::revoke_role_handler,
+    ),
+    components(schemas(ErrorBody, FieldError)),
+    modifiers(&SecurityAddon),
+    tags(
+        (name = "auth", description = "Sign up, sign in and token management"),
+        (name = "roles", description = "Roles and their assignment to users"),
+    )
+)]
+pub struct ApiDoc;
+
+pub async fn openapi_handler() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The committed spec is the contract the web client is built against, run
+    /// `pnpm run openapi` after changing the API.
+    #[test]
+    fn test_openapi_spec_is_up_to_date() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../www/src/app/api/auth.openapi.json"
+        );
+        let spec = ApiDoc::openapi().to_pretty_json().unwrap() + "\n";
+        if std::env::var("UPDATE_OPENAPI").is_ok() {
+            std::fs::write(path, &spec).unwrap();
+            return;
+        }
+        let committed = std::fs::read_to_string(path).unwrap_or_default();
+        assert!(
+            committed == spec,
+            "{} is out of date, regenerate it with `pnpm run openapi`",
+            path
+        );
+    }
+}
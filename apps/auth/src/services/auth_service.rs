@@ -1,8 +1,10 @@
 use std::vec;
 
 use async_trait::async_trait;
+use auth_middleware::rbac::ROLE_USER;
 use chrono::{Duration, Utc};
 use database::{db::Database, pgx::PgRow, redis::RedisRow};
+use errors::error::Error;
 use logger::logger::Logger;
 use security::{
     hasher::Hasher,
@@ -10,39 +12,58 @@ use security::{
     uuid::uuid_v4,
 };
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
 
-use crate::utils::constants::{AUTH_TOKEN, REFRESH_TOKEN};
+use crate::utils::constants::{AUTH_TOKEN, AUTH_TOKEN_TTL_MINUTES, REFRESH_TOKEN};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct TokenData {
     pub token: String,
     pub refresh_token: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
 pub struct SignInData {
+    #[validate(length(min = 1, max = 32))]
     pub username: String,
+    #[validate(length(min = 1, max = 128))]
     pub password: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
 pub struct SignUpData {
+    #[validate(
+        length(min = 1, max = 64),
+        custom(function = "validation::rules::not_blank")
+    )]
     pub name: String,
+    #[validate(custom(function = "validation::rules::username"))]
     pub username: String,
+    #[validate(custom(function = "validation::rules::password"))]
     pub password: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct GainNewTokenData {
-    pub old_token: String,
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
+pub struct ChangePasswordData {
+    #[validate(length(min = 1, max = 32))]
+    pub username: String,
+    /// Current password
+    #[validate(length(min = 1, max = 128))]
+    pub password: String,
+    #[validate(custom(function = "validation::rules::password"))]
+    pub new_password: String,
 }
 
 #[async_trait]
 pub trait AuthService {
-    async fn sign_in(&self, data: &SignInData) -> Result<Option<TokenData>, String>;
-    async fn sign_up(&self, data: &SignUpData) -> Result<Option<String>, String>;
-    async fn sign_out(&self, token: &str, refresh_token: &str) -> Result<Option<String>, String>;
-    async fn gain_new_token(&self, old_token: &str) -> Result<Option<String>, String>;
+    async fn sign_in(&self, data: &SignInData) -> Result<TokenData, Error>;
+    async fn sign_up(&self, data: &SignUpData) -> Result<String, Error>;
+    async fn sign_out(&self, token: &str, refresh_token: &str) -> Result<(), Error>;
+    async fn gain_new_token(&self, old_token: &str) -> Result<String, Error>;
+    /// Sets a new password, also when an admin required a reset, and signs
+    /// the user out everywhere.
+    async fn change_password(&self, data: &ChangePasswordData) -> Result<(), Error>;
 }
 
 pub struct AuthServiceImpl<T: Database<PgRow>, B: Hasher, E: Jwt, L: Logger, R: Database<RedisRow>>
@@ -66,6 +87,73 @@ impl<T: Database<PgRow>, B: Hasher, E: Jwt, L: Logger, R: Database<RedisRow>>
             redis,
         }
     }
+
+    /// Loads the role names and the union of their permissions for a user.
+    /// Both end up in the auth token so guards can check them without a query.
+    async fn load_grants(&self, user_id: &str) -> Result<(Vec<String>, Vec<String>), Error> {
+        let user_id = user_id.to_string();
+        let roles = self
+            .db
+            .query(
+                "SELECT r.name FROM user_roles ur JOIN roles r ON r.id = ur.role_id WHERE ur.user_id = $1 ORDER BY r.name",
+                &[&user_id],
+            )
+            .await;
+        let scopes = self
+            .db
+            .query(
+                "SELECT DISTINCT p.name FROM user_roles ur JOIN role_permissions rp ON rp.role_id = ur.role_id JOIN permissions p ON p.id = rp.permission_id WHERE ur.user_id = $1 ORDER BY p.name",
+                &[&user_id],
+            )
+            .await;
+        match (roles, scopes) {
+            (Ok(roles), Ok(scopes)) => Ok((
+                roles.iter().map(|row| row.get(0)).collect(),
+                scopes.iter().map(|row| row.get(0)).collect(),
+            )),
+            (Err(e), _) | (_, Err(e)) => {
+                let message = format!("failed to load roles for user {}: {}", user_id, e);
+                self.logger.error("auth_service::load_grants", &message);
+                Err(e)
+            }
+        }
+    }
+
+    /// Rejects users with a suspension in force.
+    async fn check_suspension(&self, user_id: &str) -> Result<(), Error> {
+        let rows = self
+            .db
+            .query(
+                "SELECT reason, COALESCE(to_json(expires_at) #>> '{}', '') FROM user_suspensions WHERE user_id = $1 AND lifted_at IS NULL AND (expires_at IS NULL OR expires_at > NOW()) ORDER BY created_at DESC LIMIT 1",
+                &[&user_id.to_string()],
+            )
+            .await?;
+        match rows.first() {
+            None => Ok(()),
+            Some(row) if row.get(1).is_empty() => Err(Error::Forbidden(format!(
+                "Account is suspended: {}",
+                row.get(0)
+            ))),
+            Some(row) => Err(Error::Forbidden(format!(
+                "Account is suspended until {}: {}",
+                row.get(1),
+                row.get(0)
+            ))),
+        }
+    }
+
+    /// Signing in during the grace period keeps the account.
+    async fn cancel_deletion(&self, user_id: &str) -> Result<(), Error> {
+        let message = format!("cancelling scheduled deletion of user {}", user_id);
+        self.logger.info("auth_service::cancel_deletion", &message);
+        self.db
+            .execute(
+                "UPDATE users SET deletion_scheduled_at = NULL WHERE id = $1",
+                &[&user_id.to_string()],
+            )
+            .await?;
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -77,14 +165,14 @@ impl<
         R: Database<RedisRow> + Send + Sync,
     > AuthService for AuthServiceImpl<T, B, E, L, R>
 {
-    async fn sign_in(&self, data: &SignInData) -> Result<Option<TokenData>, String> {
+    async fn sign_in(&self, data: &SignInData) -> Result<TokenData, Error> {
         self.logger
             .info("auth_service::sign_in", "sign in is initialized");
         let row = self
             .db
             .query_one(
-                "SELECT id, username, password FROM users WHERE username = $1",
-                &vec![&data.username],
+                "SELECT id, username, password, (deletion_scheduled_at IS NOT NULL)::TEXT, password_reset_required::TEXT FROM users WHERE username = $1",
+                &[&data.username],
             )
             .await;
         match row {
@@ -94,58 +182,92 @@ impl<
                 let user_id: String = row.get(0).to_string();
                 let username: String = row.get(1).to_string();
                 let password: String = row.get(2).to_string();
+                let deletion_scheduled = row.get(3) == "true";
+                let password_reset_required = row.get(4) == "true";
                 self.logger
                     .info("auth_service::sign_in", "trying to verify password");
                 if self.hasher.verify(&data.password, &password) {
                     self.logger
                         .info("auth_service::sign_in", "password verified");
+                    self.check_suspension(&user_id).await?;
+                    if password_reset_required {
+                        return Err(Error::Forbidden(
+                            "Password reset required, choose a new password".to_string(),
+                        ));
+                    }
+                    if deletion_scheduled {
+                        self.cancel_deletion(&user_id).await?;
+                    }
+                    let (roles, scopes) = self.load_grants(&user_id).await?;
                     self.logger
                         .info("auth_service::sign_in", "creating a token");
                     let token = self.jwt.sign(&Claims {
                         sub: username.clone(),
                         iat: Utc::now().timestamp() as usize,
-                        exp: (Utc::now() + Duration::hours(1)).timestamp() as usize,
+                        exp: (Utc::now() + Duration::minutes(AUTH_TOKEN_TTL_MINUTES)).timestamp()
+                            as usize,
                         nbf: Utc::now().timestamp() as usize,
                         jti: uuid_v4(),
                         additional_claims: AdditionalClaims {
                             user_id: user_id.clone(),
                             kind: AUTH_TOKEN.to_string(),
+                            roles,
+                            scopes,
                         },
-                    });
+                    })?;
                     self.logger
                         .info("auth_service::sign_in", "creating a refresh token");
+                    let session_id = uuid_v4();
+                    let expires_at = (Utc::now() + Duration::weeks(4)).timestamp();
                     let refresh_token = self.jwt.sign(&Claims {
                         sub: username.clone(),
                         iat: Utc::now().timestamp() as usize,
-                        exp: (Utc::now() + Duration::weeks(4)).timestamp() as usize,
+                        exp: expires_at as usize,
                         nbf: Utc::now().timestamp() as usize,
-                        jti: uuid_v4(),
+                        jti: session_id.clone(),
                         additional_claims: AdditionalClaims {
                             user_id: user_id.clone(),
                             kind: REFRESH_TOKEN.to_string(),
+                            roles: vec![],
+                            scopes: vec![],
                         },
-                    });
+                    })?;
+                    self.db
+                        .execute(
+                            "INSERT INTO sessions (id, user_id, expires_at) VALUES ($1, $2, to_timestamp($3::TEXT::BIGINT))",
+                            &[&session_id, &user_id, &expires_at.to_string()],
+                        )
+                        .await?;
 
                     let token_data = TokenData {
                         token,
                         refresh_token,
                     };
-                    Ok(Some(token_data))
+                    Ok(token_data)
                 } else {
                     self.logger
                         .error("auth_service::sign_in", "password is not match");
-                    Ok(None)
+                    Err(Error::Unauthorized(
+                        "Invalid username or password".to_string(),
+                    ))
                 }
             }
+            Err(Error::NotFound(_)) => {
+                self.logger
+                    .error("auth_service::sign_in", "user not found in database");
+                Err(Error::Unauthorized(
+                    "Invalid username or password".to_string(),
+                ))
+            }
             Err(e) => {
                 let message = format!("an error occurred: {}", e);
                 self.logger.error("auth_service::sign_in", &message);
-                Ok(None)
+                Err(e)
             }
         }
     }
 
-    async fn sign_up(&self, data: &SignUpData) -> Result<Option<String>, String> {
+    async fn sign_up(&self, data: &SignUpData) -> Result<String, Error> {
         self.logger.info(
             "auth_service::sign_up",
             "sign up is initialized and querying database",
@@ -153,66 +275,63 @@ impl<
         let row = self
             .db
             .query_one(
-                "INSERT INTO users (name, username, password) VALUES ($1, $2, $3) RETURNING username",
-                &vec![&data.name,  &data.username, &self.hasher.hash(&data.password)],
+                // One statement, so no account exists without its default role
+                "WITH u AS (INSERT INTO users (name, username, password) VALUES ($1, $2, $3) RETURNING id, username), \
+                 r AS (INSERT INTO user_roles (user_id, role_id) SELECT u.id, roles.id FROM u, roles WHERE roles.name = $4) \
+                 SELECT id, username FROM u",
+                &[&data.name,  &data.username, &self.hasher.hash(&data.password), &ROLE_USER.to_string()],
             )
             .await;
         match row {
             Ok(row) => {
                 self.logger
                     .info("auth_service::sign_up", "user created in database");
-                Ok(Some(row.get(0).to_string()))
+                Ok(row.get(1).to_string())
+            }
+            Err(Error::Conflict(_)) => {
+                self.logger
+                    .error("auth_service::sign_up", "username is already taken");
+                Err(Error::Conflict("Username is already taken".to_string()))
             }
             Err(e) => {
                 let message = format!("an error occurred: {}", e);
                 self.logger.error("auth_service::sign_up", &message);
-                Ok(None)
+                Err(e)
             }
         }
     }
 
-    async fn sign_out(&self, token: &str, refresh_token: &str) -> Result<Option<String>, String> {
+    async fn sign_out(&self, token: &str, refresh_token: &str) -> Result<(), Error> {
         self.logger
             .info("auth_service::sign_out", "sign out is initialized");
-        let jti = self
-            .jwt
-            .extract(token)
-            .expect("Failed to extract jti from token");
-        let refresh_jti = self
-            .jwt
-            .extract(refresh_token)
-            .expect("Failed to extract jti from token");
+        let jti = self.jwt.extract(token)?;
+        let refresh_jti = self.jwt.extract(refresh_token)?;
         self.logger
             .info("auth_service::sign_out", "inserting jti into redis");
-        let _ = self
-            .redis
-            .execute(&jti.jti, &vec![&"true".to_string()])
-            .await;
-        let _ = self
-            .redis
-            .execute(&refresh_jti.jti, &vec![&"true".to_string()])
-            .await;
+        self.redis.execute(&jti.jti, &[&"true".to_string()]).await?;
+        self.redis
+            .execute(&refresh_jti.jti, &[&"true".to_string()])
+            .await?;
+        self.db
+            .execute("DELETE FROM sessions WHERE id = $1", &[&refresh_jti.jti])
+            .await?;
 
-        Ok(Some("Successfully signed out".to_string()))
+        Ok(())
     }
 
-    async fn gain_new_token(&self, old_token: &str) -> Result<Option<String>, String> {
-        if self.jwt.verify(&old_token) {
+    async fn gain_new_token(&self, old_token: &str) -> Result<String, Error> {
+        if self.jwt.verify(old_token) {
             self.logger
                 .info("auth_service::gain_new_token", "old token is valid");
-            let old_token_claims = self.jwt.extract(&old_token);
-            if old_token_claims.is_none() {
+            let old_claims = self.jwt.extract(old_token).inspect_err(|_| {
                 self.logger.error(
                     "auth_service::gain_new_token",
                     "failed to extract claims from token",
-                );
-                return Ok(None);
-            }
-
-            let old_claims = old_token_claims.unwrap();
+                )
+            })?;
 
             // check if token is in blacklist
-            let result = self.redis.query_one(&old_claims.jti, &vec![]).await;
+            let result = self.redis.query_one(&old_claims.jti, &[]).await;
             let msg = format!("current jti is {0}", old_claims.jti);
             self.logger.info("auth_service::gain_new_token", &msg);
             match result {
@@ -220,7 +339,7 @@ impl<
                     if !value.row.is_empty() {
                         self.logger
                             .info("auth_service::gain_new_token", "jti is in blacklist");
-                        return Ok(None);
+                        return Err(Error::Unauthorized("Token has been revoked".to_string()));
                     }
                 }
                 Err(e) => {
@@ -229,32 +348,186 @@ impl<
                 }
             }
 
-            // Set expiration to 1 hour from now
-            let expired = (Utc::now() + Duration::hours(1)).timestamp() as usize;
+            // Sessions are removed on sign out and when the account is deleted
+            let session = self
+                .db
+                .query(
+                    "SELECT id FROM sessions WHERE id = $1 AND user_id = $2 AND expires_at > NOW()",
+                    &[&old_claims.jti, &old_claims.additional_claims.user_id],
+                )
+                .await?;
+            if session.is_empty() {
+                self.logger
+                    .info("auth_service::gain_new_token", "session is revoked");
+                return Err(Error::Unauthorized("Session has been revoked".to_string()));
+            }
+            self.check_suspension(&old_claims.additional_claims.user_id)
+                .await?;
+
+            let expired =
+                (Utc::now() + Duration::minutes(AUTH_TOKEN_TTL_MINUTES)).timestamp() as usize;
+
+            // Reload grants so role changes apply from the next refresh
+            let (roles, scopes) = self
+                .load_grants(&old_claims.additional_claims.user_id)
+                .await?;
 
             let claims = Claims {
                 sub: old_claims.sub.clone(),
                 iat: Utc::now().timestamp() as usize,
                 exp: expired,
                 nbf: Utc::now().timestamp() as usize,
-                jti: old_claims.jti.clone(),
+                // The refresh token's jti names the session, every auth token
+                // gets its own
+                jti: uuid_v4(),
                 additional_claims: AdditionalClaims {
                     user_id: old_claims.additional_claims.user_id.clone(),
-                    kind: old_claims.additional_claims.kind.clone(),
+                    kind: AUTH_TOKEN.to_string(),
+                    roles,
+                    scopes,
                 },
             };
 
             // Sign a new token with updated claims
-            let new_token = self.jwt.sign(&claims);
+            let new_token = self.jwt.sign(&claims)?;
             self.logger.info(
                 "auth_service::gain_new_token",
                 "new token is created successfully",
             );
-            Ok(Some(new_token))
+            Ok(new_token)
         } else {
             self.logger
                 .error("auth_service::gain_new_token", "old token is not valid");
-            Ok(None)
+            Err(Error::Unauthorized("Invalid or expired token".to_string()))
+        }
+    }
+
+    async fn change_password(&self, data: &ChangePasswordData) -> Result<(), Error> {
+        let invalid = || Error::Unauthorized("Invalid username or password".to_string());
+        let row = self
+            .db
+            .query_one(
+                "SELECT id, password FROM users WHERE username = $1",
+                &[&data.username],
+            )
+            .await
+            .map_err(|e| match e {
+                Error::NotFound(_) => invalid(),
+                e => e,
+            })?;
+        let user_id = row.get(0);
+        if !self.hasher.verify(&data.password, &row.get(1)) {
+            self.logger
+                .error("auth_service::change_password", "password is not match");
+            return Err(invalid());
+        }
+        self.check_suspension(&user_id).await?;
+        if data.new_password == data.password {
+            return Err(Error::BadRequest(
+                "New password must differ from the current one".to_string(),
+            ));
+        }
+        self.db
+            .execute(
+                "UPDATE users SET password = $1, password_reset_required = FALSE, updated_at = NOW() WHERE id = $2",
+                &[&self.hasher.hash(&data.new_password), &user_id],
+            )
+            .await?;
+        self.db
+            .execute("DELETE FROM sessions WHERE user_id = $1", &[&user_id])
+            .await?;
+        let message = format!("user {} changed their password", user_id);
+        self.logger.info("auth_service::change_password", &message);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use database::db::MockDatabase;
+    use logger::log::Log;
+    use security::{hasher::MockHasher, jwt::MockJwt};
+
+    use super::*;
+
+    fn refresh_claims() -> Claims {
+        Claims {
+            exp: 0,
+            iat: 0,
+            nbf: 0,
+            sub: "alice".to_string(),
+            jti: "session-1".to_string(),
+            additional_claims: AdditionalClaims {
+                user_id: "user-1".to_string(),
+                kind: REFRESH_TOKEN.to_string(),
+                roles: vec![],
+                scopes: vec![],
+            },
         }
     }
+
+    #[tokio::test]
+    async fn test_every_new_auth_token_gets_its_own_jti() {
+        let mut db = MockDatabase::new();
+        db.expect_query()
+            .withf(|sql, _| sql.contains("FROM sessions"))
+            .returning(|_, _| {
+                let rows = vec![PgRow::from(vec!["session-1".to_string()])];
+                Box::pin(async move { Ok(rows) })
+            });
+        db.expect_query()
+            .returning(|_, _| Box::pin(async move { Ok(vec![]) }));
+        let mut redis = MockDatabase::<RedisRow>::new();
+        redis.expect_query_one().returning(|_, _| {
+            Box::pin(async move { Err(Error::NotFound("Not found".to_string())) })
+        });
+        let mut jwt = MockJwt::new();
+        jwt.expect_verify().returning(|_| true);
+        jwt.expect_extract().returning(|_| Ok(refresh_claims()));
+        let jtis = Arc::new(Mutex::new(vec![]));
+        let signed = jtis.clone();
+        jwt.expect_sign().times(2).returning(move |claims| {
+            assert_eq!(claims.additional_claims.kind, AUTH_TOKEN);
+            signed.lock().unwrap().push(claims.jti.clone());
+            Ok("token".to_string())
+        });
+        let service = AuthServiceImpl::new(db, MockHasher::new(), jwt, Log, redis);
+
+        service.gain_new_token("refresh").await.unwrap();
+        service.gain_new_token("refresh").await.unwrap();
+        let jtis = jtis.lock().unwrap();
+        assert!(jtis.iter().all(|jti| jti != "session-1"));
+        assert_ne!(jtis[0], jtis[1]);
+    }
+
+    #[tokio::test]
+    async fn test_sign_up_fails_without_the_default_role() {
+        let mut db = MockDatabase::new();
+        db.expect_query_one()
+            .withf(|sql, params| sql.contains("INSERT INTO user_roles") && *params[3] == ROLE_USER)
+            .times(1)
+            .returning(|_, _| {
+                Box::pin(async move { Err(Error::Internal("rolled back".to_string())) })
+            });
+        db.expect_execute().never();
+        let mut hasher = MockHasher::new();
+        hasher.expect_hash().returning(|_| "hash".to_string());
+        let service = AuthServiceImpl::new(
+            db,
+            hasher,
+            MockJwt::new(),
+            Log,
+            MockDatabase::<RedisRow>::new(),
+        );
+        let data = SignUpData {
+            name: "Alice".to_string(),
+            username: "alice".to_string(),
+            password: "Secret123!".to_string(),
+        };
+
+        let result = service.sign_up(&data).await;
+        assert!(matches!(result, Err(Error::Internal(_))));
+    }
 }
@@ -1 +1,2 @@
 pub mod auth_service;
+pub mod role_service;
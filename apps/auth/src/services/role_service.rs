@@ -0,0 +1,227 @@
+use async_trait::async_trait;
+use database::{db::Database, pgx::PgRow};
+use errors::error::Error;
+use logger::logger::Logger;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct Role {
+    pub name: String,
+    pub description: String,
+    pub permissions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
+pub struct AssignRoleData {
+    #[validate(length(min = 1, max = 64))]
+    pub role: String,
+}
+
+#[async_trait]
+pub trait RoleService {
+    async fn get_roles(&self) -> Result<Vec<Role>, Error>;
+    async fn get_user_roles(&self, user_id: &str) -> Result<Vec<Role>, Error>;
+    async fn assign_role(&self, user_id: &str, role: &str) -> Result<(), Error>;
+    async fn revoke_role(&self, user_id: &str, role: &str) -> Result<(), Error>;
+}
+
+pub struct RoleServiceImpl<T: Database<PgRow>, L: Logger> {
+    db: T,
+    logger: L,
+}
+
+impl<T: Database<PgRow>, L: Logger> RoleServiceImpl<T, L> {
+    pub fn new(db: T, logger: L) -> Self {
+        Self { db, logger }
+    }
+}
+
+fn role_from_row(row: &PgRow) -> Role {
+    let permissions = row.get(2);
+    Role {
+        name: row.get(0),
+        description: row.get(1),
+        permissions: permissions
+            .split(',')
+            .filter(|p| !p.is_empty())
+            .map(|p| p.to_string())
+            .collect(),
+    }
+}
+
+const ROLE_SELECT: &str = "SELECT r.name, r.description, COALESCE(string_agg(p.name, ',' ORDER BY p.name), '') FROM roles r LEFT JOIN role_permissions rp ON rp.role_id = r.id LEFT JOIN permissions p ON p.id = rp.permission_id";
+
+#[async_trait]
+impl<T: Database<PgRow> + Send + Sync, L: Logger + Send + Sync> RoleService
+    for RoleServiceImpl<T, L>
+{
+    async fn get_roles(&self) -> Result<Vec<Role>, Error> {
+        self.logger
+            .info("role_service::get_roles", "querying roles");
+        let sql = format!(
+            "{} GROUP BY r.name, r.description ORDER BY r.name",
+            ROLE_SELECT
+        );
+        match self.db.query(&sql, &[]).await {
+            Ok(rows) => Ok(rows.iter().map(role_from_row).collect()),
+            Err(e) => {
+                let message = format!("failed to query roles: {}", e);
+                self.logger.error("role_service::get_roles", &message);
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_user_roles(&self, user_id: &str) -> Result<Vec<Role>, Error> {
+        let message = format!("querying roles of user {}", user_id);
+        self.logger.info("role_service::get_user_roles", &message);
+        let sql = format!(
+            "{} WHERE r.id IN (SELECT role_id FROM user_roles WHERE user_id = $1) GROUP BY r.name, r.description ORDER BY r.name",
+            ROLE_SELECT
+        );
+        match self.db.query(&sql, &[&user_id.to_string()]).await {
+            Ok(rows) => Ok(rows.iter().map(role_from_row).collect()),
+            Err(e) => {
+                let message = format!("failed to query roles of user {}: {}", user_id, e);
+                self.logger.error("role_service::get_user_roles", &message);
+                Err(e)
+            }
+        }
+    }
+
+    async fn assign_role(&self, user_id: &str, role: &str) -> Result<(), Error> {
+        let message = format!("assigning role {} to user {}", role, user_id);
+        self.logger.info("role_service::assign_role", &message);
+        let result = self
+            .db
+            .execute(
+                "INSERT INTO user_roles (user_id, role_id) SELECT u.id, r.id FROM users u, roles r WHERE u.id = $1 AND r.name = $2 ON CONFLICT DO NOTHING",
+                &[&user_id.to_string(), &role.to_string()],
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                // Zero rows is either an existing assignment or an unknown
+                // user/role, only the latter is an error for the caller.
+                let exists = self
+                    .db
+                    .query(
+                        "SELECT 1::TEXT FROM user_roles ur JOIN roles r ON r.id = ur.role_id WHERE ur.user_id = $1 AND r.name = $2",
+                        &[&user_id.to_string(), &role.to_string()],
+                    )
+                    .await
+                    .map(|rows| !rows.is_empty())?;
+                if exists {
+                    Ok(())
+                } else {
+                    Err(Error::NotFound("User or role not found".to_string()))
+                }
+            }
+            Err(e) => {
+                let message = format!("failed to assign role: {}", e);
+                self.logger.error("role_service::assign_role", &message);
+                Err(e)
+            }
+        }
+    }
+
+    async fn revoke_role(&self, user_id: &str, role: &str) -> Result<(), Error> {
+        let message = format!("revoking role {} from user {}", role, user_id);
+        self.logger.info("role_service::revoke_role", &message);
+        let result = self
+            .db
+            .execute(
+                "DELETE FROM user_roles ur USING roles r WHERE ur.role_id = r.id AND ur.user_id = $1 AND r.name = $2",
+                &[&user_id.to_string(), &role.to_string()],
+            )
+            .await;
+        match result {
+            Ok(0) => Err(Error::NotFound("Role assignment not found".to_string())),
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let message = format!("failed to revoke role: {}", e);
+                self.logger.error("role_service::revoke_role", &message);
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::db::MockDatabase;
+    use logger::log::Log;
+
+    use super::*;
+
+    fn service(db: MockDatabase<PgRow>) -> RoleServiceImpl<MockDatabase<PgRow>, Log> {
+        RoleServiceImpl::new(db, Log)
+    }
+
+    /// Database where inserting the assignment touches `inserted` rows and
+    /// the assignment exists afterwards when `exists`.
+    fn assign_db(inserted: u64, exists: bool) -> MockDatabase<PgRow> {
+        let mut db = MockDatabase::new();
+        db.expect_execute()
+            .withf(|sql, params| {
+                sql.starts_with("INSERT INTO user_roles")
+                    && *params[0] == "user-1"
+                    && *params[1] == "moderator"
+            })
+            .times(1)
+            .returning(move |_, _| Box::pin(async move { Ok(inserted) }));
+        db.expect_query()
+            .withf(|sql, _| sql.contains("FROM user_roles ur"))
+            .returning(move |_, _| {
+                let rows = match exists {
+                    true => vec![PgRow::from(vec!["1".to_string()])],
+                    false => vec![],
+                };
+                Box::pin(async move { Ok(rows) })
+            });
+        db
+    }
+
+    #[tokio::test]
+    async fn test_assign_role() {
+        let service = service(assign_db(1, true));
+        assert!(service.assign_role("user-1", "moderator").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_assigning_a_held_role_changes_nothing() {
+        let service = service(assign_db(0, true));
+        assert!(service.assign_role("user-1", "moderator").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_assigning_to_unknown_user_or_role_is_not_found() {
+        let result = service(assign_db(0, false))
+            .assign_role("user-1", "moderator")
+            .await;
+        assert!(matches!(result, Err(Error::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_revoke_role() {
+        for (deleted, found) in [(1, true), (0, false)] {
+            let mut db = MockDatabase::new();
+            db.expect_execute()
+                .withf(|sql, params| {
+                    sql.starts_with("DELETE FROM user_roles")
+                        && *params[0] == "user-1"
+                        && *params[1] == "moderator"
+                })
+                .times(1)
+                .returning(move |_, _| Box::pin(async move { Ok(deleted) }));
+
+            let result = service(db).revoke_role("user-1", "moderator").await;
+            match found {
+                true => assert!(result.is_ok()),
+                false => assert!(matches!(result, Err(Error::NotFound(_)))),
+            }
+        }
+    }
+}
@@ -1,2 +1,5 @@
 pub const REFRESH_TOKEN: &str = "refresh_token";
 pub const AUTH_TOKEN: &str = "auth_token";
+/// Minutes an auth token is valid. Guards don't look up revoked sessions, so
+/// a token keeps working this long after its session is revoked.
+pub const AUTH_TOKEN_TTL_MINUTES: i64 = 15;
@@ -0,0 +1,235 @@
+use actix_web::{
+    http::header::{self, ContentDisposition, DispositionParam, DispositionType},
+    web, HttpResponse,
+};
+use auth_middleware::{guard::Guard, source::TokenSource, user::AuthenticatedUser};
+use database::pgx::Postgresql;
+use errors::{
+    error::{Error, ErrorBody},
+    response::{Empty, Response},
+};
+use futures::TryStreamExt;
+use logger::log::Log;
+use security::{env::EnvImpl, jwt::JwtImpl};
+use storage::{blob::AnyBlobStore, mime::IMAGE_TYPES};
+use validation::extractor::{ValidJson, ValidQuery};
+
+use crate::services::attachment_service::{
+    Attachment, AttachmentDownload, AttachmentService, AttachmentServiceImpl, RequestUpload,
+    SignedQuery, UploadSlot, MAX_ATTACHMENT_BYTES,
+};
+
+/// Registers `/chat/attachments`, which needs an auth token, and
+/// `/chat/files`, which is opened by signed links instead.
+pub fn attachment_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let jwt_middleware = Guard::new(jwt.clone())
+        .sources(vec![
+            TokenSource::authorization(),
+            TokenSource::cookie("token"),
+        ])
+        .kinds(&["auth_token"]);
+    config
+        .service(
+            web::scope("/chat/attachments")
+                .wrap(jwt_middleware)
+                .route("", web::post().to(request_upload_handler))
+                .route("/{attachment_id}", web::get().to(get_download_handler))
+                .route("/{attachment_id}", web::delete().to(delete_handler))
+                .route("/{attachment_id}/content", web::put().to(upload_handler)),
+        )
+        .service(
+            web::scope("/chat/files")
+                .route("/{attachment_id}", web::get().to(get_content_handler))
+                .route(
+                    "/{attachment_id}/thumbnail",
+                    web::get().to(get_thumbnail_handler),
+                ),
+        );
+}
+
+/// Reads the body, stopping as soon as it grows past
+/// [`MAX_ATTACHMENT_BYTES`] instead of buffering all of it.
+async fn read_body(mut payload: web::Payload) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = payload
+        .try_next()
+        .await
+        .map_err(|e| Error::BadRequest(e.to_string()))?
+    {
+        if bytes.len() + chunk.len() > MAX_ATTACHMENT_BYTES {
+            return Err(Error::PayloadTooLarge(format!(
+                "Attachment must be at most {} bytes",
+                MAX_ATTACHMENT_BYTES
+            )));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+#[utoipa::path(
+    post,
+    path = "/chat/attachments",
+    tag = "attachments",
+    request_body = RequestUpload,
+    responses(
+        (status = 201, description = "Upload slot for the file", body = Response<UploadSlot>),
+        (status = 400, description = "File name is empty", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 413, description = "File is larger than 25 MiB or doesn't fit in the caller's quota", body = ErrorBody),
+        (status = 415, description = "Type of file isn't accepted", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn request_upload_handler(
+    service: web::Data<AttachmentServiceImpl<Postgresql, Log, AnyBlobStore>>,
+    body: ValidJson<RequestUpload>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let slot = service.request_upload(&user.user_id, &body).await?;
+    Ok(HttpResponse::Created().json(Response::new(slot, "Successfully requested upload")))
+}
+
+#[utoipa::path(
+    put,
+    path = "/chat/attachments/{attachment_id}/content",
+    tag = "attachments",
+    params(("attachment_id" = String, Path, description = "Attachment id")),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream", description = "Bytes of the file"),
+    responses(
+        (status = 200, description = "The uploaded attachment, ready to be sent with a message", body = Response<Attachment>),
+        (status = 400, description = "Undecodable image", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "Caller has no such upload slot", body = ErrorBody),
+        (status = 409, description = "Attachment is already uploaded or the slot has expired", body = ErrorBody),
+        (status = 413, description = "File is larger than requested", body = ErrorBody),
+        (status = 415, description = "Bytes aren't of an accepted type", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn upload_handler(
+    service: web::Data<AttachmentServiceImpl<Postgresql, Log, AnyBlobStore>>,
+    path: web::Path<String>,
+    payload: web::Payload,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let bytes = read_body(payload).await?;
+    let attachment = service
+        .upload(&user.user_id, &path.into_inner(), bytes)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(
+        attachment,
+        "Successfully uploaded attachment",
+    )))
+}
+
+#[utoipa::path(
+    get,
+    path = "/chat/attachments/{attachment_id}",
+    tag = "attachments",
+    params(("attachment_id" = String, Path, description = "Attachment id")),
+    responses(
+        (status = 200, description = "Short-lived links to the attachment", body = Response<AttachmentDownload>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "Attachment not found or not visible to the caller", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_download_handler(
+    service: web::Data<AttachmentServiceImpl<Postgresql, Log, AnyBlobStore>>,
+    path: web::Path<String>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let download = service
+        .get_download(&user.user_id, &path.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(download, "Successfully got attachment links")))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/chat/attachments/{attachment_id}",
+    tag = "attachments",
+    params(("attachment_id" = String, Path, description = "Attachment id")),
+    responses(
+        (status = 200, description = "Attachment is gone and no longer counts against the quota", body = Response<Empty>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "Attachment not found", body = ErrorBody),
+        (status = 409, description = "Attachment is part of a message", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn delete_handler(
+    service: web::Data<AttachmentServiceImpl<Postgresql, Log, AnyBlobStore>>,
+    path: web::Path<String>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    service
+        .delete_attachment(&user.user_id, &path.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(Empty, "Successfully deleted attachment")))
+}
+
+async fn content(
+    service: web::Data<AttachmentServiceImpl<Postgresql, Log, AnyBlobStore>>,
+    attachment_id: &str,
+    thumbnail: bool,
+    query: &SignedQuery,
+) -> Result<HttpResponse, Error> {
+    let download = service
+        .read_content(attachment_id, thumbnail, query)
+        .await?;
+    // Only images are shown inline, anything else is saved
+    let disposition = match IMAGE_TYPES.contains(&download.content_type.as_str()) {
+        true => DispositionType::Inline,
+        false => DispositionType::Attachment,
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(download.content_type)
+        .insert_header(ContentDisposition {
+            disposition,
+            parameters: vec![DispositionParam::Filename(download.file_name)],
+        })
+        .insert_header((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+        .insert_header((header::CACHE_CONTROL, "private, max-age=300"))
+        .body(download.bytes))
+}
+
+#[utoipa::path(
+    get,
+    path = "/chat/files/{attachment_id}",
+    tag = "attachments",
+    params(("attachment_id" = String, Path, description = "Attachment id"), SignedQuery),
+    responses(
+        (status = 200, description = "Content of the attachment", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 403, description = "Link is invalid or has expired", body = ErrorBody),
+        (status = 404, description = "Attachment not found", body = ErrorBody),
+    )
+)]
+async fn get_content_handler(
+    service: web::Data<AttachmentServiceImpl<Postgresql, Log, AnyBlobStore>>,
+    path: web::Path<String>,
+    query: ValidQuery<SignedQuery>,
+) -> Result<HttpResponse, Error> {
+    content(service, &path.into_inner(), false, &query).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/chat/files/{attachment_id}/thumbnail",
+    tag = "attachments",
+    params(("attachment_id" = String, Path, description = "Attachment id"), SignedQuery),
+    responses(
+        (status = 200, description = "PNG thumbnail of an image attachment", content_type = "image/png", body = Vec<u8>),
+        (status = 403, description = "Link is invalid or has expired", body = ErrorBody),
+        (status = 404, description = "Attachment not found or has no thumbnail", body = ErrorBody),
+    )
+)]
+async fn get_thumbnail_handler(
+    service: web::Data<AttachmentServiceImpl<Postgresql, Log, AnyBlobStore>>,
+    path: web::Path<String>,
+    query: ValidQuery<SignedQuery>,
+) -> Result<HttpResponse, Error> {
+    content(service, &path.into_inner(), true, &query).await
+}
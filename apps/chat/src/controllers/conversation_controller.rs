@@ -0,0 +1,391 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use auth_middleware::{guard::Guard, source::TokenSource, user::AuthenticatedUser};
+use database::pgx::Postgresql;
+use errors::{
+    error::{Error, ErrorBody},
+    response::{Empty, Response},
+};
+use events::publisher::RedisPublisher;
+use logger::log::Log;
+use pagination::page::Page;
+use security::{env::EnvImpl, jwt::JwtImpl};
+use unread::counter::RedisUnreadCounter;
+use validation::extractor::{ValidJson, ValidQuery};
+
+use crate::services::conversation_service::{
+    Conversation, ConversationService, ConversationServiceImpl, CreateConversation, CreateRoom,
+    MarkRead, QueryConversations, ReadState, Receipt, UpdateRoom,
+};
+
+pub fn conversation_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let jwt_middleware = Guard::new(jwt.clone())
+        .sources(vec![
+            TokenSource::authorization(),
+            TokenSource::cookie("token"),
+        ])
+        .kinds(&["auth_token"]);
+    config.service(
+        web::scope("/chat/conversations")
+            .wrap(jwt_middleware)
+            .route("", web::get().to(get_conversations_handler))
+            .route("", web::post().to(create_conversation_handler))
+            .route(
+                "/{conversation_id}",
+                web::get().to(get_conversation_handler),
+            )
+            .route(
+                "/{conversation_id}/leave",
+                web::post().to(leave_conversation_handler),
+            )
+            .route("/{conversation_id}/read", web::post().to(mark_read_handler))
+            .route(
+                "/{conversation_id}/receipts",
+                web::get().to(get_receipts_handler),
+            )
+            .route("/{conversation_id}/mute", web::put().to(mute_handler))
+            .route("/{conversation_id}/mute", web::delete().to(unmute_handler)),
+    );
+}
+
+pub fn room_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let jwt_middleware = Guard::new(jwt.clone())
+        .sources(vec![
+            TokenSource::authorization(),
+            TokenSource::cookie("token"),
+        ])
+        .kinds(&["auth_token"]);
+    config.service(
+        web::scope("/chat/rooms")
+            .wrap(jwt_middleware)
+            .route("", web::get().to(get_rooms_handler))
+            .route("", web::post().to(create_room_handler))
+            .route("/{room_id}", web::patch().to(update_room_handler))
+            .route("/{room_id}/join", web::post().to(join_room_handler)),
+    );
+}
+
+#[utoipa::path(
+    get,
+    path = "/chat/conversations",
+    tag = "conversations",
+    params(QueryConversations),
+    responses(
+        (status = 200, description = "Conversations of the caller, most recently active first", body = Response<Page<Conversation>>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 422, description = "Invalid query", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_conversations_handler(
+    service: web::Data<
+        ConversationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>,
+    >,
+    query: ValidQuery<QueryConversations>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let conversations = service
+        .get_conversations(&user.user_id, &query)
+        .await?
+        .with_links(&req);
+    Ok(HttpResponse::Ok().json(Response::new(
+        conversations,
+        "Successfully got conversations",
+    )))
+}
+
+#[utoipa::path(
+    post,
+    path = "/chat/conversations",
+    tag = "conversations",
+    request_body = CreateConversation,
+    responses(
+        (status = 200, description = "Direct conversation with the user already exists", body = Response<Conversation>),
+        (status = 201, description = "Conversation is started", body = Response<Conversation>),
+        (status = 400, description = "Conversation with yourself", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "A user blocked the caller, is blocked by them or doesn't accept their messages", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn create_conversation_handler(
+    service: web::Data<
+        ConversationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>,
+    >,
+    body: ValidJson<CreateConversation>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let (conversation, created) = service.create_conversation(&user.user_id, &body).await?;
+    if created {
+        return Ok(HttpResponse::Created().json(Response::new(
+            conversation,
+            "Successfully started conversation",
+        )));
+    }
+    Ok(HttpResponse::Ok().json(Response::new(conversation, "Successfully got conversation")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/chat/conversations/{conversation_id}",
+    tag = "conversations",
+    params(("conversation_id" = String, Path, description = "Conversation id")),
+    responses(
+        (status = 200, description = "The conversation", body = Response<Conversation>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "Conversation not found or the caller isn't in it", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_conversation_handler(
+    service: web::Data<
+        ConversationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>,
+    >,
+    path: web::Path<String>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let conversation = service
+        .get_conversation(&user.user_id, &path.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(conversation, "Successfully got conversation")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/chat/conversations/{conversation_id}/leave",
+    tag = "conversations",
+    params(("conversation_id" = String, Path, description = "Group or room to leave")),
+    responses(
+        (status = 200, description = "Caller left the conversation", body = Response<Empty>),
+        (status = 400, description = "Direct conversations can't be left, nor rooms by their owner while others are in them", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "Conversation not found or the caller isn't in it", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn leave_conversation_handler(
+    service: web::Data<
+        ConversationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>,
+    >,
+    path: web::Path<String>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    service
+        .leave_conversation(&user.user_id, &path.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(Empty, "Successfully left conversation")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/chat/conversations/{conversation_id}/read",
+    tag = "conversations",
+    params(("conversation_id" = String, Path, description = "Conversation id")),
+    request_body = MarkRead,
+    responses(
+        (status = 200, description = "Read position of the caller, it never moves back", body = Response<ReadState>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "Conversation or message not found, the caller isn't in the conversation or the message is a reply", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn mark_read_handler(
+    service: web::Data<
+        ConversationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>,
+    >,
+    path: web::Path<String>,
+    body: ValidJson<MarkRead>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let state = service
+        .mark_read(&user.user_id, &path.into_inner(), &body)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(state, "Successfully marked messages read")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/chat/conversations/{conversation_id}/receipts",
+    tag = "conversations",
+    params(("conversation_id" = String, Path, description = "Conversation id")),
+    responses(
+        (status = 200, description = "Read positions of the members in direct conversations and small groups, only the caller's own elsewhere", body = Response<Vec<Receipt>>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "Conversation not found or the caller isn't in it", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_receipts_handler(
+    service: web::Data<
+        ConversationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>,
+    >,
+    path: web::Path<String>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let receipts = service
+        .get_receipts(&user.user_id, &path.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(receipts, "Successfully got receipts")))
+}
+
+#[utoipa::path(
+    put,
+    path = "/chat/conversations/{conversation_id}/mute",
+    tag = "conversations",
+    params(("conversation_id" = String, Path, description = "Conversation id")),
+    responses(
+        (status = 200, description = "The conversation, muted for the caller", body = Response<Conversation>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "Conversation not found or the caller isn't in it", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn mute_handler(
+    service: web::Data<
+        ConversationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>,
+    >,
+    path: web::Path<String>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let conversation = service
+        .set_muted(&user.user_id, &path.into_inner(), true)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(
+        conversation,
+        "Successfully muted conversation",
+    )))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/chat/conversations/{conversation_id}/mute",
+    tag = "conversations",
+    params(("conversation_id" = String, Path, description = "Conversation id")),
+    responses(
+        (status = 200, description = "The conversation, no longer muted for the caller", body = Response<Conversation>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "Conversation not found or the caller isn't in it", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn unmute_handler(
+    service: web::Data<
+        ConversationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>,
+    >,
+    path: web::Path<String>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let conversation = service
+        .set_muted(&user.user_id, &path.into_inner(), false)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(
+        conversation,
+        "Successfully unmuted conversation",
+    )))
+}
+
+#[utoipa::path(
+    get,
+    path = "/chat/rooms",
+    tag = "rooms",
+    params(QueryConversations),
+    responses(
+        (status = 200, description = "Public rooms by name", body = Response<Page<Conversation>>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 422, description = "Invalid query", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_rooms_handler(
+    service: web::Data<
+        ConversationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>,
+    >,
+    query: ValidQuery<QueryConversations>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let rooms = service
+        .get_rooms(&user.user_id, &query)
+        .await?
+        .with_links(&req);
+    Ok(HttpResponse::Ok().json(Response::new(rooms, "Successfully got rooms")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/chat/rooms",
+    tag = "rooms",
+    request_body = CreateRoom,
+    responses(
+        (status = 201, description = "Room is created with the caller in it", body = Response<Conversation>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn create_room_handler(
+    service: web::Data<
+        ConversationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>,
+    >,
+    body: ValidJson<CreateRoom>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let room = service.create_room(&user.user_id, &body).await?;
+    Ok(HttpResponse::Created().json(Response::new(room, "Successfully created room")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/chat/rooms/{room_id}/join",
+    tag = "rooms",
+    params(("room_id" = String, Path, description = "Room to join")),
+    responses(
+        (status = 200, description = "Caller is in the room, joining again changes nothing", body = Response<Conversation>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "Caller is banned from the room", body = ErrorBody),
+        (status = 404, description = "Room not found", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn join_room_handler(
+    service: web::Data<
+        ConversationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>,
+    >,
+    path: web::Path<String>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let room = service.join_room(&user.user_id, &path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(Response::new(room, "Successfully joined room")))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/chat/rooms/{room_id}",
+    tag = "rooms",
+    params(("room_id" = String, Path, description = "Room id")),
+    request_body = UpdateRoom,
+    responses(
+        (status = 200, description = "The renamed room", body = Response<Conversation>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "Caller may not edit the room's settings", body = ErrorBody),
+        (status = 404, description = "Room not found or the caller isn't in it", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn update_room_handler(
+    service: web::Data<
+        ConversationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>,
+    >,
+    path: web::Path<String>,
+    body: ValidJson<UpdateRoom>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let room = service
+        .update_room(&user.user_id, &path.into_inner(), &body)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(room, "Successfully updated room")))
+}
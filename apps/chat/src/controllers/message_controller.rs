@@ -0,0 +1,289 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use auth_middleware::{
+    guard::Guard,
+    rbac::{ROLE_ADMIN, ROLE_MODERATOR},
+    source::TokenSource,
+    user::AuthenticatedUser,
+};
+use database::pgx::Postgresql;
+use errors::{
+    error::{Error, ErrorBody},
+    response::Response,
+};
+use events::publisher::RedisPublisher;
+use logger::log::Log;
+use pagination::page::Page;
+use presence::store::RedisPresence;
+use security::{env::EnvImpl, jwt::JwtImpl};
+use unread::counter::RedisUnreadCounter;
+use validation::extractor::{ValidJson, ValidQuery};
+
+use crate::services::message_service::{
+    EditMessage, Message, MessageRevision, MessageService, MessageServiceImpl, QueryMessages,
+    SendMessage,
+};
+
+/// Registers routes under `/chat/conversations/{conversation_id}/messages`,
+/// before the conversation scope takes the rest of the path.
+pub fn message_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let jwt_middleware = Guard::new(jwt.clone())
+        .sources(vec![
+            TokenSource::authorization(),
+            TokenSource::cookie("token"),
+        ])
+        .kinds(&["auth_token"]);
+    config.service(
+        web::scope("/chat/conversations/{conversation_id}/messages")
+            .wrap(jwt_middleware)
+            .route("", web::get().to(get_messages_handler))
+            .route("", web::post().to(send_message_handler))
+            .route("/pinned", web::get().to(get_pinned_handler))
+            .route("/{message_id}", web::patch().to(edit_message_handler))
+            .route("/{message_id}", web::delete().to(delete_message_handler))
+            .route(
+                "/{message_id}/revisions",
+                web::get().to(get_revisions_handler),
+            )
+            .route("/{message_id}/pin", web::put().to(pin_message_handler))
+            .route("/{message_id}/pin", web::delete().to(unpin_message_handler)),
+    );
+}
+
+#[utoipa::path(
+    get,
+    path = "/chat/conversations/{conversation_id}/messages",
+    tag = "messages",
+    params(("conversation_id" = String, Path, description = "Conversation id"), QueryMessages),
+    responses(
+        (status = 200, description = "Messages, newest first", body = Response<Page<Message>>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "Conversation not found or the caller isn't in it", body = ErrorBody),
+        (status = 422, description = "Invalid query", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_messages_handler(
+    service: web::Data<
+        MessageServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter, RedisPresence>,
+    >,
+    path: web::Path<String>,
+    query: ValidQuery<QueryMessages>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let messages = service
+        .get_messages(&user.user_id, &path.into_inner(), &query)
+        .await?
+        .with_links(&req);
+    Ok(HttpResponse::Ok().json(Response::new(messages, "Successfully got messages")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/chat/conversations/{conversation_id}/messages",
+    tag = "messages",
+    params(("conversation_id" = String, Path, description = "Conversation id")),
+    request_body = SendMessage,
+    responses(
+        (status = 201, description = "Message is sent", body = Response<Message>),
+        (status = 400, description = "Message is blank without attachments, or an attachment isn't the caller's uploaded and unsent one", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "One side of the direct conversation blocked the other", body = ErrorBody),
+        (status = 404, description = "Conversation not found or the caller isn't in it", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn send_message_handler(
+    service: web::Data<
+        MessageServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter, RedisPresence>,
+    >,
+    path: web::Path<String>,
+    body: ValidJson<SendMessage>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let message = service
+        .send_message(&user.user_id, &path.into_inner(), &body)
+        .await?;
+    Ok(HttpResponse::Created().json(Response::new(message, "Successfully sent message")))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/chat/conversations/{conversation_id}/messages/{message_id}",
+    tag = "messages",
+    params(
+        ("conversation_id" = String, Path, description = "Conversation id"),
+        ("message_id" = String, Path, description = "Message id"),
+    ),
+    request_body = EditMessage,
+    responses(
+        (status = 200, description = "Message with its new body, the old one is kept as a revision", body = Response<Message>),
+        (status = 400, description = "Message is blank", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "Caller is not the author", body = ErrorBody),
+        (status = 404, description = "Conversation or message not found, or the message is deleted", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn edit_message_handler(
+    service: web::Data<
+        MessageServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter, RedisPresence>,
+    >,
+    path: web::Path<(String, String)>,
+    body: ValidJson<EditMessage>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let (conversation_id, message_id) = path.into_inner();
+    let message = service
+        .edit_message(&user.user_id, &conversation_id, &message_id, &body)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(message, "Successfully edited message")))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/chat/conversations/{conversation_id}/messages/{message_id}",
+    tag = "messages",
+    params(
+        ("conversation_id" = String, Path, description = "Conversation id"),
+        ("message_id" = String, Path, description = "Message id"),
+    ),
+    responses(
+        (status = 200, description = "Tombstone left in place of the message", body = Response<Message>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "Caller is neither the author nor a moderator", body = ErrorBody),
+        (status = 404, description = "Conversation or message not found, or the message is deleted", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn delete_message_handler(
+    service: web::Data<
+        MessageServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter, RedisPresence>,
+    >,
+    path: web::Path<(String, String)>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let (conversation_id, message_id) = path.into_inner();
+    let moderator = user.has_role(ROLE_MODERATOR) || user.has_role(ROLE_ADMIN);
+    let message = service
+        .delete_message(&user.user_id, moderator, &conversation_id, &message_id)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(message, "Successfully deleted message")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/chat/conversations/{conversation_id}/messages/{message_id}/revisions",
+    tag = "messages",
+    params(
+        ("conversation_id" = String, Path, description = "Conversation id"),
+        ("message_id" = String, Path, description = "Message id"),
+    ),
+    responses(
+        (status = 200, description = "Earlier bodies of the message, oldest first", body = Response<Vec<MessageRevision>>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "Conversation or message not found, or the message is deleted", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_revisions_handler(
+    service: web::Data<
+        MessageServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter, RedisPresence>,
+    >,
+    path: web::Path<(String, String)>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let (conversation_id, message_id) = path.into_inner();
+    let revisions = service
+        .get_revisions(&user.user_id, &conversation_id, &message_id)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(revisions, "Successfully got revisions")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/chat/conversations/{conversation_id}/messages/pinned",
+    tag = "messages",
+    params(("conversation_id" = String, Path, description = "Conversation id")),
+    responses(
+        (status = 200, description = "Pinned messages, most recently pinned first", body = Response<Vec<Message>>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "Conversation not found or the caller isn't in it", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_pinned_handler(
+    service: web::Data<
+        MessageServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter, RedisPresence>,
+    >,
+    path: web::Path<String>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let messages = service
+        .get_pinned(&user.user_id, &path.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(messages, "Successfully got pinned messages")))
+}
+
+#[utoipa::path(
+    put,
+    path = "/chat/conversations/{conversation_id}/messages/{message_id}/pin",
+    tag = "messages",
+    params(
+        ("conversation_id" = String, Path, description = "Conversation id"),
+        ("message_id" = String, Path, description = "Message id"),
+    ),
+    responses(
+        (status = 200, description = "Message is pinned, pinning it again changes nothing", body = Response<Message>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "Caller may not pin in this room", body = ErrorBody),
+        (status = 404, description = "Conversation or message not found, or the message is deleted", body = ErrorBody),
+        (status = 409, description = "Conversation already has 50 pinned messages", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn pin_message_handler(
+    service: web::Data<
+        MessageServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter, RedisPresence>,
+    >,
+    path: web::Path<(String, String)>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let (conversation_id, message_id) = path.into_inner();
+    let message = service
+        .pin_message(&user.user_id, &conversation_id, &message_id)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(message, "Successfully pinned message")))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/chat/conversations/{conversation_id}/messages/{message_id}/pin",
+    tag = "messages",
+    params(
+        ("conversation_id" = String, Path, description = "Conversation id"),
+        ("message_id" = String, Path, description = "Message id"),
+    ),
+    responses(
+        (status = 200, description = "Message is no longer pinned", body = Response<Message>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "Caller may not pin in this room", body = ErrorBody),
+        (status = 404, description = "Conversation or message not found, or the message isn't pinned", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn unpin_message_handler(
+    service: web::Data<
+        MessageServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter, RedisPresence>,
+    >,
+    path: web::Path<(String, String)>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let (conversation_id, message_id) = path.into_inner();
+    let message = service
+        .unpin_message(&user.user_id, &conversation_id, &message_id)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(message, "Successfully unpinned message")))
+}
@@ -0,0 +1,9 @@
+pub mod attachment_controller;
+pub mod conversation_controller;
+pub mod message_controller;
+pub mod moderation_controller;
+pub mod notification_controller;
+pub mod reaction_controller;
+pub mod realtime_controller;
+pub mod search_controller;
+pub mod thread_controller;
@@ -0,0 +1,291 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use auth_middleware::{guard::Guard, source::TokenSource, user::AuthenticatedUser};
+use database::pgx::Postgresql;
+use errors::{
+    error::{Error, ErrorBody},
+    response::{Empty, Response},
+};
+use events::publisher::RedisPublisher;
+use logger::log::Log;
+use pagination::page::Page;
+use security::{env::EnvImpl, jwt::JwtImpl};
+use unread::counter::RedisUnreadCounter;
+use validation::extractor::{ValidJson, ValidQuery};
+
+use crate::services::moderation_service::{
+    BanMember, KickMember, ModerationService, ModerationServiceImpl, QueryModeration,
+    RoomAuditEntry, RoomBan, RoomMember, SetRole, TimeoutMember,
+};
+
+/// Registers the members, bans and audit routes of a room, each in its own
+/// scope so `/chat/rooms` keeps the rest of the path.
+pub fn moderation_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let jwt_middleware = || {
+        Guard::new(jwt.clone())
+            .sources(vec![
+                TokenSource::authorization(),
+                TokenSource::cookie("token"),
+            ])
+            .kinds(&["auth_token"])
+    };
+    config
+        .service(
+            web::scope("/chat/rooms/{room_id}/members")
+                .wrap(jwt_middleware())
+                .route("/{user_id}/role", web::put().to(set_role_handler))
+                .route("/{user_id}/kick", web::post().to(kick_handler))
+                .route("/{user_id}/timeout", web::put().to(timeout_handler))
+                .route("/{user_id}/timeout", web::delete().to(lift_timeout_handler)),
+        )
+        .service(
+            web::scope("/chat/rooms/{room_id}/bans")
+                .wrap(jwt_middleware())
+                .route("", web::get().to(get_bans_handler))
+                .route("/{user_id}", web::put().to(ban_handler))
+                .route("/{user_id}", web::delete().to(unban_handler)),
+        )
+        .service(
+            web::scope("/chat/rooms/{room_id}/audit")
+                .wrap(jwt_middleware())
+                .route("", web::get().to(get_audit_handler)),
+        );
+}
+
+#[utoipa::path(
+    put,
+    path = "/chat/rooms/{room_id}/members/{user_id}/role",
+    tag = "moderation",
+    params(
+        ("room_id" = String, Path, description = "Room id"),
+        ("user_id" = String, Path, description = "Member whose role changes"),
+    ),
+    request_body = SetRole,
+    responses(
+        (status = 200, description = "The member with their new role", body = Response<RoomMember>),
+        (status = 400, description = "Caller targets themselves", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "Caller may not manage roles, or the member or role isn't below theirs", body = ErrorBody),
+        (status = 404, description = "Room or member not found", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn set_role_handler(
+    service: web::Data<ModerationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>>,
+    path: web::Path<(String, String)>,
+    body: ValidJson<SetRole>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let (room_id, member_id) = path.into_inner();
+    let member = service
+        .set_role(&user.user_id, &room_id, &member_id, &body)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(member, "Successfully set role")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/chat/rooms/{room_id}/members/{user_id}/kick",
+    tag = "moderation",
+    params(
+        ("room_id" = String, Path, description = "Room id"),
+        ("user_id" = String, Path, description = "Member to remove"),
+    ),
+    request_body = KickMember,
+    responses(
+        (status = 200, description = "Member is out of the room and may join again", body = Response<Empty>),
+        (status = 400, description = "Caller targets themselves", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "Caller may not kick, or the member's role isn't below theirs", body = ErrorBody),
+        (status = 404, description = "Room or member not found", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn kick_handler(
+    service: web::Data<ModerationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>>,
+    path: web::Path<(String, String)>,
+    body: ValidJson<KickMember>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let (room_id, member_id) = path.into_inner();
+    service
+        .kick(&user.user_id, &room_id, &member_id, &body)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(Empty, "Successfully kicked member")))
+}
+
+#[utoipa::path(
+    put,
+    path = "/chat/rooms/{room_id}/members/{user_id}/timeout",
+    tag = "moderation",
+    params(
+        ("room_id" = String, Path, description = "Room id"),
+        ("user_id" = String, Path, description = "Member to time out"),
+    ),
+    request_body = TimeoutMember,
+    responses(
+        (status = 200, description = "The member, who can't send until the timeout ends", body = Response<RoomMember>),
+        (status = 400, description = "Caller targets themselves", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "Caller may not mute, or the member's role isn't below theirs", body = ErrorBody),
+        (status = 404, description = "Room or member not found", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn timeout_handler(
+    service: web::Data<ModerationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>>,
+    path: web::Path<(String, String)>,
+    body: ValidJson<TimeoutMember>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let (room_id, member_id) = path.into_inner();
+    let member = service
+        .timeout(&user.user_id, &room_id, &member_id, &body)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(member, "Successfully timed out member")))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/chat/rooms/{room_id}/members/{user_id}/timeout",
+    tag = "moderation",
+    params(
+        ("room_id" = String, Path, description = "Room id"),
+        ("user_id" = String, Path, description = "Member whose timeout ends"),
+    ),
+    responses(
+        (status = 200, description = "The member, who can send again", body = Response<RoomMember>),
+        (status = 400, description = "Caller targets themselves", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "Caller may not mute, or the member's role isn't below theirs", body = ErrorBody),
+        (status = 404, description = "Room or member not found, or the member isn't timed out", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn lift_timeout_handler(
+    service: web::Data<ModerationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>>,
+    path: web::Path<(String, String)>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let (room_id, member_id) = path.into_inner();
+    let member = service
+        .lift_timeout(&user.user_id, &room_id, &member_id)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(member, "Successfully lifted timeout")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/chat/rooms/{room_id}/bans",
+    tag = "moderation",
+    params(("room_id" = String, Path, description = "Room id"), QueryModeration),
+    responses(
+        (status = 200, description = "Bans in effect, most recent first", body = Response<Page<RoomBan>>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "Caller may not ban", body = ErrorBody),
+        (status = 404, description = "Room not found or the caller isn't in it", body = ErrorBody),
+        (status = 422, description = "Invalid query", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_bans_handler(
+    service: web::Data<ModerationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>>,
+    path: web::Path<String>,
+    query: ValidQuery<QueryModeration>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let bans = service
+        .get_bans(&user.user_id, &path.into_inner(), &query)
+        .await?
+        .with_links(&req);
+    Ok(HttpResponse::Ok().json(Response::new(bans, "Successfully got bans")))
+}
+
+#[utoipa::path(
+    put,
+    path = "/chat/rooms/{room_id}/bans/{user_id}",
+    tag = "moderation",
+    params(
+        ("room_id" = String, Path, description = "Room id"),
+        ("user_id" = String, Path, description = "User to ban, in the room or not"),
+    ),
+    request_body = BanMember,
+    responses(
+        (status = 200, description = "The ban, the user is out of the room and can't join", body = Response<RoomBan>),
+        (status = 400, description = "Caller targets themselves", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "Caller may not ban, or the member's role isn't below theirs", body = ErrorBody),
+        (status = 404, description = "Room or user not found", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn ban_handler(
+    service: web::Data<ModerationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>>,
+    path: web::Path<(String, String)>,
+    body: ValidJson<BanMember>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let (room_id, member_id) = path.into_inner();
+    let ban = service
+        .ban(&user.user_id, &room_id, &member_id, &body)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(ban, "Successfully banned user")))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/chat/rooms/{room_id}/bans/{user_id}",
+    tag = "moderation",
+    params(
+        ("room_id" = String, Path, description = "Room id"),
+        ("user_id" = String, Path, description = "User to let back in"),
+    ),
+    responses(
+        (status = 200, description = "User may join the room again", body = Response<Empty>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "Caller may not ban", body = ErrorBody),
+        (status = 404, description = "Room not found or the user isn't banned", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn unban_handler(
+    service: web::Data<ModerationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>>,
+    path: web::Path<(String, String)>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let (room_id, member_id) = path.into_inner();
+    service.unban(&user.user_id, &room_id, &member_id).await?;
+    Ok(HttpResponse::Ok().json(Response::new(Empty, "Successfully unbanned user")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/chat/rooms/{room_id}/audit",
+    tag = "moderation",
+    params(("room_id" = String, Path, description = "Room id"), QueryModeration),
+    responses(
+        (status = 200, description = "Moderation actions in the room, newest first", body = Response<Page<RoomAuditEntry>>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "Caller can't kick, ban or mute", body = ErrorBody),
+        (status = 404, description = "Room not found or the caller isn't in it", body = ErrorBody),
+        (status = 422, description = "Invalid query", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_audit_handler(
+    service: web::Data<ModerationServiceImpl<Postgresql, Log, RedisPublisher, RedisUnreadCounter>>,
+    path: web::Path<String>,
+    query: ValidQuery<QueryModeration>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let entries = service
+        .get_audit(&user.user_id, &path.into_inner(), &query)
+        .await?
+        .with_links(&req);
+    Ok(HttpResponse::Ok().json(Response::new(entries, "Successfully got audit log")))
+}
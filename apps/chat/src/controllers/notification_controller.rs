@@ -0,0 +1,105 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use auth_middleware::{guard::Guard, source::TokenSource, user::AuthenticatedUser};
+use database::pgx::Postgresql;
+use errors::{
+    error::{Error, ErrorBody},
+    response::Response,
+};
+use logger::log::Log;
+use pagination::page::Page;
+use security::{env::EnvImpl, jwt::JwtImpl};
+use validation::extractor::ValidQuery;
+
+use crate::services::notification_service::{
+    MarkedRead, Notification, NotificationService, NotificationServiceImpl, QueryNotifications,
+};
+
+pub fn notification_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let jwt_middleware = Guard::new(jwt.clone())
+        .sources(vec![
+            TokenSource::authorization(),
+            TokenSource::cookie("token"),
+        ])
+        .kinds(&["auth_token"]);
+    config.service(
+        web::scope("/chat/notifications")
+            .wrap(jwt_middleware)
+            .route("", web::get().to(get_notifications_handler))
+            .route("/read", web::post().to(mark_all_read_handler))
+            .route("/{notification_id}/read", web::post().to(mark_read_handler)),
+    );
+}
+
+#[utoipa::path(
+    get,
+    path = "/chat/notifications",
+    tag = "notifications",
+    params(QueryNotifications),
+    responses(
+        (status = 200, description = "Notifications of the caller, newest first", body = Response<Page<Notification>>),
+        (status = 400, description = "Cursor is invalid or was issued for another filter", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 422, description = "Invalid query", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_notifications_handler(
+    service: web::Data<NotificationServiceImpl<Postgresql, Log>>,
+    query: ValidQuery<QueryNotifications>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let notifications = service
+        .get_notifications(&user.user_id, &query)
+        .await?
+        .with_links(&req);
+    Ok(HttpResponse::Ok().json(Response::new(
+        notifications,
+        "Successfully got notifications",
+    )))
+}
+
+#[utoipa::path(
+    post,
+    path = "/chat/notifications/{notification_id}/read",
+    tag = "notifications",
+    params(("notification_id" = String, Path, description = "Notification id")),
+    responses(
+        (status = 200, description = "The notification, read", body = Response<Notification>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "Caller has no such notification", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn mark_read_handler(
+    service: web::Data<NotificationServiceImpl<Postgresql, Log>>,
+    path: web::Path<String>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let notification = service.mark_read(&user.user_id, &path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(Response::new(
+        notification,
+        "Successfully marked notification read",
+    )))
+}
+
+#[utoipa::path(
+    post,
+    path = "/chat/notifications/read",
+    tag = "notifications",
+    responses(
+        (status = 200, description = "How many notifications were unread", body = Response<MarkedRead>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn mark_all_read_handler(
+    service: web::Data<NotificationServiceImpl<Postgresql, Log>>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let marked = service.mark_all_read(&user.user_id).await?;
+    Ok(HttpResponse::Ok().json(Response::new(
+        marked,
+        "Successfully marked notifications read",
+    )))
+}
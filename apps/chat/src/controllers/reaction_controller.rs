@@ -0,0 +1,131 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use auth_middleware::{guard::Guard, source::TokenSource, user::AuthenticatedUser};
+use database::pgx::Postgresql;
+use errors::{
+    error::{Error, ErrorBody},
+    response::Response,
+};
+use events::publisher::RedisPublisher;
+use logger::log::Log;
+use pagination::page::Page;
+use ratelimit::limiter::RedisRateLimiter;
+use security::{env::EnvImpl, jwt::JwtImpl};
+use validation::extractor::ValidQuery;
+
+use crate::services::{
+    message_service::Message,
+    reaction_service::{QueryReactors, ReactionService, ReactionServiceImpl, Reactor},
+};
+
+/// Registers routes under
+/// `/chat/conversations/{conversation_id}/messages/{message_id}/reactions`,
+/// before the thread scope takes the rest of the path.
+pub fn reaction_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let jwt_middleware = Guard::new(jwt.clone())
+        .sources(vec![
+            TokenSource::authorization(),
+            TokenSource::cookie("token"),
+        ])
+        .kinds(&["auth_token"]);
+    config.service(
+        web::scope("/chat/conversations/{conversation_id}/messages/{message_id}/reactions")
+            .wrap(jwt_middleware)
+            .route("", web::get().to(get_reactors_handler))
+            .route("/{emoji}", web::put().to(add_reaction_handler))
+            .route("/{emoji}", web::delete().to(remove_reaction_handler)),
+    );
+}
+
+#[utoipa::path(
+    get,
+    path = "/chat/conversations/{conversation_id}/messages/{message_id}/reactions",
+    tag = "reactions",
+    params(
+        ("conversation_id" = String, Path, description = "Conversation id"),
+        ("message_id" = String, Path, description = "Message id"),
+        QueryReactors,
+    ),
+    responses(
+        (status = 200, description = "Users who reacted, in the order they did", body = Response<Page<Reactor>>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "Conversation or message not found, or the message is deleted", body = ErrorBody),
+        (status = 422, description = "Invalid query", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_reactors_handler(
+    service: web::Data<ReactionServiceImpl<Postgresql, Log, RedisPublisher, RedisRateLimiter>>,
+    path: web::Path<(String, String)>,
+    query: ValidQuery<QueryReactors>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let (conversation_id, message_id) = path.into_inner();
+    let reactors = service
+        .get_reactors(&user.user_id, &conversation_id, &message_id, &query)
+        .await?
+        .with_links(&req);
+    Ok(HttpResponse::Ok().json(Response::new(reactors, "Successfully got reactions")))
+}
+
+#[utoipa::path(
+    put,
+    path = "/chat/conversations/{conversation_id}/messages/{message_id}/reactions/{emoji}",
+    tag = "reactions",
+    params(
+        ("conversation_id" = String, Path, description = "Conversation id"),
+        ("message_id" = String, Path, description = "Message id"),
+        ("emoji" = String, Path, description = "URL encoded Unicode emoji or `custom:{id}`"),
+    ),
+    responses(
+        (status = 200, description = "Message with its reactions, reacting again changes nothing", body = Response<Message>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "One side of the direct conversation blocked the other", body = ErrorBody),
+        (status = 404, description = "Conversation or message not found, or the message is deleted", body = ErrorBody),
+        (status = 422, description = "Not an emoji", body = ErrorBody),
+        (status = 429, description = "Caller changed too many reactions lately", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn add_reaction_handler(
+    service: web::Data<ReactionServiceImpl<Postgresql, Log, RedisPublisher, RedisRateLimiter>>,
+    path: web::Path<(String, String, String)>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let (conversation_id, message_id, emoji) = path.into_inner();
+    let message = service
+        .add_reaction(&user.user_id, &conversation_id, &message_id, &emoji)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(message, "Successfully added reaction")))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/chat/conversations/{conversation_id}/messages/{message_id}/reactions/{emoji}",
+    tag = "reactions",
+    params(
+        ("conversation_id" = String, Path, description = "Conversation id"),
+        ("message_id" = String, Path, description = "Message id"),
+        ("emoji" = String, Path, description = "URL encoded Unicode emoji or `custom:{id}`"),
+    ),
+    responses(
+        (status = 200, description = "Message with its remaining reactions", body = Response<Message>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "One side of the direct conversation blocked the other", body = ErrorBody),
+        (status = 404, description = "Conversation, message or the caller's reaction not found", body = ErrorBody),
+        (status = 422, description = "Not an emoji", body = ErrorBody),
+        (status = 429, description = "Caller changed too many reactions lately", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn remove_reaction_handler(
+    service: web::Data<ReactionServiceImpl<Postgresql, Log, RedisPublisher, RedisRateLimiter>>,
+    path: web::Path<(String, String, String)>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let (conversation_id, message_id, emoji) = path.into_inner();
+    let message = service
+        .remove_reaction(&user.user_id, &conversation_id, &message_id, &emoji)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(message, "Successfully removed reaction")))
+}
@@ -0,0 +1,202 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_ws::{CloseCode, CloseReason, Message};
+use auth_middleware::{guard::Guard, source::TokenSource, user::AuthenticatedUser};
+use database::{pgx::Postgresql, redis::RedisImpl};
+use errors::error::{Error, ErrorBody};
+use events::{event::Event, publisher::RedisPublisher};
+use logger::{log::Log, logger::Logger};
+use presence::store::{PresenceStore, RedisPresence, State};
+use ratelimit::limiter::RedisRateLimiter;
+use security::{env::EnvImpl, jwt::JwtImpl};
+use serde::Deserialize;
+
+use crate::{
+    hub::Hub,
+    services::{
+        session_service::{SessionService, SessionServiceImpl},
+        typing_service::{TypingService, TypingServiceImpl},
+    },
+};
+
+/// Least time between two presence heartbeats of one connection, well
+/// within [`presence::store::HEARTBEAT_TTL_SECONDS`].
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+/// How often a connection checks its user wasn't signed out or suspended
+/// since it opened.
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Event a client sends over the websocket, such as
+/// `{"kind": "typing.start", "conversation_id": "..."}`.
+#[derive(Deserialize, Debug)]
+struct ClientEvent {
+    kind: String,
+    #[serde(default)]
+    conversation_id: String,
+}
+
+/// Acts on an event from the client, failures are sent back to it as an
+/// `error` event.
+async fn handle_client_event(
+    typing: &TypingServiceImpl<Postgresql, RedisPublisher, RedisRateLimiter>,
+    user_id: &str,
+    text: &str,
+) -> Result<(), Error> {
+    let event: ClientEvent =
+        serde_json::from_str(text).map_err(|e| Error::BadRequest(e.to_string()))?;
+    match event.kind.as_str() {
+        "typing.start" => typing.start_typing(user_id, &event.conversation_id).await,
+        "typing.stop" => typing.stop_typing(user_id, &event.conversation_id).await,
+        kind => Err(Error::BadRequest(format!("Unknown event kind {}", kind))),
+    }
+}
+
+/// Keeps the user present while the connection shows activity, at most once
+/// every [`HEARTBEAT_INTERVAL`].
+async fn heartbeat(presence: &RedisPresence, user_id: &str, last: &mut Option<Instant>) {
+    if last.is_some_and(|at| at.elapsed() < HEARTBEAT_INTERVAL) {
+        return;
+    }
+    *last = Some(Instant::now());
+    if let Err(e) = presence.heartbeat(user_id, State::Online).await {
+        let message = format!("failed to refresh presence of user {}: {}", user_id, e);
+        Log.error("realtime_controller::heartbeat", &message);
+    }
+}
+
+/// When a token that expires at unix time `exp` runs out.
+fn expires_at(exp: usize) -> tokio::time::Instant {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    tokio::time::Instant::now() + Duration::from_secs((exp as u64).saturating_sub(now))
+}
+
+/// Whether the connection may stay open. A failed check keeps it, the token
+/// expiry still ends it.
+async fn session_active(
+    sessions: &SessionServiceImpl<Postgresql, RedisImpl>,
+    user: &AuthenticatedUser,
+) -> bool {
+    sessions
+        .is_active(&user.user_id, &user.jti)
+        .await
+        .unwrap_or_else(|e| {
+            let message = format!("failed to check session of user {}: {}", user.user_id, e);
+            Log.error("realtime_controller::session_active", &message);
+            true
+        })
+}
+
+fn policy_close(description: &str) -> Option<CloseReason> {
+    Some(CloseReason {
+        code: CloseCode::Policy,
+        description: Some(description.to_string()),
+    })
+}
+
+pub fn realtime_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    // Browsers can't set headers on a websocket, the token may come in the
+    // query string instead
+    let jwt_middleware = Guard::new(jwt.clone())
+        .sources(vec![
+            TokenSource::authorization(),
+            TokenSource::cookie("token"),
+            TokenSource::query("token"),
+        ])
+        .kinds(&["auth_token"]);
+    config.service(
+        web::scope("/chat/realtime")
+            .wrap(jwt_middleware)
+            .route("", web::get().to(connect_handler)),
+    );
+}
+
+#[utoipa::path(
+    get,
+    path = "/chat/realtime",
+    tag = "realtime",
+    params(("token" = Option<String>, Query, description = "Auth token, for clients that can't send headers")),
+    responses(
+        (status = 101, description = "Websocket carrying an event for every change in the caller's conversations, such as `message.created`, `message.updated`, `message.deleted`, `reaction.added`, `reaction.removed`, `message.pinned`, `message.unpinned`, `receipt.updated`, `typing.started`, `typing.stopped`, `notification.created`, `room.updated`, `member.updated` and `member.removed`. Clients send `typing.start` while the user types and `typing.stop` when they stop, each with a `conversation_id`. Activity on the connection keeps the user present, closing their last one takes them offline. The server closes the connection with code 1008 once the token expires or the user is signed out or suspended, clients reconnect with a fresh token"),
+        (status = 400, description = "Not a websocket handshake", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn connect_handler(
+    hub: web::Data<Hub>,
+    typing: web::Data<TypingServiceImpl<Postgresql, RedisPublisher, RedisRateLimiter>>,
+    presence: web::Data<RedisPresence>,
+    sessions: web::Data<SessionServiceImpl<Postgresql, RedisImpl>>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+    body: web::Payload,
+) -> Result<HttpResponse, Error> {
+    let (response, mut session, mut stream) =
+        actix_ws::handle(&req, body).map_err(|e| Error::BadRequest(e.to_string()))?;
+    let (connection_id, mut events) = hub.connect(&user.user_id);
+    actix_web::rt::spawn(async move {
+        let mut last_heartbeat = None;
+        heartbeat(&presence, &user.user_id, &mut last_heartbeat).await;
+        let expiry = tokio::time::sleep_until(expires_at(user.exp));
+        tokio::pin!(expiry);
+        let mut session_check = tokio::time::interval_at(
+            tokio::time::Instant::now() + SESSION_CHECK_INTERVAL,
+            SESSION_CHECK_INTERVAL,
+        );
+        let mut close_reason = None;
+        loop {
+            tokio::select! {
+                _ = &mut expiry => {
+                    close_reason = policy_close("Auth token expired");
+                    break;
+                }
+                _ = session_check.tick() => {
+                    if !session_active(&sessions, &user).await {
+                        close_reason = policy_close("Session ended");
+                        break;
+                    }
+                }
+                Some(event) = events.recv() => {
+                    if session.text(event).await.is_err() {
+                        break;
+                    }
+                }
+                message = stream.recv() => match message {
+                    Some(Ok(Message::Ping(bytes))) => {
+                        heartbeat(&presence, &user.user_id, &mut last_heartbeat).await;
+                        if session.pong(&bytes).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Ok(Message::Text(text))) => {
+                        heartbeat(&presence, &user.user_id, &mut last_heartbeat).await;
+                        if let Err(e) = handle_client_event(&typing, &user.user_id, &text).await {
+                            let event = Event::new("error", e.body());
+                            let text = serde_json::to_string(&event).unwrap_or_default();
+                            if session.text(text).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+        // Another tab may still be open, only the last one takes the user
+        // offline. Connections on other instances heartbeat again on their
+        // next activity.
+        if hub.disconnect(&user.user_id, connection_id) {
+            if let Err(e) = presence.disconnect(&user.user_id).await {
+                let message = format!("failed to clear presence of user {}: {}", user.user_id, e);
+                Log.error("realtime_controller::connect_handler", &message);
+            }
+        }
+        let _ = session.close(close_reason).await;
+    });
+    Ok(response)
+}
@@ -0,0 +1,55 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use auth_middleware::{guard::Guard, source::TokenSource, user::AuthenticatedUser};
+use database::pgx::Postgresql;
+use errors::{
+    error::{Error, ErrorBody},
+    response::Response,
+};
+use logger::log::Log;
+use pagination::page::Page;
+use security::{env::EnvImpl, jwt::JwtImpl};
+use validation::extractor::ValidQuery;
+
+use crate::services::search_service::{
+    SearchMessages, SearchResult, SearchService, SearchServiceImpl,
+};
+
+pub fn search_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let jwt_middleware = Guard::new(jwt.clone())
+        .sources(vec![
+            TokenSource::authorization(),
+            TokenSource::cookie("token"),
+        ])
+        .kinds(&["auth_token"]);
+    config.service(
+        web::scope("/chat/search")
+            .wrap(jwt_middleware)
+            .route("/messages", web::get().to(search_messages_handler)),
+    );
+}
+
+#[utoipa::path(
+    get,
+    path = "/chat/search/messages",
+    tag = "search",
+    params(SearchMessages),
+    responses(
+        (status = 200, description = "Matching messages, newest first", body = Response<Page<SearchResult>>),
+        (status = 400, description = "Cursor is invalid or was issued for another search", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 422, description = "Invalid query", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn search_messages_handler(
+    service: web::Data<SearchServiceImpl<Postgresql, Log>>,
+    query: ValidQuery<SearchMessages>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let results = service
+        .search_messages(&user.user_id, &query)
+        .await?
+        .with_links(&req);
+    Ok(HttpResponse::Ok().json(Response::new(results, "Successfully searched messages")))
+}
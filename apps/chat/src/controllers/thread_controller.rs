@@ -0,0 +1,168 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use auth_middleware::{guard::Guard, source::TokenSource, user::AuthenticatedUser};
+use database::pgx::Postgresql;
+use errors::{
+    error::{Error, ErrorBody},
+    response::{Empty, Response},
+};
+use events::publisher::RedisPublisher;
+use logger::log::Log;
+use pagination::page::Page;
+use presence::store::RedisPresence;
+use security::{env::EnvImpl, jwt::JwtImpl};
+use validation::extractor::{ValidJson, ValidQuery};
+
+use crate::services::{
+    message_service::{Message, SendMessage},
+    thread_service::{QueryReplies, ThreadService, ThreadServiceImpl},
+};
+
+/// Registers the replies and subscription of a message as resources, so
+/// the rest of `/chat/conversations/{conversation_id}/messages/{message_id}`
+/// still reaches the message scope.
+pub fn thread_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let jwt_middleware = || {
+        Guard::new(jwt.clone())
+            .sources(vec![
+                TokenSource::authorization(),
+                TokenSource::cookie("token"),
+            ])
+            .kinds(&["auth_token"])
+    };
+    config
+        .service(
+            web::resource("/chat/conversations/{conversation_id}/messages/{message_id}/replies")
+                .wrap(jwt_middleware())
+                .route(web::get().to(get_replies_handler))
+                .route(web::post().to(send_reply_handler)),
+        )
+        .service(
+            web::resource(
+                "/chat/conversations/{conversation_id}/messages/{message_id}/subscription",
+            )
+            .wrap(jwt_middleware())
+            .route(web::put().to(subscribe_handler))
+            .route(web::delete().to(unsubscribe_handler)),
+        );
+}
+
+#[utoipa::path(
+    get,
+    path = "/chat/conversations/{conversation_id}/messages/{message_id}/replies",
+    tag = "threads",
+    params(
+        ("conversation_id" = String, Path, description = "Conversation id"),
+        ("message_id" = String, Path, description = "Id of the first message of the thread or of any reply in it"),
+        QueryReplies,
+    ),
+    responses(
+        (status = 200, description = "Replies, oldest first", body = Response<Page<Message>>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "Conversation or message not found, or the caller isn't in the conversation", body = ErrorBody),
+        (status = 422, description = "Invalid query", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_replies_handler(
+    service: web::Data<ThreadServiceImpl<Postgresql, Log, RedisPublisher, RedisPresence>>,
+    path: web::Path<(String, String)>,
+    query: ValidQuery<QueryReplies>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let (conversation_id, message_id) = path.into_inner();
+    let replies = service
+        .get_replies(&user.user_id, &conversation_id, &message_id, &query)
+        .await?
+        .with_links(&req);
+    Ok(HttpResponse::Ok().json(Response::new(replies, "Successfully got replies")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/chat/conversations/{conversation_id}/messages/{message_id}/replies",
+    tag = "threads",
+    params(
+        ("conversation_id" = String, Path, description = "Conversation id"),
+        ("message_id" = String, Path, description = "Message to reply to, a reply to a reply continues its thread"),
+    ),
+    request_body = SendMessage,
+    responses(
+        (status = 201, description = "Reply is sent and the thread's subscribers are notified", body = Response<Message>),
+        (status = 400, description = "Message is blank without attachments, or an attachment isn't the caller's uploaded and unsent one", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "One side of the direct conversation blocked the other", body = ErrorBody),
+        (status = 404, description = "Conversation or message not found, or the message is deleted", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn send_reply_handler(
+    service: web::Data<ThreadServiceImpl<Postgresql, Log, RedisPublisher, RedisPresence>>,
+    path: web::Path<(String, String)>,
+    body: ValidJson<SendMessage>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let (conversation_id, message_id) = path.into_inner();
+    let reply = service
+        .send_reply(&user.user_id, &conversation_id, &message_id, &body)
+        .await?;
+    Ok(HttpResponse::Created().json(Response::new(reply, "Successfully sent reply")))
+}
+
+#[utoipa::path(
+    put,
+    path = "/chat/conversations/{conversation_id}/messages/{message_id}/subscription",
+    tag = "threads",
+    params(
+        ("conversation_id" = String, Path, description = "Conversation id"),
+        ("message_id" = String, Path, description = "Id of the first message of the thread or of any reply in it"),
+    ),
+    responses(
+        (status = 200, description = "Caller is notified about new replies in the thread", body = Response<Empty>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "Conversation or message not found, or the caller isn't in the conversation", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn subscribe_handler(
+    service: web::Data<ThreadServiceImpl<Postgresql, Log, RedisPublisher, RedisPresence>>,
+    path: web::Path<(String, String)>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let (conversation_id, message_id) = path.into_inner();
+    service
+        .subscribe(&user.user_id, &conversation_id, &message_id)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(Empty, "Successfully subscribed to thread")))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/chat/conversations/{conversation_id}/messages/{message_id}/subscription",
+    tag = "threads",
+    params(
+        ("conversation_id" = String, Path, description = "Conversation id"),
+        ("message_id" = String, Path, description = "Id of the first message of the thread or of any reply in it"),
+    ),
+    responses(
+        (status = 200, description = "Caller is no longer notified about the thread", body = Response<Empty>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "Conversation or message not found, or the caller isn't in the conversation", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn unsubscribe_handler(
+    service: web::Data<ThreadServiceImpl<Postgresql, Log, RedisPublisher, RedisPresence>>,
+    path: web::Path<(String, String)>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let (conversation_id, message_id) = path.into_inner();
+    service
+        .unsubscribe(&user.user_id, &conversation_id, &message_id)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(
+        Empty,
+        "Successfully unsubscribed from thread",
+    )))
+}
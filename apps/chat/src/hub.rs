@@ -0,0 +1,120 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+};
+
+use events::event::Event;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// Data of the events published on
+/// [`events::channel::CHAT_CONVERSATIONS`]. Only `payload` reaches clients.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Delivery {
+    pub recipients: Vec<String>,
+    pub payload: serde_json::Value,
+}
+
+/// Open connections of one user with their ids.
+type Connections = Vec<(u64, UnboundedSender<String>)>;
+
+/// Realtime connections of this instance by user. Every instance gets every
+/// event and hands it to the recipients connected to it.
+#[derive(Default)]
+pub struct Hub {
+    connections: RwLock<HashMap<String, Connections>>,
+    next_id: AtomicU64,
+}
+
+impl Hub {
+    /// Registers a connection of the user, events for them arrive on the
+    /// receiver as JSON. The id disconnects it again.
+    pub fn connect(&self, user_id: &str) -> (u64, UnboundedReceiver<String>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = unbounded_channel();
+        self.connections
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(user_id.to_string())
+            .or_default()
+            .push((id, sender));
+        (id, receiver)
+    }
+
+    /// Returns whether it was the last connection of the user here.
+    pub fn disconnect(&self, user_id: &str, id: u64) -> bool {
+        let mut connections = self.connections.write().unwrap_or_else(|e| e.into_inner());
+        let Some(senders) = connections.get_mut(user_id) else {
+            return false;
+        };
+        senders.retain(|(connection_id, _)| *connection_id != id);
+        if !senders.is_empty() {
+            return false;
+        }
+        connections.remove(user_id);
+        true
+    }
+
+    /// Hands an event published on the conversations channel to the
+    /// recipients connected here.
+    pub fn deliver(&self, event: Event) {
+        let Ok(delivery) = serde_json::from_value::<Delivery>(event.data) else {
+            return;
+        };
+        let event = Event {
+            data: delivery.payload,
+            ..event
+        };
+        let Ok(text) = serde_json::to_string(&event) else {
+            return;
+        };
+        let connections = self.connections.read().unwrap_or_else(|e| e.into_inner());
+        for recipient in &delivery.recipients {
+            for (_, sender) in connections.get(recipient).into_iter().flatten() {
+                // A closed receiver is removed once its connection ends
+                let _ = sender.send(text.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_deliver_to_connected_recipients() {
+        let hub = Hub::default();
+        let (_, mut alice) = hub.connect("alice");
+        let (bob_id, mut bob) = hub.connect("bob");
+        let (_, mut carol) = hub.connect("carol");
+        assert!(hub.disconnect("bob", bob_id));
+
+        let delivery = Delivery {
+            recipients: vec!["alice".to_string(), "bob".to_string()],
+            payload: json!({ "id": "1", "body": "hi" }),
+        };
+        hub.deliver(Event::new("message.created", delivery));
+
+        let event: Event = serde_json::from_str(&alice.try_recv().unwrap()).unwrap();
+        assert_eq!(event.kind, "message.created");
+        assert_eq!(event.data, json!({ "id": "1", "body": "hi" }));
+        assert!(bob.try_recv().is_err());
+        assert!(carol.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_disconnect_tells_when_the_last_connection_closed() {
+        let hub = Hub::default();
+        let (first, _first) = hub.connect("alice");
+        let (second, _second) = hub.connect("alice");
+        assert!(!hub.disconnect("alice", first));
+        assert!(hub.disconnect("alice", second));
+        assert!(!hub.disconnect("alice", second));
+    }
+}
@@ -0,0 +1,23 @@
+use std::time::Duration;
+
+use actix_web::web;
+use database::pgx::Postgresql;
+use logger::log::Log;
+use storage::blob::AnyBlobStore;
+
+use crate::services::attachment_service::{AttachmentService, AttachmentServiceImpl, PURGE_BATCH};
+
+/// How often every instance looks for work. Purging twice is harmless, so
+/// instances don't coordinate.
+const INTERVAL: Duration = Duration::from_secs(60);
+
+/// Removes attachments of deleted messages and upload slots that expired
+/// without content. Full batches run again right away, failures are retried
+/// on the next tick.
+pub async fn run(attachments: web::Data<AttachmentServiceImpl<Postgresql, Log, AnyBlobStore>>) {
+    let mut interval = tokio::time::interval(INTERVAL);
+    loop {
+        interval.tick().await;
+        while let Ok(PURGE_BATCH) = attachments.purge_released().await {}
+    }
+}
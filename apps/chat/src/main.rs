@@ -0,0 +1,233 @@
+use actix_web::{web, App, HttpServer};
+use controllers::{
+    attachment_controller::attachment_controller,
+    conversation_controller::{conversation_controller, room_controller},
+    message_controller::message_controller,
+    moderation_controller::moderation_controller,
+    notification_controller::notification_controller,
+    reaction_controller::reaction_controller,
+    realtime_controller::realtime_controller,
+    search_controller::search_controller,
+    thread_controller::thread_controller,
+};
+use database::{pgx::Postgresql, redis::RedisImpl};
+use events::{channel::CHAT_CONVERSATIONS, publisher::RedisPublisher, subscriber::RedisSubscriber};
+use hub::Hub;
+use logger::log::Log;
+use pagination::cursor::CursorCodec;
+use presence::store::RedisPresence;
+use ratelimit::limiter::RedisRateLimiter;
+use security::{env::EnvImpl, jwt::JwtImpl};
+use services::{
+    attachment_service::AttachmentServiceImpl, conversation_service::ConversationServiceImpl,
+    message_service::MessageServiceImpl, moderation_service::ModerationServiceImpl,
+    notification_service::NotificationServiceImpl, reaction_service::ReactionServiceImpl,
+    search_service::SearchServiceImpl, session_service::SessionServiceImpl,
+    thread_service::ThreadServiceImpl, typing_service::TypingServiceImpl,
+};
+use storage::{blob, signed::UrlSigner};
+use unread::counter::RedisUnreadCounter;
+
+mod controllers;
+mod hub;
+mod jobs;
+mod openapi;
+mod services;
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let jwt = JwtImpl::new(EnvImpl);
+    let cursors = CursorCodec::from_env(EnvImpl);
+    let conversation_service = ConversationServiceImpl::new(
+        Postgresql::new(EnvImpl).await,
+        Log,
+        RedisPublisher::new(EnvImpl),
+        RedisUnreadCounter::new(EnvImpl),
+        cursors.clone(),
+    );
+    let message_service = MessageServiceImpl::new(
+        Postgresql::new(EnvImpl).await,
+        Log,
+        RedisPublisher::new(EnvImpl),
+        RedisUnreadCounter::new(EnvImpl),
+        RedisPresence::new(EnvImpl),
+        cursors.clone(),
+    );
+    let moderation_service = ModerationServiceImpl::new(
+        Postgresql::new(EnvImpl).await,
+        Log,
+        RedisPublisher::new(EnvImpl),
+        RedisUnreadCounter::new(EnvImpl),
+        cursors.clone(),
+    );
+    let thread_service = ThreadServiceImpl::new(
+        Postgresql::new(EnvImpl).await,
+        Log,
+        RedisPublisher::new(EnvImpl),
+        RedisPresence::new(EnvImpl),
+        cursors.clone(),
+    );
+    let reaction_service = ReactionServiceImpl::new(
+        Postgresql::new(EnvImpl).await,
+        Log,
+        RedisPublisher::new(EnvImpl),
+        RedisRateLimiter::new(EnvImpl),
+        cursors.clone(),
+    );
+    let typing_service = TypingServiceImpl::new(
+        Postgresql::new(EnvImpl).await,
+        RedisPublisher::new(EnvImpl),
+        RedisRateLimiter::new(EnvImpl),
+    );
+    let attachment_service = AttachmentServiceImpl::new(
+        Postgresql::new(EnvImpl).await,
+        Log,
+        blob::from_env(EnvImpl),
+        UrlSigner::from_env(EnvImpl),
+    );
+    let search_service =
+        SearchServiceImpl::new(Postgresql::new(EnvImpl).await, Log, cursors.clone());
+    let notification_service =
+        NotificationServiceImpl::new(Postgresql::new(EnvImpl).await, Log, cursors);
+    let conversation_service_data = web::Data::new(conversation_service);
+    let message_service_data = web::Data::new(message_service);
+    let moderation_service_data = web::Data::new(moderation_service);
+    let thread_service_data = web::Data::new(thread_service);
+    let reaction_service_data = web::Data::new(reaction_service);
+    let typing_service_data = web::Data::new(typing_service);
+    let attachment_service_data = web::Data::new(attachment_service);
+    let search_service_data = web::Data::new(search_service);
+    let notification_service_data = web::Data::new(notification_service);
+    actix_web::rt::spawn(jobs::run(attachment_service_data.clone()));
+    let presence_data = web::Data::new(RedisPresence::new(EnvImpl));
+    let session_service_data = web::Data::new(SessionServiceImpl::new(
+        Postgresql::new(EnvImpl).await,
+        RedisImpl::new(EnvImpl),
+    ));
+    let hub = web::Data::new(Hub::default());
+    // Every instance hears every change and passes it to its own connections
+    let listener = hub.clone();
+    RedisSubscriber::new(EnvImpl).listen(CHAT_CONVERSATIONS, move |event| listener.deliver(event));
+    HttpServer::new(move || {
+        let app = App::new()
+            .app_data(conversation_service_data.clone())
+            .app_data(message_service_data.clone())
+            .app_data(moderation_service_data.clone())
+            .app_data(thread_service_data.clone())
+            .app_data(reaction_service_data.clone())
+            .app_data(typing_service_data.clone())
+            .app_data(attachment_service_data.clone())
+            .app_data(search_service_data.clone())
+            .app_data(notification_service_data.clone())
+            .app_data(presence_data.clone())
+            .app_data(session_service_data.clone())
+            .app_data(hub.clone())
+            .route(
+                "/chat/openapi.json",
+                web::get().to(openapi::openapi_handler),
+            );
+        #[cfg(feature = "swagger-ui")]
+        let app = app.service(
+            utoipa_swagger_ui::SwaggerUi::new("/chat/swagger-ui/{_:.*}")
+                .config(utoipa_swagger_ui::Config::from("../openapi.json")),
+        );
+        app.configure(|config| routes(config, &jwt))
+    })
+    .bind("0.0.0.0:8080")?
+    .run()
+    .await
+}
+
+fn routes(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    // Reaction routes live under a message, message routes under a
+    // conversation and moderation routes under a room. A scope takes every
+    // path under it even without a matching route, register the deepest first
+    config
+        .configure(|config| reaction_controller(config, jwt))
+        .configure(|config| thread_controller(config, jwt))
+        .configure(|config| message_controller(config, jwt))
+        .configure(|config| conversation_controller(config, jwt))
+        .configure(|config| moderation_controller(config, jwt))
+        .configure(|config| room_controller(config, jwt))
+        .configure(|config| attachment_controller(config, jwt))
+        .configure(|config| search_controller(config, jwt))
+        .configure(|config| notification_controller(config, jwt))
+        .configure(|config| realtime_controller(config, jwt));
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::{Method, StatusCode},
+        test::{init_service, try_call_service, TestRequest},
+    };
+    use security::jwt::{AdditionalClaims, Claims, Jwt};
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_every_controller_is_reachable() {
+        std::env::set_var("JWT_SECRET", "routes-test");
+        let jwt = JwtImpl::new(EnvImpl);
+        let token = jwt
+            .sign(&Claims {
+                exp: u32::MAX as usize,
+                iat: 0,
+                nbf: 0,
+                sub: "alice".to_string(),
+                jti: "jti".to_string(),
+                additional_claims: AdditionalClaims {
+                    user_id: "alice".to_string(),
+                    kind: "auth_token".to_string(),
+                    roles: vec![],
+                    scopes: vec![],
+                },
+            })
+            .unwrap();
+        let app = init_service(App::new().configure(|config| routes(config, &jwt))).await;
+        let messages = "/chat/conversations/c1/messages";
+        let cases = [
+            (Method::GET, format!("{}/5/reactions", messages)),
+            (Method::GET, format!("{}/5/replies", messages)),
+            (Method::PUT, format!("{}/5/subscription", messages)),
+            (Method::GET, format!("{}/pinned", messages)),
+            (Method::PATCH, format!("{}/5", messages)),
+            (Method::GET, format!("{}/5/revisions", messages)),
+            (Method::PUT, format!("{}/5/pin", messages)),
+            (Method::GET, "/chat/conversations".to_string()),
+            (Method::GET, "/chat/rooms/r1/bans".to_string()),
+            (Method::PATCH, "/chat/rooms/r1".to_string()),
+            (Method::POST, "/chat/attachments".to_string()),
+            (Method::DELETE, "/chat/attachments/a1".to_string()),
+            (Method::GET, "/chat/search/messages".to_string()),
+            (Method::GET, "/chat/notifications".to_string()),
+            (Method::GET, "/chat/realtime".to_string()),
+        ];
+        // Handlers fail without their services, only a missing route is 404
+        let status = |method: Method, uri: String| {
+            let req = TestRequest::default()
+                .method(method)
+                .uri(&uri)
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request();
+            let app = &app;
+            async move {
+                match try_call_service(app, req).await {
+                    Ok(res) => res.status(),
+                    Err(e) => e.as_response_error().status_code(),
+                }
+            }
+        };
+        for (method, uri) in cases {
+            let label = format!("{} {}", method, uri);
+            assert_ne!(
+                status(method, uri).await,
+                StatusCode::NOT_FOUND,
+                "{}",
+                label
+            );
+        }
+        let unknown = format!("{}/5/unknown", messages);
+        assert_eq!(status(Method::GET, unknown).await, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,108 @@
+use actix_web::HttpResponse;
+use auth_middleware::openapi::SecurityAddon;
+use errors::error::{ErrorBody, FieldError};
+use utoipa::OpenApi;
+
+use crate::controllers::{
+    attachment_controller, conversation_controller, message_controller, moderation_controller,
+    notification_controller, reaction_controller, realtime_controller, search_controller,
+    thread_controller,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Chat API"),
+    paths(
+        conversation_controller::get_conversations_handler,
+        conversation_controller::create_conversation_handler,
+        conversation_controller::get_conversation_handler,
+        conversation_controller::leave_conversation_handler,
+        conversation_controller::mark_read_handler,
+        conversation_controller::get_receipts_handler,
+        conversation_controller::mute_handler,
+        conversation_controller::unmute_handler,
+        conversation_controller::get_rooms_handler,
+        conversation_controller::create_room_handler,
+        conversation_controller::join_room_handler,
+        conversation_controller::update_room_handler,
+        moderation_controller::set_role_handler,
+        moderation_controller::kick_handler,
+        moderation_controller::timeout_handler,
+        moderation_controller::lift_timeout_handler,
+        moderation_controller::get_bans_handler,
+        moderation_controller::ban_handler,
+        moderation_controller::unban_handler,
+        moderation_controller::get_audit_handler,
+        message_controller::get_messages_handler,
+        message_controller::send_message_handler,
+        message_controller::edit_message_handler,
+        message_controller::delete_message_handler,
+        message_controller::get_revisions_handler,
+        message_controller::get_pinned_handler,
+        message_controller::pin_message_handler,
+        message_controller::unpin_message_handler,
+        thread_controller::get_replies_handler,
+        thread_controller::send_reply_handler,
+        thread_controller::subscribe_handler,
+        thread_controller::unsubscribe_handler,
+        reaction_controller::get_reactors_handler,
+        reaction_controller::add_reaction_handler,
+        reaction_controller::remove_reaction_handler,
+        attachment_controller::request_upload_handler,
+        attachment_controller::upload_handler,
+        attachment_controller::get_download_handler,
+        attachment_controller::delete_handler,
+        attachment_controller::get_content_handler,
+        attachment_controller::get_thumbnail_handler,
+        search_controller::search_messages_handler,
+        notification_controller::get_notifications_handler,
+        notification_controller::mark_read_handler,
+        notification_controller::mark_all_read_handler,
+        realtime_controller::connect_handler,
+    ),
+    components(schemas(ErrorBody, FieldError)),
+    modifiers(&SecurityAddon),
+    tags(
+        (name = "conversations", description = "Direct and group conversations of the caller"),
+        (name = "rooms", description = "Public rooms anyone can join"),
+        (name = "moderation", description = "Roles, kicks, bans, timeouts and the audit log of a room"),
+        (name = "messages", description = "Messages in a conversation"),
+        (name = "threads", description = "Replies to a message and subscriptions to them"),
+        (name = "reactions", description = "Emoji reactions to a message"),
+        (name = "attachments", description = "Files sent with messages"),
+        (name = "search", description = "Full-text search over messages the caller can see"),
+        (name = "notifications", description = "Notifications of the caller"),
+        (name = "realtime", description = "Live events for connected clients"),
+    )
+)]
+pub struct ApiDoc;
+
+pub async fn openapi_handler() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The committed spec is the contract the web client is built against, run
+    /// `pnpm run openapi` after changing the API.
+    #[test]
+    fn test_openapi_spec_is_up_to_date() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../www/src/app/api/chat.openapi.json"
+        );
+        let spec = ApiDoc::openapi().to_pretty_json().unwrap() + "\n";
+        if std::env::var("UPDATE_OPENAPI").is_ok() {
+            std::fs::write(path, &spec).unwrap();
+            return;
+        }
+        let committed = std::fs::read_to_string(path).unwrap_or_default();
+        assert!(
+            committed == spec,
+            "{} is out of date, regenerate it with `pnpm run openapi`",
+            path
+        );
+    }
+}
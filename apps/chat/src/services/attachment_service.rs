@@ -0,0 +1,824 @@
+use async_trait::async_trait;
+use database::{db::Database, pgx::PgRow};
+use errors::error::Error;
+use logger::logger::Logger;
+use serde::{Deserialize, Serialize};
+use storage::{
+    archive::ZIP,
+    blob::BlobStore,
+    mime::{self, GIF, IMAGE_TYPES, JPEG, MP4, PDF, PNG, WEBP},
+    signed::{self, UrlSigner},
+    thumbnail,
+};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+/// Largest file accepted as an attachment.
+pub const MAX_ATTACHMENT_BYTES: usize = 25 * 1024 * 1024;
+/// Bytes a user may have uploaded in total, pending uploads included and
+/// attachments of deleted messages left out.
+pub const ATTACHMENT_QUOTA_BYTES: u64 = 1024 * 1024 * 1024;
+/// Seconds an upload slot stays open. Expired slots no longer count
+/// against the quota.
+pub const UPLOAD_SLOT_SECONDS: u64 = 15 * 60;
+/// Attachments purged per round, see [`AttachmentService::purge_released`].
+pub const PURGE_BATCH: usize = 100;
+/// Seconds a download link works.
+pub const DOWNLOAD_URL_SECONDS: u64 = 5 * 60;
+/// Thumbnails of images fit in a square of this many pixels.
+pub const THUMBNAIL_SIZE: u32 = 320;
+
+/// Types attachments may have, checked against the bytes rather than what
+/// the client claims.
+pub const ATTACHMENT_TYPES: [&str; 7] = [PNG, JPEG, GIF, WEBP, PDF, ZIP, MP4];
+
+/// Columns of [`Attachment`] from `attachments a`.
+const ATTACHMENT_COLUMNS: &str = "a.id, a.file_name, a.content_type, a.size::TEXT, \
+    COALESCE(a.width::TEXT, ''), COALESCE(a.height::TEXT, ''), a.has_thumbnail::TEXT, \
+    to_json(a.created_at) #>> '{}'";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct Attachment {
+    id: String,
+    file_name: String,
+    /// Detected from the uploaded bytes
+    content_type: String,
+    /// Bytes
+    size: i64,
+    /// Pixels, for images
+    width: Option<u32>,
+    height: Option<u32>,
+    has_thumbnail: bool,
+    created_at: String,
+}
+
+fn attachment_from_row(row: &PgRow) -> Attachment {
+    Attachment {
+        id: row.get(0),
+        file_name: row.get(1),
+        content_type: row.get(2),
+        size: row.get(3).parse().unwrap_or_default(),
+        width: row.get(4).parse().ok(),
+        height: row.get(5).parse().ok(),
+        has_thumbnail: row.get(6) == "true",
+        created_at: row.get(7),
+    }
+}
+
+fn original_key(user_id: &str, attachment_id: &str) -> String {
+    format!("attachments/{}/{}/original", user_id, attachment_id)
+}
+
+fn thumbnail_key(user_id: &str, attachment_id: &str) -> String {
+    format!("attachments/{}/{}/thumbnail.png", user_id, attachment_id)
+}
+
+/// Prefix of every blob of an attachment.
+fn attachment_prefix(user_id: &str, attachment_id: &str) -> String {
+    format!("attachments/{}/{}", user_id, attachment_id)
+}
+
+/// Path of the content of an attachment, or of its thumbnail.
+fn content_path(attachment_id: &str, thumbnail: bool) -> String {
+    match thumbnail {
+        true => format!("/chat/files/{}/thumbnail", attachment_id),
+        false => format!("/chat/files/{}", attachment_id),
+    }
+}
+
+/// Checks the attachments were uploaded by the author and aren't part of a
+/// message yet.
+pub(crate) async fn check_attachments<D: Database<PgRow>>(
+    db: &D,
+    author_id: &str,
+    attachment_ids: &[String],
+) -> Result<(), Error> {
+    let mut ids = attachment_ids.to_vec();
+    ids.sort();
+    ids.dedup();
+    let row = db
+        .query_one(
+            "SELECT COUNT(*)::TEXT FROM attachments \
+             WHERE id = ANY(string_to_array($1, ',')) AND user_id = $2 \
+               AND uploaded_at IS NOT NULL AND message_id IS NULL",
+            &[&ids.join(","), &author_id.to_string()],
+        )
+        .await?;
+    if row.get(0) != ids.len().to_string() {
+        return Err(Error::BadRequest(
+            "Attachments must be uploaded by you and not sent yet".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
+pub struct RequestUpload {
+    #[validate(length(min = 1, max = 255))]
+    file_name: String,
+    /// Type of the file, one of the accepted ones. The uploaded bytes decide
+    /// the type it ends up with.
+    #[validate(length(min = 1, max = 127))]
+    content_type: String,
+    /// Bytes, at most 25 MiB
+    #[validate(range(min = 1))]
+    size: u64,
+}
+
+/// Where to upload the bytes of an attachment.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct UploadSlot {
+    attachment_id: String,
+    /// `PUT` the bytes here, relative to the API root
+    upload_url: String,
+    /// Seconds until the slot closes
+    expires_in: u64,
+}
+
+/// Signed links to the content of an attachment, relative to the API root.
+/// They work without an auth token until they expire.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct AttachmentDownload {
+    url: String,
+    /// For images
+    thumbnail_url: Option<String>,
+    expires_in: u64,
+}
+
+/// Signature of a download link.
+#[derive(Deserialize, Debug, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SignedQuery {
+    expires: u64,
+    #[validate(length(max = 128))]
+    signature: String,
+}
+
+/// Content of an attachment, or of its thumbnail.
+pub struct Download {
+    pub file_name: String,
+    pub content_type: String,
+    pub bytes: Vec<u8>,
+}
+
+#[async_trait]
+pub trait AttachmentService {
+    /// Reserves room in the caller's quota for a file and returns where to
+    /// upload it. The attachment can be sent with a message once uploaded.
+    async fn request_upload(
+        &self,
+        user_id: &str,
+        data: &RequestUpload,
+    ) -> Result<UploadSlot, Error>;
+    /// Stores the bytes of an open upload slot of the caller, and a
+    /// thumbnail of images.
+    async fn upload(
+        &self,
+        user_id: &str,
+        attachment_id: &str,
+        bytes: Vec<u8>,
+    ) -> Result<Attachment, Error>;
+    /// Links to an attachment the caller uploaded, or that was sent in a
+    /// conversation they are in.
+    async fn get_download(
+        &self,
+        user_id: &str,
+        attachment_id: &str,
+    ) -> Result<AttachmentDownload, Error>;
+    /// Content behind a download link.
+    async fn read_content(
+        &self,
+        attachment_id: &str,
+        thumbnail: bool,
+        query: &SignedQuery,
+    ) -> Result<Download, Error>;
+    /// Deletes an attachment of the caller that isn't part of a message, or
+    /// whose message was deleted, giving its bytes back to the quota.
+    async fn delete_attachment(&self, user_id: &str, attachment_id: &str) -> Result<(), Error>;
+    /// Removes up to [`PURGE_BATCH`] attachments of deleted messages and
+    /// upload slots that expired without content, returns how many went.
+    async fn purge_released(&self) -> Result<usize, Error>;
+}
+
+pub struct AttachmentServiceImpl<D: Database<PgRow>, L: Logger, B: BlobStore> {
+    db: D,
+    logger: L,
+    blobs: B,
+    signer: UrlSigner,
+}
+
+impl<D: Database<PgRow>, L: Logger, B: BlobStore> AttachmentServiceImpl<D, L, B> {
+    pub fn new(db: D, logger: L, blobs: B, signer: UrlSigner) -> Self {
+        Self {
+            db,
+            logger,
+            blobs,
+            signer,
+        }
+    }
+}
+
+#[async_trait]
+impl<D: Database<PgRow> + Send + Sync, L: Logger + Send + Sync, B: BlobStore + Send + Sync>
+    AttachmentService for AttachmentServiceImpl<D, L, B>
+{
+    async fn request_upload(
+        &self,
+        user_id: &str,
+        data: &RequestUpload,
+    ) -> Result<UploadSlot, Error> {
+        if data.size > MAX_ATTACHMENT_BYTES as u64 {
+            return Err(Error::PayloadTooLarge(format!(
+                "Attachment must be at most {} bytes",
+                MAX_ATTACHMENT_BYTES
+            )));
+        }
+        if !ATTACHMENT_TYPES.contains(&data.content_type.as_str()) {
+            return Err(Error::UnsupportedMediaType(format!(
+                "Attachment must be one of {}",
+                ATTACHMENT_TYPES.join(", ")
+            )));
+        }
+        // Only the name, without any path the client sent along
+        let file_name: String = data
+            .file_name
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .filter(|c| !c.is_control())
+            .collect();
+        if file_name.trim().is_empty() {
+            return Err(Error::BadRequest("File name is empty".to_string()));
+        }
+        let row = self
+            .db
+            .query_one(
+                "WITH used AS (SELECT COALESCE(SUM(a.size), 0) AS bytes FROM attachments a \
+                   LEFT JOIN messages m ON m.id = a.message_id \
+                   WHERE a.user_id = $1 AND m.deleted_at IS NULL AND (a.uploaded_at IS NOT NULL \
+                     OR a.created_at > NOW() - make_interval(secs => $5::TEXT::INT))) \
+                 INSERT INTO attachments (user_id, file_name, content_type, size) \
+                 SELECT $1, $2, $3, $4::TEXT::BIGINT FROM used \
+                 WHERE used.bytes + $4::TEXT::BIGINT <= $6::TEXT::BIGINT \
+                 RETURNING id",
+                &[
+                    &user_id.to_string(),
+                    &file_name,
+                    &data.content_type,
+                    &data.size.to_string(),
+                    &UPLOAD_SLOT_SECONDS.to_string(),
+                    &ATTACHMENT_QUOTA_BYTES.to_string(),
+                ],
+            )
+            .await
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::PayloadTooLarge(format!(
+                    "Attachments may take up at most {} bytes, delete unsent attachments \
+                     or messages with attachments, or wait for pending uploads to expire",
+                    ATTACHMENT_QUOTA_BYTES
+                )),
+                e => e,
+            })?;
+        let attachment_id = row.get(0);
+        Ok(UploadSlot {
+            upload_url: format!("/chat/attachments/{}/content", attachment_id),
+            attachment_id,
+            expires_in: UPLOAD_SLOT_SECONDS,
+        })
+    }
+
+    async fn upload(
+        &self,
+        user_id: &str,
+        attachment_id: &str,
+        bytes: Vec<u8>,
+    ) -> Result<Attachment, Error> {
+        let slot = self
+            .db
+            .query_one(
+                "SELECT size::TEXT, (uploaded_at IS NOT NULL)::TEXT, \
+                   (created_at < NOW() - make_interval(secs => $3::TEXT::INT))::TEXT \
+                 FROM attachments WHERE id::TEXT = $1 AND user_id = $2",
+                &[
+                    &attachment_id.to_string(),
+                    &user_id.to_string(),
+                    &UPLOAD_SLOT_SECONDS.to_string(),
+                ],
+            )
+            .await
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound("Attachment not found".to_string()),
+                e => e,
+            })?;
+        if slot.get(1) == "true" {
+            return Err(Error::Conflict(
+                "Attachment is already uploaded".to_string(),
+            ));
+        }
+        if slot.get(2) == "true" {
+            return Err(Error::Conflict(
+                "Upload slot has expired, request a new one".to_string(),
+            ));
+        }
+        let declared: usize = slot.get(0).parse().unwrap_or_default();
+        if bytes.len() > declared {
+            return Err(Error::PayloadTooLarge(format!(
+                "Attachment must be at most the {} bytes requested",
+                declared
+            )));
+        }
+        let content_type = mime::sniff(&bytes)
+            .filter(|content_type| ATTACHMENT_TYPES.contains(content_type))
+            .ok_or_else(|| {
+                Error::UnsupportedMediaType(format!(
+                    "Attachment must be one of {}",
+                    ATTACHMENT_TYPES.join(", ")
+                ))
+            })?;
+        let preview = match IMAGE_TYPES.contains(&content_type) {
+            true => Some(thumbnail::preview(&bytes, THUMBNAIL_SIZE)?),
+            false => None,
+        };
+
+        let dimension = |pick: fn(&thumbnail::Preview) -> u32| {
+            preview
+                .as_ref()
+                .map(|preview| pick(preview).to_string())
+                .unwrap_or_default()
+        };
+        // Claims the slot before writing, so concurrent uploads to it can't
+        // mix the bytes of one with the details of another
+        let sql = format!(
+            "UPDATE attachments a SET content_type = $3, size = $4::TEXT::BIGINT, \
+               width = NULLIF($5, '')::INT, height = NULLIF($6, '')::INT, \
+               has_thumbnail = $7::BOOLEAN, uploaded_at = NOW() \
+             WHERE a.id = $1 AND a.user_id = $2 AND a.uploaded_at IS NULL \
+               AND a.created_at >= NOW() - make_interval(secs => $8::TEXT::INT) \
+             RETURNING {}",
+            ATTACHMENT_COLUMNS
+        );
+        let params = [
+            &attachment_id.to_string(),
+            &user_id.to_string(),
+            &content_type.to_string(),
+            &bytes.len().to_string(),
+            &dimension(|preview| preview.width),
+            &dimension(|preview| preview.height),
+            &preview.is_some().to_string(),
+            &UPLOAD_SLOT_SECONDS.to_string(),
+        ];
+        let attachment = match self.db.query_one(&sql, &params).await {
+            Ok(row) => attachment_from_row(&row),
+            Err(Error::NotFound(_)) => {
+                return Err(Error::Conflict(
+                    "Attachment is already uploaded or the slot has expired".to_string(),
+                ))
+            }
+            Err(e) => {
+                let message = format!("failed to store attachment {}: {}", attachment_id, e);
+                self.logger.error("attachment_service::upload", &message);
+                return Err(e);
+            }
+        };
+
+        let mut stored = self
+            .blobs
+            .put(&original_key(user_id, attachment_id), &bytes)
+            .await;
+        if let (Ok(()), Some(preview)) = (&stored, &preview) {
+            stored = self
+                .blobs
+                .put(&thumbnail_key(user_id, attachment_id), &preview.png)
+                .await;
+        }
+        if let Err(e) = stored {
+            let message = format!(
+                "failed to store content of attachment {}: {}",
+                attachment_id, e
+            );
+            self.logger.error("attachment_service::upload", &message);
+            // Opens the slot again for another try
+            self.db
+                .execute(
+                    "UPDATE attachments SET uploaded_at = NULL WHERE id = $1 AND user_id = $2",
+                    &[&attachment_id.to_string(), &user_id.to_string()],
+                )
+                .await?;
+            return Err(e);
+        }
+        Ok(attachment)
+    }
+
+    async fn get_download(
+        &self,
+        user_id: &str,
+        attachment_id: &str,
+    ) -> Result<AttachmentDownload, Error> {
+        // Others only reach attachments of messages in their conversations,
+        // anything else looks the same as a missing attachment
+        let sql = format!(
+            "SELECT {} FROM attachments a LEFT JOIN messages m ON m.id = a.message_id \
+             WHERE a.id::TEXT = $1 AND a.uploaded_at IS NOT NULL AND m.deleted_at IS NULL \
+               AND (a.user_id = $2 OR (EXISTS ( \
+                 SELECT 1 FROM conversation_members cm \
+                 WHERE cm.conversation_id = m.conversation_id AND cm.user_id = $2)))",
+            ATTACHMENT_COLUMNS
+        );
+        let attachment = self
+            .db
+            .query_one(&sql, &[&attachment_id.to_string(), &user_id.to_string()])
+            .await
+            .map(|row| attachment_from_row(&row))
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound("Attachment not found".to_string()),
+                e => e,
+            })?;
+        let expires = signed::now() + DOWNLOAD_URL_SECONDS;
+        Ok(AttachmentDownload {
+            url: self
+                .signer
+                .sign(&content_path(&attachment.id, false), expires),
+            thumbnail_url: attachment.has_thumbnail.then(|| {
+                self.signer
+                    .sign(&content_path(&attachment.id, true), expires)
+            }),
+            expires_in: DOWNLOAD_URL_SECONDS,
+        })
+    }
+
+    async fn read_content(
+        &self,
+        attachment_id: &str,
+        thumbnail: bool,
+        query: &SignedQuery,
+    ) -> Result<Download, Error> {
+        self.signer.verify(
+            &content_path(attachment_id, thumbnail),
+            query.expires,
+            &query.signature,
+        )?;
+        let row = self
+            .db
+            .query_one(
+                "SELECT a.user_id, a.file_name, a.content_type, a.has_thumbnail::TEXT \
+                 FROM attachments a LEFT JOIN messages m ON m.id = a.message_id \
+                 WHERE a.id::TEXT = $1 AND a.uploaded_at IS NOT NULL AND m.deleted_at IS NULL",
+                &[&attachment_id.to_string()],
+            )
+            .await
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound("Attachment not found".to_string()),
+                e => e,
+            })?;
+        let owner = row.get(0);
+        if thumbnail && row.get(3) != "true" {
+            return Err(Error::NotFound("Attachment has no thumbnail".to_string()));
+        }
+        let (key, content_type) = match thumbnail {
+            true => (thumbnail_key(&owner, attachment_id), PNG.to_string()),
+            false => (original_key(&owner, attachment_id), row.get(2)),
+        };
+        Ok(Download {
+            file_name: row.get(1),
+            content_type,
+            bytes: self.blobs.get(&key).await?,
+        })
+    }
+
+    async fn delete_attachment(&self, user_id: &str, attachment_id: &str) -> Result<(), Error> {
+        // The message is compared again on delete, so an attachment sent
+        // meanwhile stays
+        let row = self
+            .db
+            .query_one(
+                "WITH target AS (SELECT a.id, a.message_id, \
+                   (a.message_id IS NOT NULL AND m.deleted_at IS NULL) AS sent \
+                   FROM attachments a LEFT JOIN messages m ON m.id = a.message_id \
+                   WHERE a.id::TEXT = $1 AND a.user_id = $2), \
+                 deleted AS (DELETE FROM attachments a USING target \
+                   WHERE a.id = target.id AND NOT target.sent \
+                     AND a.message_id IS NOT DISTINCT FROM target.message_id RETURNING a.id) \
+                 SELECT target.sent::TEXT, EXISTS (SELECT 1 FROM deleted)::TEXT FROM target",
+                &[&attachment_id.to_string(), &user_id.to_string()],
+            )
+            .await
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound("Attachment not found".to_string()),
+                e => e,
+            })?;
+        if row.get(1) != "true" {
+            return Err(Error::Conflict(
+                "Attachment is part of a message, delete the message instead".to_string(),
+            ));
+        }
+        // The row is gone either way, content left behind only takes space
+        if let Err(e) = self
+            .blobs
+            .delete_prefix(&attachment_prefix(user_id, attachment_id))
+            .await
+        {
+            let message = format!(
+                "failed to delete content of attachment {}: {}",
+                attachment_id, e
+            );
+            self.logger
+                .error("attachment_service::delete_attachment", &message);
+        }
+        Ok(())
+    }
+
+    async fn purge_released(&self) -> Result<usize, Error> {
+        let rows = self
+            .db
+            .query(
+                "SELECT a.user_id, a.id FROM attachments a \
+                 LEFT JOIN messages m ON m.id = a.message_id \
+                 WHERE m.deleted_at IS NOT NULL OR (a.uploaded_at IS NULL \
+                   AND a.created_at < NOW() - make_interval(secs => $1::TEXT::INT)) \
+                 ORDER BY a.created_at LIMIT $2::TEXT::INT",
+                &[&UPLOAD_SLOT_SECONDS.to_string(), &PURGE_BATCH.to_string()],
+            )
+            .await
+            .inspect_err(|e| {
+                let message = format!("failed to find released attachments: {}", e);
+                self.logger
+                    .error("attachment_service::purge_released", &message);
+            })?;
+        // Content goes first, so a failure leaves the row to try again. A
+        // released attachment never comes back, instances racing here only
+        // delete the same blobs twice.
+        let mut purged = vec![];
+        for row in &rows {
+            let (owner, id) = (row.get(0), row.get(1));
+            match self
+                .blobs
+                .delete_prefix(&attachment_prefix(&owner, &id))
+                .await
+            {
+                Ok(()) => purged.push(id),
+                Err(e) => {
+                    let message = format!("failed to delete content of attachment {}: {}", id, e);
+                    self.logger
+                        .error("attachment_service::purge_released", &message);
+                }
+            }
+        }
+        if !purged.is_empty() {
+            self.db
+                .execute(
+                    "DELETE FROM attachments WHERE id = ANY(string_to_array($1, ','))",
+                    &[&purged.join(",")],
+                )
+                .await
+                .inspect_err(|e| {
+                    let message = format!("failed to delete released attachments: {}", e);
+                    self.logger
+                        .error("attachment_service::purge_released", &message);
+                })?;
+        }
+        Ok(purged.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use database::db::MockDatabase;
+    use logger::log::Log;
+    use storage::local::LocalBlobStore;
+
+    use super::*;
+
+    fn blobs() -> LocalBlobStore {
+        let root = std::env::temp_dir().join(format!("blobs-{}", security::uuid::uuid_v4()));
+        LocalBlobStore::new(root)
+    }
+
+    fn png() -> Vec<u8> {
+        let mut png = Vec::new();
+        image::DynamicImage::new_rgb8(640, 480)
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    fn slot_row() -> PgRow {
+        PgRow::from(vec![
+            "1000000".to_string(),
+            "false".to_string(),
+            "false".to_string(),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_upload_sniffs_the_type_and_makes_a_thumbnail() {
+        let mut db = MockDatabase::new();
+        db.expect_query_one()
+            .withf(|sql, _| sql.starts_with("SELECT size"))
+            .returning(|_, _| {
+                let row = slot_row();
+                Box::pin(async move { Ok(row) })
+            });
+        db.expect_query_one()
+            .withf(|sql, params| {
+                sql.starts_with("UPDATE attachments")
+                    && *params[2] == PNG
+                    && *params[4] == "640"
+                    && *params[6] == "true"
+            })
+            .times(1)
+            .returning(|_, _| {
+                let row = PgRow::from(vec![
+                    "a1".to_string(),
+                    "cat.png".to_string(),
+                    PNG.to_string(),
+                    "1234".to_string(),
+                    "640".to_string(),
+                    "480".to_string(),
+                    "true".to_string(),
+                    "2026-01-01T00:00:00+00:00".to_string(),
+                ]);
+                Box::pin(async move { Ok(row) })
+            });
+        let blobs = blobs();
+        let service = AttachmentServiceImpl::new(db, Log, blobs, UrlSigner::new(b"secret"));
+
+        let result = service.upload("alice", "a1", b"<svg></svg>".to_vec()).await;
+        assert!(matches!(result, Err(Error::UnsupportedMediaType(_))));
+
+        let attachment = service.upload("alice", "a1", png()).await.unwrap();
+        assert!(attachment.has_thumbnail);
+        let thumbnail = service
+            .blobs
+            .get(&thumbnail_key("alice", "a1"))
+            .await
+            .unwrap();
+        let thumbnail = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (320, 240));
+        service.blobs.delete_prefix("attachments").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_upload_losing_the_slot_writes_nothing() {
+        let mut db = MockDatabase::new();
+        db.expect_query_one()
+            .withf(|sql, _| sql.starts_with("SELECT size"))
+            .returning(|_, _| {
+                let row = slot_row();
+                Box::pin(async move { Ok(row) })
+            });
+        // A concurrent upload claimed the slot after it was checked
+        db.expect_query_one()
+            .withf(|sql, _| sql.starts_with("UPDATE attachments"))
+            .returning(|_, _| Box::pin(async move { Err(Error::NotFound("no rows".to_string())) }));
+        let service = AttachmentServiceImpl::new(db, Log, blobs(), UrlSigner::new(b"secret"));
+
+        let result = service.upload("alice", "a1", png()).await;
+        assert!(matches!(result, Err(Error::Conflict(_))));
+        assert!(service
+            .blobs
+            .get(&original_key("alice", "a1"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_request_upload_respects_the_quota() {
+        let mut db = MockDatabase::new();
+        db.expect_query_one()
+            .returning(|_, _| Box::pin(async move { Err(Error::NotFound("no rows".to_string())) }));
+        let service = AttachmentServiceImpl::new(db, Log, blobs(), UrlSigner::new(b"secret"));
+        let data: RequestUpload = serde_json::from_value(serde_json::json!({
+            "file_name": "report.pdf",
+            "content_type": PDF,
+            "size": 1024,
+        }))
+        .unwrap();
+
+        let result = service.request_upload("alice", &data).await;
+        assert!(matches!(result, Err(Error::PayloadTooLarge(_))));
+    }
+
+    #[tokio::test]
+    async fn test_download_links_only_open_their_own_content() {
+        let mut db = MockDatabase::new();
+        db.expect_query_one()
+            .withf(|sql, _| sql.starts_with("SELECT a.id"))
+            .returning(|_, _| {
+                let row = PgRow::from(vec![
+                    "a1".to_string(),
+                    "report.pdf".to_string(),
+                    PDF.to_string(),
+                    "9".to_string(),
+                    "".to_string(),
+                    "".to_string(),
+                    "false".to_string(),
+                    "2026-01-01T00:00:00+00:00".to_string(),
+                ]);
+                Box::pin(async move { Ok(row) })
+            });
+        db.expect_query_one()
+            .withf(|sql, _| sql.starts_with("SELECT a.user_id"))
+            .returning(|_, _| {
+                let row = PgRow::from(vec![
+                    "alice".to_string(),
+                    "report.pdf".to_string(),
+                    PDF.to_string(),
+                    "false".to_string(),
+                ]);
+                Box::pin(async move { Ok(row) })
+            });
+        let blobs = blobs();
+        blobs
+            .put(&original_key("alice", "a1"), b"%PDF-1.7")
+            .await
+            .unwrap();
+        let service = AttachmentServiceImpl::new(db, Log, blobs, UrlSigner::new(b"secret"));
+
+        let download = service.get_download("bob", "a1").await.unwrap();
+        assert_eq!(download.thumbnail_url, None);
+        let query: SignedQuery =
+            serde_urlencoded::from_str(download.url.split_once('?').unwrap().1).unwrap();
+        let content = service.read_content("a1", false, &query).await.unwrap();
+        assert_eq!(content.bytes, b"%PDF-1.7");
+        assert!(matches!(
+            service.read_content("a2", false, &query).await,
+            Err(Error::Forbidden(_))
+        ));
+        service.blobs.delete_prefix("attachments").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delete_attachment_keeps_sent_ones() {
+        let mut db = MockDatabase::new();
+        db.expect_query_one()
+            .withf(|_, params| *params[0] == "sent")
+            .returning(|_, _| {
+                let row = PgRow::from(vec!["true".to_string(), "false".to_string()]);
+                Box::pin(async move { Ok(row) })
+            });
+        db.expect_query_one()
+            .withf(|_, params| *params[0] == "a1")
+            .returning(|_, _| {
+                let row = PgRow::from(vec!["false".to_string(), "true".to_string()]);
+                Box::pin(async move { Ok(row) })
+            });
+        db.expect_query_one()
+            .withf(|_, params| *params[0] == "a2")
+            .returning(|_, _| Box::pin(async move { Err(Error::NotFound("no rows".to_string())) }));
+        let blobs = blobs();
+        blobs
+            .put(&original_key("alice", "a1"), b"%PDF-1.7")
+            .await
+            .unwrap();
+        let service = AttachmentServiceImpl::new(db, Log, blobs, UrlSigner::new(b"secret"));
+
+        assert!(matches!(
+            service.delete_attachment("alice", "sent").await,
+            Err(Error::Conflict(_))
+        ));
+        assert!(matches!(
+            service.delete_attachment("alice", "a2").await,
+            Err(Error::NotFound(_))
+        ));
+        service.delete_attachment("alice", "a1").await.unwrap();
+        assert!(service
+            .blobs
+            .get(&original_key("alice", "a1"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_purge_released_removes_content_then_rows() {
+        let mut db = MockDatabase::new();
+        db.expect_query().returning(|_, _| {
+            let rows = vec![
+                PgRow::from(vec!["alice".to_string(), "a1".to_string()]),
+                PgRow::from(vec!["bob".to_string(), "a2".to_string()]),
+            ];
+            Box::pin(async move { Ok(rows) })
+        });
+        db.expect_execute()
+            .withf(|sql, params| {
+                sql.starts_with("DELETE FROM attachments") && *params[0] == "a1,a2"
+            })
+            .times(1)
+            .returning(|_, _| Box::pin(async move { Ok(2) }));
+        let blobs = blobs();
+        blobs
+            .put(&original_key("alice", "a1"), b"%PDF-1.7")
+            .await
+            .unwrap();
+        let service = AttachmentServiceImpl::new(db, Log, blobs, UrlSigner::new(b"secret"));
+
+        assert_eq!(service.purge_released().await.unwrap(), 2);
+        assert!(service
+            .blobs
+            .get(&original_key("alice", "a1"))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,1235 @@
+use async_trait::async_trait;
+use database::{db::Database, pgx::PgRow};
+use errors::error::Error;
+use events::{channel::CHAT_CONVERSATIONS, event::Event, publisher::Publisher};
+use logger::logger::Logger;
+use pagination::{
+    cursor::CursorCodec,
+    page::{Page, PageRequest},
+};
+use serde::{Deserialize, Serialize};
+use unread::counter::{Count, UnreadCounter};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::{
+    hub::Delivery,
+    services::{
+        message_service::Message,
+        moderation_service::{audit, permission_bits, Permission, RoomRole, ACTION_UPDATE_ROOM},
+    },
+};
+
+pub const RECEIPT_UPDATED: &str = "receipt.updated";
+pub const ROOM_UPDATED: &str = "room.updated";
+
+/// Largest conversation whose members see each other's read receipts.
+/// Everyone else only hears about their own, to sync their devices.
+pub const RECEIPT_MAX_MEMBERS: i64 = 10;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ConversationKind {
+    /// Between two users, there is only ever one per pair
+    Direct,
+    /// Small invite only conversation
+    Group,
+    /// Public room anyone can join
+    Room,
+}
+
+impl ConversationKind {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "direct" => ConversationKind::Direct,
+            "group" => ConversationKind::Group,
+            _ => ConversationKind::Room,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct ConversationMember {
+    user_id: String,
+    username: String,
+    name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct Conversation {
+    id: String,
+    kind: ConversationKind,
+    /// Empty for direct conversations and unnamed groups
+    name: String,
+    /// Everyone in a direct or group conversation, empty for rooms
+    members: Vec<ConversationMember>,
+    member_count: i64,
+    /// Messages from others the caller hasn't read
+    unread_count: i64,
+    /// Unread messages mentioning the caller, replies in threads aside
+    mention_count: i64,
+    /// The caller gets no notifications from a muted conversation
+    muted: bool,
+    /// The caller's role in a room, missing elsewhere and in rooms they
+    /// aren't in
+    role: Option<RoomRole>,
+    /// What the caller may do here, nothing where they aren't a member
+    permissions: Vec<Permission>,
+    /// The caller can't send in the room until then
+    timed_out_until: Option<String>,
+    last_message: Option<Message>,
+    last_activity_at: String,
+    created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
+pub struct CreateConversation {
+    /// One user for a direct conversation, up to nine for a group
+    #[validate(length(min = 1, max = 9))]
+    user_ids: Vec<String>,
+    /// Only used for groups
+    #[validate(length(max = 100))]
+    name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
+pub struct CreateRoom {
+    #[validate(length(min = 1, max = 100))]
+    name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
+pub struct UpdateRoom {
+    #[validate(
+        length(min = 1, max = 100),
+        custom(function = "validation::rules::not_blank")
+    )]
+    name: String,
+}
+
+/// Data of [`ROOM_UPDATED`] events.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct RoomSettings {
+    conversation_id: String,
+    name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
+pub struct MarkRead {
+    /// Latest message the caller has read, earlier positions are kept
+    #[validate(length(min = 1, max = 20))]
+    message_id: String,
+}
+
+/// Read position of the caller after marking messages read.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct ReadState {
+    conversation_id: String,
+    last_read_message_id: String,
+    /// Messages from others after the read position
+    unread_count: i64,
+    /// Messages after the read position mentioning the caller
+    mention_count: i64,
+}
+
+/// How far a member has read, also the data of [`RECEIPT_UPDATED`] events.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct Receipt {
+    conversation_id: String,
+    user_id: String,
+    /// `0` until the member read anything
+    last_read_message_id: String,
+    last_read_at: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryConversations {
+    #[validate(range(min = 1, max = 100))]
+    limit: Option<u32>,
+    /// `next_cursor` or `prev_cursor` of the previous page
+    #[validate(length(max = 1024))]
+    cursor: Option<String>,
+}
+
+/// The caller's membership in a conversation.
+pub(crate) struct Membership {
+    pub kind: ConversationKind,
+    /// Someone else in a direct conversation blocked the caller or was
+    /// blocked by them
+    pub blocked: bool,
+    /// Only means something in rooms
+    pub role: RoomRole,
+    /// End of the caller's timeout in a room
+    pub timed_out_until: Option<String>,
+}
+
+impl Membership {
+    pub fn can(&self, permission: Permission) -> bool {
+        permission_bits(self.kind, self.role) & permission.bit() != 0
+    }
+
+    pub fn require(&self, permission: Permission) -> Result<(), Error> {
+        match self.can(permission) {
+            true => Ok(()),
+            false => Err(Error::Forbidden(format!(
+                "You don't have the {} permission here",
+                permission.as_str()
+            ))),
+        }
+    }
+}
+
+/// End of the timeout of the member `me` in their room, empty when they
+/// aren't timed out.
+pub(crate) const TIMED_OUT_UNTIL: &str = "COALESCE((SELECT to_json(t.expires_at) #>> '{}' \
+      FROM room_timeouts t WHERE t.conversation_id = me.conversation_id \
+        AND t.user_id = me.user_id AND t.expires_at > NOW()), '')";
+
+/// Finds the caller's membership, conversations they aren't in are not
+/// found.
+pub(crate) async fn find_membership<D: Database<PgRow>>(
+    db: &D,
+    conversation_id: &str,
+    user_id: &str,
+) -> Result<Membership, Error> {
+    let sql = format!(
+        "SELECT c.kind, EXISTS (SELECT 1 FROM conversation_members other \
+           JOIN user_blocks b ON (b.blocker_id = other.user_id AND b.blocked_id = $2) \
+             OR (b.blocker_id = $2 AND b.blocked_id = other.user_id) \
+           WHERE other.conversation_id = c.id AND other.user_id <> $2)::TEXT, me.role, {} \
+         FROM conversations c \
+         JOIN conversation_members me ON me.conversation_id = c.id AND me.user_id = $2 \
+         WHERE c.id = $1",
+        TIMED_OUT_UNTIL
+    );
+    let row = db
+        .query_one(&sql, &[&conversation_id.to_string(), &user_id.to_string()])
+        .await
+        .map_err(|e| match e {
+            Error::NotFound(_) => Error::NotFound("Conversation not found".to_string()),
+            e => e,
+        })?;
+    let timed_out_until = row.get(3);
+    Ok(Membership {
+        kind: ConversationKind::parse(&row.get(0)),
+        blocked: row.get(1) == "true",
+        role: RoomRole::parse(&row.get(2)),
+        timed_out_until: (!timed_out_until.is_empty()).then_some(timed_out_until),
+    })
+}
+
+/// Publishes an event to everyone in the conversation.
+pub(crate) async fn publish<D: Database<PgRow>, P: Publisher>(
+    db: &D,
+    publisher: &P,
+    conversation_id: &str,
+    kind: &str,
+    payload: impl Serialize,
+) -> Result<(), Error> {
+    let recipients = members(db, conversation_id).await?;
+    publish_to(publisher, recipients, kind, payload).await
+}
+
+/// Ids of everyone in the conversation.
+pub(crate) async fn members<D: Database<PgRow>>(
+    db: &D,
+    conversation_id: &str,
+) -> Result<Vec<String>, Error> {
+    let rows = db
+        .query(
+            "SELECT user_id FROM conversation_members WHERE conversation_id = $1",
+            &[&conversation_id.to_string()],
+        )
+        .await?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+/// Publishes a realtime event to the given users only.
+pub(crate) async fn publish_to<P: Publisher>(
+    publisher: &P,
+    recipients: Vec<String>,
+    kind: &str,
+    payload: impl Serialize,
+) -> Result<(), Error> {
+    let delivery = Delivery {
+        recipients,
+        payload: serde_json::to_value(payload).map_err(|e| Error::Internal(e.to_string()))?,
+    };
+    publisher
+        .publish(CHAT_CONVERSATIONS, &Event::new(kind, delivery))
+        .await
+}
+
+/// Columns of [`Conversation`] as seen by the member bound to `$1`, from
+/// `conversations c` joined with their membership as `me`. Unread counts
+/// are filled in afterwards from the counters. The role is empty where the
+/// caller isn't a member.
+const CONVERSATION_COLUMNS: &str = "c.id, c.kind, c.name, \
+    COALESCE((SELECT json_agg(json_build_object('user_id', u.id, 'username', u.username, 'name', u.name) \
+        ORDER BY u.username) \
+      FROM conversation_members cm JOIN users u ON u.id = cm.user_id \
+      WHERE cm.conversation_id = c.id AND c.kind <> 'room'), '[]')::TEXT, \
+    (SELECT COUNT(*) FROM conversation_members cm WHERE cm.conversation_id = c.id)::TEXT, \
+    COALESCE((SELECT json_build_object('id', m.id::TEXT, 'conversation_id', m.conversation_id, \
+        'author_id', m.author_id, 'body', CASE WHEN m.deleted_at IS NULL THEN m.body ELSE '' END, \
+        'edited_at', m.edited_at, 'deleted_at', m.deleted_at, 'thread_id', NULL, \
+        'reply_count', m.reply_count, 'last_reply_at', m.last_reply_at, \
+        'last_reply_author_id', m.last_reply_author_id, 'created_at', m.created_at) \
+      FROM messages m WHERE m.conversation_id = c.id AND m.thread_id IS NULL \
+      ORDER BY m.id DESC LIMIT 1)::TEXT, ''), \
+    to_json(c.last_activity_at) #>> '{}', to_json(c.created_at) #>> '{}', \
+    COALESCE(me.mention_count, 0)::TEXT, COALESCE(me.muted, FALSE)::TEXT, \
+    COALESCE(me.role, ''), \
+    COALESCE((SELECT to_json(t.expires_at) #>> '{}' FROM room_timeouts t \
+      WHERE t.conversation_id = c.id AND t.user_id = me.user_id AND t.expires_at > NOW()), '')";
+
+/// Unread counts of the member bound to `$1` in the conversations listed in
+/// `$2`, separated by commas.
+const UNREAD_SQL: &str = "SELECT me.conversation_id, (SELECT COUNT(*) FROM messages m \
+      WHERE m.conversation_id = me.conversation_id AND m.thread_id IS NULL \
+        AND m.id > me.last_read_message_id AND m.author_id <> me.user_id \
+        AND m.deleted_at IS NULL)::TEXT \
+    FROM conversation_members me \
+    WHERE me.user_id = $1 AND me.conversation_id = ANY(string_to_array($2, ','))";
+
+/// Whether members see each other's read receipts. Rooms are public and
+/// can grow large, so they never do.
+fn shares_receipts(kind: ConversationKind, member_count: i64) -> bool {
+    kind != ConversationKind::Room && member_count <= RECEIPT_MAX_MEMBERS
+}
+
+fn conversation_from_row(row: &PgRow) -> Conversation {
+    let kind = ConversationKind::parse(&row.get(1));
+    let role = (!row.get(10).is_empty()).then(|| RoomRole::parse(&row.get(10)));
+    let timed_out_until = row.get(11);
+    Conversation {
+        id: row.get(0),
+        kind,
+        name: row.get(2),
+        members: serde_json::from_str(&row.get(3)).unwrap_or_default(),
+        member_count: row.get(4).parse().unwrap_or_default(),
+        unread_count: 0,
+        mention_count: row.get(8).parse().unwrap_or_default(),
+        muted: row.get(9) == "true",
+        role: role.filter(|_| kind == ConversationKind::Room),
+        permissions: role
+            .map(|role| Permission::list(permission_bits(kind, role)))
+            .unwrap_or_default(),
+        timed_out_until: (!timed_out_until.is_empty()).then_some(timed_out_until),
+        last_message: serde_json::from_str(&row.get(5)).ok(),
+        last_activity_at: row.get(6),
+        created_at: row.get(7),
+    }
+}
+
+/// Key of the direct conversation between two users, the same whichever of
+/// them starts it.
+fn direct_key(user_id: &str, other_id: &str) -> String {
+    let mut ids = [user_id, other_id];
+    ids.sort();
+    ids.join(":")
+}
+
+#[async_trait]
+pub trait ConversationService {
+    /// Conversations of the caller, most recently active first.
+    async fn get_conversations(
+        &self,
+        user_id: &str,
+        query: &QueryConversations,
+    ) -> Result<Page<Conversation>, Error>;
+    async fn get_conversation(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+    ) -> Result<Conversation, Error>;
+    /// Starts a direct or group conversation, `true` when it is new. Asking
+    /// for a direct conversation that exists returns it.
+    async fn create_conversation(
+        &self,
+        user_id: &str,
+        data: &CreateConversation,
+    ) -> Result<(Conversation, bool), Error>;
+    /// Leaves a group or room, direct conversations can't be left. Owners
+    /// hand their room over before leaving it to others.
+    async fn leave_conversation(&self, user_id: &str, conversation_id: &str) -> Result<(), Error>;
+    /// Public rooms by name.
+    async fn get_rooms(
+        &self,
+        user_id: &str,
+        query: &QueryConversations,
+    ) -> Result<Page<Conversation>, Error>;
+    /// Creates a room owned by the caller.
+    async fn create_room(&self, user_id: &str, data: &CreateRoom) -> Result<Conversation, Error>;
+    /// Joins a room the caller isn't banned from.
+    async fn join_room(&self, user_id: &str, room_id: &str) -> Result<Conversation, Error>;
+    /// Renames a room, members who may edit its settings only.
+    async fn update_room(
+        &self,
+        user_id: &str,
+        room_id: &str,
+        data: &UpdateRoom,
+    ) -> Result<Conversation, Error>;
+    /// Moves the caller's read position forward to a message, never back.
+    /// Members of direct conversations and small groups get a receipt.
+    async fn mark_read(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        data: &MarkRead,
+    ) -> Result<ReadState, Error>;
+    /// Read positions of the members, only the caller's own where receipts
+    /// aren't shared.
+    async fn get_receipts(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+    ) -> Result<Vec<Receipt>, Error>;
+    /// Stops or resumes notifications to the caller from a conversation.
+    /// Mentions are still counted while muted.
+    async fn set_muted(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        muted: bool,
+    ) -> Result<Conversation, Error>;
+}
+
+pub struct ConversationServiceImpl<D: Database<PgRow>, L: Logger, P: Publisher, U: UnreadCounter> {
+    db: D,
+    logger: L,
+    publisher: P,
+    counter: U,
+    cursors: CursorCodec,
+}
+
+impl<D: Database<PgRow>, L: Logger, P: Publisher, U: UnreadCounter>
+    ConversationServiceImpl<D, L, P, U>
+{
+    pub fn new(db: D, logger: L, publisher: P, counter: U, cursors: CursorCodec) -> Self {
+        Self {
+            db,
+            logger,
+            publisher,
+            counter,
+            cursors,
+        }
+    }
+
+    /// Fills in the caller's unread counts from the counters, computing and
+    /// storing the ones they don't know. Counters that are down only cost
+    /// computing every count.
+    async fn with_unread(
+        &self,
+        user_id: &str,
+        conversations: &mut [Conversation],
+    ) -> Result<(), Error> {
+        let ids: Vec<String> = conversations.iter().map(|c| c.id.clone()).collect();
+        // Without the versions computed counts can't be stored safely
+        let (cached, versioned) = match self.counter.counts(user_id, &ids).await {
+            Ok(cached) => (cached, true),
+            Err(e) => {
+                let message = format!("failed to get unread counts of user {}: {}", user_id, e);
+                self.logger
+                    .error("conversation_service::with_unread", &message);
+                (vec![Count::default(); ids.len()], false)
+            }
+        };
+        let missing: Vec<&str> = ids
+            .iter()
+            .zip(&cached)
+            .filter(|(_, count)| count.value.is_none())
+            .map(|(id, _)| id.as_str())
+            .collect();
+        let mut computed: Vec<(String, i64)> = vec![];
+        if !missing.is_empty() {
+            let rows = self
+                .db
+                .query(UNREAD_SQL, &[&user_id.to_string(), &missing.join(",")])
+                .await?;
+            for row in &rows {
+                let (id, count) = (row.get(0), row.get(1).parse().unwrap_or_default());
+                let version = ids
+                    .iter()
+                    .position(|known| known == &id)
+                    .map(|index| cached[index].version);
+                if let (true, Some(version)) = (versioned, version) {
+                    if let Err(e) = self.counter.set(user_id, &id, count, version).await {
+                        let message =
+                            format!("failed to store unread count of user {}: {}", user_id, e);
+                        self.logger
+                            .error("conversation_service::with_unread", &message);
+                    }
+                }
+                computed.push((id, count));
+            }
+        }
+        for (conversation, cached) in conversations.iter_mut().zip(cached) {
+            conversation.unread_count = cached
+                .value
+                .or_else(|| {
+                    computed
+                        .iter()
+                        .find(|(id, _)| id == &conversation.id)
+                        .map(|(_, count)| *count)
+                })
+                .unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    /// Checks the caller may message each of `user_ids`: they exist, neither
+    /// side blocked the other and their DM policy lets the caller in.
+    async fn check_reachable(&self, user_id: &str, user_ids: &[String]) -> Result<(), Error> {
+        let rows = self
+            .db
+            .query(
+                "SELECT u.id, u.username, u.dm_policy, \
+                   EXISTS (SELECT 1 FROM user_blocks WHERE blocker_id = $2 AND blocked_id = u.id)::TEXT, \
+                   EXISTS (SELECT 1 FROM user_blocks WHERE blocker_id = u.id AND blocked_id = $2)::TEXT, \
+                   EXISTS (SELECT 1 FROM user_relationships r WHERE r.status = 'accepted' \
+                     AND ((r.requester_id = u.id AND r.addressee_id = $2) \
+                       OR (r.addressee_id = u.id AND r.requester_id = $2)))::TEXT \
+                 FROM users u WHERE u.id = ANY(string_to_array($1, ',')) \
+                   AND u.deletion_scheduled_at IS NULL",
+                &[&user_ids.join(","), &user_id.to_string()],
+            )
+            .await?;
+        for id in user_ids {
+            let Some(row) = rows.iter().find(|row| &row.get(0) == id) else {
+                return Err(Error::NotFound(format!("User {} not found", id)));
+            };
+            if row.get(3) == "true" {
+                return Err(Error::Forbidden(format!(
+                    "Unblock {} to message them",
+                    row.get(1)
+                )));
+            }
+            // Being blocked looks the same as not being let in
+            let allowed = row.get(4) != "true" && policy_allows(&row.get(2), row.get(5) == "true");
+            if !allowed {
+                return Err(Error::Forbidden(format!(
+                    "{} doesn't accept messages from you",
+                    row.get(1)
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Id of the direct conversation between the users, `true` when it was
+    /// created.
+    async fn upsert_direct(&self, user_id: &str, other_id: &str) -> Result<(String, bool), Error> {
+        let key = direct_key(user_id, other_id);
+        let params = [&key, &user_id.to_string()];
+        // A conversation created concurrently is committed but not visible
+        // to the statement that lost the race, the retry sees it
+        for _ in 0..2 {
+            let rows = self
+                .db
+                .query(
+                    "WITH created AS (INSERT INTO conversations (kind, dm_key, created_by) \
+                       VALUES ('direct', $1, $2) ON CONFLICT (dm_key) DO NOTHING RETURNING id) \
+                     SELECT id, 'true' FROM created \
+                     UNION ALL SELECT id, 'false' FROM conversations WHERE dm_key = $1",
+                    &params,
+                )
+                .await?;
+            if let Some(row) = rows.first() {
+                return Ok((row.get(0), row.get(1) == "true"));
+            }
+        }
+        Err(Error::Internal(format!(
+            "direct conversation {} is neither created nor found",
+            key
+        )))
+    }
+
+    async fn list(
+        &self,
+        user_id: &str,
+        sql: &str,
+        request: &PageRequest,
+        params: &[&String],
+        key: impl Fn(&Conversation) -> Vec<String>,
+    ) -> Result<Page<Conversation>, Error> {
+        let rows = self.db.query(sql, params).await?;
+        let mut conversations: Vec<Conversation> = rows.iter().map(conversation_from_row).collect();
+        self.with_unread(user_id, &mut conversations).await?;
+        Ok(request.page(&self.cursors, conversations, key))
+    }
+}
+
+/// Whether a `dm_policy` of `everyone`, `friends` or `nobody` lets the caller
+/// in, unknown values let nobody in.
+fn policy_allows(dm_policy: &str, friends: bool) -> bool {
+    match dm_policy {
+        "everyone" => true,
+        "friends" => friends,
+        _ => false,
+    }
+}
+
+#[async_trait]
+impl<
+        D: Database<PgRow> + Send + Sync,
+        L: Logger + Send + Sync,
+        P: Publisher + Send + Sync,
+        U: UnreadCounter + Send + Sync,
+    > ConversationService for ConversationServiceImpl<D, L, P, U>
+{
+    async fn get_conversations(
+        &self,
+        user_id: &str,
+        query: &QueryConversations,
+    ) -> Result<Page<Conversation>, Error> {
+        let request = PageRequest::new(
+            &self.cursors,
+            &format!("conversations:{}", user_id),
+            query.limit.unwrap_or(20),
+            query.cursor.as_deref(),
+        )?
+        .descending();
+        let key = request.key();
+        let (at, id) = (
+            key.first().cloned().unwrap_or_default(),
+            key.get(1).cloned().unwrap_or_default(),
+        );
+        let sql = format!(
+            "SELECT {} FROM conversation_members me \
+             JOIN conversations c ON c.id = me.conversation_id \
+             WHERE me.user_id = $1 \
+               AND ($2 = '' OR (c.last_activity_at, c.id) {} (NULLIF($2, '')::TIMESTAMPTZ, $3)) \
+             ORDER BY c.last_activity_at {}, c.id {} LIMIT $4::TEXT::INT",
+            CONVERSATION_COLUMNS,
+            request.comparator(),
+            request.order(),
+            request.order()
+        );
+        let params = [&user_id.to_string(), &at, &id, &request.fetch_limit()];
+        self.list(user_id, &sql, &request, &params, |c: &Conversation| {
+            vec![c.last_activity_at.clone(), c.id.clone()]
+        })
+        .await
+        .inspect_err(|e| {
+            let message = format!("failed to query conversations of user {}: {}", user_id, e);
+            self.logger
+                .error("conversation_service::get_conversations", &message);
+        })
+    }
+
+    async fn get_conversation(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+    ) -> Result<Conversation, Error> {
+        let sql = format!(
+            "SELECT {} FROM conversation_members me \
+             JOIN conversations c ON c.id = me.conversation_id \
+             WHERE me.user_id = $1 AND c.id = $2",
+            CONVERSATION_COLUMNS
+        );
+        let row = self
+            .db
+            .query_one(&sql, &[&user_id.to_string(), &conversation_id.to_string()])
+            .await
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound("Conversation not found".to_string()),
+                e => e,
+            })?;
+        let mut conversations = [conversation_from_row(&row)];
+        self.with_unread(user_id, &mut conversations).await?;
+        let [conversation] = conversations;
+        Ok(conversation)
+    }
+
+    async fn create_conversation(
+        &self,
+        user_id: &str,
+        data: &CreateConversation,
+    ) -> Result<(Conversation, bool), Error> {
+        let mut others: Vec<String> = vec![];
+        for id in data.user_ids.iter().map(|id| id.trim()) {
+            if !id.is_empty() && id != user_id && !others.iter().any(|seen| seen == id) {
+                others.push(id.to_string());
+            }
+        }
+        if others.is_empty() {
+            return Err(Error::BadRequest(
+                "Cannot start a conversation with yourself".to_string(),
+            ));
+        }
+        self.check_reachable(user_id, &others).await?;
+
+        let (conversation_id, created) = if let [other_id] = others.as_slice() {
+            self.upsert_direct(user_id, other_id).await?
+        } else {
+            let name = data.name.as_deref().unwrap_or_default().trim().to_string();
+            let row = self
+                .db
+                .query_one(
+                    "INSERT INTO conversations (kind, name, created_by) VALUES ('group', $1, $2) \
+                     RETURNING id",
+                    &[&name, &user_id.to_string()],
+                )
+                .await?;
+            (row.get(0), true)
+        };
+        let mut members = others.clone();
+        members.push(user_id.to_string());
+        self.db
+            .execute(
+                "INSERT INTO conversation_members (conversation_id, user_id) \
+                 SELECT $1, unnest(string_to_array($2, ',')) ON CONFLICT DO NOTHING",
+                &[&conversation_id, &members.join(",")],
+            )
+            .await?;
+        if created {
+            let message = format!(
+                "user {} started conversation {} with {}",
+                user_id,
+                conversation_id,
+                others.join(", ")
+            );
+            self.logger
+                .info("conversation_service::create_conversation", &message);
+        }
+        let conversation = self.get_conversation(user_id, &conversation_id).await?;
+        Ok((conversation, created))
+    }
+
+    async fn leave_conversation(&self, user_id: &str, conversation_id: &str) -> Result<(), Error> {
+        let membership = find_membership(&self.db, conversation_id, user_id).await?;
+        if membership.kind == ConversationKind::Direct {
+            return Err(Error::BadRequest(
+                "Direct conversations can't be left".to_string(),
+            ));
+        }
+        if membership.role == RoomRole::Owner && members(&self.db, conversation_id).await?.len() > 1
+        {
+            return Err(Error::BadRequest(
+                "Hand the room over to another member before leaving".to_string(),
+            ));
+        }
+        self.db
+            .execute(
+                "DELETE FROM conversation_members WHERE conversation_id = $1 AND user_id = $2",
+                &[&conversation_id.to_string(), &user_id.to_string()],
+            )
+            .await?;
+        // A stale count would come back if they join again
+        if let Err(e) = self
+            .counter
+            .forget(conversation_id, &[user_id.to_string()])
+            .await
+        {
+            let message = format!("failed to forget unread count of user {}: {}", user_id, e);
+            self.logger
+                .error("conversation_service::leave_conversation", &message);
+        }
+        Ok(())
+    }
+
+    async fn get_rooms(
+        &self,
+        user_id: &str,
+        query: &QueryConversations,
+    ) -> Result<Page<Conversation>, Error> {
+        let request = PageRequest::new(
+            &self.cursors,
+            "rooms",
+            query.limit.unwrap_or(20),
+            query.cursor.as_deref(),
+        )?;
+        let key = request.key();
+        let (name, id) = (
+            key.first().cloned().unwrap_or_default(),
+            key.get(1).cloned().unwrap_or_default(),
+        );
+        // Rooms the caller isn't in have no read position, so nothing unread
+        let sql = format!(
+            "SELECT {} FROM conversations c \
+             LEFT JOIN conversation_members me ON me.conversation_id = c.id AND me.user_id = $1 \
+             WHERE c.kind = 'room' AND ($2 = '' OR (c.name, c.id) {} ($2, $3)) \
+             ORDER BY c.name {}, c.id {} LIMIT $4::TEXT::INT",
+            CONVERSATION_COLUMNS,
+            request.comparator(),
+            request.order(),
+            request.order()
+        );
+        let params = [&user_id.to_string(), &name, &id, &request.fetch_limit()];
+        self.list(user_id, &sql, &request, &params, |c: &Conversation| {
+            vec![c.name.clone(), c.id.clone()]
+        })
+        .await
+    }
+
+    async fn create_room(&self, user_id: &str, data: &CreateRoom) -> Result<Conversation, Error> {
+        let row = self
+            .db
+            .query_one(
+                "WITH created AS (INSERT INTO conversations (kind, name, created_by) \
+                   VALUES ('room', $1, $2) RETURNING id), \
+                 joined AS (INSERT INTO conversation_members (conversation_id, user_id, role) \
+                   SELECT id, $2, 'owner' FROM created) \
+                 SELECT id FROM created",
+                &[&data.name.trim().to_string(), &user_id.to_string()],
+            )
+            .await?;
+        let room_id = row.get(0);
+        let message = format!("user {} created room {}", user_id, room_id);
+        self.logger
+            .info("conversation_service::create_room", &message);
+        self.get_conversation(user_id, &room_id).await
+    }
+
+    async fn join_room(&self, user_id: &str, room_id: &str) -> Result<Conversation, Error> {
+        let params = [&room_id.to_string(), &user_id.to_string()];
+        let row = self
+            .db
+            .query_one(
+                "SELECT COALESCE((SELECT COALESCE(to_json(b.expires_at) #>> '{}', 'forever') \
+                   FROM room_bans b WHERE b.conversation_id = c.id AND b.user_id = $2 \
+                     AND (b.expires_at IS NULL OR b.expires_at > NOW())), '') \
+                 FROM conversations c WHERE c.id = $1 AND c.kind = 'room'",
+                &params,
+            )
+            .await
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound("Room not found".to_string()),
+                e => e,
+            })?;
+        match row.get(0).as_str() {
+            "" => {}
+            "forever" => {
+                return Err(Error::Forbidden(
+                    "You are banned from this room".to_string(),
+                ))
+            }
+            until => {
+                return Err(Error::Forbidden(format!(
+                    "You are banned from this room until {}",
+                    until
+                )))
+            }
+        }
+        // Unread counting starts from here, not from the room's beginning
+        self.db
+            .execute(
+                "INSERT INTO conversation_members (conversation_id, user_id, last_read_message_id) \
+                 SELECT $1, $2, COALESCE(MAX(id), 0) FROM messages WHERE conversation_id = $1 \
+                 ON CONFLICT DO NOTHING",
+                &params,
+            )
+            .await?;
+        self.get_conversation(user_id, room_id).await
+    }
+
+    async fn update_room(
+        &self,
+        user_id: &str,
+        room_id: &str,
+        data: &UpdateRoom,
+    ) -> Result<Conversation, Error> {
+        let membership =
+            find_membership(&self.db, room_id, user_id)
+                .await
+                .map_err(|e| match e {
+                    Error::NotFound(_) => Error::NotFound("Room not found".to_string()),
+                    e => e,
+                })?;
+        if membership.kind != ConversationKind::Room {
+            return Err(Error::NotFound("Room not found".to_string()));
+        }
+        membership.require(Permission::EditSettings)?;
+        let name = data.name.trim().to_string();
+        let row = self
+            .db
+            .query_one(
+                "UPDATE conversations c SET name = $2 FROM conversations old \
+                 WHERE c.id = $1 AND old.id = c.id RETURNING old.name",
+                &[&room_id.to_string(), &name],
+            )
+            .await?;
+        audit(
+            &self.db,
+            &self.logger,
+            room_id,
+            user_id,
+            None,
+            ACTION_UPDATE_ROOM,
+            serde_json::json!({ "name": { "from": row.get(0), "to": name } }),
+        )
+        .await?;
+        let settings = RoomSettings {
+            conversation_id: room_id.to_string(),
+            name,
+        };
+        if let Err(e) = publish(&self.db, &self.publisher, room_id, ROOM_UPDATED, &settings).await {
+            let message = format!("failed to publish {}: {}", ROOM_UPDATED, e);
+            self.logger
+                .error("conversation_service::update_room", &message);
+        }
+        self.get_conversation(user_id, room_id).await
+    }
+
+    async fn mark_read(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        data: &MarkRead,
+    ) -> Result<ReadState, Error> {
+        let membership = find_membership(&self.db, conversation_id, user_id).await?;
+        // Taken before counting, so a message counted meanwhile isn't lost
+        let version = self
+            .counter
+            .counts(user_id, &[conversation_id.to_string()])
+            .await
+            .map(|counts| {
+                counts
+                    .first()
+                    .map(|count| count.version)
+                    .unwrap_or_default()
+            });
+        // Replies are read in their thread and don't move the position
+        let row = self
+            .db
+            .query_one(
+                "WITH target AS (SELECT id FROM messages \
+                   WHERE conversation_id = $1 AND id::TEXT = $3 AND thread_id IS NULL), \
+                 previous AS (SELECT last_read_message_id, mention_count FROM conversation_members \
+                   WHERE conversation_id = $1 AND user_id = $2), \
+                 me AS (UPDATE conversation_members \
+                   SET last_read_message_id = target.id, last_read_at = NOW(), \
+                     mention_count = (SELECT COUNT(*) FROM message_mentions mm \
+                       JOIN messages m ON m.id = mm.message_id \
+                       WHERE mm.user_id = $2 AND m.conversation_id = $1 AND m.thread_id IS NULL \
+                         AND m.id > target.id AND m.deleted_at IS NULL) \
+                   FROM target \
+                   WHERE conversation_id = $1 AND user_id = $2 AND last_read_message_id < target.id \
+                   RETURNING conversation_members.last_read_message_id, last_read_at, \
+                     conversation_members.mention_count), \
+                 position AS (SELECT COALESCE(me.last_read_message_id, \
+                     previous.last_read_message_id) AS id, me.last_read_at, \
+                     COALESCE(me.mention_count, previous.mention_count) AS mention_count \
+                   FROM target CROSS JOIN previous LEFT JOIN me ON TRUE) \
+                 SELECT position.id::TEXT, (SELECT COUNT(*) FROM messages m \
+                     WHERE m.conversation_id = $1 AND m.thread_id IS NULL AND m.id > position.id \
+                       AND m.author_id <> $2 AND m.deleted_at IS NULL)::TEXT, \
+                   COALESCE(to_json(position.last_read_at) #>> '{}', ''), \
+                   (SELECT COUNT(*) FROM conversation_members WHERE conversation_id = $1)::TEXT, \
+                   position.mention_count::TEXT \
+                 FROM position",
+                &[
+                    &conversation_id.to_string(),
+                    &user_id.to_string(),
+                    &data.message_id,
+                ],
+            )
+            .await
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound("Message not found".to_string()),
+                e => e,
+            })?;
+        let state = ReadState {
+            conversation_id: conversation_id.to_string(),
+            last_read_message_id: row.get(0),
+            unread_count: row.get(1).parse().unwrap_or_default(),
+            mention_count: row.get(4).parse().unwrap_or_default(),
+        };
+        let stored = match version {
+            Ok(version) => {
+                self.counter
+                    .set(user_id, conversation_id, state.unread_count, version)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = stored {
+            let message = format!("failed to store unread count of user {}: {}", user_id, e);
+            self.logger
+                .error("conversation_service::mark_read", &message);
+        }
+        let read_at = row.get(2);
+        if read_at.is_empty() {
+            return Ok(state);
+        }
+        let receipt = Receipt {
+            conversation_id: conversation_id.to_string(),
+            user_id: user_id.to_string(),
+            last_read_message_id: state.last_read_message_id.clone(),
+            last_read_at: Some(read_at),
+        };
+        let member_count = row.get(3).parse().unwrap_or_default();
+        let recipients = if shares_receipts(membership.kind, member_count) {
+            members(&self.db, conversation_id).await
+        } else {
+            Ok(vec![user_id.to_string()])
+        };
+        let result = match recipients {
+            Ok(recipients) => {
+                publish_to(&self.publisher, recipients, RECEIPT_UPDATED, &receipt).await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            let message = format!("failed to publish {}: {}", RECEIPT_UPDATED, e);
+            self.logger
+                .error("conversation_service::mark_read", &message);
+        }
+        Ok(state)
+    }
+
+    async fn get_receipts(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+    ) -> Result<Vec<Receipt>, Error> {
+        let membership = find_membership(&self.db, conversation_id, user_id).await?;
+        // Rooms can be large, only their caller's own position is read
+        let only = if membership.kind == ConversationKind::Room {
+            user_id.to_string()
+        } else {
+            String::new()
+        };
+        let rows = self
+            .db
+            .query(
+                "SELECT user_id, last_read_message_id::TEXT, \
+                   COALESCE(to_json(last_read_at) #>> '{}', '') \
+                 FROM conversation_members \
+                 WHERE conversation_id = $1 AND ($2 = '' OR user_id = $2) ORDER BY user_id",
+                &[&conversation_id.to_string(), &only],
+            )
+            .await?;
+        let shared = shares_receipts(membership.kind, rows.len() as i64);
+        Ok(rows
+            .iter()
+            .filter(|row| shared || row.get(0) == user_id)
+            .map(|row| {
+                let last_read_at = row.get(2);
+                Receipt {
+                    conversation_id: conversation_id.to_string(),
+                    user_id: row.get(0),
+                    last_read_message_id: row.get(1),
+                    last_read_at: (!last_read_at.is_empty()).then_some(last_read_at),
+                }
+            })
+            .collect())
+    }
+
+    async fn set_muted(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        muted: bool,
+    ) -> Result<Conversation, Error> {
+        find_membership(&self.db, conversation_id, user_id).await?;
+        self.db
+            .execute(
+                "UPDATE conversation_members SET muted = $3::BOOLEAN \
+                 WHERE conversation_id = $1 AND user_id = $2",
+                &[
+                    &conversation_id.to_string(),
+                    &user_id.to_string(),
+                    &muted.to_string(),
+                ],
+            )
+            .await?;
+        self.get_conversation(user_id, conversation_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use database::db::MockDatabase;
+    use events::publisher::MockPublisher;
+    use logger::log::Log;
+    use unread::counter::MockUnreadCounter;
+
+    use super::*;
+
+    type Service =
+        ConversationServiceImpl<MockDatabase<PgRow>, Log, MockPublisher, MockUnreadCounter>;
+
+    fn service(db: MockDatabase<PgRow>) -> Service {
+        service_with(db, MockUnreadCounter::new())
+    }
+
+    fn service_with(db: MockDatabase<PgRow>, counter: MockUnreadCounter) -> Service {
+        ConversationServiceImpl::new(
+            db,
+            Log,
+            MockPublisher::new(),
+            counter,
+            CursorCodec::new(b"secret"),
+        )
+    }
+
+    fn target(id: &str, dm_policy: &str, blocked: bool, blocked_by: bool, friends: bool) -> PgRow {
+        PgRow::from(vec![
+            id.to_string(),
+            id.to_string(),
+            dm_policy.to_string(),
+            blocked.to_string(),
+            blocked_by.to_string(),
+            friends.to_string(),
+        ])
+    }
+
+    #[test]
+    fn test_direct_key_is_symmetric() {
+        assert_eq!(direct_key("alice", "bob"), direct_key("bob", "alice"));
+        assert_ne!(direct_key("alice", "bob"), direct_key("alice", "carol"));
+    }
+
+    #[test]
+    fn test_receipts_are_shared_in_small_conversations() {
+        assert!(shares_receipts(ConversationKind::Direct, 2));
+        assert!(shares_receipts(
+            ConversationKind::Group,
+            RECEIPT_MAX_MEMBERS
+        ));
+        assert!(!shares_receipts(
+            ConversationKind::Group,
+            RECEIPT_MAX_MEMBERS + 1
+        ));
+        assert!(!shares_receipts(ConversationKind::Room, 2));
+    }
+
+    /// Database of conversation `c1` with 4 unread messages, calling
+    /// `counting` when they are counted.
+    fn unread_db(counting: impl Fn() + Send + 'static) -> MockDatabase<PgRow> {
+        let mut db = MockDatabase::new();
+        db.expect_query_one().returning(|_, _| {
+            let row = PgRow::from(vec![
+                "c1".to_string(),
+                "group".to_string(),
+                "".to_string(),
+                "[]".to_string(),
+                "3".to_string(),
+                "".to_string(),
+                "2026-01-01T00:00:00+00:00".to_string(),
+                "2026-01-01T00:00:00+00:00".to_string(),
+                "0".to_string(),
+                "false".to_string(),
+                "member".to_string(),
+                "".to_string(),
+            ]);
+            Box::pin(async move { Ok(row) })
+        });
+        db.expect_query()
+            .withf(|sql, _| sql == UNREAD_SQL)
+            .times(1)
+            .returning(move |_, _| {
+                counting();
+                let rows = vec![PgRow::from(vec!["c1".to_string(), "4".to_string()])];
+                Box::pin(async move { Ok(rows) })
+            });
+        db
+    }
+
+    #[tokio::test]
+    async fn test_unknown_unread_counts_are_computed_and_stored() {
+        let db = unread_db(|| {});
+        let mut counter = MockUnreadCounter::new();
+        counter.expect_counts().returning(|_, _| {
+            Ok(vec![Count {
+                value: None,
+                version: 7,
+            }])
+        });
+        counter
+            .expect_set()
+            .withf(|user_id, conversation_id, count, version| {
+                user_id == "alice" && conversation_id == "c1" && *count == 4 && *version == 7
+            })
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+
+        let conversation = service_with(db, counter)
+            .get_conversation("alice", "c1")
+            .await
+            .unwrap();
+        assert_eq!(conversation.unread_count, 4);
+    }
+
+    #[tokio::test]
+    async fn test_messages_counted_while_computing_are_not_lost() {
+        // The cached count, version and whether it is known, as in redis
+        let cache = Arc::new(Mutex::new((None::<i64>, 7)));
+        let increment = cache.clone();
+        // Another message is counted between reading the version and
+        // storing the computed count, which may have missed it
+        let db = unread_db(move || increment.lock().unwrap().1 += 1);
+        let mut counter = MockUnreadCounter::new();
+        let read = cache.clone();
+        counter.expect_counts().returning(move |_, _| {
+            let (value, version) = *read.lock().unwrap();
+            Ok(vec![Count { value, version }])
+        });
+        let stored = cache.clone();
+        counter
+            .expect_set()
+            .times(1)
+            .returning(move |_, _, count, version| {
+                let mut cache = stored.lock().unwrap();
+                cache.0 = (cache.1 == version).then_some(count);
+                Ok(())
+            });
+
+        let conversation = service_with(db, counter)
+            .get_conversation("alice", "c1")
+            .await
+            .unwrap();
+        assert_eq!(conversation.unread_count, 4);
+        // Dropped rather than kept short of the new message
+        assert_eq!(*cache.lock().unwrap(), (None, 8));
+    }
+
+    #[tokio::test]
+    async fn test_cannot_message_yourself() {
+        let data = CreateConversation {
+            user_ids: vec!["alice".to_string(), " alice ".to_string()],
+            name: None,
+        };
+        let result = service(MockDatabase::new())
+            .create_conversation("alice", &data)
+            .await;
+        assert!(matches!(result, Err(Error::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn test_dm_policy_and_blocks_are_respected() {
+        let cases = [
+            (("everyone", true, false, false), "Unblock"),
+            (("everyone", false, true, true), "doesn't accept"),
+            (("friends", false, false, false), "doesn't accept"),
+            (("nobody", false, false, true), "doesn't accept"),
+        ];
+        for ((dm_policy, blocked, blocked_by, friends), expected) in cases {
+            let mut db = MockDatabase::new();
+            db.expect_query().times(1).returning(move |_, _| {
+                let rows = vec![target("bob", dm_policy, blocked, blocked_by, friends)];
+                Box::pin(async move { Ok(rows) })
+            });
+            db.expect_execute().never();
+            let data = CreateConversation {
+                user_ids: vec!["bob".to_string()],
+                name: None,
+            };
+            match service(db).create_conversation("alice", &data).await {
+                Err(Error::Forbidden(message)) => {
+                    assert!(message.contains(expected), "{}", message)
+                }
+                other => panic!(
+                    "expected forbidden, got {:?}",
+                    other.map(|(_, created)| created)
+                ),
+            }
+        }
+    }
+}
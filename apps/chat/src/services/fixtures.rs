@@ -0,0 +1,34 @@
+//! Rows the mocked database hands back in tests of the services.
+
+use database::pgx::PgRow;
+
+/// What [`super::conversation_service::find_membership`] reads, for a member
+/// who isn't blocked or timed out.
+pub(crate) fn membership_row(kind: &str, role: &str) -> PgRow {
+    PgRow::from(vec![
+        kind.to_string(),
+        "false".to_string(),
+        role.to_string(),
+        "".to_string(),
+    ])
+}
+
+/// A message in `room` as read through
+/// [`super::message_service::MESSAGE_COLUMNS`], `thread_id` is empty outside
+/// threads.
+pub(crate) fn message_row(id: &str, author_id: &str, thread_id: &str) -> PgRow {
+    PgRow::from(vec![
+        id.to_string(),
+        "room".to_string(),
+        author_id.to_string(),
+        "hello".to_string(),
+        "".to_string(),
+        "".to_string(),
+        thread_id.to_string(),
+        "0".to_string(),
+        "".to_string(),
+        "".to_string(),
+        "2026-01-01T00:00:00+00:00".to_string(),
+        "[]".to_string(),
+    ])
+}
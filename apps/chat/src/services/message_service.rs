@@ -0,0 +1,1049 @@
+use async_trait::async_trait;
+use database::{db::Database, pgx::PgRow};
+use errors::error::Error;
+use events::publisher::Publisher;
+use logger::logger::Logger;
+use pagination::{
+    cursor::CursorCodec,
+    page::{Page, PageRequest},
+};
+use presence::store::PresenceStore;
+use serde::{Deserialize, Serialize};
+use unread::counter::UnreadCounter;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::services::{
+    attachment_service::{check_attachments, Attachment},
+    conversation_service::{find_membership, members, publish_to, ConversationKind},
+    moderation_service::{audit, Permission, ACTION_DELETE_MESSAGE, ACTION_PIN, ACTION_UNPIN},
+    notification_service::notify_mentions,
+};
+
+pub const MESSAGE_CREATED: &str = "message.created";
+pub const MESSAGE_UPDATED: &str = "message.updated";
+pub const MESSAGE_DELETED: &str = "message.deleted";
+pub const MESSAGE_PINNED: &str = "message.pinned";
+pub const MESSAGE_UNPINNED: &str = "message.unpinned";
+
+/// Most messages a conversation can have pinned at once.
+pub const MAX_PINS: i64 = 50;
+
+/// Columns of [`Message`] from `messages m`. Deleted messages keep their
+/// body for moderation but don't hand it out, their attachments are purged.
+pub(crate) const MESSAGE_COLUMNS: &str = "m.id::TEXT, m.conversation_id, m.author_id, \
+    CASE WHEN m.deleted_at IS NULL THEN m.body ELSE '' END, \
+    COALESCE(to_json(m.edited_at) #>> '{}', ''), COALESCE(to_json(m.deleted_at) #>> '{}', ''), \
+    COALESCE(m.thread_id::TEXT, ''), m.reply_count::TEXT, \
+    COALESCE(to_json(m.last_reply_at) #>> '{}', ''), COALESCE(m.last_reply_author_id, ''), \
+    to_json(m.created_at) #>> '{}', \
+    COALESCE((SELECT json_agg(json_build_object('id', a.id, 'file_name', a.file_name, \
+        'content_type', a.content_type, 'size', a.size, 'width', a.width, 'height', a.height, \
+        'has_thumbnail', a.has_thumbnail, 'created_at', a.created_at) ORDER BY a.created_at, a.id) \
+      FROM attachments a WHERE a.message_id = m.id AND m.deleted_at IS NULL), '[]')::TEXT";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct Message {
+    /// Increases with every message, so later messages have larger ids
+    id: String,
+    conversation_id: String,
+    author_id: String,
+    /// Empty once the message is deleted
+    body: String,
+    /// When the body last changed, missing if it never did
+    edited_at: Option<String>,
+    /// Set on the tombstone left by deleting the message
+    deleted_at: Option<String>,
+    /// First message of the thread this one replies in
+    thread_id: Option<String>,
+    /// Replies in the thread this message starts
+    reply_count: i64,
+    last_reply_at: Option<String>,
+    last_reply_author_id: Option<String>,
+    /// Reactions by emoji in the order they were first used. Only filled in
+    /// history, realtime events leave it empty.
+    #[serde(default)]
+    reactions: Vec<ReactionCount>,
+    /// Files sent with the message, download them through their links
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+    created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct ReactionCount {
+    /// A Unicode emoji or `custom:{id}`
+    emoji: String,
+    count: i64,
+    reacted_by_me: bool,
+}
+
+impl Message {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn conversation_id(&self) -> &str {
+        &self.conversation_id
+    }
+
+    pub fn author_id(&self) -> &str {
+        &self.author_id
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn thread_id(&self) -> Option<&str> {
+        self.thread_id.as_deref()
+    }
+}
+
+pub(crate) fn message_from_row(row: &PgRow) -> Message {
+    let optional = |value: String| (!value.is_empty()).then_some(value);
+    Message {
+        id: row.get(0),
+        conversation_id: row.get(1),
+        author_id: row.get(2),
+        body: row.get(3),
+        edited_at: optional(row.get(4)),
+        deleted_at: optional(row.get(5)),
+        thread_id: optional(row.get(6)),
+        reply_count: row.get(7).parse().unwrap_or_default(),
+        last_reply_at: optional(row.get(8)),
+        last_reply_author_id: optional(row.get(9)),
+        reactions: vec![],
+        attachments: serde_json::from_str(&row.get(11)).unwrap_or_default(),
+        created_at: row.get(10),
+    }
+}
+
+/// Fills in the reactions of messages as seen by the user. Deleted messages
+/// show none.
+pub(crate) async fn attach_reactions<D: Database<PgRow>>(
+    db: &D,
+    user_id: &str,
+    messages: &mut [Message],
+) -> Result<(), Error> {
+    let ids: Vec<&str> = messages
+        .iter()
+        .filter(|message| message.deleted_at.is_none())
+        .map(|message| message.id.as_str())
+        .collect();
+    if ids.is_empty() {
+        return Ok(());
+    }
+    let rows = db
+        .query(
+            "SELECT message_id::TEXT, emoji, COUNT(*)::TEXT, bool_or(user_id = $2)::TEXT \
+             FROM message_reactions \
+             WHERE message_id = ANY(string_to_array($1, ',')::BIGINT[]) \
+             GROUP BY message_id, emoji ORDER BY message_id, MIN(id)",
+            &[&ids.join(","), &user_id.to_string()],
+        )
+        .await?;
+    for row in &rows {
+        let message_id = row.get(0);
+        if let Some(message) = messages.iter_mut().find(|m| m.id == message_id) {
+            message.reactions.push(ReactionCount {
+                emoji: row.get(1),
+                count: row.get(2).parse().unwrap_or_default(),
+                reacted_by_me: row.get(3) == "true",
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Finds a message that isn't deleted.
+pub(crate) async fn find_message<D: Database<PgRow>>(
+    db: &D,
+    conversation_id: &str,
+    message_id: &str,
+) -> Result<Message, Error> {
+    let sql = format!(
+        "SELECT {} FROM messages m \
+         WHERE m.conversation_id = $1 AND m.id::TEXT = $2 AND m.deleted_at IS NULL",
+        MESSAGE_COLUMNS
+    );
+    db.query_one(
+        &sql,
+        &[&conversation_id.to_string(), &message_id.to_string()],
+    )
+    .await
+    .map(|row| message_from_row(&row))
+    .map_err(|e| match e {
+        Error::NotFound(_) => Error::NotFound("Message not found".to_string()),
+        e => e,
+    })
+}
+
+/// Checks the caller may post in the conversation: their role lets them
+/// and they aren't timed out.
+pub(crate) async fn check_can_send<D: Database<PgRow>>(
+    db: &D,
+    conversation_id: &str,
+    user_id: &str,
+) -> Result<(), Error> {
+    let membership = find_membership(db, conversation_id, user_id).await?;
+    if membership.kind == ConversationKind::Direct && membership.blocked {
+        return Err(Error::Forbidden(
+            "Messages can't be sent while either of you blocks the other".to_string(),
+        ));
+    }
+    membership.require(Permission::Send)?;
+    if let Some(until) = membership.timed_out_until {
+        return Err(Error::Forbidden(format!(
+            "You are timed out until {}",
+            until
+        )));
+    }
+    Ok(())
+}
+
+/// Posts a message, in the thread started by `thread_id` if given. The
+/// author has read everything up to their own message, and taking part in a
+/// thread subscribes them to it. The author of the first message is
+/// subscribed by the first reply.
+pub(crate) async fn insert_message<D: Database<PgRow>>(
+    db: &D,
+    conversation_id: &str,
+    author_id: &str,
+    data: &SendMessage,
+    thread_id: Option<&str>,
+) -> Result<Message, Error> {
+    if data.body.trim().is_empty() && data.attachment_ids.is_empty() {
+        return Err(Error::BadRequest("Message is empty".to_string()));
+    }
+    if !data.attachment_ids.is_empty() {
+        check_attachments(db, author_id, &data.attachment_ids).await?;
+    }
+    let sql = format!(
+        "WITH m AS (INSERT INTO messages (conversation_id, author_id, body, thread_id) \
+           VALUES ($1, $2, $3, NULLIF($4, '')::BIGINT) RETURNING *), \
+         thread AS (UPDATE messages SET reply_count = messages.reply_count + 1, \
+             last_reply_at = m.created_at, last_reply_author_id = m.author_id \
+           FROM m WHERE messages.id = m.thread_id \
+           RETURNING messages.id, messages.author_id, messages.reply_count), \
+         subscribed AS (INSERT INTO thread_subscriptions (thread_id, user_id) \
+           SELECT thread.id, m.author_id FROM thread, m \
+           UNION SELECT id, author_id FROM thread WHERE reply_count = 1 \
+           ON CONFLICT DO NOTHING), \
+         bumped AS (UPDATE conversations SET last_activity_at = m.created_at \
+           FROM m WHERE conversations.id = m.conversation_id), \
+         read AS (UPDATE conversation_members \
+           SET last_read_message_id = m.id, last_read_at = m.created_at FROM m WHERE conversation_members.conversation_id = m.conversation_id \
+             AND conversation_members.user_id = m.author_id \
+             AND m.thread_id IS NULL), \
+         attached AS (UPDATE attachments SET message_id = m.id FROM m \
+           WHERE attachments.id = ANY(string_to_array(NULLIF($5, ''), ',')) \
+             AND attachments.user_id = m.author_id AND attachments.uploaded_at IS NOT NULL \
+             AND attachments.message_id IS NULL) \
+         SELECT {} FROM m",
+        MESSAGE_COLUMNS
+    );
+    let row = db
+        .query_one(
+            &sql,
+            &[
+                &conversation_id.to_string(),
+                &author_id.to_string(),
+                &data.body,
+                &thread_id.unwrap_or_default().to_string(),
+                &data.attachment_ids.join(","),
+            ],
+        )
+        .await?;
+    let message = message_from_row(&row);
+    if data.attachment_ids.is_empty() {
+        return Ok(message);
+    }
+    // The statement that links the attachments can't see them linked
+    let sql = format!(
+        "SELECT {} FROM messages m WHERE m.id = $1::BIGINT",
+        MESSAGE_COLUMNS
+    );
+    let row = db.query_one(&sql, &[&message.id]).await?;
+    Ok(message_from_row(&row))
+}
+
+/// Body of a message before an edit.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct MessageRevision {
+    body: String,
+    /// When this body was replaced
+    replaced_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
+pub struct SendMessage {
+    /// May be empty when the message has attachments
+    #[serde(default)]
+    #[validate(length(max = 4000))]
+    body: String,
+    /// Uploaded attachments of the caller to send with the message
+    #[serde(default)]
+    #[validate(length(max = 10))]
+    attachment_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
+pub struct EditMessage {
+    #[validate(length(min = 1, max = 4000))]
+    body: String,
+}
+
+#[derive(Deserialize, Debug, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryMessages {
+    #[validate(range(min = 1, max = 100))]
+    limit: Option<u32>,
+    /// `next_cursor` or `prev_cursor` of the previous page
+    #[validate(length(max = 1024))]
+    cursor: Option<String>,
+}
+
+#[async_trait]
+pub trait MessageService {
+    /// Messages of a conversation the caller is in, newest first, with
+    /// their reactions. Replies are only listed in their thread.
+    async fn get_messages(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        query: &QueryMessages,
+    ) -> Result<Page<Message>, Error>;
+    async fn send_message(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        data: &SendMessage,
+    ) -> Result<Message, Error>;
+    /// Replaces the body of the caller's own message, keeping the old one
+    /// as a revision.
+    async fn edit_message(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+        data: &EditMessage,
+    ) -> Result<Message, Error>;
+    /// Leaves a tombstone in place of the message. Authors delete their own,
+    /// `moderator` lets the caller delete anyone's, as does a room role
+    /// that may delete messages.
+    async fn delete_message(
+        &self,
+        user_id: &str,
+        moderator: bool,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Result<Message, Error>;
+    /// Earlier bodies of a message, oldest first.
+    async fn get_revisions(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Result<Vec<MessageRevision>, Error>;
+    /// Pinned messages of the conversation, most recently pinned first.
+    async fn get_pinned(&self, user_id: &str, conversation_id: &str)
+        -> Result<Vec<Message>, Error>;
+    /// Pins a message, pinning it again changes nothing.
+    async fn pin_message(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Result<Message, Error>;
+    async fn unpin_message(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Result<Message, Error>;
+}
+
+pub struct MessageServiceImpl<
+    D: Database<PgRow>,
+    L: Logger,
+    P: Publisher,
+    U: UnreadCounter,
+    S: PresenceStore,
+> {
+    db: D,
+    logger: L,
+    publisher: P,
+    counter: U,
+    presence: S,
+    cursors: CursorCodec,
+}
+
+impl<D: Database<PgRow>, L: Logger, P: Publisher, U: UnreadCounter, S: PresenceStore>
+    MessageServiceImpl<D, L, P, U, S>
+{
+    pub fn new(
+        db: D,
+        logger: L,
+        publisher: P,
+        counter: U,
+        presence: S,
+        cursors: CursorCodec,
+    ) -> Self {
+        Self {
+            db,
+            logger,
+            publisher,
+            counter,
+            presence,
+            cursors,
+        }
+    }
+
+    /// Tells the members about a change and returns who they are, nobody
+    /// when publishing fails.
+    async fn emit(&self, kind: &str, message: &Message) -> Vec<String> {
+        let result = match members(&self.db, &message.conversation_id).await {
+            Ok(recipients) => publish_to(&self.publisher, recipients.clone(), kind, message)
+                .await
+                .map(|_| recipients),
+            Err(e) => Err(e),
+        };
+        result.unwrap_or_else(|e| {
+            let message = format!("failed to publish {}: {}", kind, e);
+            self.logger.error("message_service::emit", &message);
+            vec![]
+        })
+    }
+
+    /// Logs a failed counter update, the counts heal once they expire.
+    fn check_counter(&self, result: Result<(), Error>, conversation_id: &str) {
+        if let Err(e) = result {
+            let message = format!(
+                "failed to update unread counts of conversation {}: {}",
+                conversation_id, e
+            );
+            self.logger
+                .error("message_service::check_counter", &message);
+        }
+    }
+}
+
+#[async_trait]
+impl<
+        D: Database<PgRow> + Send + Sync,
+        L: Logger + Send + Sync,
+        P: Publisher + Send + Sync,
+        U: UnreadCounter + Send + Sync,
+        S: PresenceStore + Send + Sync,
+    > MessageService for MessageServiceImpl<D, L, P, U, S>
+{
+    async fn get_messages(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        query: &QueryMessages,
+    ) -> Result<Page<Message>, Error> {
+        find_membership(&self.db, conversation_id, user_id).await?;
+        let request = PageRequest::new(
+            &self.cursors,
+            &format!("messages:{}", conversation_id),
+            query.limit.unwrap_or(50),
+            query.cursor.as_deref(),
+        )?
+        .descending();
+        let before = request.key().first().cloned().unwrap_or_default();
+        let sql = format!(
+            "SELECT {} FROM messages m \
+             WHERE m.conversation_id = $1 AND m.thread_id IS NULL \
+               AND ($2 = '' OR m.id {} NULLIF($2, '')::BIGINT) \
+             ORDER BY m.id {} LIMIT $3::TEXT::INT",
+            MESSAGE_COLUMNS,
+            request.comparator(),
+            request.order()
+        );
+        let params = [
+            &conversation_id.to_string(),
+            &before,
+            &request.fetch_limit(),
+        ];
+        let result = match self.db.query(&sql, &params).await {
+            Ok(rows) => {
+                let mut messages: Vec<Message> = rows.iter().map(message_from_row).collect();
+                attach_reactions(&self.db, user_id, &mut messages)
+                    .await
+                    .map(|_| messages)
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(messages) => Ok(request.page(&self.cursors, messages, |message: &Message| {
+                vec![message.id.clone()]
+            })),
+            Err(e) => {
+                let message = format!(
+                    "failed to query messages of conversation {}: {}",
+                    conversation_id, e
+                );
+                self.logger.error("message_service::get_messages", &message);
+                Err(e)
+            }
+        }
+    }
+
+    async fn send_message(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        data: &SendMessage,
+    ) -> Result<Message, Error> {
+        check_can_send(&self.db, conversation_id, user_id).await?;
+        // Taken before sending, so a message counted meanwhile isn't lost
+        let version = self
+            .counter
+            .counts(user_id, &[conversation_id.to_string()])
+            .await
+            .map(|counts| {
+                counts
+                    .first()
+                    .map(|count| count.version)
+                    .unwrap_or_default()
+            });
+        let message = insert_message(&self.db, conversation_id, user_id, data, None)
+            .await
+            .inspect_err(|e| {
+                let message = format!(
+                    "failed to send message to conversation {}: {}",
+                    conversation_id, e
+                );
+                self.logger.error("message_service::send_message", &message);
+            })?;
+        let mut others = self.emit(MESSAGE_CREATED, &message).await;
+        // Sending reads everything before it
+        others.retain(|id| id != user_id);
+        let result = self.counter.increment(conversation_id, &others).await;
+        self.check_counter(result, conversation_id);
+        let result = match version {
+            Ok(version) => self.counter.set(user_id, conversation_id, 0, version).await,
+            Err(e) => Err(e),
+        };
+        self.check_counter(result, conversation_id);
+        if let Err(e) = notify_mentions(&self.db, &self.publisher, &self.presence, &message).await {
+            let message = format!("failed to notify mentions of message {}: {}", message.id, e);
+            self.logger.error("message_service::send_message", &message);
+        }
+        Ok(message)
+    }
+
+    async fn edit_message(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+        data: &EditMessage,
+    ) -> Result<Message, Error> {
+        find_membership(&self.db, conversation_id, user_id).await?;
+        let message = find_message(&self.db, conversation_id, message_id).await?;
+        if message.author_id != user_id {
+            return Err(Error::Forbidden(
+                "Only the author can edit a message".to_string(),
+            ));
+        }
+        if data.body.trim().is_empty() {
+            return Err(Error::BadRequest("Message is empty".to_string()));
+        }
+        if data.body == message.body {
+            return Ok(message);
+        }
+        // The old body is read under the row lock, so concurrent edits each
+        // keep the body they replaced
+        let sql = format!(
+            "WITH previous AS (SELECT id, body FROM messages \
+               WHERE id = $1::BIGINT AND deleted_at IS NULL FOR UPDATE), \
+             m AS (UPDATE messages SET body = $2, edited_at = NOW() FROM previous \
+               WHERE messages.id = previous.id RETURNING messages.*), \
+             revision AS (INSERT INTO message_revisions (message_id, body) \
+               SELECT id, body FROM previous) \
+             SELECT {} FROM m",
+            MESSAGE_COLUMNS
+        );
+        let row = self
+            .db
+            .query_one(&sql, &[&message.id, &data.body])
+            .await
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound("Message not found".to_string()),
+                e => e,
+            })?;
+        let message = message_from_row(&row);
+        self.emit(MESSAGE_UPDATED, &message).await;
+        Ok(message)
+    }
+
+    async fn delete_message(
+        &self,
+        user_id: &str,
+        moderator: bool,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Result<Message, Error> {
+        // Moderators act on conversations they aren't in
+        let room_moderator = match moderator {
+            true => false,
+            false => find_membership(&self.db, conversation_id, user_id)
+                .await?
+                .can(Permission::DeleteMessages),
+        };
+        let message = find_message(&self.db, conversation_id, message_id).await?;
+        if message.author_id != user_id && !moderator && !room_moderator {
+            return Err(Error::Forbidden(
+                "Only the author or a moderator can delete a message".to_string(),
+            ));
+        }
+        // Deleted replies leave the count of their thread, which shows the
+        // latest reply left instead. Nothing deleted stays pinned.
+        let sql = format!(
+            "WITH m AS (UPDATE messages SET deleted_at = NOW(), deleted_by = $2 \
+               WHERE id = $1::BIGINT AND deleted_at IS NULL RETURNING *), \
+             thread AS (UPDATE messages SET reply_count = messages.reply_count - 1, \
+               last_reply_at = latest.created_at, last_reply_author_id = latest.author_id \
+               FROM m LEFT JOIN LATERAL (SELECT r.created_at, r.author_id FROM messages r \
+                 WHERE r.thread_id = m.thread_id AND r.deleted_at IS NULL AND r.id <> m.id \
+                 ORDER BY r.id DESC LIMIT 1) latest ON TRUE \
+               WHERE messages.id = m.thread_id), \
+             unpinned AS (DELETE FROM message_pins p USING m WHERE p.message_id = m.id) \
+             SELECT {} FROM m",
+            MESSAGE_COLUMNS
+        );
+        let row = self
+            .db
+            .query_one(&sql, &[&message.id, &user_id.to_string()])
+            .await
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound("Message not found".to_string()),
+                e => e,
+            })?;
+        if message.author_id != user_id {
+            let log = format!(
+                "moderator {} deleted message {} of user {}",
+                user_id, message.id, message.author_id
+            );
+            self.logger.info("message_service::delete_message", &log);
+            // Global moderators too, the log is all a room has to see who
+            // removed what
+            audit(
+                &self.db,
+                &self.logger,
+                conversation_id,
+                user_id,
+                Some(&message.author_id),
+                ACTION_DELETE_MESSAGE,
+                serde_json::json!({ "message_id": message.id }),
+            )
+            .await?;
+        }
+        let message = message_from_row(&row);
+        let recipients = self.emit(MESSAGE_DELETED, &message).await;
+        // Whoever hadn't read it yet counted it, let their counts be redone
+        if message.thread_id.is_none() {
+            let result = self.counter.forget(conversation_id, &recipients).await;
+            self.check_counter(result, conversation_id);
+        }
+        Ok(message)
+    }
+
+    async fn get_revisions(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Result<Vec<MessageRevision>, Error> {
+        find_membership(&self.db, conversation_id, user_id).await?;
+        let message = find_message(&self.db, conversation_id, message_id).await?;
+        let rows = self
+            .db
+            .query(
+                "SELECT body, to_json(replaced_at) #>> '{}' FROM message_revisions \
+                 WHERE message_id = $1::BIGINT ORDER BY id",
+                &[&message.id],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| MessageRevision {
+                body: row.get(0),
+                replaced_at: row.get(1),
+            })
+            .collect())
+    }
+
+    async fn get_pinned(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+    ) -> Result<Vec<Message>, Error> {
+        find_membership(&self.db, conversation_id, user_id).await?;
+        let sql = format!(
+            "SELECT {} FROM message_pins p JOIN messages m ON m.id = p.message_id \
+             WHERE p.conversation_id = $1 AND m.deleted_at IS NULL \
+             ORDER BY p.created_at DESC, m.id DESC",
+            MESSAGE_COLUMNS
+        );
+        let rows = self.db.query(&sql, &[&conversation_id.to_string()]).await?;
+        let mut messages: Vec<Message> = rows.iter().map(message_from_row).collect();
+        attach_reactions(&self.db, user_id, &mut messages).await?;
+        Ok(messages)
+    }
+
+    async fn pin_message(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Result<Message, Error> {
+        let membership = find_membership(&self.db, conversation_id, user_id).await?;
+        membership.require(Permission::Pin)?;
+        let message = find_message(&self.db, conversation_id, message_id).await?;
+        // The limit is checked by the statement that pins, so pins racing
+        // each other can't both take the last place
+        let row = self
+            .db
+            .query_one(
+                "WITH pins AS (SELECT COUNT(*) AS count, \
+                     COALESCE(bool_or(message_id = $1::BIGINT), FALSE) AS pinned \
+                   FROM message_pins WHERE conversation_id = $2), \
+                 inserted AS (INSERT INTO message_pins (message_id, conversation_id, pinned_by) \
+                   SELECT $1::BIGINT, $2, $3 FROM pins \
+                   WHERE NOT pins.pinned AND pins.count < $4::TEXT::INT \
+                   ON CONFLICT DO NOTHING RETURNING message_id) \
+                 SELECT EXISTS (SELECT 1 FROM inserted)::TEXT, pins.pinned::TEXT, \
+                   pins.count::TEXT \
+                 FROM pins",
+                &[
+                    &message.id,
+                    &conversation_id.to_string(),
+                    &user_id.to_string(),
+                    &MAX_PINS.to_string(),
+                ],
+            )
+            .await?;
+        if row.get(0) != "true" {
+            // Nothing was inserted: the message is already pinned, or a
+            // concurrent pin of the same message got there first
+            let full = row.get(2).parse::<i64>().unwrap_or_default() >= MAX_PINS;
+            if row.get(1) != "true" && full {
+                return Err(Error::Conflict(format!(
+                    "A conversation can have at most {} pinned messages",
+                    MAX_PINS
+                )));
+            }
+            return Ok(message);
+        }
+        if membership.kind == ConversationKind::Room {
+            audit(
+                &self.db,
+                &self.logger,
+                conversation_id,
+                user_id,
+                Some(&message.author_id),
+                ACTION_PIN,
+                serde_json::json!({ "message_id": message.id }),
+            )
+            .await?;
+        }
+        self.emit(MESSAGE_PINNED, &message).await;
+        Ok(message)
+    }
+
+    async fn unpin_message(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Result<Message, Error> {
+        let membership = find_membership(&self.db, conversation_id, user_id).await?;
+        membership.require(Permission::Pin)?;
+        let message = find_message(&self.db, conversation_id, message_id).await?;
+        let unpinned = self
+            .db
+            .execute(
+                "DELETE FROM message_pins WHERE message_id = $1::BIGINT",
+                &[&message.id],
+            )
+            .await?;
+        if unpinned == 0 {
+            return Err(Error::NotFound("Message is not pinned".to_string()));
+        }
+        if membership.kind == ConversationKind::Room {
+            audit(
+                &self.db,
+                &self.logger,
+                conversation_id,
+                user_id,
+                Some(&message.author_id),
+                ACTION_UNPIN,
+                serde_json::json!({ "message_id": message.id }),
+            )
+            .await?;
+        }
+        self.emit(MESSAGE_UNPINNED, &message).await;
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::db::MockDatabase;
+    use events::publisher::MockPublisher;
+    use logger::log::Log;
+    use presence::store::MockPresenceStore;
+    use unread::counter::{Count, MockUnreadCounter};
+
+    use super::*;
+    use crate::services::fixtures::{membership_row, message_row};
+
+    fn service(
+        db: MockDatabase<PgRow>,
+        publisher: MockPublisher,
+        counter: MockUnreadCounter,
+    ) -> MessageServiceImpl<
+        MockDatabase<PgRow>,
+        Log,
+        MockPublisher,
+        MockUnreadCounter,
+        MockPresenceStore,
+    > {
+        MessageServiceImpl::new(
+            db,
+            Log,
+            publisher,
+            counter,
+            MockPresenceStore::new(),
+            CursorCodec::new(b"secret"),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_blocked_direct_conversation_rejects_messages() {
+        let mut db = MockDatabase::new();
+        db.expect_query_one().times(1).returning(|_, _| {
+            let row = PgRow::from(vec![
+                "direct".to_string(),
+                "true".to_string(),
+                "member".to_string(),
+                "".to_string(),
+            ]);
+            Box::pin(async move { Ok(row) })
+        });
+        let data = SendMessage {
+            body: "hello".to_string(),
+            attachment_ids: vec![],
+        };
+
+        let result = service(db, MockPublisher::new(), MockUnreadCounter::new())
+            .send_message("alice", "dm", &data)
+            .await;
+        assert!(matches!(result, Err(Error::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_members_cannot_send() {
+        let mut db = MockDatabase::new();
+        db.expect_query_one().times(1).returning(|_, _| {
+            let row = PgRow::from(vec![
+                "room".to_string(),
+                "false".to_string(),
+                "member".to_string(),
+                "2026-01-01T00:10:00+00:00".to_string(),
+            ]);
+            Box::pin(async move { Ok(row) })
+        });
+        let data = SendMessage {
+            body: "hello".to_string(),
+            attachment_ids: vec![],
+        };
+
+        match service(db, MockPublisher::new(), MockUnreadCounter::new())
+            .send_message("alice", "room", &data)
+            .await
+        {
+            Err(Error::Forbidden(message)) => assert!(message.contains("timed out")),
+            other => panic!("expected forbidden, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sending_counts_as_unread_for_the_others() {
+        let mut db = MockDatabase::new();
+        db.expect_query_one()
+            .withf(|sql, _| sql.contains("conversation_members me"))
+            .returning(|_, _| {
+                let row = membership_row("group", "member");
+                Box::pin(async move { Ok(row) })
+            });
+        db.expect_query_one()
+            .withf(|sql, _| sql.starts_with("WITH m AS (INSERT"))
+            .returning(|_, _| {
+                let row = message_row("1", "alice", "");
+                Box::pin(async move { Ok(row) })
+            });
+        db.expect_query().returning(|_, _| {
+            let rows = ["alice", "bob", "carol"]
+                .map(|id| PgRow::from(vec![id.to_string()]))
+                .into();
+            Box::pin(async move { Ok(rows) })
+        });
+        let mut publisher = MockPublisher::new();
+        publisher.expect_publish().returning(|_, _| Ok(()));
+        let mut counter = MockUnreadCounter::new();
+        counter
+            .expect_increment()
+            .withf(|_, user_ids| user_ids == ["bob".to_string(), "carol".to_string()])
+            .times(1)
+            .returning(|_, _| Ok(()));
+        counter.expect_counts().returning(|_, _| {
+            Ok(vec![Count {
+                value: Some(2),
+                version: 3,
+            }])
+        });
+        counter
+            .expect_set()
+            .withf(|user_id, _, count, version| user_id == "alice" && *count == 0 && *version == 3)
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+        let data = SendMessage {
+            body: "hello".to_string(),
+            attachment_ids: vec![],
+        };
+
+        service(db, publisher, counter)
+            .send_message("alice", "group", &data)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_attachments_must_be_the_authors_unsent_uploads() {
+        let mut db = MockDatabase::new();
+        db.expect_query_one()
+            .withf(|sql, _| sql.contains("conversation_members me"))
+            .returning(|_, _| {
+                let row = membership_row("group", "member");
+                Box::pin(async move { Ok(row) })
+            });
+        db.expect_query_one()
+            .withf(|sql, params| sql.contains("FROM attachments") && *params[0] == "a1,a2")
+            .returning(|_, _| {
+                let row = PgRow::from(vec!["1".to_string()]);
+                Box::pin(async move { Ok(row) })
+            });
+        let data = SendMessage {
+            body: "".to_string(),
+            attachment_ids: vec!["a2".to_string(), "a1".to_string(), "a2".to_string()],
+        };
+        let mut counter = MockUnreadCounter::new();
+        counter
+            .expect_counts()
+            .returning(|_, _| Ok(vec![Count::default()]));
+
+        let result = service(db, MockPublisher::new(), counter)
+            .send_message("alice", "group", &data)
+            .await;
+        assert!(matches!(result, Err(Error::BadRequest(_))));
+    }
+
+    fn pin_service(pins: [&str; 3]) -> impl MessageService {
+        let pins = pins.map(String::from).to_vec();
+        let mut db = MockDatabase::new();
+        db.expect_query_one()
+            .withf(|sql, _| sql.contains("conversation_members me"))
+            .returning(|_, _| {
+                let row = membership_row("group", "member");
+                Box::pin(async move { Ok(row) })
+            });
+        db.expect_query_one()
+            .withf(|sql, _| sql.starts_with("SELECT") && sql.contains("FROM messages m WHERE"))
+            .returning(|_, _| {
+                let row = message_row("1", "bob", "");
+                Box::pin(async move { Ok(row) })
+            });
+        db.expect_query_one()
+            .withf(|sql, _| sql.starts_with("WITH pins AS"))
+            .times(1)
+            .returning(move |_, _| {
+                let row = PgRow::from(pins.clone());
+                Box::pin(async move { Ok(row) })
+            });
+        let mut publisher = MockPublisher::new();
+        publisher.expect_publish().never();
+        service(db, publisher, MockUnreadCounter::new())
+    }
+
+    #[tokio::test]
+    async fn test_pinning_again_changes_nothing() {
+        let message = pin_service(["false", "true", "3"])
+            .pin_message("alice", "group", "1")
+            .await
+            .unwrap();
+        assert_eq!(message.id(), "1");
+    }
+
+    #[tokio::test]
+    async fn test_pins_are_limited() {
+        let result = pin_service(["false", "false", &MAX_PINS.to_string()])
+            .pin_message("alice", "group", "1")
+            .await;
+        assert!(matches!(result, Err(Error::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn test_only_author_or_moderator_deletes() {
+        // Membership, then the message of bob
+        let mut db = MockDatabase::new();
+        db.expect_query_one()
+            .withf(|sql, _| sql.contains("conversation_members me"))
+            .returning(|_, _| {
+                let row = membership_row("room", "member");
+                Box::pin(async move { Ok(row) })
+            });
+        db.expect_query_one()
+            .withf(|sql, _| sql.starts_with("SELECT") && sql.contains("FROM messages m WHERE"))
+            .returning(|_, _| {
+                let row = message_row("1", "bob", "");
+                Box::pin(async move { Ok(row) })
+            });
+        db.expect_query_one()
+            .withf(|sql, _| {
+                sql.contains("SET deleted_at")
+                    && sql.contains("last_reply_author_id = latest.author_id")
+            })
+            .times(1)
+            .returning(|_, _| {
+                let row = message_row("1", "bob", "");
+                Box::pin(async move { Ok(row) })
+            });
+        db.expect_execute()
+            .withf(|sql, params| sql.contains("room_audit_log") && *params[1] == "mod")
+            .times(1)
+            .returning(|_, _| Box::pin(async { Ok(1) }));
+        db.expect_query()
+            .returning(|_, _| Box::pin(async { Ok(vec![]) }));
+        let mut publisher = MockPublisher::new();
+        publisher
+            .expect_publish()
+            .withf(|_, event| event.kind == MESSAGE_DELETED)
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let mut counter = MockUnreadCounter::new();
+        counter.expect_forget().times(1).returning(|_, _| Ok(()));
+        let service = service(db, publisher, counter);
+
+        let result = service.delete_message("alice", false, "room", "1").await;
+        assert!(matches!(result, Err(Error::Forbidden(_))));
+        service
+            .delete_message("mod", true, "room", "1")
+            .await
+            .unwrap();
+    }
+}
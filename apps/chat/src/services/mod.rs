@@ -0,0 +1,12 @@
+pub mod attachment_service;
+pub mod conversation_service;
+#[cfg(test)]
+pub(crate) mod fixtures;
+pub mod message_service;
+pub mod moderation_service;
+pub mod notification_service;
+pub mod reaction_service;
+pub mod search_service;
+pub mod session_service;
+pub mod thread_service;
+pub mod typing_service;
@@ -0,0 +1,948 @@
+use async_trait::async_trait;
+use database::{db::Database, pgx::PgRow};
+use errors::error::Error;
+use events::publisher::Publisher;
+use logger::logger::Logger;
+use pagination::{
+    cursor::CursorCodec,
+    page::{Page, PageRequest},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use unread::counter::UnreadCounter;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::services::conversation_service::{
+    find_membership, members, publish_to, ConversationKind, Membership, TIMED_OUT_UNTIL,
+};
+
+pub const MEMBER_UPDATED: &str = "member.updated";
+pub const MEMBER_REMOVED: &str = "member.removed";
+
+pub const ACTION_UPDATE_ROOM: &str = "update_room";
+pub const ACTION_SET_ROLE: &str = "set_role";
+pub const ACTION_KICK: &str = "kick";
+pub const ACTION_BAN: &str = "ban";
+pub const ACTION_UNBAN: &str = "unban";
+pub const ACTION_TIMEOUT: &str = "timeout";
+pub const ACTION_LIFT_TIMEOUT: &str = "lift_timeout";
+pub const ACTION_DELETE_MESSAGE: &str = "delete_message";
+pub const ACTION_PIN: &str = "pin";
+pub const ACTION_UNPIN: &str = "unpin";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// Post messages, replies and reactions, and show typing
+    Send,
+    /// Delete messages of others
+    DeleteMessages,
+    /// Pin and unpin messages
+    Pin,
+    /// Remove members, who may join again
+    Kick,
+    /// Remove members and keep them out
+    Ban,
+    /// Time members out, keeping them from sending for a while
+    Mute,
+    /// Give members roles below the caller's own
+    ManageRoles,
+    /// Rename the room
+    EditSettings,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 8] = [
+        Permission::Send,
+        Permission::DeleteMessages,
+        Permission::Pin,
+        Permission::Kick,
+        Permission::Ban,
+        Permission::Mute,
+        Permission::ManageRoles,
+        Permission::EditSettings,
+    ];
+
+    pub fn bit(self) -> u32 {
+        1 << self as u32
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Permission::Send => "send",
+            Permission::DeleteMessages => "delete_messages",
+            Permission::Pin => "pin",
+            Permission::Kick => "kick",
+            Permission::Ban => "ban",
+            Permission::Mute => "mute",
+            Permission::ManageRoles => "manage_roles",
+            Permission::EditSettings => "edit_settings",
+        }
+    }
+
+    /// Permissions whose bits are set, in declaration order.
+    pub fn list(bits: u32) -> Vec<Permission> {
+        Permission::ALL
+            .into_iter()
+            .filter(|permission| bits & permission.bit() != 0)
+            .collect()
+    }
+}
+
+/// Role of a member in a room, later variants outrank earlier ones. A room
+/// has a single owner, who can only hand it over.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RoomRole {
+    Member,
+    Moderator,
+    Admin,
+    Owner,
+}
+
+impl RoomRole {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "owner" => RoomRole::Owner,
+            "admin" => RoomRole::Admin,
+            "moderator" => RoomRole::Moderator,
+            _ => RoomRole::Member,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RoomRole::Owner => "owner",
+            RoomRole::Admin => "admin",
+            RoomRole::Moderator => "moderator",
+            RoomRole::Member => "member",
+        }
+    }
+}
+
+/// Permission bits of a member. Roles only apply to rooms, everyone in a
+/// direct or group conversation may send and pin.
+pub fn permission_bits(kind: ConversationKind, role: RoomRole) -> u32 {
+    let bits = |permissions: &[Permission]| {
+        permissions
+            .iter()
+            .fold(0, |bits, permission| bits | permission.bit())
+    };
+    if kind != ConversationKind::Room {
+        return bits(&[Permission::Send, Permission::Pin]);
+    }
+    match role {
+        RoomRole::Owner | RoomRole::Admin => bits(&Permission::ALL),
+        RoomRole::Moderator => bits(&[
+            Permission::Send,
+            Permission::DeleteMessages,
+            Permission::Pin,
+            Permission::Kick,
+            Permission::Ban,
+            Permission::Mute,
+        ]),
+        RoomRole::Member => bits(&[Permission::Send]),
+    }
+}
+
+/// Records what a member did in a room. The change is already made, a
+/// failure to record it is still returned so it doesn't go unnoticed.
+pub(crate) async fn audit<D: Database<PgRow>, L: Logger>(
+    db: &D,
+    logger: &L,
+    room_id: &str,
+    actor_id: &str,
+    target_id: Option<&str>,
+    action: &str,
+    details: serde_json::Value,
+) -> Result<(), Error> {
+    let message = format!(
+        "user {} performs {} on {} in room {}",
+        actor_id,
+        action,
+        target_id.unwrap_or("the room"),
+        room_id
+    );
+    logger.info("moderation_service::audit", &message);
+    db.execute(
+        "INSERT INTO room_audit_log (conversation_id, actor_id, target_id, action, details) \
+         VALUES ($1, $2, NULLIF($3, ''), $4, $5::JSONB)",
+        &[
+            &room_id.to_string(),
+            &actor_id.to_string(),
+            &target_id.unwrap_or_default().to_string(),
+            &action.to_string(),
+            &details.to_string(),
+        ],
+    )
+    .await
+    .inspect_err(|e| {
+        let message = format!("failed to audit {} in room {}: {}", action, room_id, e);
+        logger.error("moderation_service::audit", &message);
+    })?;
+    Ok(())
+}
+
+/// A member of a room, also the data of [`MEMBER_UPDATED`] events sent to
+/// them.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct RoomMember {
+    conversation_id: String,
+    user_id: String,
+    role: RoomRole,
+    permissions: Vec<Permission>,
+    /// Can't send until then, whatever the permissions say
+    timed_out_until: Option<String>,
+}
+
+/// Data of [`MEMBER_REMOVED`] events, sent to the room and to the member
+/// who was removed.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct MemberRemoved {
+    conversation_id: String,
+    user_id: String,
+    /// Kicked members may join again, banned ones can't
+    banned: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct RoomBan {
+    user_id: String,
+    reason: String,
+    banned_by: String,
+    created_at: String,
+    /// Missing for bans that last until lifted
+    expires_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct RoomAuditEntry {
+    id: String,
+    actor_id: String,
+    /// Missing for changes to the room itself
+    target_id: Option<String>,
+    action: String,
+    #[schema(value_type = Object)]
+    details: serde_json::Value,
+    created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
+pub struct SetRole {
+    /// Giving `owner` hands the room over, the previous owner becomes an
+    /// admin
+    role: RoomRole,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
+pub struct KickMember {
+    #[validate(
+        length(min = 1, max = 500),
+        custom(function = "validation::rules::not_blank")
+    )]
+    reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
+pub struct BanMember {
+    #[validate(
+        length(min = 1, max = 500),
+        custom(function = "validation::rules::not_blank")
+    )]
+    reason: String,
+    /// From a minute to a year, bans until lifted when missing
+    #[validate(range(min = 60, max = 31536000))]
+    duration_seconds: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
+pub struct TimeoutMember {
+    #[validate(
+        length(min = 1, max = 500),
+        custom(function = "validation::rules::not_blank")
+    )]
+    reason: String,
+    /// From a minute to 28 days
+    #[validate(range(min = 60, max = 2419200))]
+    duration_seconds: u32,
+}
+
+#[derive(Deserialize, Debug, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryModeration {
+    #[validate(range(min = 1, max = 100))]
+    limit: Option<u32>,
+    /// `next_cursor` or `prev_cursor` of the previous page
+    #[validate(length(max = 1024))]
+    cursor: Option<String>,
+}
+
+#[async_trait]
+pub trait ModerationService {
+    /// Changes the role of a member below the caller's own to another role
+    /// below it. Only the owner can give `owner`, handing the room over.
+    async fn set_role(
+        &self,
+        user_id: &str,
+        room_id: &str,
+        member_id: &str,
+        data: &SetRole,
+    ) -> Result<RoomMember, Error>;
+    /// Removes a member below the caller's role, they may join again.
+    async fn kick(
+        &self,
+        user_id: &str,
+        room_id: &str,
+        member_id: &str,
+        data: &KickMember,
+    ) -> Result<(), Error>;
+    /// Keeps a user out of the room, removing them if they are in it.
+    /// Banning someone again replaces their ban.
+    async fn ban(
+        &self,
+        user_id: &str,
+        room_id: &str,
+        member_id: &str,
+        data: &BanMember,
+    ) -> Result<RoomBan, Error>;
+    async fn unban(&self, user_id: &str, room_id: &str, member_id: &str) -> Result<(), Error>;
+    /// Bans in effect, most recent first.
+    async fn get_bans(
+        &self,
+        user_id: &str,
+        room_id: &str,
+        query: &QueryModeration,
+    ) -> Result<Page<RoomBan>, Error>;
+    /// Keeps a member below the caller's role from sending for a while.
+    /// Timing someone out again replaces their timeout.
+    async fn timeout(
+        &self,
+        user_id: &str,
+        room_id: &str,
+        member_id: &str,
+        data: &TimeoutMember,
+    ) -> Result<RoomMember, Error>;
+    async fn lift_timeout(
+        &self,
+        user_id: &str,
+        room_id: &str,
+        member_id: &str,
+    ) -> Result<RoomMember, Error>;
+    /// What owners, admins and moderators did in the room, newest first.
+    /// Only readable by members who can kick, ban or mute.
+    async fn get_audit(
+        &self,
+        user_id: &str,
+        room_id: &str,
+        query: &QueryModeration,
+    ) -> Result<Page<RoomAuditEntry>, Error>;
+}
+
+pub struct ModerationServiceImpl<D: Database<PgRow>, L: Logger, P: Publisher, U: UnreadCounter> {
+    db: D,
+    logger: L,
+    publisher: P,
+    counter: U,
+    cursors: CursorCodec,
+}
+
+impl<D: Database<PgRow>, L: Logger, P: Publisher, U: UnreadCounter>
+    ModerationServiceImpl<D, L, P, U>
+{
+    pub fn new(db: D, logger: L, publisher: P, counter: U, cursors: CursorCodec) -> Self {
+        Self {
+            db,
+            logger,
+            publisher,
+            counter,
+            cursors,
+        }
+    }
+
+    /// The caller's membership in a room, when it grants the permission.
+    async fn authorize(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        permission: Permission,
+    ) -> Result<Membership, Error> {
+        let membership = self.room(room_id, user_id).await?;
+        membership.require(permission)?;
+        Ok(membership)
+    }
+
+    /// The caller's membership, other conversations are no rooms.
+    async fn room(&self, room_id: &str, user_id: &str) -> Result<Membership, Error> {
+        let room_not_found = || Error::NotFound("Room not found".to_string());
+        let membership =
+            find_membership(&self.db, room_id, user_id)
+                .await
+                .map_err(|e| match e {
+                    Error::NotFound(_) => room_not_found(),
+                    e => e,
+                })?;
+        if membership.kind != ConversationKind::Room {
+            return Err(room_not_found());
+        }
+        Ok(membership)
+    }
+
+    /// Finds a member the caller may act on, someone else with a lower
+    /// role. `None` when the user isn't in the room.
+    async fn target(
+        &self,
+        room_id: &str,
+        actor: &Membership,
+        user_id: &str,
+        member_id: &str,
+    ) -> Result<Option<RoomMember>, Error> {
+        if user_id == member_id {
+            return Err(Error::BadRequest("Cannot moderate yourself".to_string()));
+        }
+        let member = self.member(room_id, member_id).await?;
+        if member
+            .as_ref()
+            .is_some_and(|member| member.role >= actor.role)
+        {
+            return Err(Error::Forbidden(
+                "Only members with a lower role than yours can be moderated".to_string(),
+            ));
+        }
+        Ok(member)
+    }
+
+    async fn member(&self, room_id: &str, member_id: &str) -> Result<Option<RoomMember>, Error> {
+        let sql = format!(
+            "SELECT me.role, {} FROM conversation_members me \
+             WHERE me.conversation_id = $1 AND me.user_id = $2",
+            TIMED_OUT_UNTIL
+        );
+        let rows = self
+            .db
+            .query(&sql, &[&room_id.to_string(), &member_id.to_string()])
+            .await?;
+        Ok(rows.first().map(|row| {
+            let role = RoomRole::parse(&row.get(0));
+            let timed_out_until = row.get(1);
+            RoomMember {
+                conversation_id: room_id.to_string(),
+                user_id: member_id.to_string(),
+                role,
+                permissions: Permission::list(permission_bits(ConversationKind::Room, role)),
+                timed_out_until: (!timed_out_until.is_empty()).then_some(timed_out_until),
+            }
+        }))
+    }
+
+    async fn updated_member(&self, room_id: &str, member_id: &str) -> Result<RoomMember, Error> {
+        let member = self
+            .member(room_id, member_id)
+            .await?
+            .ok_or_else(|| Error::NotFound("Member not found".to_string()))?;
+        // The change is made, the member only misses hearing about it live
+        if let Err(e) = publish_to(
+            &self.publisher,
+            vec![member_id.to_string()],
+            MEMBER_UPDATED,
+            &member,
+        )
+        .await
+        {
+            let message = format!("failed to publish {}: {}", MEMBER_UPDATED, e);
+            self.logger
+                .error("moderation_service::updated_member", &message);
+        }
+        Ok(member)
+    }
+
+    /// Takes a member out of the room and tells everyone, them included.
+    /// `false` when they weren't in it.
+    async fn remove(&self, room_id: &str, member_id: &str, banned: bool) -> Result<bool, Error> {
+        let removed = self
+            .db
+            .execute(
+                "DELETE FROM conversation_members WHERE conversation_id = $1 AND user_id = $2",
+                &[&room_id.to_string(), &member_id.to_string()],
+            )
+            .await?;
+        if removed == 0 {
+            return Ok(false);
+        }
+        // A stale count would come back if they join again
+        if let Err(e) = self.counter.forget(room_id, &[member_id.to_string()]).await {
+            let message = format!("failed to forget unread count of user {}: {}", member_id, e);
+            self.logger.error("moderation_service::remove", &message);
+        }
+        let event = MemberRemoved {
+            conversation_id: room_id.to_string(),
+            user_id: member_id.to_string(),
+            banned,
+        };
+        let result = match members(&self.db, room_id).await {
+            Ok(mut recipients) => {
+                recipients.push(member_id.to_string());
+                publish_to(&self.publisher, recipients, MEMBER_REMOVED, &event).await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            let message = format!("failed to publish {}: {}", MEMBER_REMOVED, e);
+            self.logger.error("moderation_service::remove", &message);
+        }
+        Ok(true)
+    }
+}
+
+fn ban_from_row(row: &PgRow) -> RoomBan {
+    let expires_at = row.get(4);
+    RoomBan {
+        user_id: row.get(0),
+        reason: row.get(1),
+        banned_by: row.get(2),
+        created_at: row.get(3),
+        expires_at: (!expires_at.is_empty()).then_some(expires_at),
+    }
+}
+
+const BAN_COLUMNS: &str = "user_id, reason, banned_by, to_json(created_at) #>> '{}', \
+    COALESCE(to_json(expires_at) #>> '{}', '')";
+
+#[async_trait]
+impl<
+        D: Database<PgRow> + Send + Sync,
+        L: Logger + Send + Sync,
+        P: Publisher + Send + Sync,
+        U: UnreadCounter + Send + Sync,
+    > ModerationService for ModerationServiceImpl<D, L, P, U>
+{
+    async fn set_role(
+        &self,
+        user_id: &str,
+        room_id: &str,
+        member_id: &str,
+        data: &SetRole,
+    ) -> Result<RoomMember, Error> {
+        let actor = self
+            .authorize(room_id, user_id, Permission::ManageRoles)
+            .await?;
+        let member = self
+            .target(room_id, &actor, user_id, member_id)
+            .await?
+            .ok_or_else(|| Error::NotFound("Member not found".to_string()))?;
+        let params = [
+            &room_id.to_string(),
+            &member_id.to_string(),
+            &data.role.as_str().to_string(),
+            &user_id.to_string(),
+        ];
+        if data.role == RoomRole::Owner {
+            if actor.role != RoomRole::Owner {
+                return Err(Error::Forbidden(
+                    "Only the owner can hand the room over".to_string(),
+                ));
+            }
+            self.db
+                .execute(
+                    "UPDATE conversation_members \
+                     SET role = CASE WHEN user_id = $2 THEN $3 ELSE 'admin' END \
+                     WHERE conversation_id = $1 AND user_id IN ($2, $4)",
+                    &params,
+                )
+                .await?;
+        } else {
+            if data.role >= actor.role {
+                return Err(Error::Forbidden(
+                    "Only roles lower than yours can be given".to_string(),
+                ));
+            }
+            self.db
+                .execute(
+                    "UPDATE conversation_members SET role = $3 \
+                     WHERE conversation_id = $1 AND user_id = $2",
+                    &params[..3],
+                )
+                .await?;
+        }
+        audit(
+            &self.db,
+            &self.logger,
+            room_id,
+            user_id,
+            Some(member_id),
+            ACTION_SET_ROLE,
+            json!({ "from": member.role, "to": data.role }),
+        )
+        .await?;
+        self.updated_member(room_id, member_id).await
+    }
+
+    async fn kick(
+        &self,
+        user_id: &str,
+        room_id: &str,
+        member_id: &str,
+        data: &KickMember,
+    ) -> Result<(), Error> {
+        let actor = self.authorize(room_id, user_id, Permission::Kick).await?;
+        let member_not_found = || Error::NotFound("Member not found".to_string());
+        self.target(room_id, &actor, user_id, member_id)
+            .await?
+            .ok_or_else(member_not_found)?;
+        if !self.remove(room_id, member_id, false).await? {
+            return Err(member_not_found());
+        }
+        audit(
+            &self.db,
+            &self.logger,
+            room_id,
+            user_id,
+            Some(member_id),
+            ACTION_KICK,
+            json!({ "reason": data.reason }),
+        )
+        .await
+    }
+
+    async fn ban(
+        &self,
+        user_id: &str,
+        room_id: &str,
+        member_id: &str,
+        data: &BanMember,
+    ) -> Result<RoomBan, Error> {
+        let actor = self.authorize(room_id, user_id, Permission::Ban).await?;
+        self.target(room_id, &actor, user_id, member_id).await?;
+        let duration = data
+            .duration_seconds
+            .map(|seconds| seconds.to_string())
+            .unwrap_or_default();
+        let sql = format!(
+            "INSERT INTO room_bans (conversation_id, user_id, reason, banned_by, expires_at) \
+             SELECT $1, u.id, $3, $4, NOW() + make_interval(secs => NULLIF($5, '')::INT) \
+             FROM users u WHERE u.id = $2 \
+             ON CONFLICT (conversation_id, user_id) DO UPDATE SET reason = EXCLUDED.reason, \
+               banned_by = EXCLUDED.banned_by, created_at = NOW(), expires_at = EXCLUDED.expires_at \
+             RETURNING {}",
+            BAN_COLUMNS
+        );
+        let row = self
+            .db
+            .query_one(
+                &sql,
+                &[
+                    &room_id.to_string(),
+                    &member_id.to_string(),
+                    &data.reason.trim().to_string(),
+                    &user_id.to_string(),
+                    &duration,
+                ],
+            )
+            .await
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound("User not found".to_string()),
+                e => e,
+            })?;
+        let ban = ban_from_row(&row);
+        let removed = self.remove(room_id, member_id, true).await?;
+        audit(
+            &self.db,
+            &self.logger,
+            room_id,
+            user_id,
+            Some(member_id),
+            ACTION_BAN,
+            json!({
+                "reason": ban.reason,
+                "expires_at": ban.expires_at,
+                "removed": removed,
+            }),
+        )
+        .await?;
+        Ok(ban)
+    }
+
+    async fn unban(&self, user_id: &str, room_id: &str, member_id: &str) -> Result<(), Error> {
+        self.authorize(room_id, user_id, Permission::Ban).await?;
+        let lifted = self
+            .db
+            .execute(
+                "DELETE FROM room_bans WHERE conversation_id = $1 AND user_id = $2 \
+                   AND (expires_at IS NULL OR expires_at > NOW())",
+                &[&room_id.to_string(), &member_id.to_string()],
+            )
+            .await?;
+        if lifted == 0 {
+            return Err(Error::NotFound("User is not banned".to_string()));
+        }
+        audit(
+            &self.db,
+            &self.logger,
+            room_id,
+            user_id,
+            Some(member_id),
+            ACTION_UNBAN,
+            json!({}),
+        )
+        .await
+    }
+
+    async fn get_bans(
+        &self,
+        user_id: &str,
+        room_id: &str,
+        query: &QueryModeration,
+    ) -> Result<Page<RoomBan>, Error> {
+        self.authorize(room_id, user_id, Permission::Ban).await?;
+        let request = PageRequest::new(
+            &self.cursors,
+            &format!("bans:{}", room_id),
+            query.limit.unwrap_or(20),
+            query.cursor.as_deref(),
+        )?
+        .descending();
+        let key = request.key();
+        let (at, id) = (
+            key.first().cloned().unwrap_or_default(),
+            key.get(1).cloned().unwrap_or_default(),
+        );
+        let sql = format!(
+            "SELECT {} FROM room_bans \
+             WHERE conversation_id = $1 AND (expires_at IS NULL OR expires_at > NOW()) \
+               AND ($2 = '' OR (created_at, user_id) {} (NULLIF($2, '')::TIMESTAMPTZ, $3)) \
+             ORDER BY created_at {}, user_id {} LIMIT $4::TEXT::INT",
+            BAN_COLUMNS,
+            request.comparator(),
+            request.order(),
+            request.order()
+        );
+        let rows = self
+            .db
+            .query(
+                &sql,
+                &[&room_id.to_string(), &at, &id, &request.fetch_limit()],
+            )
+            .await?;
+        let bans = rows.iter().map(ban_from_row).collect();
+        Ok(request.page(&self.cursors, bans, |ban: &RoomBan| {
+            vec![ban.created_at.clone(), ban.user_id.clone()]
+        }))
+    }
+
+    async fn timeout(
+        &self,
+        user_id: &str,
+        room_id: &str,
+        member_id: &str,
+        data: &TimeoutMember,
+    ) -> Result<RoomMember, Error> {
+        let actor = self.authorize(room_id, user_id, Permission::Mute).await?;
+        self.target(room_id, &actor, user_id, member_id)
+            .await?
+            .ok_or_else(|| Error::NotFound("Member not found".to_string()))?;
+        let row = self
+            .db
+            .query_one(
+                "INSERT INTO room_timeouts (conversation_id, user_id, reason, timed_out_by, expires_at) \
+                 VALUES ($1, $2, $3, $4, NOW() + make_interval(secs => $5::TEXT::INT)) \
+                 ON CONFLICT (conversation_id, user_id) DO UPDATE SET reason = EXCLUDED.reason, \
+                   timed_out_by = EXCLUDED.timed_out_by, created_at = NOW(), \
+                   expires_at = EXCLUDED.expires_at \
+                 RETURNING to_json(expires_at) #>> '{}'",
+                &[
+                    &room_id.to_string(),
+                    &member_id.to_string(),
+                    &data.reason.trim().to_string(),
+                    &user_id.to_string(),
+                    &data.duration_seconds.to_string(),
+                ],
+            )
+            .await?;
+        audit(
+            &self.db,
+            &self.logger,
+            room_id,
+            user_id,
+            Some(member_id),
+            ACTION_TIMEOUT,
+            json!({ "reason": data.reason.trim(), "expires_at": row.get(0) }),
+        )
+        .await?;
+        self.updated_member(room_id, member_id).await
+    }
+
+    async fn lift_timeout(
+        &self,
+        user_id: &str,
+        room_id: &str,
+        member_id: &str,
+    ) -> Result<RoomMember, Error> {
+        let actor = self.authorize(room_id, user_id, Permission::Mute).await?;
+        self.target(room_id, &actor, user_id, member_id)
+            .await?
+            .ok_or_else(|| Error::NotFound("Member not found".to_string()))?;
+        let lifted = self
+            .db
+            .execute(
+                "DELETE FROM room_timeouts WHERE conversation_id = $1 AND user_id = $2 \
+                   AND expires_at > NOW()",
+                &[&room_id.to_string(), &member_id.to_string()],
+            )
+            .await?;
+        if lifted == 0 {
+            return Err(Error::NotFound("Member is not timed out".to_string()));
+        }
+        audit(
+            &self.db,
+            &self.logger,
+            room_id,
+            user_id,
+            Some(member_id),
+            ACTION_LIFT_TIMEOUT,
+            json!({}),
+        )
+        .await?;
+        self.updated_member(room_id, member_id).await
+    }
+
+    async fn get_audit(
+        &self,
+        user_id: &str,
+        room_id: &str,
+        query: &QueryModeration,
+    ) -> Result<Page<RoomAuditEntry>, Error> {
+        let membership = self.room(room_id, user_id).await?;
+        let moderates = [Permission::Kick, Permission::Ban, Permission::Mute]
+            .into_iter()
+            .any(|permission| membership.can(permission));
+        if !moderates {
+            return Err(Error::Forbidden(
+                "Only moderators can read the audit log".to_string(),
+            ));
+        }
+        let request = PageRequest::new(
+            &self.cursors,
+            &format!("room_audit:{}", room_id),
+            query.limit.unwrap_or(20),
+            query.cursor.as_deref(),
+        )?
+        .descending();
+        let after = request.key().first().cloned().unwrap_or_default();
+        let sql = format!(
+            "SELECT id::TEXT, actor_id, COALESCE(target_id, ''), action, details::TEXT, \
+               to_json(created_at) #>> '{{}}' \
+             FROM room_audit_log WHERE conversation_id = $1 \
+             AND ($2 = '' OR id {} NULLIF($2, '')::BIGINT) \
+             ORDER BY id {} LIMIT $3::TEXT::INT",
+            request.comparator(),
+            request.order()
+        );
+        let rows = self
+            .db
+            .query(
+                &sql,
+                &[&room_id.to_string(), &after, &request.fetch_limit()],
+            )
+            .await?;
+        let entries = rows
+            .iter()
+            .map(|row| {
+                let target_id = row.get(2);
+                RoomAuditEntry {
+                    id: row.get(0),
+                    actor_id: row.get(1),
+                    target_id: (!target_id.is_empty()).then_some(target_id),
+                    action: row.get(3),
+                    details: serde_json::from_str(&row.get(4)).unwrap_or_default(),
+                    created_at: row.get(5),
+                }
+            })
+            .collect();
+        Ok(
+            request.page(&self.cursors, entries, |entry: &RoomAuditEntry| {
+                vec![entry.id.clone()]
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::db::MockDatabase;
+    use events::publisher::MockPublisher;
+    use logger::log::Log;
+    use unread::counter::MockUnreadCounter;
+
+    use super::*;
+    use crate::services::fixtures::membership_row;
+
+    fn service(
+        db: MockDatabase<PgRow>,
+    ) -> ModerationServiceImpl<MockDatabase<PgRow>, Log, MockPublisher, MockUnreadCounter> {
+        ModerationServiceImpl::new(
+            db,
+            Log,
+            MockPublisher::new(),
+            MockUnreadCounter::new(),
+            CursorCodec::new(b"secret"),
+        )
+    }
+
+    #[test]
+    fn test_permissions_of_roles() {
+        let room = |role| Permission::list(permission_bits(ConversationKind::Room, role));
+        assert_eq!(room(RoomRole::Member), [Permission::Send]);
+        assert!(!room(RoomRole::Moderator).contains(&Permission::ManageRoles));
+        assert!(room(RoomRole::Moderator).contains(&Permission::Ban));
+        assert_eq!(room(RoomRole::Admin), Permission::ALL);
+        assert_eq!(room(RoomRole::Owner), Permission::ALL);
+        assert_eq!(
+            Permission::list(permission_bits(ConversationKind::Group, RoomRole::Member)),
+            [Permission::Send, Permission::Pin]
+        );
+        assert!(RoomRole::Owner > RoomRole::Admin && RoomRole::Moderator > RoomRole::Member);
+    }
+
+    #[tokio::test]
+    async fn test_only_lower_roles_can_be_moderated() {
+        let mut db = MockDatabase::new();
+        db.expect_query_one()
+            .returning(|_, _| Box::pin(async { Ok(membership_row("room", "moderator")) }));
+        db.expect_query()
+            .withf(|sql, params| sql.contains("SELECT me.role") && *params[1] == "bob")
+            .returning(|_, _| {
+                let rows = vec![PgRow::from(vec!["admin".to_string(), "".to_string()])];
+                Box::pin(async move { Ok(rows) })
+            });
+        db.expect_execute().never();
+        let service = service(db);
+        let data = KickMember {
+            reason: "spam".to_string(),
+        };
+
+        let result = service.kick("alice", "room", "bob", &data).await;
+        assert!(matches!(result, Err(Error::Forbidden(_))));
+        let result = service.kick("alice", "room", "alice", &data).await;
+        assert!(matches!(result, Err(Error::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn test_members_cannot_ban_or_moderate_other_conversations() {
+        for (kind, role) in [("room", "member"), ("group", "owner")] {
+            let mut db = MockDatabase::new();
+            db.expect_query_one()
+                .returning(move |_, _| Box::pin(async move { Ok(membership_row(kind, role)) }));
+            db.expect_execute().never();
+            let data = BanMember {
+                reason: "spam".to_string(),
+                duration_seconds: None,
+            };
+
+            let result = service(db).ban("alice", "c1", "bob", &data).await;
+            match kind {
+                "room" => assert!(matches!(result, Err(Error::Forbidden(_)))),
+                _ => assert!(matches!(result, Err(Error::NotFound(_)))),
+            }
+        }
+    }
+}
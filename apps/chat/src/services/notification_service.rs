@@ -0,0 +1,393 @@
+use async_trait::async_trait;
+use database::{db::Database, pgx::PgRow};
+use errors::error::Error;
+use events::publisher::Publisher;
+use logger::logger::Logger;
+use pagination::{
+    cursor::CursorCodec,
+    page::{Page, PageRequest},
+};
+use presence::store::{PresenceStore, State};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::services::{
+    conversation_service::{members, publish_to},
+    message_service::Message,
+};
+
+pub const NOTIFICATION_CREATED: &str = "notification.created";
+
+/// Distinct usernames a message can mention, later ones are left as text.
+pub const MENTION_MAX_USERS: usize = 20;
+
+/// Columns of [`Notification`] from `notifications n`.
+pub(crate) const NOTIFICATION_COLUMNS: &str = "n.id::TEXT, n.kind, n.conversation_id, \
+    n.message_id::TEXT, COALESCE(n.thread_id::TEXT, ''), COALESCE(n.actor_id, ''), \
+    to_json(n.created_at) #>> '{}', COALESCE(to_json(n.read_at) #>> '{}', '')";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct Notification {
+    id: String,
+    /// What happened, `reply` for a reply in a subscribed thread or
+    /// `mention` for a message mentioning the user
+    kind: String,
+    conversation_id: String,
+    message_id: String,
+    /// Thread the message is in, if any
+    thread_id: Option<String>,
+    /// User whose message caused the notification
+    actor_id: Option<String>,
+    created_at: String,
+    read_at: Option<String>,
+}
+
+pub(crate) fn notification_from_row(row: &PgRow) -> Notification {
+    let optional = |value: String| (!value.is_empty()).then_some(value);
+    Notification {
+        id: row.get(0),
+        kind: row.get(1),
+        conversation_id: row.get(2),
+        message_id: row.get(3),
+        thread_id: optional(row.get(4)),
+        actor_id: optional(row.get(5)),
+        created_at: row.get(6),
+        read_at: optional(row.get(7)),
+    }
+}
+
+#[derive(Deserialize, Debug, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryNotifications {
+    /// Leave out notifications already read
+    unread_only: Option<bool>,
+    #[validate(range(min = 1, max = 100))]
+    limit: Option<u32>,
+    /// `next_cursor` or `prev_cursor` of the previous page
+    #[validate(length(max = 1024))]
+    cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct MarkedRead {
+    /// Notifications that were unread until now
+    count: u64,
+}
+
+/// Who a message body mentions: `@username`, `@room` for every member and
+/// `@here` for the members online. An `@` right after a letter, digit, `_`
+/// or `.` is part of something else, such as an email address.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Mentions {
+    usernames: Vec<String>,
+    room: bool,
+    here: bool,
+}
+
+impl Mentions {
+    pub(crate) fn parse(body: &str) -> Self {
+        let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+        let chars: Vec<char> = body.chars().collect();
+        let mut mentions = Self::default();
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i] != '@' || (i > 0 && is_name(chars[i - 1])) {
+                i += 1;
+                continue;
+            }
+            let start = i + 1;
+            let mut end = start;
+            while end < chars.len() && is_name(chars[end]) {
+                end += 1;
+            }
+            i = end;
+            // A trailing `.` ends the sentence rather than the username
+            let name = chars[start..end]
+                .iter()
+                .collect::<String>()
+                .trim_end_matches('.')
+                .to_ascii_lowercase();
+            match name.as_str() {
+                "room" => mentions.room = true,
+                "here" => mentions.here = true,
+                _ if validation::rules::username(&name).is_ok()
+                    && !mentions.usernames.contains(&name)
+                    && mentions.usernames.len() < MENTION_MAX_USERS =>
+                {
+                    mentions.usernames.push(name)
+                }
+                _ => {}
+            }
+        }
+        mentions
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.usernames.is_empty() && !self.room && !self.here
+    }
+}
+
+/// Records the members a new message mentions, other than its author, and
+/// notifies the ones who haven't muted the conversation or turned mention
+/// notifications off. Mentions in the conversation itself count towards
+/// the member's mention count, those in threads are only notified.
+pub(crate) async fn notify_mentions<D: Database<PgRow>, P: Publisher, S: PresenceStore>(
+    db: &D,
+    publisher: &P,
+    presence: &S,
+    message: &Message,
+) -> Result<(), Error> {
+    let mentions = Mentions::parse(message.body());
+    if mentions.is_empty() {
+        return Ok(());
+    }
+    let mut online = vec![];
+    if mentions.here {
+        let mut others = members(db, message.conversation_id()).await?;
+        others.retain(|id| id != message.author_id());
+        let states = presence.states(&others).await?;
+        online = others
+            .into_iter()
+            .zip(states)
+            .filter(|(_, state)| *state == Some(State::Online))
+            .map(|(id, _)| id)
+            .collect();
+    }
+    let sql = format!(
+        "WITH mentioned AS (SELECT cm.user_id, \
+               CASE WHEN u.username = ANY(string_to_array($3, ',')) THEN 'user' \
+                 WHEN cm.user_id = ANY(string_to_array($5, ',')) THEN 'here' ELSE 'room' END AS kind \
+             FROM conversation_members cm JOIN users u ON u.id = cm.user_id \
+             WHERE cm.conversation_id = $1 AND cm.user_id <> $6 \
+               AND (u.username = ANY(string_to_array($3, ',')) OR $4 = 'true' \
+                 OR cm.user_id = ANY(string_to_array($5, ',')))), \
+         recorded AS (INSERT INTO message_mentions (message_id, user_id, kind) \
+             SELECT $2::BIGINT, user_id, kind FROM mentioned \
+             ON CONFLICT DO NOTHING RETURNING user_id), \
+         counted AS (UPDATE conversation_members cm SET mention_count = cm.mention_count + 1 \
+             FROM recorded r WHERE cm.conversation_id = $1 AND cm.user_id = r.user_id AND $7 = ''), \
+         n AS (INSERT INTO notifications \
+               (user_id, kind, conversation_id, message_id, thread_id, actor_id) \
+             SELECT r.user_id, 'mention', $1, $2::BIGINT, NULLIF($7, '')::BIGINT, $6 \
+             FROM recorded r \
+             JOIN conversation_members cm ON cm.conversation_id = $1 AND cm.user_id = r.user_id \
+             LEFT JOIN user_settings us ON us.user_id = r.user_id \
+             WHERE NOT cm.muted \
+               AND COALESCE(us.data #>> '{{notifications,mentions}}', 'true') <> 'false' \
+             RETURNING *) \
+         SELECT {}, n.user_id FROM n",
+        NOTIFICATION_COLUMNS
+    );
+    let params = [
+        &message.conversation_id().to_string(),
+        &message.id().to_string(),
+        &mentions.usernames.join(","),
+        &mentions.room.to_string(),
+        &online.join(","),
+        &message.author_id().to_string(),
+        &message.thread_id().unwrap_or_default().to_string(),
+    ];
+    let rows = db.query(&sql, &params).await?;
+    publish_notifications(publisher, &rows).await
+}
+
+/// Sends each notification to its user, from rows of
+/// [`NOTIFICATION_COLUMNS`] followed by the user id.
+pub(crate) async fn publish_notifications<P: Publisher>(
+    publisher: &P,
+    rows: &[PgRow],
+) -> Result<(), Error> {
+    for row in rows {
+        let notification = notification_from_row(row);
+        publish_to(
+            publisher,
+            vec![row.get(8)],
+            NOTIFICATION_CREATED,
+            &notification,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+pub trait NotificationService {
+    /// Notifications of the caller, newest first.
+    async fn get_notifications(
+        &self,
+        user_id: &str,
+        query: &QueryNotifications,
+    ) -> Result<Page<Notification>, Error>;
+    /// Marks one of the caller's notifications read, keeping when it first
+    /// was.
+    async fn mark_read(&self, user_id: &str, notification_id: &str) -> Result<Notification, Error>;
+    async fn mark_all_read(&self, user_id: &str) -> Result<MarkedRead, Error>;
+}
+
+pub struct NotificationServiceImpl<D: Database<PgRow>, L: Logger> {
+    db: D,
+    logger: L,
+    cursors: CursorCodec,
+}
+
+impl<D: Database<PgRow>, L: Logger> NotificationServiceImpl<D, L> {
+    pub fn new(db: D, logger: L, cursors: CursorCodec) -> Self {
+        Self {
+            db,
+            logger,
+            cursors,
+        }
+    }
+}
+
+#[async_trait]
+impl<D: Database<PgRow> + Send + Sync, L: Logger + Send + Sync> NotificationService
+    for NotificationServiceImpl<D, L>
+{
+    async fn get_notifications(
+        &self,
+        user_id: &str,
+        query: &QueryNotifications,
+    ) -> Result<Page<Notification>, Error> {
+        let unread_only = query.unread_only.unwrap_or_default().to_string();
+        let request = PageRequest::new(
+            &self.cursors,
+            &format!("notifications:{}:{}", user_id, unread_only),
+            query.limit.unwrap_or(50),
+            query.cursor.as_deref(),
+        )?
+        .descending();
+        let before = request.key().first().cloned().unwrap_or_default();
+        let sql = format!(
+            "SELECT {} FROM notifications n \
+             WHERE n.user_id = $1 AND ($4 = 'false' OR n.read_at IS NULL) \
+               AND ($2 = '' OR n.id {} NULLIF($2, '')::BIGINT) \
+             ORDER BY n.id {} LIMIT $3::TEXT::INT",
+            NOTIFICATION_COLUMNS,
+            request.comparator(),
+            request.order()
+        );
+        let params = [
+            &user_id.to_string(),
+            &before,
+            &request.fetch_limit(),
+            &unread_only,
+        ];
+        match self.db.query(&sql, &params).await {
+            Ok(rows) => {
+                let notifications = rows.iter().map(notification_from_row).collect();
+                Ok(
+                    request.page(&self.cursors, notifications, |n: &Notification| {
+                        vec![n.id.clone()]
+                    }),
+                )
+            }
+            Err(e) => {
+                let message = format!("failed to query notifications of user {}: {}", user_id, e);
+                self.logger
+                    .error("notification_service::get_notifications", &message);
+                Err(e)
+            }
+        }
+    }
+
+    async fn mark_read(&self, user_id: &str, notification_id: &str) -> Result<Notification, Error> {
+        let sql = format!(
+            "UPDATE notifications n SET read_at = COALESCE(n.read_at, NOW()) \
+             WHERE n.user_id = $1 AND n.id::TEXT = $2 RETURNING {}",
+            NOTIFICATION_COLUMNS
+        );
+        self.db
+            .query_one(&sql, &[&user_id.to_string(), &notification_id.to_string()])
+            .await
+            .map(|row| notification_from_row(&row))
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound("Notification not found".to_string()),
+                e => e,
+            })
+    }
+
+    async fn mark_all_read(&self, user_id: &str) -> Result<MarkedRead, Error> {
+        let count = self
+            .db
+            .execute(
+                "UPDATE notifications SET read_at = NOW() WHERE user_id = $1 AND read_at IS NULL",
+                &[&user_id.to_string()],
+            )
+            .await
+            .inspect_err(|e| {
+                let message = format!("failed to read notifications of user {}: {}", user_id, e);
+                self.logger
+                    .error("notification_service::mark_all_read", &message);
+            })?;
+        Ok(MarkedRead { count })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::db::MockDatabase;
+    use events::publisher::MockPublisher;
+    use presence::store::MockPresenceStore;
+
+    use super::*;
+
+    fn message(body: &str) -> Message {
+        serde_json::from_value(serde_json::json!({
+            "id": "7",
+            "conversation_id": "room",
+            "author_id": "alice",
+            "body": body,
+            "edited_at": null,
+            "deleted_at": null,
+            "thread_id": null,
+            "reply_count": 0,
+            "last_reply_at": null,
+            "last_reply_author_id": null,
+            "created_at": "2026-01-01T00:00:00+00:00",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_mentions() {
+        let mentions = Mentions::parse("@Bob and @carol.d. ask @bob, bob@mail.com or @@x_1 @room");
+        assert_eq!(mentions.usernames, ["bob", "carol.d", "x_1"]);
+        assert!(mentions.room);
+        assert!(!mentions.here);
+        assert!(Mentions::parse("no one @ all, @_x or @ab").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_here_mentions_only_members_online() {
+        let mut db = MockDatabase::new();
+        db.expect_query()
+            .withf(|sql, _| sql.starts_with("SELECT user_id FROM conversation_members"))
+            .times(1)
+            .returning(|_, _| {
+                let rows = ["alice", "bob", "carol"]
+                    .map(|id| PgRow::from(vec![id.to_string()]))
+                    .into();
+                Box::pin(async move { Ok(rows) })
+            });
+        db.expect_query()
+            .withf(|sql, params| {
+                sql.starts_with("WITH mentioned")
+                    && params[2].is_empty()
+                    && *params[3] == "false"
+                    && *params[4] == "carol"
+            })
+            .times(1)
+            .returning(|_, _| Box::pin(async move { Ok(vec![]) }));
+        let mut presence = MockPresenceStore::new();
+        presence
+            .expect_states()
+            .withf(|ids| ids == ["bob", "carol"])
+            .returning(|_| Ok(vec![Some(State::Idle), Some(State::Online)]));
+
+        notify_mentions(&db, &MockPublisher::new(), &presence, &message("@here"))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,352 @@
+use async_trait::async_trait;
+use database::{db::Database, pgx::PgRow};
+use errors::error::{Error, FieldError};
+use events::publisher::Publisher;
+use logger::logger::Logger;
+use pagination::{
+    cursor::CursorCodec,
+    page::{Page, PageRequest},
+};
+use ratelimit::limiter::RateLimiter;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::services::{
+    conversation_service::{find_membership, publish},
+    message_service::{attach_reactions, check_can_send, find_message, Message},
+};
+
+pub const REACTION_ADDED: &str = "reaction.added";
+pub const REACTION_REMOVED: &str = "reaction.removed";
+
+/// Reactions added or removed by a user within [`REACTION_WINDOW_SECONDS`].
+pub const REACTION_LIMIT: u64 = 20;
+pub const REACTION_WINDOW_SECONDS: u64 = 10;
+
+/// A user who reacted to a message.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct Reactor {
+    /// Id of the reaction
+    id: String,
+    user_id: String,
+    username: String,
+    name: String,
+    emoji: String,
+    created_at: String,
+}
+
+/// Data of [`REACTION_ADDED`] and [`REACTION_REMOVED`] events.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct ReactionChange {
+    conversation_id: String,
+    message_id: String,
+    /// Thread the message replies in, if any
+    thread_id: Option<String>,
+    user_id: String,
+    emoji: String,
+}
+
+#[derive(Deserialize, Debug, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryReactors {
+    /// Only list users who reacted with this emoji
+    #[validate(custom(function = "validation::rules::emoji"))]
+    emoji: Option<String>,
+    #[validate(range(min = 1, max = 100))]
+    limit: Option<u32>,
+    /// `next_cursor` or `prev_cursor` of the previous page
+    #[validate(length(max = 1024))]
+    cursor: Option<String>,
+}
+
+#[async_trait]
+pub trait ReactionService {
+    /// Reacts to a message, reacting twice with the same emoji changes
+    /// nothing. Returns the message with its reactions.
+    async fn add_reaction(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+        emoji: &str,
+    ) -> Result<Message, Error>;
+    async fn remove_reaction(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+        emoji: &str,
+    ) -> Result<Message, Error>;
+    /// Users who reacted to a message, in the order they did.
+    async fn get_reactors(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+        query: &QueryReactors,
+    ) -> Result<Page<Reactor>, Error>;
+}
+
+pub struct ReactionServiceImpl<D: Database<PgRow>, L: Logger, P: Publisher, R: RateLimiter> {
+    db: D,
+    logger: L,
+    publisher: P,
+    limiter: R,
+    cursors: CursorCodec,
+}
+
+impl<D: Database<PgRow>, L: Logger, P: Publisher, R: RateLimiter> ReactionServiceImpl<D, L, P, R> {
+    pub fn new(db: D, logger: L, publisher: P, limiter: R, cursors: CursorCodec) -> Self {
+        Self {
+            db,
+            logger,
+            publisher,
+            limiter,
+            cursors,
+        }
+    }
+
+    /// Checks the emoji and the caller's rate, then finds the message they
+    /// react to.
+    async fn prepare(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+        emoji: &str,
+    ) -> Result<Message, Error> {
+        validation::rules::emoji(emoji).map_err(|e| {
+            let message = e.message.as_deref().unwrap_or_default();
+            Error::Validation(vec![FieldError::new("emoji", &e.code, message)])
+        })?;
+        check_can_send(&self.db, conversation_id, user_id).await?;
+        let message = find_message(&self.db, conversation_id, message_id).await?;
+        let key = format!("reactions:{}", user_id);
+        if !self
+            .limiter
+            .allow(&key, REACTION_LIMIT, REACTION_WINDOW_SECONDS)
+            .await?
+        {
+            return Err(Error::TooManyRequests(
+                "Too many reactions, try again in a few seconds".to_string(),
+            ));
+        }
+        Ok(message)
+    }
+
+    /// Tells the members about a change.
+    async fn emit(
+        &self,
+        kind: &str,
+        user_id: &str,
+        conversation_id: &str,
+        message: &Message,
+        emoji: &str,
+    ) {
+        let change = ReactionChange {
+            conversation_id: conversation_id.to_string(),
+            message_id: message.id().to_string(),
+            thread_id: message.thread_id().map(str::to_string),
+            user_id: user_id.to_string(),
+            emoji: emoji.to_string(),
+        };
+        if let Err(e) = publish(&self.db, &self.publisher, conversation_id, kind, &change).await {
+            let message = format!("failed to publish {}: {}", kind, e);
+            self.logger.error("reaction_service::emit", &message);
+        }
+    }
+
+    /// The message with its reactions as the user sees them.
+    async fn with_reactions(&self, user_id: &str, message: Message) -> Result<Message, Error> {
+        let mut messages = [message];
+        attach_reactions(&self.db, user_id, &mut messages).await?;
+        let [message] = messages;
+        Ok(message)
+    }
+}
+
+#[async_trait]
+impl<
+        D: Database<PgRow> + Send + Sync,
+        L: Logger + Send + Sync,
+        P: Publisher + Send + Sync,
+        R: RateLimiter + Send + Sync,
+    > ReactionService for ReactionServiceImpl<D, L, P, R>
+{
+    async fn add_reaction(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+        emoji: &str,
+    ) -> Result<Message, Error> {
+        let message = self
+            .prepare(user_id, conversation_id, message_id, emoji)
+            .await?;
+        let added = self
+            .db
+            .execute(
+                "INSERT INTO message_reactions (message_id, user_id, emoji) \
+                 VALUES ($1::BIGINT, $2, $3) ON CONFLICT DO NOTHING",
+                &[
+                    &message.id().to_string(),
+                    &user_id.to_string(),
+                    &emoji.to_string(),
+                ],
+            )
+            .await?;
+        if added > 0 {
+            self.emit(REACTION_ADDED, user_id, conversation_id, &message, emoji)
+                .await;
+        }
+        self.with_reactions(user_id, message).await
+    }
+
+    async fn remove_reaction(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+        emoji: &str,
+    ) -> Result<Message, Error> {
+        let message = self
+            .prepare(user_id, conversation_id, message_id, emoji)
+            .await?;
+        let removed = self
+            .db
+            .execute(
+                "DELETE FROM message_reactions \
+                 WHERE message_id = $1::BIGINT AND user_id = $2 AND emoji = $3",
+                &[
+                    &message.id().to_string(),
+                    &user_id.to_string(),
+                    &emoji.to_string(),
+                ],
+            )
+            .await?;
+        if removed == 0 {
+            return Err(Error::NotFound("Reaction not found".to_string()));
+        }
+        self.emit(REACTION_REMOVED, user_id, conversation_id, &message, emoji)
+            .await;
+        self.with_reactions(user_id, message).await
+    }
+
+    async fn get_reactors(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+        query: &QueryReactors,
+    ) -> Result<Page<Reactor>, Error> {
+        find_membership(&self.db, conversation_id, user_id).await?;
+        let message = find_message(&self.db, conversation_id, message_id).await?;
+        let emoji = query.emoji.clone().unwrap_or_default();
+        let request = PageRequest::new(
+            &self.cursors,
+            &format!("reactions:{}:{}", message.id(), emoji),
+            query.limit.unwrap_or(50),
+            query.cursor.as_deref(),
+        )?;
+        let after = request.key().first().cloned().unwrap_or_default();
+        let sql = format!(
+            "SELECT r.id::TEXT, u.id, u.username, u.name, r.emoji, \
+               to_json(r.created_at) #>> '{{}}' \
+             FROM message_reactions r JOIN users u ON u.id = r.user_id \
+             WHERE r.message_id = $1::BIGINT AND ($2 = '' OR r.emoji = $2) \
+               AND ($3 = '' OR r.id {} NULLIF($3, '')::BIGINT) \
+             ORDER BY r.id {} LIMIT $4::TEXT::INT",
+            request.comparator(),
+            request.order()
+        );
+        let params = [
+            &message.id().to_string(),
+            &emoji,
+            &after,
+            &request.fetch_limit(),
+        ];
+        match self.db.query(&sql, &params).await {
+            Ok(rows) => {
+                let reactors = rows
+                    .iter()
+                    .map(|row| Reactor {
+                        id: row.get(0),
+                        user_id: row.get(1),
+                        username: row.get(2),
+                        name: row.get(3),
+                        emoji: row.get(4),
+                        created_at: row.get(5),
+                    })
+                    .collect();
+                Ok(request.page(&self.cursors, reactors, |reactor: &Reactor| {
+                    vec![reactor.id.clone()]
+                }))
+            }
+            Err(e) => {
+                let message = format!("failed to query reactions of message {}: {}", message_id, e);
+                self.logger
+                    .error("reaction_service::get_reactors", &message);
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::db::MockDatabase;
+    use events::publisher::MockPublisher;
+    use logger::log::Log;
+    use ratelimit::limiter::MockRateLimiter;
+
+    use super::*;
+    use crate::services::fixtures::{membership_row, message_row};
+
+    #[tokio::test]
+    async fn test_reaction_must_be_an_emoji() {
+        let service = ReactionServiceImpl::new(
+            MockDatabase::new(),
+            Log,
+            MockPublisher::new(),
+            MockRateLimiter::new(),
+            CursorCodec::new(b"secret"),
+        );
+
+        let result = service.add_reaction("alice", "room", "1", "lol").await;
+        assert!(matches!(result, Err(Error::Validation(fields)) if fields[0].field == "emoji"));
+    }
+
+    #[tokio::test]
+    async fn test_reactions_are_rate_limited_per_user() {
+        let mut db = MockDatabase::new();
+        db.expect_query_one()
+            .withf(|sql, _| sql.contains("conversation_members me"))
+            .returning(|_, _| {
+                let row = membership_row("room", "member");
+                Box::pin(async move { Ok(row) })
+            });
+        db.expect_query_one()
+            .withf(|sql, _| sql.contains("FROM messages m WHERE"))
+            .returning(|_, _| {
+                let row = message_row("1", "bob", "");
+                Box::pin(async move { Ok(row) })
+            });
+        db.expect_execute().never();
+        let mut limiter = MockRateLimiter::new();
+        limiter
+            .expect_allow()
+            .withf(|key, limit, _| key == "reactions:alice" && *limit == REACTION_LIMIT)
+            .returning(|_, _, _| Ok(false));
+        let service = ReactionServiceImpl::new(
+            db,
+            Log,
+            MockPublisher::new(),
+            limiter,
+            CursorCodec::new(b"secret"),
+        );
+
+        let result = service.add_reaction("alice", "room", "1", "👍").await;
+        assert!(matches!(result, Err(Error::TooManyRequests(_))));
+    }
+}
@@ -0,0 +1,284 @@
+use async_trait::async_trait;
+use database::{db::Database, pgx::PgRow};
+use errors::error::Error;
+use logger::logger::Logger;
+use pagination::{
+    cursor::CursorCodec,
+    page::{Page, PageRequest},
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::services::message_service::{
+    attach_reactions, message_from_row, Message, MESSAGE_COLUMNS,
+};
+
+/// Options of `ts_headline`. Bodies are escaped before highlighting, so the
+/// only markup in a snippet is the `<mark>` around matches.
+const HEADLINE_OPTIONS: &str =
+    "StartSel=<mark>, StopSel=</mark>, MaxWords=24, MinWords=8, MaxFragments=2, FragmentDelimiter=\" … \"";
+
+#[derive(Deserialize, Debug, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchMessages {
+    /// Words to look for. Quote a phrase to match it exactly, prefix a word
+    /// with `-` to leave out messages containing it.
+    #[validate(
+        length(min = 1, max = 200),
+        custom(function = "validation::rules::not_blank")
+    )]
+    q: String,
+    /// Only search this conversation
+    #[validate(length(max = 64))]
+    conversation_id: Option<String>,
+    /// Only messages sent by this user
+    #[validate(length(max = 64))]
+    author_id: Option<String>,
+    /// RFC 3339 timestamp, only messages sent at or after it
+    #[validate(custom(function = "validation::rules::timestamp"))]
+    after: Option<String>,
+    /// RFC 3339 timestamp, only messages sent before it
+    #[validate(custom(function = "validation::rules::timestamp"))]
+    before: Option<String>,
+    /// Only messages with, or without, attachments
+    has_attachment: Option<bool>,
+    /// Only messages mentioning the caller, by name, `@room` or `@here`
+    mentions_me: Option<bool>,
+    #[validate(range(min = 1, max = 50))]
+    limit: Option<u32>,
+    /// `next_cursor` or `prev_cursor` of the previous page
+    #[validate(length(max = 1024))]
+    cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct SearchResult {
+    message: Message,
+    /// HTML-escaped excerpts of the body with matching words wrapped in
+    /// `<mark>`
+    snippet: String,
+}
+
+#[async_trait]
+pub trait SearchService {
+    /// Messages matching the query in conversations the caller is in,
+    /// replies included, newest first. Messages of conversations the caller
+    /// isn't in are never returned, whatever the filters.
+    async fn search_messages(
+        &self,
+        user_id: &str,
+        query: &SearchMessages,
+    ) -> Result<Page<SearchResult>, Error>;
+}
+
+pub struct SearchServiceImpl<D: Database<PgRow>, L: Logger> {
+    db: D,
+    logger: L,
+    cursors: CursorCodec,
+}
+
+impl<D: Database<PgRow>, L: Logger> SearchServiceImpl<D, L> {
+    pub fn new(db: D, logger: L, cursors: CursorCodec) -> Self {
+        Self {
+            db,
+            logger,
+            cursors,
+        }
+    }
+}
+
+fn search_sql(comparator: &str, order: &str) -> String {
+    format!(
+        "SELECT {}, ts_headline('simple', \
+             replace(replace(replace(m.body, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), \
+             s.query, '{}') \
+         FROM messages m \
+         JOIN conversation_members cm ON cm.conversation_id = m.conversation_id AND cm.user_id = $1 \
+         CROSS JOIN (SELECT websearch_to_tsquery('simple', $2) AS query) s \
+         WHERE m.search @@ s.query AND m.deleted_at IS NULL \
+           AND ($3 = '' OR m.conversation_id = $3) \
+           AND ($4 = '' OR m.author_id = $4) \
+           AND ($5 = '' OR m.created_at >= NULLIF($5, '')::TIMESTAMPTZ) \
+           AND ($6 = '' OR m.created_at < NULLIF($6, '')::TIMESTAMPTZ) \
+           AND ($7 = '' OR NULLIF($7, '')::BOOLEAN = EXISTS \
+               (SELECT 1 FROM attachments a WHERE a.message_id = m.id)) \
+           AND ($8 = '' OR NULLIF($8, '')::BOOLEAN = EXISTS \
+               (SELECT 1 FROM message_mentions mm WHERE mm.message_id = m.id AND mm.user_id = $1)) \
+           AND ($9 = '' OR m.id {} NULLIF($9, '')::BIGINT) \
+         ORDER BY m.id {} LIMIT $10::TEXT::INT",
+        MESSAGE_COLUMNS, HEADLINE_OPTIONS, comparator, order
+    )
+}
+
+fn flag(value: Option<bool>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+#[async_trait]
+impl<D: Database<PgRow> + Send + Sync, L: Logger + Send + Sync> SearchService
+    for SearchServiceImpl<D, L>
+{
+    async fn search_messages(
+        &self,
+        user_id: &str,
+        query: &SearchMessages,
+    ) -> Result<Page<SearchResult>, Error> {
+        let q = query.q.trim().to_string();
+        let filters = [
+            q.clone(),
+            query.conversation_id.clone().unwrap_or_default(),
+            query.author_id.clone().unwrap_or_default(),
+            query.after.clone().unwrap_or_default(),
+            query.before.clone().unwrap_or_default(),
+            flag(query.has_attachment),
+            flag(query.mentions_me),
+        ];
+        // A cursor only continues the search it came from
+        let context = format!(
+            "search:{}:{}",
+            user_id,
+            serde_json::to_string(&filters).unwrap_or_default()
+        );
+        let request = PageRequest::new(
+            &self.cursors,
+            &context,
+            query.limit.unwrap_or(20),
+            query.cursor.as_deref(),
+        )?
+        .descending();
+        let key = request.key().first().cloned().unwrap_or_default();
+        let sql = search_sql(request.comparator(), request.order());
+        let [q, conversation_id, author_id, after, before, has_attachment, mentions_me] = filters;
+        let params = [
+            &user_id.to_string(),
+            &q,
+            &conversation_id,
+            &author_id,
+            &after,
+            &before,
+            &has_attachment,
+            &mentions_me,
+            &key,
+            &request.fetch_limit(),
+        ];
+        let result = match self.db.query(&sql, &params).await {
+            Ok(rows) => {
+                let mut messages: Vec<Message> = rows.iter().map(message_from_row).collect();
+                let snippets: Vec<String> = rows.iter().map(|row| row.get(12)).collect();
+                attach_reactions(&self.db, user_id, &mut messages)
+                    .await
+                    .map(|_| {
+                        messages
+                            .into_iter()
+                            .zip(snippets)
+                            .map(|(message, snippet)| SearchResult { message, snippet })
+                            .collect::<Vec<_>>()
+                    })
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(results) => Ok(
+                request.page(&self.cursors, results, |result: &SearchResult| {
+                    vec![result.message.id().to_string()]
+                }),
+            ),
+            Err(e) => {
+                let message = format!("failed to search messages for {}: {}", user_id, e);
+                self.logger
+                    .error("search_service::search_messages", &message);
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::db::MockDatabase;
+    use logger::log::Log;
+    use pagination::cursor::{Cursor, Direction};
+
+    use super::*;
+
+    fn search(q: &str) -> SearchMessages {
+        SearchMessages {
+            q: q.to_string(),
+            conversation_id: None,
+            author_id: None,
+            after: None,
+            before: None,
+            has_attachment: None,
+            mentions_me: None,
+            limit: None,
+            cursor: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_only_covers_the_callers_conversations() {
+        let mut db = MockDatabase::new();
+        db.expect_query()
+            .withf(|sql, params| {
+                sql.contains("JOIN conversation_members cm")
+                    && sql.contains("cm.user_id = $1")
+                    && *params[0] == "alice"
+                    && *params[1] == "hello world"
+                    && *params[6] == "true"
+                    && params[7].is_empty()
+            })
+            .times(1)
+            .returning(|_, _| {
+                let mut columns: Vec<String> = [
+                    "7",
+                    "group",
+                    "bob",
+                    "hello world",
+                    "",
+                    "",
+                    "",
+                    "0",
+                    "",
+                    "",
+                    "2024-05-01",
+                    "[]",
+                ]
+                .map(String::from)
+                .into();
+                columns.push("<mark>hello</mark> <mark>world</mark>".to_string());
+                let rows = vec![PgRow::from(columns)];
+                Box::pin(async move { Ok(rows) })
+            });
+        db.expect_query()
+            .withf(|sql, _| sql.contains("FROM message_reactions"))
+            .times(1)
+            .returning(|_, _| Box::pin(async move { Ok(vec![]) }));
+        let service = SearchServiceImpl::new(db, Log, CursorCodec::new(b"secret"));
+
+        let mut query = search("  hello world ");
+        query.has_attachment = Some(true);
+        let page = service.search_messages("alice", &query).await.unwrap();
+        assert_eq!(page.data.len(), 1);
+        assert_eq!(page.data[0].message.id(), "7");
+        assert_eq!(
+            page.data[0].snippet,
+            "<mark>hello</mark> <mark>world</mark>"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cursor_is_bound_to_the_filters() {
+        let cursors = CursorCodec::new(b"secret");
+        let service = SearchServiceImpl::new(MockDatabase::new(), Log, cursors.clone());
+        let filters = serde_json::to_string(&["hello", "", "", "", "", "", ""]).unwrap();
+        let cursor = Cursor {
+            direction: Direction::After,
+            key: vec!["7".to_string()],
+        };
+        let mut query = search("goodbye");
+        query.cursor = Some(cursors.encode(&format!("search:alice:{}", filters), &cursor));
+
+        assert!(service.search_messages("alice", &query).await.is_err());
+    }
+}
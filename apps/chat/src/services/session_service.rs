@@ -0,0 +1,73 @@
+use async_trait::async_trait;
+use database::{db::Database, pgx::PgRow, redis::RedisRow};
+use errors::error::Error;
+
+/// Auth tokens stay valid until they expire, even after signing out or a
+/// suspension. Connections that outlive a request ask here now and then.
+#[async_trait]
+pub trait SessionService {
+    /// False once the token was signed out, or the user has no session left
+    /// or is suspended.
+    async fn is_active(&self, user_id: &str, jti: &str) -> Result<bool, Error>;
+}
+
+pub struct SessionServiceImpl<D: Database<PgRow>, R: Database<RedisRow>> {
+    db: D,
+    redis: R,
+}
+
+impl<D: Database<PgRow>, R: Database<RedisRow>> SessionServiceImpl<D, R> {
+    pub fn new(db: D, redis: R) -> Self {
+        Self { db, redis }
+    }
+}
+
+#[async_trait]
+impl<D: Database<PgRow> + Send + Sync, R: Database<RedisRow> + Send + Sync> SessionService
+    for SessionServiceImpl<D, R>
+{
+    async fn is_active(&self, user_id: &str, jti: &str) -> Result<bool, Error> {
+        // Signing out lists the jti of the auth token
+        if self.redis.query_one(jti, &[]).await.is_ok() {
+            return Ok(false);
+        }
+        let row = self
+            .db
+            .query_one(
+                "SELECT (EXISTS (SELECT 1 FROM sessions WHERE user_id = $1 AND expires_at > NOW()) \
+                   AND NOT EXISTS (SELECT 1 FROM user_suspensions WHERE user_id = $1 \
+                     AND lifted_at IS NULL AND (expires_at IS NULL OR expires_at > NOW())))::TEXT",
+                &[&user_id.to_string()],
+            )
+            .await?;
+        Ok(row.get(0) == "true")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::db::MockDatabase;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_signed_out_tokens_skip_the_database() {
+        let mut db = MockDatabase::<PgRow>::new();
+        db.expect_query_one().times(1).returning(|_, _| {
+            let row = PgRow::from(vec!["true".to_string()]);
+            Box::pin(async move { Ok(row) })
+        });
+        let mut redis = MockDatabase::<RedisRow>::new();
+        redis
+            .expect_query_one()
+            .withf(|key, _| key == "signed-out")
+            .returning(|_, _| Box::pin(async move { Ok(RedisRow::new(vec!["true".to_string()])) }));
+        redis
+            .expect_query_one()
+            .returning(|_, _| Box::pin(async move { Err(Error::NotFound("no data".to_string())) }));
+        let service = SessionServiceImpl::new(db, redis);
+
+        assert!(!service.is_active("alice", "signed-out").await.unwrap());
+        assert!(service.is_active("alice", "jti").await.unwrap());
+    }
+}
@@ -0,0 +1,349 @@
+use async_trait::async_trait;
+use database::{db::Database, pgx::PgRow};
+use errors::error::Error;
+use events::publisher::Publisher;
+use logger::logger::Logger;
+use pagination::{
+    cursor::CursorCodec,
+    page::{Page, PageRequest},
+};
+use presence::store::PresenceStore;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use validator::Validate;
+
+use crate::services::{
+    conversation_service::{find_membership, publish},
+    message_service::{
+        attach_reactions, check_can_send, find_message, insert_message, message_from_row, Message,
+        SendMessage, MESSAGE_COLUMNS, MESSAGE_CREATED,
+    },
+    notification_service::{notify_mentions, publish_notifications, NOTIFICATION_COLUMNS},
+};
+
+#[derive(Deserialize, Debug, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryReplies {
+    #[validate(range(min = 1, max = 100))]
+    limit: Option<u32>,
+    /// `next_cursor` or `prev_cursor` of the previous page
+    #[validate(length(max = 1024))]
+    cursor: Option<String>,
+}
+
+#[async_trait]
+pub trait ThreadService {
+    /// Replies in the thread of a message, oldest first, with their
+    /// reactions. Replies stay readable after the first message is deleted.
+    async fn get_replies(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+        query: &QueryReplies,
+    ) -> Result<Page<Message>, Error>;
+    /// Replies to a message. Replying to a reply continues its thread, and
+    /// everyone subscribed to the thread is notified.
+    async fn send_reply(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+        data: &SendMessage,
+    ) -> Result<Message, Error>;
+    /// Notifies the caller about replies in the thread of a message.
+    async fn subscribe(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Result<(), Error>;
+    async fn unsubscribe(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Result<(), Error>;
+}
+
+pub struct ThreadServiceImpl<D: Database<PgRow>, L: Logger, P: Publisher, S: PresenceStore> {
+    db: D,
+    logger: L,
+    publisher: P,
+    presence: S,
+    cursors: CursorCodec,
+}
+
+impl<D: Database<PgRow>, L: Logger, P: Publisher, S: PresenceStore> ThreadServiceImpl<D, L, P, S> {
+    pub fn new(db: D, logger: L, publisher: P, presence: S, cursors: CursorCodec) -> Self {
+        Self {
+            db,
+            logger,
+            publisher,
+            presence,
+            cursors,
+        }
+    }
+
+    /// Id of the first message of the thread the message is in, deleted or
+    /// not.
+    async fn find_thread(&self, conversation_id: &str, message_id: &str) -> Result<String, Error> {
+        self.db
+            .query_one(
+                "SELECT COALESCE(thread_id, id)::TEXT FROM messages \
+                 WHERE conversation_id = $1 AND id::TEXT = $2",
+                &[&conversation_id.to_string(), &message_id.to_string()],
+            )
+            .await
+            .map(|row| row.get(0))
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound("Message not found".to_string()),
+                e => e,
+            })
+    }
+
+    /// Notifies the other subscribers of the thread who are still in the
+    /// conversation, haven't muted it and haven't turned reply notifications
+    /// off. Subscribers the reply mentions already got a mention instead.
+    /// Failures are logged like those of [`Publisher`] events.
+    async fn notify(&self, reply: &Message, thread_id: &str, actor_id: &str) {
+        let sql = format!(
+            "WITH n AS (INSERT INTO notifications \
+               (user_id, kind, conversation_id, message_id, thread_id, actor_id) \
+               SELECT s.user_id, 'reply', cm.conversation_id, $2::BIGINT, s.thread_id, $3 \
+               FROM thread_subscriptions s \
+               JOIN messages t ON t.id = s.thread_id \
+               JOIN conversation_members cm \
+                 ON cm.conversation_id = t.conversation_id AND cm.user_id = s.user_id \
+               LEFT JOIN user_settings us ON us.user_id = s.user_id \
+               WHERE s.thread_id = $1::BIGINT AND s.user_id <> $3 AND NOT cm.muted \
+                 AND NOT EXISTS (SELECT 1 FROM message_mentions mm \
+                   WHERE mm.message_id = $2::BIGINT AND mm.user_id = s.user_id) \
+                 AND COALESCE(us.data #>> '{{notifications,replies}}', 'true') <> 'false' \
+               RETURNING *) \
+             SELECT {}, n.user_id FROM n",
+            NOTIFICATION_COLUMNS
+        );
+        let params = [
+            &thread_id.to_string(),
+            &reply.id().to_string(),
+            &actor_id.to_string(),
+        ];
+        let result = match self.db.query(&sql, &params).await {
+            Ok(rows) => publish_notifications(&self.publisher, &rows).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            let message = format!("failed to notify thread {}: {}", thread_id, e);
+            self.logger.error("thread_service::notify", &message);
+        }
+    }
+}
+
+#[async_trait]
+impl<
+        D: Database<PgRow> + Send + Sync,
+        L: Logger + Send + Sync,
+        P: Publisher + Send + Sync,
+        S: PresenceStore + Send + Sync,
+    > ThreadService for ThreadServiceImpl<D, L, P, S>
+{
+    async fn get_replies(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+        query: &QueryReplies,
+    ) -> Result<Page<Message>, Error> {
+        find_membership(&self.db, conversation_id, user_id).await?;
+        let thread_id = self.find_thread(conversation_id, message_id).await?;
+        let request = PageRequest::new(
+            &self.cursors,
+            &format!("replies:{}", thread_id),
+            query.limit.unwrap_or(50),
+            query.cursor.as_deref(),
+        )?;
+        let after = request.key().first().cloned().unwrap_or_default();
+        let sql = format!(
+            "SELECT {} FROM messages m \
+             WHERE m.thread_id = $1::BIGINT AND ($2 = '' OR m.id {} NULLIF($2, '')::BIGINT) \
+             ORDER BY m.id {} LIMIT $3::TEXT::INT",
+            MESSAGE_COLUMNS,
+            request.comparator(),
+            request.order()
+        );
+        let params = [&thread_id, &after, &request.fetch_limit()];
+        let result = match self.db.query(&sql, &params).await {
+            Ok(rows) => {
+                let mut replies: Vec<Message> = rows.iter().map(message_from_row).collect();
+                attach_reactions(&self.db, user_id, &mut replies)
+                    .await
+                    .map(|_| replies)
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(replies) => Ok(request.page(&self.cursors, replies, |reply: &Message| {
+                vec![reply.id().to_string()]
+            })),
+            Err(e) => {
+                let message = format!("failed to query replies of thread {}: {}", thread_id, e);
+                self.logger.error("thread_service::get_replies", &message);
+                Err(e)
+            }
+        }
+    }
+
+    async fn send_reply(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+        data: &SendMessage,
+    ) -> Result<Message, Error> {
+        check_can_send(&self.db, conversation_id, user_id).await?;
+        let parent = find_message(&self.db, conversation_id, message_id).await?;
+        // Threads don't nest, a reply to a reply goes to the same thread
+        let thread_id = parent.thread_id().unwrap_or(parent.id()).to_string();
+        let reply = insert_message(&self.db, conversation_id, user_id, data, Some(&thread_id))
+            .await
+            .inspect_err(|e| {
+                let message = format!("failed to reply in thread {}: {}", thread_id, e);
+                self.logger.error("thread_service::send_reply", &message);
+            })?;
+        if let Err(e) = publish(
+            &self.db,
+            &self.publisher,
+            conversation_id,
+            MESSAGE_CREATED,
+            &reply,
+        )
+        .await
+        {
+            let message = format!("failed to publish {}: {}", MESSAGE_CREATED, e);
+            self.logger.error("thread_service::send_reply", &message);
+        }
+        // Mentions first, so mentioned subscribers aren't notified twice
+        if let Err(e) = notify_mentions(&self.db, &self.publisher, &self.presence, &reply).await {
+            let message = format!("failed to notify mentions of reply {}: {}", reply.id(), e);
+            self.logger.error("thread_service::send_reply", &message);
+        }
+        self.notify(&reply, &thread_id, user_id).await;
+        Ok(reply)
+    }
+
+    async fn subscribe(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Result<(), Error> {
+        find_membership(&self.db, conversation_id, user_id).await?;
+        let thread_id = self.find_thread(conversation_id, message_id).await?;
+        self.db
+            .execute(
+                "INSERT INTO thread_subscriptions (thread_id, user_id) \
+                 VALUES ($1::BIGINT, $2) ON CONFLICT DO NOTHING",
+                &[&thread_id, &user_id.to_string()],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn unsubscribe(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Result<(), Error> {
+        find_membership(&self.db, conversation_id, user_id).await?;
+        let thread_id = self.find_thread(conversation_id, message_id).await?;
+        self.db
+            .execute(
+                "DELETE FROM thread_subscriptions WHERE thread_id = $1::BIGINT AND user_id = $2",
+                &[&thread_id, &user_id.to_string()],
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::db::MockDatabase;
+    use events::publisher::MockPublisher;
+    use logger::log::Log;
+    use presence::store::MockPresenceStore;
+
+    use super::*;
+    use crate::services::fixtures::{membership_row, message_row};
+
+    fn reply() -> SendMessage {
+        serde_json::from_value(serde_json::json!({ "body": "hi" })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reply_to_reply_continues_its_thread() {
+        let mut db = MockDatabase::new();
+        db.expect_query_one()
+            .withf(|sql, _| sql.contains("conversation_members me"))
+            .returning(|_, _| {
+                let row = membership_row("room", "member");
+                Box::pin(async move { Ok(row) })
+            });
+        db.expect_query_one()
+            .withf(|sql, _| sql.starts_with("SELECT") && sql.contains("FROM messages m WHERE"))
+            .returning(|_, _| {
+                let row = message_row("7", "bob", "3");
+                Box::pin(async move { Ok(row) })
+            });
+        db.expect_query_one()
+            .withf(|sql, params| sql.starts_with("WITH m AS (INSERT") && *params[3] == "3")
+            .times(1)
+            .returning(|_, _| {
+                let row = message_row("9", "bob", "3");
+                Box::pin(async move { Ok(row) })
+            });
+        db.expect_query()
+            .returning(|_, _| Box::pin(async move { Ok(vec![]) }));
+        let mut publisher = MockPublisher::new();
+        publisher.expect_publish().returning(|_, _| Ok(()));
+        let service = ThreadServiceImpl::new(
+            db,
+            Log,
+            publisher,
+            MockPresenceStore::new(),
+            CursorCodec::new(b"secret"),
+        );
+
+        let reply = service
+            .send_reply("alice", "room", "7", &reply())
+            .await
+            .unwrap();
+        assert_eq!(reply.thread_id(), Some("3"));
+    }
+
+    #[tokio::test]
+    async fn test_reply_to_deleted_message_is_not_found() {
+        let mut db = MockDatabase::new();
+        db.expect_query_one()
+            .withf(|sql, _| sql.contains("conversation_members me"))
+            .returning(|_, _| {
+                let row = membership_row("room", "member");
+                Box::pin(async move { Ok(row) })
+            });
+        db.expect_query_one()
+            .withf(|sql, _| sql.contains("deleted_at IS NULL"))
+            .returning(|_, _| Box::pin(async move { Err(Error::NotFound("no rows".to_string())) }));
+        let service = ThreadServiceImpl::new(
+            db,
+            Log,
+            MockPublisher::new(),
+            MockPresenceStore::new(),
+            CursorCodec::new(b"secret"),
+        );
+
+        let result = service.send_reply("alice", "room", "7", &reply()).await;
+        assert!(matches!(result, Err(Error::NotFound(_))));
+    }
+}
@@ -0,0 +1,146 @@
+use async_trait::async_trait;
+use database::{db::Database, pgx::PgRow};
+use errors::error::Error;
+use events::publisher::Publisher;
+use ratelimit::limiter::RateLimiter;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::services::{
+    conversation_service::{find_membership, members, publish_to},
+    message_service::check_can_send,
+};
+
+pub const TYPING_STARTED: &str = "typing.started";
+pub const TYPING_STOPPED: &str = "typing.stopped";
+
+/// Seconds an indicator shows without being refreshed. Clients refresh it
+/// about every [`TYPING_THROTTLE_SECONDS`] while the user keeps typing.
+pub const TYPING_TTL_SECONDS: u64 = 8;
+/// Typing of a user in a conversation is passed on at most once per window,
+/// refreshes in between are dropped.
+pub const TYPING_THROTTLE_SECONDS: u64 = 3;
+
+/// Data of [`TYPING_STARTED`] and [`TYPING_STOPPED`] events.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct Typing {
+    conversation_id: String,
+    user_id: String,
+    /// Seconds until the indicator goes away unless refreshed, `0` once the
+    /// user stopped
+    expires_in: u64,
+}
+
+/// Typing indicators only travel as events between the members, nothing is
+/// stored.
+#[async_trait]
+pub trait TypingService {
+    /// Shows the caller as typing to the other members for
+    /// [`TYPING_TTL_SECONDS`].
+    async fn start_typing(&self, user_id: &str, conversation_id: &str) -> Result<(), Error>;
+    async fn stop_typing(&self, user_id: &str, conversation_id: &str) -> Result<(), Error>;
+}
+
+pub struct TypingServiceImpl<D: Database<PgRow>, P: Publisher, R: RateLimiter> {
+    db: D,
+    publisher: P,
+    limiter: R,
+}
+
+impl<D: Database<PgRow>, P: Publisher, R: RateLimiter> TypingServiceImpl<D, P, R> {
+    pub fn new(db: D, publisher: P, limiter: R) -> Self {
+        Self {
+            db,
+            publisher,
+            limiter,
+        }
+    }
+
+    /// Publishes through Redis, so members connected to any instance hear
+    /// it.
+    async fn emit(
+        &self,
+        kind: &str,
+        user_id: &str,
+        conversation_id: &str,
+        expires_in: u64,
+    ) -> Result<(), Error> {
+        let mut others = members(&self.db, conversation_id).await?;
+        others.retain(|id| id != user_id);
+        let typing = Typing {
+            conversation_id: conversation_id.to_string(),
+            user_id: user_id.to_string(),
+            expires_in,
+        };
+        publish_to(&self.publisher, others, kind, &typing).await
+    }
+}
+
+#[async_trait]
+impl<
+        D: Database<PgRow> + Send + Sync,
+        P: Publisher + Send + Sync,
+        R: RateLimiter + Send + Sync,
+    > TypingService for TypingServiceImpl<D, P, R>
+{
+    async fn start_typing(&self, user_id: &str, conversation_id: &str) -> Result<(), Error> {
+        // Checked first, so dropped refreshes cost no queries
+        let key = format!("typing:{}:{}", user_id, conversation_id);
+        if !self.limiter.allow(&key, 1, TYPING_THROTTLE_SECONDS).await? {
+            return Ok(());
+        }
+        check_can_send(&self.db, conversation_id, user_id).await?;
+        self.emit(TYPING_STARTED, user_id, conversation_id, TYPING_TTL_SECONDS)
+            .await
+    }
+
+    async fn stop_typing(&self, user_id: &str, conversation_id: &str) -> Result<(), Error> {
+        find_membership(&self.db, conversation_id, user_id).await?;
+        self.emit(TYPING_STOPPED, user_id, conversation_id, 0).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::db::MockDatabase;
+    use events::publisher::MockPublisher;
+    use ratelimit::limiter::MockRateLimiter;
+
+    use super::*;
+    use crate::services::fixtures::membership_row;
+
+    #[tokio::test]
+    async fn test_typing_is_throttled_and_skips_the_typist() {
+        let mut db = MockDatabase::new();
+        db.expect_query_one().times(1).returning(|_, _| {
+            let row = membership_row("group", "member");
+            Box::pin(async move { Ok(row) })
+        });
+        db.expect_query().times(1).returning(|_, _| {
+            let rows = ["alice", "bob"]
+                .map(|id| PgRow::from(vec![id.to_string()]))
+                .into();
+            Box::pin(async move { Ok(rows) })
+        });
+        let mut limiter = MockRateLimiter::new();
+        let mut allowed = [true, false].into_iter();
+        limiter
+            .expect_allow()
+            .times(2)
+            .returning(move |_, _, _| Ok(allowed.next().unwrap_or_default()));
+        let mut publisher = MockPublisher::new();
+        publisher
+            .expect_publish()
+            .withf(|_, event| {
+                event.kind == TYPING_STARTED
+                    && event.data["recipients"] == serde_json::json!(["bob"])
+                    && event.data["payload"]["expires_in"] == TYPING_TTL_SECONDS
+            })
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let service = TypingServiceImpl::new(db, publisher, limiter);
+
+        service.start_typing("alice", "group").await.unwrap();
+        service.start_typing("alice", "group").await.unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use auth_middleware::{
+    guard::Guard, rbac::ROLE_ADMIN, source::TokenSource, user::AuthenticatedUser,
+};
+use database::pgx::Postgresql;
+use errors::{
+    error::{Error, ErrorBody},
+    response::Response,
+};
+use logger::log::Log;
+use pagination::page::Page;
+use security::{env::EnvImpl, jwt::JwtImpl};
+use validation::extractor::{ValidJson, ValidQuery};
+
+use crate::services::admin_service::{
+    AdminService, AdminServiceImpl, AdminUser, AuditEntry, QueryAdminUsers, QueryAudit,
+    RevokedSessions, SuspendUser, UnsuspendUser,
+};
+
+pub fn admin_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let admin_middleware = Guard::new(jwt.clone())
+        .sources(vec![
+            TokenSource::authorization(),
+            TokenSource::cookie("token"),
+        ])
+        .kinds(&["auth_token"])
+        .roles(&[ROLE_ADMIN]);
+    config.service(
+        web::scope("/user/admin/users")
+            .wrap(admin_middleware)
+            .route("", web::get().to(get_users_handler))
+            .route("/{user_id}", web::get().to(get_user_handler))
+            .route("/{user_id}/suspend", web::post().to(suspend_user_handler))
+            .route(
+                "/{user_id}/unsuspend",
+                web::post().to(unsuspend_user_handler),
+            )
+            .route(
+                "/{user_id}/password-reset",
+                web::post().to(force_password_reset_handler),
+            )
+            .route(
+                "/{user_id}/sessions",
+                web::delete().to(revoke_sessions_handler),
+            )
+            .route("/{user_id}/audit", web::get().to(get_audit_handler)),
+    );
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/admin/users",
+    tag = "admin",
+    params(QueryAdminUsers),
+    responses(
+        (status = 200, description = "Users by username", body = Response<Page<AdminUser>>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "Caller is not an admin", body = ErrorBody),
+        (status = 422, description = "Invalid query", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_users_handler(
+    service: web::Data<AdminServiceImpl<Postgresql, Log>>,
+    query: ValidQuery<QueryAdminUsers>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let users = service.get_users(&query).await?.with_links(&req);
+    Ok(HttpResponse::Ok().json(Response::new(users, "Successfully got users")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/admin/users/{user_id}",
+    tag = "admin",
+    params(("user_id" = String, Path, description = "User id")),
+    responses(
+        (status = 200, description = "The user", body = Response<AdminUser>),
+        (status = 403, description = "Caller is not an admin", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_user_handler(
+    service: web::Data<AdminServiceImpl<Postgresql, Log>>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let user = service.get_user(&path.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(Response::new(user, "Successfully got user")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/admin/users/{user_id}/suspend",
+    tag = "admin",
+    params(("user_id" = String, Path, description = "User to suspend")),
+    request_body = SuspendUser,
+    responses(
+        (status = 200, description = "User is suspended and signed out everywhere, auth tokens already issued run out within 15 minutes", body = Response<AdminUser>),
+        (status = 400, description = "Suspending yourself", body = ErrorBody),
+        (status = 403, description = "Caller is not an admin", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+        (status = 409, description = "User is already suspended", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn suspend_user_handler(
+    service: web::Data<AdminServiceImpl<Postgresql, Log>>,
+    path: web::Path<String>,
+    body: ValidJson<SuspendUser>,
+    admin: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let user = service
+        .suspend_user(&admin.user_id, &path.into_inner(), &body)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(user, "Successfully suspended user")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/admin/users/{user_id}/unsuspend",
+    tag = "admin",
+    params(("user_id" = String, Path, description = "User to unsuspend")),
+    request_body = UnsuspendUser,
+    responses(
+        (status = 200, description = "Suspension is lifted", body = Response<AdminUser>),
+        (status = 403, description = "Caller is not an admin", body = ErrorBody),
+        (status = 404, description = "User is not suspended", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn unsuspend_user_handler(
+    service: web::Data<AdminServiceImpl<Postgresql, Log>>,
+    path: web::Path<String>,
+    body: ValidJson<UnsuspendUser>,
+    admin: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let user = service
+        .unsuspend_user(&admin.user_id, &path.into_inner(), &body)
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(user, "Successfully unsuspended user")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/admin/users/{user_id}/password-reset",
+    tag = "admin",
+    params(("user_id" = String, Path, description = "User who must choose a new password")),
+    responses(
+        (status = 200, description = "User is signed out and must set a new password with `/auth/password`, auth tokens already issued run out within 15 minutes", body = Response<AdminUser>),
+        (status = 403, description = "Caller is not an admin", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn force_password_reset_handler(
+    service: web::Data<AdminServiceImpl<Postgresql, Log>>,
+    path: web::Path<String>,
+    admin: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let user = service
+        .force_password_reset(&admin.user_id, &path.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(user, "Successfully required password reset")))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/user/admin/users/{user_id}/sessions",
+    tag = "admin",
+    params(("user_id" = String, Path, description = "User to sign out")),
+    responses(
+        (status = 200, description = "Refresh tokens stop working, auth tokens already issued run out within 15 minutes", body = Response<RevokedSessions>),
+        (status = 403, description = "Caller is not an admin", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn revoke_sessions_handler(
+    service: web::Data<AdminServiceImpl<Postgresql, Log>>,
+    path: web::Path<String>,
+    admin: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let revoked = service
+        .revoke_sessions(&admin.user_id, &path.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(revoked, "Successfully revoked sessions")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/admin/users/{user_id}/audit",
+    tag = "admin",
+    params(("user_id" = String, Path, description = "User acted on"), QueryAudit),
+    responses(
+        (status = 200, description = "Admin actions on the user, newest first", body = Response<Page<AuditEntry>>),
+        (status = 403, description = "Caller is not an admin", body = ErrorBody),
+        (status = 422, description = "Invalid query", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_audit_handler(
+    service: web::Data<AdminServiceImpl<Postgresql, Log>>,
+    path: web::Path<String>,
+    query: ValidQuery<QueryAudit>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let entries = service
+        .get_audit(&path.into_inner(), &query)
+        .await?
+        .with_links(&req);
+    Ok(HttpResponse::Ok().json(Response::new(entries, "Successfully got audit history")))
+}
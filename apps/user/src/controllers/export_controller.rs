@@ -0,0 +1,120 @@
+use actix_web::{http::header, web, HttpResponse};
+use auth_middleware::{guard::Guard, source::TokenSource, user::AuthenticatedUser};
+use database::pgx::Postgresql;
+use errors::{
+    error::{Error, ErrorBody},
+    response::Response,
+};
+use logger::log::Log;
+use security::{env::EnvImpl, jwt::JwtImpl};
+use storage::blob::AnyBlobStore;
+
+use crate::services::export_service::{DataExport, ExportService, ExportServiceImpl};
+
+pub fn export_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let jwt_middleware = Guard::new(jwt.clone())
+        .sources(vec![
+            TokenSource::authorization(),
+            TokenSource::cookie("token"),
+        ])
+        .kinds(&["auth_token"]);
+    config.service(
+        web::scope("/user/exports")
+            .wrap(jwt_middleware)
+            .route("", web::get().to(get_exports_handler))
+            .route("", web::post().to(request_export_handler))
+            .route("/{export_id}", web::get().to(get_export_handler))
+            .route(
+                "/{export_id}/download",
+                web::get().to(download_export_handler),
+            ),
+    );
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/exports",
+    tag = "exports",
+    responses(
+        (status = 202, description = "Export is queued, poll it until it is ready", body = Response<DataExport>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 409, description = "An export is already in progress", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn request_export_handler(
+    service: web::Data<ExportServiceImpl<Postgresql, Log, AnyBlobStore>>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let export = service.request_export(&user.user_id).await?;
+    Ok(HttpResponse::Accepted().json(Response::new(export, "Successfully requested export")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/exports",
+    tag = "exports",
+    responses(
+        (status = 200, description = "Exports of the caller, newest first", body = Response<Vec<DataExport>>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_exports_handler(
+    service: web::Data<ExportServiceImpl<Postgresql, Log, AnyBlobStore>>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let exports = service.get_exports(&user.user_id).await?;
+    Ok(HttpResponse::Ok().json(Response::new(exports, "Successfully got exports")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/exports/{export_id}",
+    tag = "exports",
+    params(("export_id" = String, Path, description = "Export id")),
+    responses(
+        (status = 200, description = "The export", body = Response<DataExport>),
+        (status = 404, description = "Export not found", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_export_handler(
+    service: web::Data<ExportServiceImpl<Postgresql, Log, AnyBlobStore>>,
+    path: web::Path<String>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let export = service
+        .get_export(&user.user_id, &path.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(export, "Successfully got export")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/exports/{export_id}/download",
+    tag = "exports",
+    params(("export_id" = String, Path, description = "Export id")),
+    responses(
+        (status = 200, description = "ZIP archive with `data.json` and the avatar", content_type = "application/zip", body = Vec<u8>),
+        (status = 404, description = "Export not found, failed or expired", body = ErrorBody),
+        (status = 409, description = "Export is not ready yet", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn download_export_handler(
+    service: web::Data<ExportServiceImpl<Postgresql, Log, AnyBlobStore>>,
+    path: web::Path<String>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let export_id = path.into_inner();
+    let archive = service.download_export(&user.user_id, &export_id).await?;
+    Ok(HttpResponse::Ok()
+        .content_type(storage::archive::ZIP)
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"export-{}.zip\"", export_id),
+        ))
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .body(archive))
+}
@@ -1 +1,7 @@
+pub mod admin_controller;
+pub mod export_controller;
+pub mod presence_controller;
+pub mod privacy_controller;
+pub mod relationship_controller;
+pub mod settings_controller;
 pub mod user_controller;
@@ -0,0 +1,108 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web, HttpMessage, HttpResponse,
+};
+use auth_middleware::{guard::Guard, source::TokenSource, user::AuthenticatedUser};
+use database::pgx::Postgresql;
+use errors::{
+    error::{Error, ErrorBody},
+    response::{Empty, Response},
+};
+use futures::executor::block_on;
+use logger::log::Log;
+use presence::store::RedisPresence;
+use security::{env::EnvImpl, jwt::JwtImpl};
+use validation::extractor::{ValidJson, ValidQuery};
+
+use crate::services::presence_service::{
+    Presence, PresenceService, PresenceServiceImpl, QueryPresence, UpdatePresence,
+};
+
+pub fn presence_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let jwt_middleware = Guard::new(jwt.clone())
+        .sources(vec![
+            TokenSource::authorization(),
+            TokenSource::cookie("token"),
+        ])
+        .kinds(&["auth_token"]);
+    config.service(
+        web::scope("/user/presence")
+            .wrap(jwt_middleware)
+            .route("", web::get().to(get_presence_handler))
+            .route("", web::put().to(update_presence_handler)),
+    );
+}
+
+/// Counts authenticated requests as activity, once per user every 30
+/// seconds. Wraps the whole app so it sees the caller the guards resolved,
+/// and refreshes presence without holding up the response.
+pub async fn touch_presence(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let response = next.call(req).await?;
+    let request = response.request();
+    // Heartbeats set the status themselves, idle ones must not turn online
+    if request.path() == "/user/presence" {
+        return Ok(response);
+    }
+    let user = request.extensions().get::<AuthenticatedUser>().cloned();
+    let service = request
+        .app_data::<web::Data<PresenceServiceImpl<Postgresql, Log, RedisPresence>>>()
+        .cloned();
+    let (Some(user), Some(service)) = (user, service) else {
+        return Ok(response);
+    };
+    if service.claim_touch(&user.user_id) {
+        actix_web::rt::spawn(async move {
+            // The store calls redis synchronously, keep it off the workers
+            let _ = web::block(move || block_on(service.touch(&user.user_id))).await;
+        });
+    }
+    Ok(response)
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/presence",
+    tag = "presence",
+    params(QueryPresence),
+    responses(
+        (status = 200, description = "Presence of the users the caller may see, in the order asked for", body = Response<Vec<Presence>>),
+        (status = 400, description = "More than 100 user ids", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 422, description = "Invalid query", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_presence_handler(
+    service: web::Data<PresenceServiceImpl<Postgresql, Log, RedisPresence>>,
+    query: ValidQuery<QueryPresence>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let presences = service.get_presence(&user.user_id, &query).await?;
+    Ok(HttpResponse::Ok().json(Response::new(presences, "Successfully got presence")))
+}
+
+#[utoipa::path(
+    put,
+    path = "/user/presence",
+    tag = "presence",
+    request_body = UpdatePresence,
+    responses(
+        (status = 200, description = "Heartbeat is recorded, send one about every 30 seconds", body = Response<Empty>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn update_presence_handler(
+    service: web::Data<PresenceServiceImpl<Postgresql, Log, RedisPresence>>,
+    body: ValidJson<UpdatePresence>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    service.set_status(&user.user_id, &body).await?;
+    Ok(HttpResponse::Ok().json(Response::new(Empty, "Successfully updated presence")))
+}
@@ -0,0 +1,244 @@
+use actix_web::{web, HttpResponse};
+use auth_middleware::{
+    guard::Guard, rbac::INTERNAL_READ, source::TokenSource, user::AuthenticatedUser,
+};
+use database::pgx::Postgresql;
+use errors::{
+    error::{Error, ErrorBody},
+    response::{Empty, Response},
+};
+use events::publisher::RedisPublisher;
+use logger::log::Log;
+use security::{env::EnvImpl, jwt::JwtImpl};
+use validation::extractor::ValidJson;
+
+use crate::services::privacy_service::{
+    BlockUser, BlockedUser, PrivacyService, PrivacyServiceImpl, PrivacySettings, RelationshipStatus,
+};
+
+pub fn privacy_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let jwt_middleware = || {
+        Guard::new(jwt.clone())
+            .sources(vec![
+                TokenSource::authorization(),
+                TokenSource::cookie("token"),
+            ])
+            .kinds(&["auth_token"])
+    };
+    config.service(
+        web::scope("/user/privacy")
+            .wrap(jwt_middleware())
+            .route("", web::get().to(get_privacy_handler))
+            .route("", web::put().to(update_privacy_handler)),
+    );
+    config.service(
+        web::scope("/user/blocks")
+            .wrap(jwt_middleware())
+            .route("", web::get().to(get_blocked_handler))
+            .route("", web::post().to(block_user_handler))
+            .route("/{user_id}", web::delete().to(unblock_user_handler)),
+    );
+}
+
+/// Routes for other services. They live outside /user so the gateway does
+/// not expose them.
+pub fn internal_privacy_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let jwt_middleware = Guard::new(jwt.clone())
+        .sources(vec![TokenSource::authorization()])
+        .kinds(&["auth_token"])
+        .scopes(&[INTERNAL_READ]);
+    config.service(web::scope("/internal/user").wrap(jwt_middleware).route(
+        "/{user_id}/relationship/{other_id}",
+        web::get().to(get_relationship_handler),
+    ));
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/privacy",
+    tag = "privacy",
+    responses(
+        (status = 200, description = "Privacy settings of the caller", body = Response<PrivacySettings>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_privacy_handler(
+    service: web::Data<PrivacyServiceImpl<Postgresql, Log, RedisPublisher>>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let settings = service.get_settings(&user.user_id).await?;
+    Ok(HttpResponse::Ok().json(Response::new(settings, "Successfully got privacy settings")))
+}
+
+#[utoipa::path(
+    put,
+    path = "/user/privacy",
+    tag = "privacy",
+    request_body = PrivacySettings,
+    responses(
+        (status = 200, description = "Updated privacy settings", body = Response<PrivacySettings>),
+        (status = 400, description = "Malformed body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn update_privacy_handler(
+    service: web::Data<PrivacyServiceImpl<Postgresql, Log, RedisPublisher>>,
+    body: ValidJson<PrivacySettings>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let settings = service.update_settings(&user.user_id, &body).await?;
+    Ok(HttpResponse::Ok().json(Response::new(
+        settings,
+        "Successfully updated privacy settings",
+    )))
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/blocks",
+    tag = "privacy",
+    responses(
+        (status = 200, description = "Users the caller blocked, newest first", body = Response<Vec<BlockedUser>>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_blocked_handler(
+    service: web::Data<PrivacyServiceImpl<Postgresql, Log, RedisPublisher>>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let blocked = service.get_blocked(&user.user_id).await?;
+    Ok(HttpResponse::Ok().json(Response::new(blocked, "Successfully got blocked users")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/blocks",
+    tag = "privacy",
+    request_body = BlockUser,
+    responses(
+        (status = 200, description = "User is blocked, friendship and requests are removed", body = Response<Empty>),
+        (status = 400, description = "Blocking yourself", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn block_user_handler(
+    service: web::Data<PrivacyServiceImpl<Postgresql, Log, RedisPublisher>>,
+    body: ValidJson<BlockUser>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    service.block_user(&user.user_id, &body).await?;
+    Ok(HttpResponse::Ok().json(Response::new(Empty, "Successfully blocked user")))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/user/blocks/{user_id}",
+    tag = "privacy",
+    params(("user_id" = String, Path, description = "User to unblock")),
+    responses(
+        (status = 200, description = "User is unblocked", body = Response<Empty>),
+        (status = 404, description = "Blocked user not found", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn unblock_user_handler(
+    service: web::Data<PrivacyServiceImpl<Postgresql, Log, RedisPublisher>>,
+    path: web::Path<String>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    service
+        .unblock_user(&user.user_id, &path.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(Empty, "Successfully unblocked user")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/internal/user/{user_id}/relationship/{other_id}",
+    tag = "internal",
+    params(
+        ("user_id" = String, Path, description = "User acting"),
+        ("other_id" = String, Path, description = "User acted on"),
+    ),
+    responses(
+        (status = 200, description = "Blocks, friendship and whether `user_id` may message `other_id`", body = Response<RelationshipStatus>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "Token lacks the internal:read scope", body = ErrorBody),
+        (status = 404, description = "Other user not found", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+async fn get_relationship_handler(
+    service: web::Data<PrivacyServiceImpl<Postgresql, Log, RedisPublisher>>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (user_id, other_id) = path.into_inner();
+    let status = service.get_relationship(&user_id, &other_id).await?;
+    Ok(HttpResponse::Ok().json(Response::new(status, "Successfully got relationship")))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::StatusCode,
+        test::{init_service, try_call_service, TestRequest},
+        App,
+    };
+    use auth_middleware::rbac::{
+        ROLES_READ, ROLES_WRITE, ROLE_ADMIN, ROLE_USER, USERS_READ, USERS_WRITE,
+    };
+    use chrono::{Duration, Utc};
+    use security::jwt::{AdditionalClaims, Claims, Jwt};
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_internal_routes_reject_people() {
+        std::env::set_var("JWT_SECRET", "privacy-controller-test");
+        let jwt = JwtImpl::new(EnvImpl);
+        let sign = |roles: &[&str], scopes: &[&str]| {
+            jwt.sign(&Claims {
+                exp: (Utc::now() + Duration::minutes(5)).timestamp() as usize,
+                iat: Utc::now().timestamp() as usize,
+                nbf: Utc::now().timestamp() as usize,
+                sub: "alice".to_string(),
+                jti: "jti".to_string(),
+                additional_claims: AdditionalClaims {
+                    user_id: "user-1".to_string(),
+                    kind: "auth_token".to_string(),
+                    roles: roles.iter().map(|r| r.to_string()).collect(),
+                    scopes: scopes.iter().map(|s| s.to_string()).collect(),
+                },
+            })
+            .unwrap()
+        };
+        let tokens = [
+            sign(&[ROLE_USER], &[USERS_READ]),
+            sign(
+                &[ROLE_ADMIN],
+                &[USERS_READ, USERS_WRITE, ROLES_READ, ROLES_WRITE],
+            ),
+        ];
+        let app =
+            init_service(App::new().configure(|config| internal_privacy_controller(config, &jwt)))
+                .await;
+
+        for token in tokens {
+            let req = TestRequest::get()
+                .uri("/internal/user/user-1/relationship/user-2")
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request();
+            let res = try_call_service(&app, req).await;
+            assert_eq!(
+                res.err().map(|e| e.as_response_error().status_code()),
+                Some(StatusCode::FORBIDDEN)
+            );
+        }
+    }
+}
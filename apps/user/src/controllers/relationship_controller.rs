@@ -0,0 +1,210 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use auth_middleware::{guard::Guard, source::TokenSource, user::AuthenticatedUser};
+use database::pgx::Postgresql;
+use errors::{
+    error::{Error, ErrorBody},
+    response::{Empty, Response},
+};
+use events::publisher::RedisPublisher;
+use logger::log::Log;
+use pagination::page::Page;
+use security::{env::EnvImpl, jwt::JwtImpl};
+use validation::extractor::{ValidJson, ValidQuery};
+
+use crate::services::{
+    relationship_service::{
+        FriendRequest, QueryFriendRequests, QueryFriends, RelationshipService,
+        RelationshipServiceImpl, SendFriendRequest,
+    },
+    user_service::User,
+};
+
+pub fn relationship_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let jwt_middleware = Guard::new(jwt.clone())
+        .sources(vec![
+            TokenSource::authorization(),
+            TokenSource::cookie("token"),
+        ])
+        .kinds(&["auth_token"]);
+    config.service(
+        web::scope("/user/friends")
+            .wrap(jwt_middleware)
+            .route("", web::get().to(get_friends_handler))
+            .route("/requests", web::get().to(get_friend_requests_handler))
+            .route("/requests", web::post().to(send_friend_request_handler))
+            .route(
+                "/requests/{user_id}/accept",
+                web::post().to(accept_friend_request_handler),
+            )
+            .route(
+                "/requests/{user_id}/decline",
+                web::post().to(decline_friend_request_handler),
+            )
+            .route(
+                "/requests/{user_id}",
+                web::delete().to(cancel_friend_request_handler),
+            )
+            .route("/{user_id}", web::delete().to(remove_friend_handler)),
+    );
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/friends",
+    tag = "friends",
+    params(QueryFriends),
+    responses(
+        (status = 200, description = "Friends of the caller by username", body = Response<Page<User>>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 422, description = "Invalid query", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_friends_handler(
+    service: web::Data<RelationshipServiceImpl<Postgresql, Log, RedisPublisher>>,
+    query: ValidQuery<QueryFriends>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let friends = service
+        .get_friends(&user.user_id, &query)
+        .await?
+        .with_links(&req);
+    Ok(HttpResponse::Ok().json(Response::new(friends, "Successfully got friends")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/friends/requests",
+    tag = "friends",
+    params(QueryFriendRequests),
+    responses(
+        (status = 200, description = "Pending requests, newest first", body = Response<Vec<FriendRequest>>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_friend_requests_handler(
+    service: web::Data<RelationshipServiceImpl<Postgresql, Log, RedisPublisher>>,
+    query: ValidQuery<QueryFriendRequests>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let requests = service
+        .get_friend_requests(&user.user_id, query.direction())
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(requests, "Successfully got friend requests")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/friends/requests",
+    tag = "friends",
+    request_body = SendFriendRequest,
+    responses(
+        (status = 200, description = "Request is sent, or accepted if the user already sent one", body = Response<Empty>),
+        (status = 400, description = "Request to yourself", body = ErrorBody),
+        (status = 403, description = "One of the users blocked the other", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+        (status = 409, description = "Already friends or already requested", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn send_friend_request_handler(
+    service: web::Data<RelationshipServiceImpl<Postgresql, Log, RedisPublisher>>,
+    body: ValidJson<SendFriendRequest>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    service.send_request(&user.user_id, &body).await?;
+    Ok(HttpResponse::Ok().json(Response::new(Empty, "Successfully sent friend request")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/friends/requests/{user_id}/accept",
+    tag = "friends",
+    params(("user_id" = String, Path, description = "User who sent the request")),
+    responses(
+        (status = 200, description = "Users are friends", body = Response<Empty>),
+        (status = 404, description = "Friend request not found", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn accept_friend_request_handler(
+    service: web::Data<RelationshipServiceImpl<Postgresql, Log, RedisPublisher>>,
+    path: web::Path<String>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    service
+        .accept_request(&user.user_id, &path.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(Empty, "Successfully accepted friend request")))
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/friends/requests/{user_id}/decline",
+    tag = "friends",
+    params(("user_id" = String, Path, description = "User who sent the request")),
+    responses(
+        (status = 200, description = "Request is declined", body = Response<Empty>),
+        (status = 404, description = "Friend request not found", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn decline_friend_request_handler(
+    service: web::Data<RelationshipServiceImpl<Postgresql, Log, RedisPublisher>>,
+    path: web::Path<String>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    service
+        .decline_request(&user.user_id, &path.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(Empty, "Successfully declined friend request")))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/user/friends/requests/{user_id}",
+    tag = "friends",
+    params(("user_id" = String, Path, description = "User the request was sent to")),
+    responses(
+        (status = 200, description = "Request is cancelled", body = Response<Empty>),
+        (status = 404, description = "Friend request not found", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn cancel_friend_request_handler(
+    service: web::Data<RelationshipServiceImpl<Postgresql, Log, RedisPublisher>>,
+    path: web::Path<String>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    service
+        .cancel_request(&user.user_id, &path.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(
+        Empty,
+        "Successfully cancelled friend request",
+    )))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/user/friends/{user_id}",
+    tag = "friends",
+    params(("user_id" = String, Path, description = "Friend to remove")),
+    responses(
+        (status = 200, description = "Users are no longer friends", body = Response<Empty>),
+        (status = 404, description = "Friend not found", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn remove_friend_handler(
+    service: web::Data<RelationshipServiceImpl<Postgresql, Log, RedisPublisher>>,
+    path: web::Path<String>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    service
+        .remove_friend(&user.user_id, &path.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(Response::new(Empty, "Successfully removed friend")))
+}
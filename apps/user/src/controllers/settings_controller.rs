@@ -0,0 +1,125 @@
+use actix_web::{
+    http::header::{self, HeaderMap},
+    web, HttpRequest, HttpResponse,
+};
+use auth_middleware::{guard::Guard, source::TokenSource, user::AuthenticatedUser};
+use database::pgx::Postgresql;
+use errors::{
+    error::{Error, ErrorBody},
+    response::Response,
+};
+use logger::log::Log;
+use security::{env::EnvImpl, jwt::JwtImpl};
+use validation::extractor::ValidJson;
+
+use crate::services::settings_service::{
+    Settings, SettingsPatch, SettingsService, SettingsServiceImpl, UserSettings,
+};
+
+pub fn settings_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let jwt_middleware = Guard::new(jwt.clone())
+        .sources(vec![
+            TokenSource::authorization(),
+            TokenSource::cookie("token"),
+        ])
+        .kinds(&["auth_token"]);
+    config.service(
+        web::scope("/user/settings")
+            .wrap(jwt_middleware)
+            .route("", web::get().to(get_settings_handler))
+            .route("", web::patch().to(update_settings_handler)),
+    );
+}
+
+fn etag(version: u32) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Version named by `If-Match`, `None` for `*`.
+fn if_match(headers: &HeaderMap) -> Result<Option<u32>, Error> {
+    let value = headers
+        .get(header::IF_MATCH)
+        .ok_or_else(|| {
+            Error::PreconditionRequired(
+                "If-Match with the ETag of the settings is required".to_string(),
+            )
+        })?
+        .to_str()
+        .unwrap_or_default()
+        .trim();
+    if value == "*" {
+        return Ok(None);
+    }
+    value
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .parse()
+        .map(Some)
+        .map_err(|_| Error::PreconditionFailed("If-Match is not a settings ETag".to_string()))
+}
+
+fn settings_response(settings: UserSettings, message: &str) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((header::ETAG, etag(settings.version())))
+        .json(Response::new(settings, message))
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/settings",
+    tag = "settings",
+    responses(
+        (status = 200, description = "Settings of the caller, the `ETag` header holds their version", body = Response<UserSettings>),
+        (status = 304, description = "`If-None-Match` holds the current version"),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_settings_handler(
+    service: web::Data<SettingsServiceImpl<Postgresql, Log>>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let settings = service.get_settings(&user.user_id).await?;
+    let current = etag(settings.version());
+    let not_modified = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.trim().trim_start_matches("W/") == current);
+    if not_modified {
+        return Ok(HttpResponse::NotModified()
+            .insert_header((header::ETAG, current))
+            .finish());
+    }
+    Ok(settings_response(settings, "Successfully got settings"))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/user/settings",
+    tag = "settings",
+    request_body(content = Settings, content_type = "application/merge-patch+json", description = "Only the keys to change, `null` resets a key to its default"),
+    params(("If-Match" = String, Header, description = "`ETag` of the settings being changed, `*` overwrites any version")),
+    responses(
+        (status = 200, description = "Updated settings with their new `ETag`", body = Response<UserSettings>),
+        (status = 400, description = "Body is not a JSON object", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 412, description = "Settings changed since the `ETag` was read", body = ErrorBody),
+        (status = 422, description = "Unknown setting or invalid value", body = ErrorBody),
+        (status = 428, description = "`If-Match` is missing", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn update_settings_handler(
+    service: web::Data<SettingsServiceImpl<Postgresql, Log>>,
+    body: ValidJson<SettingsPatch>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let version = if_match(req.headers())?;
+    let settings = service
+        .update_settings(&user.user_id, version, &body)
+        .await?;
+    Ok(settings_response(settings, "Successfully updated settings"))
+}
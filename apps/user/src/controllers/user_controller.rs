@@ -1,69 +1,353 @@
-use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use actix_multipart::Multipart;
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use auth_middleware::{
+    guard::Guard, rbac::USERS_WRITE, source::TokenSource, user::AuthenticatedUser,
+};
 use database::pgx::Postgresql;
+use errors::{
+    error::{Error, ErrorBody},
+    response::Response,
+};
+use futures::TryStreamExt;
 use logger::log::Log;
+use pagination::page::Page;
+use security::{env::EnvImpl, hasher::Bcrypt, jwt::JwtImpl};
+use storage::blob::AnyBlobStore;
+use utoipa::ToSchema;
+use validation::extractor::{ValidJson, ValidQuery};
 
-use crate::{
-    middlewares::jwt_middleware::Middleware,
-    services::user_service::{QueryUser, UpdateUser, UserService, UserServiceImpl},
+use crate::services::user_service::{
+    AccountDeletion, AvatarSize, DeleteAccount, QueryUser, SearchUser, UpdateProfile, UpdateUser,
+    User, UserService, UserServiceImpl, UsernameChange, MAX_AVATAR_BYTES,
 };
 
-pub fn user_controller(config: &mut web::ServiceConfig) {
-    let jwt_middleware = Middleware {
-        roles: vec!["auth_token".to_string()],
-    };
+pub fn user_controller(config: &mut web::ServiceConfig, jwt: &JwtImpl<EnvImpl>) {
+    let jwt_middleware = Guard::new(jwt.clone())
+        .sources(vec![
+            TokenSource::authorization(),
+            TokenSource::cookie("token"),
+        ])
+        .kinds(&["auth_token"]);
     config.service(
         web::scope("/user")
             .wrap(jwt_middleware)
             .route("/profile", web::get().to(get_user_handler))
+            .route("/profile", web::put().to(update_profile_handler))
+            .route("/profile", web::delete().to(delete_account_handler))
+            .route(
+                "/profile/usernames",
+                web::get().to(get_username_history_handler),
+            )
+            .route("/profile/avatar", web::post().to(upload_avatar_handler))
+            .route("/profile/avatar", web::delete().to(remove_avatar_handler))
+            .route("/search", web::get().to(search_users_handler))
+            .route(
+                "/{user_id}/avatar/{size}",
+                web::get().to(get_avatar_handler),
+            )
             .route("/{user_id}", web::get().to(get_user_by_id_handler))
             .route("/", web::get().to(get_users_handler))
-            .route("/", web::put().to(update_user_with_id_handler)),
+            .route("/{user_id}", web::patch().to(update_user_with_id_handler)),
     );
 }
 
+#[utoipa::path(
+    get,
+    path = "/user/profile",
+    tag = "user",
+    responses(
+        (status = 200, description = "The signed in user", body = Response<User>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
 async fn get_user_handler(
-    service: web::Data<UserServiceImpl<Postgresql, Log>>,
-    req: HttpRequest,
-) -> HttpResponse {
-    if let Some(user_id) = req.extensions().get::<String>() {
-        match service.get_user_by_id(user_id).await {
-            Ok(user) => HttpResponse::Ok().json(user),
-            Err(err) => HttpResponse::InternalServerError().json(err),
-        }
-    } else {
-        HttpResponse::NotFound().json("User id not found in request")
-    }
+    service: web::Data<UserServiceImpl<Postgresql, Log, AnyBlobStore, Bcrypt>>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let user = service.get_user_by_id(&user.user_id).await?;
+    Ok(HttpResponse::Ok().json(Response::new(user, "Successfully got user")))
 }
 
+#[utoipa::path(
+    get,
+    path = "/user/{user_id}",
+    tag = "user",
+    params(("user_id" = String, Path, description = "User id")),
+    responses(
+        (status = 200, description = "The user", body = Response<User>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
 async fn get_user_by_id_handler(
-    service: web::Data<UserServiceImpl<Postgresql, Log>>,
+    service: web::Data<UserServiceImpl<Postgresql, Log, AnyBlobStore, Bcrypt>>,
     path: web::Path<String>,
-) -> HttpResponse {
+) -> Result<HttpResponse, Error> {
     let user_id = path.into_inner();
-    match service.get_user_by_id(&user_id).await {
-        Ok(user) => HttpResponse::Ok().json(user),
-        Err(err) => HttpResponse::InternalServerError().json(err),
-    }
+    let user = service.get_user_by_id(&user_id).await?;
+    Ok(HttpResponse::Ok().json(Response::new(user, "Successfully got user")))
 }
 
+#[utoipa::path(
+    get,
+    path = "/user/",
+    tag = "user",
+    params(QueryUser),
+    responses(
+        (status = 200, description = "Users matching the query", body = Response<Page<User>>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 422, description = "Invalid query", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
 async fn get_users_handler(
-    service: web::Data<UserServiceImpl<Postgresql, Log>>,
-    query: web::Query<QueryUser>,
-) -> HttpResponse {
-    match service.get_users(&query).await {
-        Ok(users) => HttpResponse::Ok().json(users),
-        Err(err) => HttpResponse::InternalServerError().json(err),
-    }
+    service: web::Data<UserServiceImpl<Postgresql, Log, AnyBlobStore, Bcrypt>>,
+    query: ValidQuery<QueryUser>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let users = service
+        .get_users(&user.user_id, &query)
+        .await?
+        .with_links(&req);
+    Ok(HttpResponse::Ok().json(Response::new(users, "Successfully got users")))
 }
 
+#[utoipa::path(
+    get,
+    path = "/user/search",
+    tag = "user",
+    params(SearchUser),
+    responses(
+        (status = 200, description = "Best matches first", body = Response<Vec<User>>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 422, description = "Invalid query", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn search_users_handler(
+    service: web::Data<UserServiceImpl<Postgresql, Log, AnyBlobStore, Bcrypt>>,
+    query: ValidQuery<SearchUser>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let users = service.search_users(&user.user_id, &query).await?;
+    Ok(HttpResponse::Ok().json(Response::new(users, "Successfully searched users")))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/user/{user_id}",
+    tag = "user",
+    params(("user_id" = String, Path, description = "User id")),
+    request_body = UpdateUser,
+    responses(
+        (status = 200, description = "Only the fields provided are updated", body = Response<User>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "Updating another user without `users:write`", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+        (status = 409, description = "Username is already taken", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+        (status = 429, description = "Username was changed within the last 30 days", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
 async fn update_user_with_id_handler(
-    service: web::Data<UserServiceImpl<Postgresql, Log>>,
+    service: web::Data<UserServiceImpl<Postgresql, Log, AnyBlobStore, Bcrypt>>,
     path: web::Path<String>,
-    body: web::Json<UpdateUser>,
-) -> HttpResponse {
+    body: ValidJson<UpdateUser>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
     let user_id = path.into_inner();
-    match service.update_user(&user_id, &body).await {
-        Ok(user) => HttpResponse::Ok().json(user),
-        Err(err) => HttpResponse::InternalServerError().json(err),
+    // Anyone may edit their own account, editing others needs users:write
+    if user.user_id != user_id && !user.has_scope(USERS_WRITE) {
+        return Err(Error::Forbidden(
+            "Missing permission to update this user".to_string(),
+        ));
     }
+    let user = service.update_user(&user_id, &body).await?;
+    Ok(HttpResponse::Ok().json(Response::new(user, "Successfully updated user")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/profile/usernames",
+    tag = "user",
+    responses(
+        (status = 200, description = "Usernames the signed in user gave up, newest first", body = Response<Vec<UsernameChange>>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_username_history_handler(
+    service: web::Data<UserServiceImpl<Postgresql, Log, AnyBlobStore, Bcrypt>>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let history = service.get_username_history(&user.user_id).await?;
+    Ok(HttpResponse::Ok().json(Response::new(history, "Successfully got username history")))
+}
+
+/// Multipart body of an avatar upload, only used for the OpenAPI document.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+struct AvatarUpload {
+    /// PNG, JPEG, GIF or WebP image of at most 5 MiB
+    #[schema(value_type = String, format = Binary)]
+    avatar: Vec<u8>,
+}
+
+/// Reads the `avatar` field of a multipart body, stopping as soon as the
+/// body grows past [`MAX_AVATAR_BYTES`] instead of buffering all of it.
+async fn read_avatar(mut payload: Multipart) -> Result<Vec<u8>, Error> {
+    let too_large =
+        || Error::PayloadTooLarge(format!("Avatar must be at most {} bytes", MAX_AVATAR_BYTES));
+    let mut avatar = None;
+    let mut received = 0;
+    while let Some(mut field) = payload
+        .try_next()
+        .await
+        .map_err(|e| Error::BadRequest(e.to_string()))?
+    {
+        let is_avatar = field.name() == Some("avatar");
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field
+            .try_next()
+            .await
+            .map_err(|e| Error::BadRequest(e.to_string()))?
+        {
+            received += chunk.len();
+            if received > MAX_AVATAR_BYTES {
+                return Err(too_large());
+            }
+            if is_avatar {
+                bytes.extend_from_slice(&chunk);
+            }
+        }
+        if is_avatar {
+            avatar = Some(bytes);
+        }
+    }
+    avatar.ok_or_else(|| Error::BadRequest("Missing avatar field".to_string()))
+}
+
+#[utoipa::path(
+    put,
+    path = "/user/profile",
+    tag = "user",
+    request_body = UpdateProfile,
+    responses(
+        (status = 200, description = "The updated user", body = Response<User>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn update_profile_handler(
+    service: web::Data<UserServiceImpl<Postgresql, Log, AnyBlobStore, Bcrypt>>,
+    body: ValidJson<UpdateProfile>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let user = service.update_profile(&user.user_id, &body).await?;
+    Ok(HttpResponse::Ok().json(Response::new(user, "Successfully updated profile")))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/user/profile",
+    tag = "user",
+    request_body = DeleteAccount,
+    responses(
+        (status = 202, description = "Deletion is scheduled and every session is revoked", body = Response<AccountDeletion>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 403, description = "Password is incorrect", body = ErrorBody),
+        (status = 409, description = "Deletion is already scheduled", body = ErrorBody),
+        (status = 422, description = "Invalid request body", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn delete_account_handler(
+    service: web::Data<UserServiceImpl<Postgresql, Log, AnyBlobStore, Bcrypt>>,
+    body: ValidJson<DeleteAccount>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let deletion = service.delete_account(&user.user_id, &body).await?;
+    Ok(HttpResponse::Accepted().json(Response::new(
+        deletion,
+        "Successfully scheduled account deletion",
+    )))
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/profile/avatar",
+    tag = "user",
+    request_body(content = AvatarUpload, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "The user with the new avatar", body = Response<User>),
+        (status = 400, description = "Missing avatar field or undecodable image", body = ErrorBody),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 413, description = "Avatar is larger than 5 MiB", body = ErrorBody),
+        (status = 415, description = "Avatar is not a PNG, JPEG, GIF or WebP image", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn upload_avatar_handler(
+    service: web::Data<UserServiceImpl<Postgresql, Log, AnyBlobStore, Bcrypt>>,
+    payload: Multipart,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let avatar = read_avatar(payload).await?;
+    let user = service.update_avatar(&user.user_id, avatar).await?;
+    Ok(HttpResponse::Ok().json(Response::new(user, "Successfully updated avatar")))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/user/profile/avatar",
+    tag = "user",
+    responses(
+        (status = 200, description = "The user without an avatar", body = Response<User>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn remove_avatar_handler(
+    service: web::Data<UserServiceImpl<Postgresql, Log, AnyBlobStore, Bcrypt>>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, Error> {
+    let user = service.remove_avatar(&user.user_id).await?;
+    Ok(HttpResponse::Ok().json(Response::new(user, "Successfully removed avatar")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/{user_id}/avatar/{size}",
+    tag = "user",
+    params(
+        ("user_id" = String, Path, description = "User id"),
+        ("size" = AvatarSize, Path, description = "Thumbnail size"),
+    ),
+    responses(
+        (status = 200, description = "PNG thumbnail", content_type = "image/png", body = Vec<u8>),
+        (status = 401, description = "Missing or invalid auth token", body = ErrorBody),
+        (status = 404, description = "User has no avatar", body = ErrorBody),
+    ),
+    security(("bearer" = []), ("cookie" = []))
+)]
+async fn get_avatar_handler(
+    service: web::Data<UserServiceImpl<Postgresql, Log, AnyBlobStore, Bcrypt>>,
+    path: web::Path<(String, AvatarSize)>,
+) -> Result<HttpResponse, Error> {
+    let (user_id, size) = path.into_inner();
+    let avatar = service.get_avatar(&user_id, size).await?;
+    // Avatar URLs carry a version, a new upload gets a new URL
+    Ok(HttpResponse::Ok()
+        .content_type(storage::mime::PNG)
+        .insert_header((
+            header::CACHE_CONTROL,
+            "private, max-age=31536000, immutable",
+        ))
+        .body(avatar))
 }
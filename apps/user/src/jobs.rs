@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use actix_web::web;
+use database::pgx::Postgresql;
+use logger::log::Log;
+use presence::store::RedisPresence;
+use security::hasher::Bcrypt;
+use storage::blob::AnyBlobStore;
+
+use crate::services::{
+    export_service::{ExportService, ExportServiceImpl},
+    presence_service::{PresenceService, PresenceServiceImpl},
+    user_service::{UserService, UserServiceImpl},
+};
+
+/// How often every instance looks for work. The queries claim rows
+/// atomically, so instances never do the same work twice.
+const INTERVAL: Duration = Duration::from_secs(60);
+
+/// Hard deletes accounts past their grace period, builds queued data exports,
+/// removes expired ones and records who went offline. Failures are logged by the services and retried
+/// on the next tick.
+pub async fn run(
+    users: web::Data<UserServiceImpl<Postgresql, Log, AnyBlobStore, Bcrypt>>,
+    exports: web::Data<ExportServiceImpl<Postgresql, Log, AnyBlobStore>>,
+    presence: web::Data<PresenceServiceImpl<Postgresql, Log, RedisPresence>>,
+) {
+    let mut interval = tokio::time::interval(INTERVAL);
+    loop {
+        interval.tick().await;
+        let _ = users.purge_deleted_accounts().await;
+        while let Ok(true) = exports.process_next().await {}
+        let _ = exports.expire_exports().await;
+        let _ = presence.record_offline().await;
+    }
+}
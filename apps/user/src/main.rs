@@ -1,25 +1,102 @@
-use actix_web::{web, App, HttpServer};
-use controllers::user_controller::user_controller;
+use actix_web::{middleware::from_fn, web, App, HttpServer};
+use controllers::{
+    admin_controller::admin_controller,
+    export_controller::export_controller,
+    presence_controller::{presence_controller, touch_presence},
+    privacy_controller::{internal_privacy_controller, privacy_controller},
+    relationship_controller::relationship_controller,
+    settings_controller::settings_controller,
+    user_controller::user_controller,
+};
 use database::pgx::Postgresql;
+use events::publisher::RedisPublisher;
 use logger::log::Log;
-use security::env::EnvImpl;
-use services::user_service::UserServiceImpl;
+use pagination::cursor::CursorCodec;
+use presence::store::RedisPresence;
+use security::{env::EnvImpl, hasher::Bcrypt, jwt::JwtImpl};
+use services::{
+    admin_service::AdminServiceImpl, export_service::ExportServiceImpl,
+    presence_service::PresenceServiceImpl, privacy_service::PrivacyServiceImpl,
+    relationship_service::RelationshipServiceImpl, settings_service::SettingsServiceImpl,
+    user_service::UserServiceImpl,
+};
+use storage::blob;
 
 mod controllers;
-mod middlewares;
+mod jobs;
+mod openapi;
 mod services;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let env = EnvImpl::default();
-    let db = Postgresql::new(env).await;
-    let logger = Log::default();
-    let service = UserServiceImpl::new(db, logger);
+    let db = Postgresql::new(EnvImpl).await;
+    let jwt = JwtImpl::new(EnvImpl);
+    let logger = Log;
+    let blobs = blob::from_env(EnvImpl);
+    let cursors = CursorCodec::from_env(EnvImpl);
+    let service = UserServiceImpl::new(db, logger, blobs, Bcrypt, cursors.clone());
+    let relationship_service = RelationshipServiceImpl::new(
+        Postgresql::new(EnvImpl).await,
+        Log,
+        RedisPublisher::new(EnvImpl),
+        cursors.clone(),
+    );
+    let privacy_service = PrivacyServiceImpl::new(
+        Postgresql::new(EnvImpl).await,
+        Log,
+        RedisPublisher::new(EnvImpl),
+    );
+    let admin_service = AdminServiceImpl::new(Postgresql::new(EnvImpl).await, Log, cursors);
+    let export_service =
+        ExportServiceImpl::new(Postgresql::new(EnvImpl).await, Log, blob::from_env(EnvImpl));
+    let presence_service = PresenceServiceImpl::new(
+        Postgresql::new(EnvImpl).await,
+        Log,
+        RedisPresence::new(EnvImpl),
+    );
+    let settings_service = SettingsServiceImpl::new(Postgresql::new(EnvImpl).await, Log);
     let web_service = web::Data::new(service);
+    let relationship_service_data = web::Data::new(relationship_service);
+    let privacy_service_data = web::Data::new(privacy_service);
+    let export_service_data = web::Data::new(export_service);
+    let admin_service_data = web::Data::new(admin_service);
+    let presence_service_data = web::Data::new(presence_service);
+    let settings_service_data = web::Data::new(settings_service);
+    actix_web::rt::spawn(jobs::run(
+        web_service.clone(),
+        export_service_data.clone(),
+        presence_service_data.clone(),
+    ));
     HttpServer::new(move || {
-        App::new()
+        let app = App::new()
+            .wrap(from_fn(touch_presence))
             .app_data(web_service.clone())
-            .configure(user_controller)
+            .app_data(relationship_service_data.clone())
+            .app_data(privacy_service_data.clone())
+            .app_data(export_service_data.clone())
+            .app_data(admin_service_data.clone())
+            .app_data(presence_service_data.clone())
+            .app_data(settings_service_data.clone())
+            // Documentation routes live under /user too, register them first
+            .route(
+                "/user/openapi.json",
+                web::get().to(openapi::openapi_handler),
+            );
+        #[cfg(feature = "swagger-ui")]
+        let app = app.service(
+            utoipa_swagger_ui::SwaggerUi::new("/user/swagger-ui/{_:.*}")
+                .config(utoipa_swagger_ui::Config::from("../openapi.json")),
+        );
+        // Friend, privacy, block, export, presence, settings and admin routes
+        // live under /user, register them before the /user scope
+        app.configure(|config| relationship_controller(config, &jwt))
+            .configure(|config| admin_controller(config, &jwt))
+            .configure(|config| export_controller(config, &jwt))
+            .configure(|config| presence_controller(config, &jwt))
+            .configure(|config| settings_controller(config, &jwt))
+            .configure(|config| privacy_controller(config, &jwt))
+            .configure(|config| internal_privacy_controller(config, &jwt))
+            .configure(|config| user_controller(config, &jwt))
     })
     .bind("0.0.0.0:8080")?
     .run()
@@ -0,0 +1,98 @@
+use actix_web::HttpResponse;
+use auth_middleware::openapi::SecurityAddon;
+use errors::error::{ErrorBody, FieldError};
+use utoipa::OpenApi;
+
+use crate::controllers::{
+    admin_controller, export_controller, presence_controller, privacy_controller,
+    relationship_controller, settings_controller, user_controller,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "User API"),
+    paths(
+        user_controller::get_user_handler,
+        user_controller::get_user_by_id_handler,
+        user_controller::get_users_handler,
+        user_controller::search_users_handler,
+        user_controller::update_user_with_id_handler,
+        user_controller::get_username_history_handler,
+        user_controller::update_profile_handler,
+        user_controller::delete_account_handler,
+        user_controller::upload_avatar_handler,
+        user_controller::remove_avatar_handler,
+        user_controller::get_avatar_handler,
+        relationship_controller::get_friends_handler,
+        relationship_controller::get_friend_requests_handler,
+        relationship_controller::send_friend_request_handler,
+        relationship_controller::accept_friend_request_handler,
+        relationship_controller::decline_friend_request_handler,
+        relationship_controller::cancel_friend_request_handler,
+        relationship_controller::remove_friend_handler,
+        privacy_controller::get_privacy_handler,
+        privacy_controller::update_privacy_handler,
+        privacy_controller::get_blocked_handler,
+        privacy_controller::block_user_handler,
+        privacy_controller::unblock_user_handler,
+        privacy_controller::get_relationship_handler,
+        export_controller::request_export_handler,
+        export_controller::get_exports_handler,
+        export_controller::get_export_handler,
+        export_controller::download_export_handler,
+        settings_controller::get_settings_handler,
+        settings_controller::update_settings_handler,
+        presence_controller::get_presence_handler,
+        presence_controller::update_presence_handler,
+        admin_controller::get_users_handler,
+        admin_controller::get_user_handler,
+        admin_controller::suspend_user_handler,
+        admin_controller::unsuspend_user_handler,
+        admin_controller::force_password_reset_handler,
+        admin_controller::revoke_sessions_handler,
+        admin_controller::get_audit_handler,
+    ),
+    components(schemas(ErrorBody, FieldError)),
+    modifiers(&SecurityAddon),
+    tags(
+        (name = "user", description = "User profiles and lookup"),
+        (name = "friends", description = "Friend requests and friendships"),
+        (name = "privacy", description = "Privacy settings and blocked users"),
+        (name = "settings", description = "Client settings shared by the caller's devices"),
+        (name = "presence", description = "Online status and last seen times"),
+        (name = "exports", description = "Downloadable archives of everything held about the caller"),
+        (name = "admin", description = "User management for admins"),
+        (name = "internal", description = "Lookups for other services, not exposed by the gateway"),
+    )
+)]
+pub struct ApiDoc;
+
+pub async fn openapi_handler() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The committed spec is the contract the web client is built against, run
+    /// `pnpm run openapi` after changing the API.
+    #[test]
+    fn test_openapi_spec_is_up_to_date() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../www/src/app/api/user.openapi.json"
+        );
+        let spec = ApiDoc::openapi().to_pretty_json().unwrap() + "\n";
+        if std::env::var("UPDATE_OPENAPI").is_ok() {
+            std::fs::write(path, &spec).unwrap();
+            return;
+        }
+        let committed = std::fs::read_to_string(path).unwrap_or_default();
+        assert!(
+            committed == spec,
+            "{} is out of date, regenerate it with `pnpm run openapi`",
+            path
+        );
+    }
+}
@@ -0,0 +1,522 @@
+use async_trait::async_trait;
+use database::{
+    db::Database,
+    pgx::{escape_like, PgRow},
+};
+use errors::error::Error;
+use logger::logger::Logger;
+use pagination::{
+    cursor::CursorCodec,
+    page::{Page, PageRequest},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::services::user_service::{user_from_row, User, USER_COLUMNS};
+
+pub const ACTION_SUSPEND: &str = "suspend";
+pub const ACTION_UNSUSPEND: &str = "unsuspend";
+pub const ACTION_PASSWORD_RESET: &str = "password_reset";
+pub const ACTION_REVOKE_SESSIONS: &str = "revoke_sessions";
+
+/// Condition on `user_suspensions s` for suspensions in force.
+const ACTIVE_SUSPENSION: &str =
+    "s.lifted_at IS NULL AND (s.expires_at IS NULL OR s.expires_at > NOW())";
+
+/// Selects [`AdminUser`] rows, in the order [`admin_user_from_row`] reads
+/// them. Callers append the `WHERE` clause.
+fn admin_user_sql() -> String {
+    format!(
+        "SELECT {}, \
+           COALESCE((SELECT string_agg(r.name, ',' ORDER BY r.name) FROM user_roles ur \
+             JOIN roles r ON r.id = ur.role_id WHERE ur.user_id = users.id), ''), \
+           COALESCE(s.id, ''), COALESCE(s.reason, ''), COALESCE(s.suspended_by, ''), \
+           COALESCE(to_json(s.created_at) #>> '{{}}', ''), \
+           COALESCE(to_json(s.expires_at) #>> '{{}}', ''), \
+           users.password_reset_required::TEXT, \
+           COALESCE(to_json(users.deletion_scheduled_at) #>> '{{}}', '') \
+         FROM users LEFT JOIN LATERAL ( \
+           SELECT * FROM user_suspensions s WHERE s.user_id = users.id AND {} \
+           ORDER BY s.created_at DESC LIMIT 1) s ON TRUE",
+        USER_COLUMNS, ACTIVE_SUSPENSION
+    )
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct Suspension {
+    id: String,
+    reason: String,
+    /// Admin who suspended the user
+    suspended_by: String,
+    created_at: String,
+    /// Suspended until lifted when missing
+    expires_at: Option<String>,
+}
+
+/// A user with what only admins get to see.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct AdminUser {
+    user: User,
+    roles: Vec<String>,
+    /// The suspension in force, if any
+    suspension: Option<Suspension>,
+    password_reset_required: bool,
+    deletion_scheduled_at: Option<String>,
+}
+
+fn admin_user_from_row(row: &PgRow) -> AdminUser {
+    let optional = |value: String| (!value.is_empty()).then_some(value);
+    let roles = row.get(10);
+    let suspension_id = row.get(11);
+    AdminUser {
+        user: user_from_row(row),
+        roles: roles
+            .split(',')
+            .filter(|role| !role.is_empty())
+            .map(str::to_string)
+            .collect(),
+        suspension: (!suspension_id.is_empty()).then(|| Suspension {
+            id: suspension_id,
+            reason: row.get(12),
+            suspended_by: row.get(13),
+            created_at: row.get(14),
+            expires_at: optional(row.get(15)),
+        }),
+        password_reset_required: row.get(16) == "true",
+        deletion_scheduled_at: optional(row.get(17)),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountStatus {
+    /// Neither suspended nor waiting for deletion
+    Active,
+    Suspended,
+    DeletionScheduled,
+}
+
+impl AccountStatus {
+    fn name(&self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Suspended => "suspended",
+            AccountStatus::DeletionScheduled => "deletion_scheduled",
+        }
+    }
+
+    fn condition(&self) -> &'static str {
+        match self {
+            AccountStatus::Active => "s.id IS NULL AND users.deletion_scheduled_at IS NULL",
+            AccountStatus::Suspended => "s.id IS NOT NULL",
+            AccountStatus::DeletionScheduled => "users.deletion_scheduled_at IS NOT NULL",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryAdminUsers {
+    /// Matches name or username
+    #[validate(length(max = 64))]
+    q: Option<String>,
+    status: Option<AccountStatus>,
+    /// Only users holding this role
+    #[validate(length(max = 64))]
+    role: Option<String>,
+    #[validate(range(min = 1, max = 100))]
+    limit: Option<u32>,
+    /// `next_cursor` or `prev_cursor` of the previous page
+    #[validate(length(max = 1024))]
+    cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
+pub struct SuspendUser {
+    #[validate(
+        length(min = 1, max = 500),
+        custom(function = "validation::rules::not_blank")
+    )]
+    reason: String,
+    /// RFC 3339 timestamp in the future, suspends until lifted when missing
+    #[validate(custom(function = "validation::rules::future_timestamp"))]
+    expires_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
+pub struct UnsuspendUser {
+    #[validate(
+        length(min = 1, max = 500),
+        custom(function = "validation::rules::not_blank")
+    )]
+    reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct RevokedSessions {
+    revoked: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct AuditEntry {
+    id: String,
+    /// Admin who acted
+    actor_id: String,
+    action: String,
+    #[schema(value_type = Object)]
+    details: serde_json::Value,
+    created_at: String,
+}
+
+#[derive(Deserialize, Debug, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryAudit {
+    #[validate(range(min = 1, max = 100))]
+    limit: Option<u32>,
+    /// `next_cursor` or `prev_cursor` of the previous page
+    #[validate(length(max = 1024))]
+    cursor: Option<String>,
+}
+
+#[async_trait]
+pub trait AdminService {
+    async fn get_users(&self, query: &QueryAdminUsers) -> Result<Page<AdminUser>, Error>;
+    async fn get_user(&self, user_id: &str) -> Result<AdminUser, Error>;
+    /// Suspends the user and revokes their sessions.
+    async fn suspend_user(
+        &self,
+        admin_id: &str,
+        user_id: &str,
+        data: &SuspendUser,
+    ) -> Result<AdminUser, Error>;
+    async fn unsuspend_user(
+        &self,
+        admin_id: &str,
+        user_id: &str,
+        data: &UnsuspendUser,
+    ) -> Result<AdminUser, Error>;
+    /// Makes the user choose a new password before signing in again and
+    /// revokes their sessions.
+    async fn force_password_reset(&self, admin_id: &str, user_id: &str)
+        -> Result<AdminUser, Error>;
+    async fn revoke_sessions(
+        &self,
+        admin_id: &str,
+        user_id: &str,
+    ) -> Result<RevokedSessions, Error>;
+    /// Admin actions on the user, newest first.
+    async fn get_audit(&self, user_id: &str, query: &QueryAudit)
+        -> Result<Page<AuditEntry>, Error>;
+}
+
+pub struct AdminServiceImpl<D: Database<PgRow>, L: Logger> {
+    db: D,
+    logger: L,
+    cursors: CursorCodec,
+}
+
+impl<D: Database<PgRow>, L: Logger> AdminServiceImpl<D, L> {
+    pub fn new(db: D, logger: L, cursors: CursorCodec) -> Self {
+        Self {
+            db,
+            logger,
+            cursors,
+        }
+    }
+
+    /// Records an admin action. Runs after the action, so a failure is
+    /// returned to the admin to retry rather than lost.
+    async fn audit(
+        &self,
+        admin_id: &str,
+        user_id: &str,
+        action: &str,
+        details: serde_json::Value,
+    ) -> Result<(), Error> {
+        let message = format!("admin {} performs {} on user {}", admin_id, action, user_id);
+        self.logger.info("admin_service::audit", &message);
+        self.db
+            .execute(
+                "INSERT INTO admin_audit_log (actor_id, target_id, action, details) VALUES ($1, $2, $3, $4::JSONB)",
+                &[
+                    &admin_id.to_string(),
+                    &user_id.to_string(),
+                    &action.to_string(),
+                    &details.to_string(),
+                ],
+            )
+            .await
+            .inspect_err(|e| {
+                let message = format!("failed to audit {} on user {}: {}", action, user_id, e);
+                self.logger.error("admin_service::audit", &message);
+            })?;
+        Ok(())
+    }
+
+    async fn delete_sessions(&self, user_id: &str) -> Result<u64, Error> {
+        self.db
+            .execute(
+                "DELETE FROM sessions WHERE user_id = $1",
+                &[&user_id.to_string()],
+            )
+            .await
+    }
+}
+
+#[async_trait]
+impl<D: Database<PgRow> + Send + Sync, L: Logger + Send + Sync> AdminService
+    for AdminServiceImpl<D, L>
+{
+    async fn get_users(&self, query: &QueryAdminUsers) -> Result<Page<AdminUser>, Error> {
+        let q = query.q.clone().unwrap_or_default();
+        let role = query.role.clone().unwrap_or_default();
+        let context = format!(
+            "admin_users:{}:{}:{}",
+            q,
+            query.status.map(|status| status.name()).unwrap_or_default(),
+            role
+        );
+        let request = PageRequest::new(
+            &self.cursors,
+            &context,
+            query.limit.unwrap_or(20),
+            query.cursor.as_deref(),
+        )?;
+        let pattern = format!("%{}%", escape_like(&q));
+        let after = request.key().first().cloned().unwrap_or_default();
+        let sql =
+            format!(
+            "{} WHERE (users.username ILIKE $1 ESCAPE '\\' OR users.name ILIKE $1 ESCAPE '\\') \
+             AND ($2 = '' OR EXISTS (SELECT 1 FROM user_roles ur JOIN roles r ON r.id = ur.role_id \
+               WHERE ur.user_id = users.id AND r.name = $2)) \
+             AND {} AND ($3 = '' OR users.username {} $3) \
+             ORDER BY users.username {} LIMIT $4::TEXT::INT",
+            admin_user_sql(),
+            query.status.map(|status| status.condition()).unwrap_or("TRUE"),
+            request.comparator(),
+            request.order()
+        );
+        match self
+            .db
+            .query(&sql, &[&pattern, &role, &after, &request.fetch_limit()])
+            .await
+        {
+            Ok(rows) => {
+                let users = rows.iter().map(admin_user_from_row).collect();
+                Ok(request.page(&self.cursors, users, |user: &AdminUser| {
+                    vec![user.user.username().to_string()]
+                }))
+            }
+            Err(e) => {
+                let message = format!("failed to query users: {}", e);
+                self.logger.error("admin_service::get_users", &message);
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_user(&self, user_id: &str) -> Result<AdminUser, Error> {
+        let sql = format!("{} WHERE users.id = $1", admin_user_sql());
+        self.db
+            .query_one(&sql, &[&user_id.to_string()])
+            .await
+            .map(|row| admin_user_from_row(&row))
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound("User not found".to_string()),
+                e => e,
+            })
+    }
+
+    async fn suspend_user(
+        &self,
+        admin_id: &str,
+        user_id: &str,
+        data: &SuspendUser,
+    ) -> Result<AdminUser, Error> {
+        if admin_id == user_id {
+            return Err(Error::BadRequest("Cannot suspend yourself".to_string()));
+        }
+        if self.get_user(user_id).await?.suspension.is_some() {
+            return Err(Error::Conflict("User is already suspended".to_string()));
+        }
+        let expires_at = data.expires_at.clone().unwrap_or_default();
+        self.db
+            .execute(
+                "INSERT INTO user_suspensions (user_id, reason, suspended_by, expires_at) \
+                 VALUES ($1, $2, $3, NULLIF($4, '')::TIMESTAMPTZ)",
+                &[
+                    &user_id.to_string(),
+                    &data.reason,
+                    &admin_id.to_string(),
+                    &expires_at,
+                ],
+            )
+            .await?;
+        self.delete_sessions(user_id).await?;
+        self.audit(
+            admin_id,
+            user_id,
+            ACTION_SUSPEND,
+            json!({ "reason": data.reason, "expires_at": data.expires_at }),
+        )
+        .await?;
+        self.get_user(user_id).await
+    }
+
+    async fn unsuspend_user(
+        &self,
+        admin_id: &str,
+        user_id: &str,
+        data: &UnsuspendUser,
+    ) -> Result<AdminUser, Error> {
+        let sql = format!(
+            "UPDATE user_suspensions s SET lifted_at = NOW(), lifted_by = $2 \
+             WHERE s.user_id = $1 AND {}",
+            ACTIVE_SUSPENSION
+        );
+        let lifted = self
+            .db
+            .execute(&sql, &[&user_id.to_string(), &admin_id.to_string()])
+            .await?;
+        if lifted == 0 {
+            return Err(Error::NotFound("User is not suspended".to_string()));
+        }
+        self.audit(
+            admin_id,
+            user_id,
+            ACTION_UNSUSPEND,
+            json!({ "reason": data.reason }),
+        )
+        .await?;
+        self.get_user(user_id).await
+    }
+
+    async fn force_password_reset(
+        &self,
+        admin_id: &str,
+        user_id: &str,
+    ) -> Result<AdminUser, Error> {
+        let updated = self
+            .db
+            .execute(
+                "UPDATE users SET password_reset_required = TRUE, updated_at = NOW() WHERE id = $1",
+                &[&user_id.to_string()],
+            )
+            .await?;
+        if updated == 0 {
+            return Err(Error::NotFound("User not found".to_string()));
+        }
+        let revoked = self.delete_sessions(user_id).await?;
+        self.audit(
+            admin_id,
+            user_id,
+            ACTION_PASSWORD_RESET,
+            json!({ "revoked_sessions": revoked }),
+        )
+        .await?;
+        self.get_user(user_id).await
+    }
+
+    async fn revoke_sessions(
+        &self,
+        admin_id: &str,
+        user_id: &str,
+    ) -> Result<RevokedSessions, Error> {
+        // Make sure a typo in the id is not audited as a no-op
+        self.get_user(user_id).await?;
+        let revoked = self.delete_sessions(user_id).await?;
+        self.audit(
+            admin_id,
+            user_id,
+            ACTION_REVOKE_SESSIONS,
+            json!({ "revoked_sessions": revoked }),
+        )
+        .await?;
+        Ok(RevokedSessions { revoked })
+    }
+
+    async fn get_audit(
+        &self,
+        user_id: &str,
+        query: &QueryAudit,
+    ) -> Result<Page<AuditEntry>, Error> {
+        let request = PageRequest::new(
+            &self.cursors,
+            &format!("audit:{}", user_id),
+            query.limit.unwrap_or(20),
+            query.cursor.as_deref(),
+        )?
+        .descending();
+        let after = request.key().first().cloned().unwrap_or_default();
+        let sql = format!(
+            "SELECT id::TEXT, actor_id, action, details::TEXT, to_json(created_at) #>> '{{}}' \
+             FROM admin_audit_log WHERE target_id = $1 \
+             AND ($2 = '' OR id {} NULLIF($2, '')::BIGINT) \
+             ORDER BY id {} LIMIT $3::TEXT::INT",
+            request.comparator(),
+            request.order()
+        );
+        let rows = self
+            .db
+            .query(
+                &sql,
+                &[&user_id.to_string(), &after, &request.fetch_limit()],
+            )
+            .await?;
+        let entries = rows
+            .iter()
+            .map(|row| AuditEntry {
+                id: row.get(0),
+                actor_id: row.get(1),
+                action: row.get(2),
+                details: serde_json::from_str(&row.get(3)).unwrap_or_default(),
+                created_at: row.get(4),
+            })
+            .collect();
+        Ok(request.page(&self.cursors, entries, |entry: &AuditEntry| {
+            vec![entry.id.clone()]
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::db::MockDatabase;
+    use logger::log::Log;
+
+    use super::*;
+
+    fn service(db: MockDatabase<PgRow>) -> AdminServiceImpl<MockDatabase<PgRow>, Log> {
+        AdminServiceImpl::new(db, Log, CursorCodec::new(b"secret"))
+    }
+
+    #[tokio::test]
+    async fn test_cannot_suspend_yourself() {
+        let data = SuspendUser {
+            reason: "spam".to_string(),
+            expires_at: None,
+        };
+        let result = service(MockDatabase::new())
+            .suspend_user("admin", "admin", &data)
+            .await;
+        assert!(matches!(result, Err(Error::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn test_unsuspend_requires_suspension() {
+        let mut db = MockDatabase::new();
+        db.expect_execute()
+            .times(1)
+            .returning(|_, _| Box::pin(async { Ok(0) }));
+        let data = UnsuspendUser {
+            reason: "appeal".to_string(),
+        };
+        let result = service(db).unsuspend_user("admin", "alice", &data).await;
+        assert_eq!(
+            result.unwrap_err(),
+            Error::NotFound("User is not suspended".to_string())
+        );
+    }
+}
@@ -0,0 +1,343 @@
+use async_trait::async_trait;
+use database::{db::Database, pgx::PgRow};
+use errors::error::Error;
+use logger::logger::Logger;
+use serde::{Deserialize, Serialize};
+use storage::{archive, blob::BlobStore};
+use utoipa::ToSchema;
+
+use crate::services::user_service::{avatar_key, AvatarSize};
+
+/// Days a finished export can be downloaded before it is removed.
+pub const EXPORT_RETENTION_DAYS: u32 = 7;
+
+/// Columns selected for [`DataExport`], in the order [`export_from_row`]
+/// reads them.
+const EXPORT_COLUMNS: &str = "id, status, to_json(created_at) #>> '{}', \
+    COALESCE(to_json(completed_at) #>> '{}', ''), COALESCE(to_json(expires_at) #>> '{}', '')";
+
+/// Everything held about the user `$1` as one JSON document, chat data
+/// included. Passwords and token identifiers are left out, attachments only
+/// come with their metadata.
+const EXPORT_SQL: &str = "SELECT json_build_object( \
+    'exported_at', NOW(), \
+    'profile', (SELECT json_build_object( \
+        'id', id, 'name', name, 'username', username, 'display_name', display_name, \
+        'bio', bio, 'status_text', status_text, 'timezone', timezone, \
+        'last_seen_at', last_seen_at, 'created_at', created_at, 'updated_at', updated_at) \
+        FROM users WHERE id = $1), \
+    'username_history', COALESCE((SELECT json_agg(json_build_object( \
+        'old_username', old_username, 'new_username', new_username, \
+        'changed_at', changed_at) ORDER BY changed_at) \
+        FROM username_history WHERE user_id = $1), '[]'), \
+    'privacy', (SELECT json_build_object( \
+        'dm_policy', dm_policy, 'search_visibility', search_visibility, \
+        'last_seen_visibility', last_seen_visibility) FROM users WHERE id = $1), \
+    'settings', COALESCE((SELECT data FROM user_settings WHERE user_id = $1), '{}'), \
+    'roles', COALESCE((SELECT json_agg(r.name ORDER BY r.name) FROM user_roles ur \
+        JOIN roles r ON r.id = ur.role_id WHERE ur.user_id = $1), '[]'), \
+    'relationships', COALESCE((SELECT json_agg(json_build_object( \
+        'requester', requester.username, 'addressee', addressee.username, \
+        'status', r.status, 'created_at', r.created_at) ORDER BY r.created_at) \
+        FROM user_relationships r \
+        JOIN users requester ON requester.id = r.requester_id \
+        JOIN users addressee ON addressee.id = r.addressee_id \
+        WHERE $1 IN (r.requester_id, r.addressee_id)), '[]'), \
+    'blocks', COALESCE((SELECT json_agg(json_build_object( \
+        'username', u.username, 'created_at', b.created_at) ORDER BY b.created_at) \
+        FROM user_blocks b JOIN users u ON u.id = b.blocked_id WHERE b.blocker_id = $1), '[]'), \
+    'suspensions', COALESCE((SELECT json_agg(json_build_object( \
+        'reason', reason, 'created_at', created_at, 'expires_at', expires_at, \
+        'lifted_at', lifted_at) ORDER BY created_at) \
+        FROM user_suspensions WHERE user_id = $1), '[]'), \
+    'sessions', COALESCE((SELECT json_agg(json_build_object( \
+        'created_at', created_at, 'expires_at', expires_at) ORDER BY created_at) \
+        FROM sessions WHERE user_id = $1), '[]'), \
+    'conversations', COALESCE((SELECT json_agg(json_build_object( \
+        'id', c.id, 'kind', c.kind, 'name', c.name, 'role', cm.role, 'muted', cm.muted, \
+        'joined_at', cm.joined_at, 'last_read_at', cm.last_read_at) ORDER BY cm.joined_at) \
+        FROM conversation_members cm JOIN conversations c ON c.id = cm.conversation_id \
+        WHERE cm.user_id = $1), '[]'), \
+    'messages', COALESCE((SELECT json_agg(json_build_object( \
+        'id', m.id, 'conversation_id', m.conversation_id, 'thread_id', m.thread_id, \
+        'body', m.body, 'created_at', m.created_at, 'edited_at', m.edited_at, \
+        'deleted_at', m.deleted_at, \
+        'revisions', COALESCE((SELECT json_agg(json_build_object( \
+            'body', r.body, 'replaced_at', r.replaced_at) ORDER BY r.id) \
+            FROM message_revisions r WHERE r.message_id = m.id), '[]')) ORDER BY m.id) \
+        FROM messages m WHERE m.author_id = $1), '[]'), \
+    'attachments', COALESCE((SELECT json_agg(json_build_object( \
+        'id', id, 'message_id', message_id, 'file_name', file_name, \
+        'content_type', content_type, 'size', size, 'created_at', created_at, \
+        'uploaded_at', uploaded_at) ORDER BY created_at) \
+        FROM attachments WHERE user_id = $1), '[]'), \
+    'reactions', COALESCE((SELECT json_agg(json_build_object( \
+        'message_id', message_id, 'emoji', emoji, 'created_at', created_at) ORDER BY id) \
+        FROM message_reactions WHERE user_id = $1), '[]'), \
+    'thread_subscriptions', COALESCE((SELECT json_agg(json_build_object( \
+        'thread_id', thread_id, 'created_at', created_at) ORDER BY created_at) \
+        FROM thread_subscriptions WHERE user_id = $1), '[]'), \
+    'notifications', COALESCE((SELECT json_agg(json_build_object( \
+        'kind', kind, 'conversation_id', conversation_id, 'message_id', message_id, \
+        'created_at', created_at, 'read_at', read_at) ORDER BY id) \
+        FROM notifications WHERE user_id = $1), '[]') \
+    )::TEXT";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportStatus {
+    Pending,
+    Running,
+    Ready,
+    Failed,
+}
+
+impl ExportStatus {
+    fn parse(value: &str) -> Self {
+        match value {
+            "pending" => ExportStatus::Pending,
+            "running" => ExportStatus::Running,
+            "ready" => ExportStatus::Ready,
+            _ => ExportStatus::Failed,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct DataExport {
+    id: String,
+    status: ExportStatus,
+    created_at: String,
+    completed_at: Option<String>,
+    /// When a ready archive stops being downloadable
+    expires_at: Option<String>,
+}
+
+fn export_from_row(row: &PgRow) -> DataExport {
+    let optional = |value: String| (!value.is_empty()).then_some(value);
+    DataExport {
+        id: row.get(0),
+        status: ExportStatus::parse(&row.get(1)),
+        created_at: row.get(2),
+        completed_at: optional(row.get(3)),
+        expires_at: optional(row.get(4)),
+    }
+}
+
+fn export_key(user_id: &str, export_id: &str) -> String {
+    format!("exports/{}/{}.zip", user_id, export_id)
+}
+
+#[async_trait]
+pub trait ExportService {
+    /// Queues an archive of everything held about the user, built by
+    /// [`ExportService::process_next`] in the background.
+    async fn request_export(&self, user_id: &str) -> Result<DataExport, Error>;
+    async fn get_exports(&self, user_id: &str) -> Result<Vec<DataExport>, Error>;
+    async fn get_export(&self, user_id: &str, export_id: &str) -> Result<DataExport, Error>;
+    async fn download_export(&self, user_id: &str, export_id: &str) -> Result<Vec<u8>, Error>;
+    /// Builds the oldest queued export. Returns `false` when the queue is
+    /// empty.
+    async fn process_next(&self) -> Result<bool, Error>;
+    /// Removes the archives past their retention, returns how many.
+    async fn expire_exports(&self) -> Result<usize, Error>;
+}
+
+pub struct ExportServiceImpl<D: Database<PgRow>, L: Logger, B: BlobStore> {
+    db: D,
+    logger: L,
+    blobs: B,
+}
+
+impl<D: Database<PgRow>, L: Logger, B: BlobStore> ExportServiceImpl<D, L, B> {
+    pub fn new(db: D, logger: L, blobs: B) -> Self {
+        Self { db, logger, blobs }
+    }
+
+    /// Writes `data.json` and the avatar, if any, into the archive blob.
+    async fn build(&self, user_id: &str, export_id: &str) -> Result<(), Error> {
+        let data = self
+            .db
+            .query_one(EXPORT_SQL, &[&user_id.to_string()])
+            .await?
+            .get(0);
+        let mut entries = vec![("data.json".to_string(), data.into_bytes())];
+        let version = self
+            .db
+            .query_one(
+                "SELECT avatar FROM users WHERE id = $1",
+                &[&user_id.to_string()],
+            )
+            .await?
+            .get(0);
+        if !version.is_empty() {
+            let key = avatar_key(user_id, &version, AvatarSize::Large);
+            entries.push(("avatar.png".to_string(), self.blobs.get(&key).await?));
+        }
+        let bytes = tokio::task::spawn_blocking(move || archive::zip(&entries))
+            .await
+            .map_err(|e| Error::Internal(format!("Archive task failed: {}", e)))??;
+        self.blobs
+            .put(&export_key(user_id, export_id), &bytes)
+            .await
+    }
+}
+
+#[async_trait]
+impl<D: Database<PgRow> + Send + Sync, L: Logger + Send + Sync, B: BlobStore + Send + Sync>
+    ExportService for ExportServiceImpl<D, L, B>
+{
+    async fn request_export(&self, user_id: &str) -> Result<DataExport, Error> {
+        let sql = format!(
+            "INSERT INTO data_exports (user_id) VALUES ($1) RETURNING {}",
+            EXPORT_COLUMNS
+        );
+        match self.db.query_one(&sql, &[&user_id.to_string()]).await {
+            Ok(row) => {
+                let message = format!("queued data export for user {}", user_id);
+                self.logger.info("export_service::request_export", &message);
+                Ok(export_from_row(&row))
+            }
+            Err(Error::Conflict(_)) => Err(Error::Conflict(
+                "An export is already in progress".to_string(),
+            )),
+            Err(Error::NotFound(_)) => Err(Error::NotFound("User not found".to_string())),
+            Err(e) => {
+                let message = format!("failed to queue export for user {}: {}", user_id, e);
+                self.logger
+                    .error("export_service::request_export", &message);
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_exports(&self, user_id: &str) -> Result<Vec<DataExport>, Error> {
+        let sql = format!(
+            "SELECT {} FROM data_exports WHERE user_id = $1 ORDER BY created_at DESC",
+            EXPORT_COLUMNS
+        );
+        let rows = self.db.query(&sql, &[&user_id.to_string()]).await?;
+        Ok(rows.iter().map(export_from_row).collect())
+    }
+
+    async fn get_export(&self, user_id: &str, export_id: &str) -> Result<DataExport, Error> {
+        let sql = format!(
+            "SELECT {} FROM data_exports WHERE id = $1 AND user_id = $2",
+            EXPORT_COLUMNS
+        );
+        self.db
+            .query_one(&sql, &[&export_id.to_string(), &user_id.to_string()])
+            .await
+            .map(|row| export_from_row(&row))
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound("Export not found".to_string()),
+                e => e,
+            })
+    }
+
+    async fn download_export(&self, user_id: &str, export_id: &str) -> Result<Vec<u8>, Error> {
+        let export = self.get_export(user_id, export_id).await?;
+        match export.status {
+            ExportStatus::Ready => self.blobs.get(&export_key(user_id, export_id)).await,
+            ExportStatus::Failed => Err(Error::NotFound("Export failed".to_string())),
+            ExportStatus::Pending | ExportStatus::Running => {
+                Err(Error::Conflict("Export is not ready yet".to_string()))
+            }
+        }
+    }
+
+    async fn process_next(&self) -> Result<bool, Error> {
+        // Runs cut short by a restart are picked up again after an hour
+        let rows = self
+            .db
+            .query(
+                "UPDATE data_exports SET status = 'running' WHERE id = ( \
+                   SELECT id FROM data_exports \
+                   WHERE status = 'pending' \
+                     OR (status = 'running' AND created_at < NOW() - INTERVAL '1 hour') \
+                   ORDER BY created_at LIMIT 1 FOR UPDATE SKIP LOCKED) \
+                 RETURNING id, user_id",
+                &[],
+            )
+            .await?;
+        let Some(row) = rows.first() else {
+            return Ok(false);
+        };
+        let (export_id, user_id) = (row.get(0), row.get(1));
+        match self.build(&user_id, &export_id).await {
+            Ok(()) => {
+                self.db
+                    .execute(
+                        "UPDATE data_exports SET status = 'ready', completed_at = NOW(), \
+                         expires_at = NOW() + make_interval(days => $1::TEXT::INT) WHERE id = $2",
+                        &[&EXPORT_RETENTION_DAYS.to_string(), &export_id],
+                    )
+                    .await?;
+                let message = format!("built data export {} for user {}", export_id, user_id);
+                self.logger.info("export_service::process_next", &message);
+            }
+            Err(e) => {
+                let message = format!("failed to build data export {}: {}", export_id, e);
+                self.logger.error("export_service::process_next", &message);
+                self.db
+                    .execute(
+                        "UPDATE data_exports SET status = 'failed', completed_at = NOW() WHERE id = $1",
+                        &[&export_id],
+                    )
+                    .await?;
+            }
+        }
+        Ok(true)
+    }
+
+    async fn expire_exports(&self) -> Result<usize, Error> {
+        let rows = self
+            .db
+            .query(
+                "DELETE FROM data_exports WHERE expires_at <= NOW() RETURNING id, user_id",
+                &[],
+            )
+            .await?;
+        for row in &rows {
+            let key = export_key(&row.get(1), &row.get(0));
+            if let Err(e) = self.blobs.delete_prefix(&key).await {
+                let message = format!("failed to delete {}: {}", key, e);
+                self.logger
+                    .error("export_service::expire_exports", &message);
+            }
+        }
+        Ok(rows.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::db::MockDatabase;
+    use logger::log::Log;
+    use storage::local::LocalBlobStore;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_empty_queue() {
+        let mut db = MockDatabase::new();
+        db.expect_query()
+            .returning(|_, _| Box::pin(async { Ok(vec![]) }));
+        let service = ExportServiceImpl::new(db, Log, LocalBlobStore::new("unused"));
+        assert!(!service.process_next().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_second_export_conflicts() {
+        let mut db = MockDatabase::new();
+        db.expect_query_one().returning(|_, _| {
+            Box::pin(async { Err(Error::Conflict("duplicate key".to_string())) })
+        });
+        let service = ExportServiceImpl::new(db, Log, LocalBlobStore::new("unused"));
+        let result = service.request_export("alice").await;
+        assert_eq!(
+            result.unwrap_err(),
+            Error::Conflict("An export is already in progress".to_string())
+        );
+    }
+}
@@ -1 +1,7 @@
+pub mod admin_service;
+pub mod export_service;
+pub mod presence_service;
+pub mod privacy_service;
+pub mod relationship_service;
+pub mod settings_service;
 pub mod user_service;
@@ -0,0 +1,338 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use database::{db::Database, pgx::PgRow};
+use errors::error::Error;
+use logger::logger::Logger;
+use presence::store::{PresenceStore, State};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::services::{privacy_service::Audience, user_service::not_blocked_by};
+
+/// Most users one presence lookup can ask for.
+pub const MAX_PRESENCE_IDS: usize = 100;
+
+/// Users claimed per round when recording who went offline.
+const OFFLINE_BATCH: usize = 100;
+
+/// Least time between two touches of one user, well within
+/// [`presence::store::HEARTBEAT_TTL_SECONDS`].
+const TOUCH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Touch times kept before the ones older than [`TOUCH_INTERVAL`] are
+/// dropped.
+const MAX_TOUCHED: usize = 10_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PresenceStatus {
+    Online,
+    /// Connected but inactive
+    Idle,
+    Offline,
+}
+
+impl From<Option<State>> for PresenceStatus {
+    fn from(state: Option<State>) -> Self {
+        match state {
+            Some(State::Online) => PresenceStatus::Online,
+            Some(State::Idle) => PresenceStatus::Idle,
+            None => PresenceStatus::Offline,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, ToSchema)]
+pub struct Presence {
+    user_id: String,
+    /// Always `offline` when the user's last seen visibility leaves the
+    /// caller out
+    status: PresenceStatus,
+    /// When the user went offline, missing while present or hidden
+    last_seen_at: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryPresence {
+    /// Comma separated user ids, at most 100
+    #[validate(length(min = 1, max = 4096))]
+    ids: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
+pub struct UpdatePresence {
+    /// `offline` records the last seen time right away, for signing out or
+    /// closing the app
+    status: PresenceStatus,
+}
+
+#[async_trait]
+pub trait PresenceService {
+    /// Whether activity of the user is due to be counted, at most once every
+    /// 30 seconds. Counts it as sent when it is.
+    fn claim_touch(&self, user_id: &str) -> bool;
+    /// Counts as activity, sent for authenticated requests once claimed.
+    async fn touch(&self, user_id: &str) -> Result<(), Error>;
+    /// Heartbeat from a client, keeps the user present for
+    /// [`presence::store::HEARTBEAT_TTL_SECONDS`].
+    async fn set_status(&self, user_id: &str, data: &UpdatePresence) -> Result<(), Error>;
+    /// Presence of the users in the order asked for. Unknown, blocked and
+    /// deleted users are left out.
+    async fn get_presence(
+        &self,
+        caller_id: &str,
+        query: &QueryPresence,
+    ) -> Result<Vec<Presence>, Error>;
+    /// Writes the last seen time of the users whose heartbeats ran out,
+    /// returns how many.
+    async fn record_offline(&self) -> Result<usize, Error>;
+}
+
+pub struct PresenceServiceImpl<D: Database<PgRow>, L: Logger, S: PresenceStore> {
+    db: D,
+    logger: L,
+    store: S,
+    /// When each user was last touched by this instance
+    touched: Mutex<HashMap<String, Instant>>,
+}
+
+impl<D: Database<PgRow>, L: Logger, S: PresenceStore> PresenceServiceImpl<D, L, S> {
+    pub fn new(db: D, logger: L, store: S) -> Self {
+        Self {
+            db,
+            logger,
+            store,
+            touched: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl<D: Database<PgRow> + Send + Sync, L: Logger + Send + Sync, S: PresenceStore + Send + Sync>
+    PresenceService for PresenceServiceImpl<D, L, S>
+{
+    fn claim_touch(&self, user_id: &str) -> bool {
+        let now = Instant::now();
+        let mut touched = self.touched.lock().unwrap_or_else(|e| e.into_inner());
+        if touched
+            .get(user_id)
+            .is_some_and(|at| now.duration_since(*at) < TOUCH_INTERVAL)
+        {
+            return false;
+        }
+        if touched.len() >= MAX_TOUCHED {
+            touched.retain(|_, at| now.duration_since(*at) < TOUCH_INTERVAL);
+        }
+        touched.insert(user_id.to_string(), now);
+        true
+    }
+
+    async fn touch(&self, user_id: &str) -> Result<(), Error> {
+        self.store
+            .heartbeat(user_id, State::Online)
+            .await
+            .inspect_err(|e| {
+                let message = format!("failed to touch presence of user {}: {}", user_id, e);
+                self.logger.error("presence_service::touch", &message);
+            })
+    }
+
+    async fn set_status(&self, user_id: &str, data: &UpdatePresence) -> Result<(), Error> {
+        match data.status {
+            PresenceStatus::Online => self.store.heartbeat(user_id, State::Online).await,
+            PresenceStatus::Idle => self.store.heartbeat(user_id, State::Idle).await,
+            PresenceStatus::Offline => {
+                self.store.disconnect(user_id).await?;
+                self.db
+                    .execute(
+                        "UPDATE users SET last_seen_at = NOW() WHERE id = $1",
+                        &[&user_id.to_string()],
+                    )
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn get_presence(
+        &self,
+        caller_id: &str,
+        query: &QueryPresence,
+    ) -> Result<Vec<Presence>, Error> {
+        let mut ids: Vec<String> = vec![];
+        for id in query
+            .ids
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+        {
+            if !ids.iter().any(|seen| seen == id) {
+                ids.push(id.to_string());
+            }
+        }
+        if ids.len() > MAX_PRESENCE_IDS {
+            return Err(Error::BadRequest(format!(
+                "At most {} user ids can be looked up at once",
+                MAX_PRESENCE_IDS
+            )));
+        }
+        let sql = format!(
+            "SELECT users.id, users.last_seen_visibility, \
+               COALESCE(to_json(users.last_seen_at) #>> '{{}}', ''), \
+               EXISTS (SELECT 1 FROM user_relationships r WHERE r.status = 'accepted' \
+                 AND ((r.requester_id = users.id AND r.addressee_id = $2) \
+                   OR (r.addressee_id = users.id AND r.requester_id = $2)))::TEXT \
+             FROM users WHERE users.id = ANY(string_to_array($1, ',')) \
+               AND users.deletion_scheduled_at IS NULL \
+               AND users.id NOT IN (SELECT blocker_id FROM user_blocks WHERE blocked_id = $2) \
+               AND {}",
+            not_blocked_by(2)
+        );
+        let rows = self
+            .db
+            .query(&sql, &[&ids.join(","), &caller_id.to_string()])
+            .await?;
+        let states = self.store.states(&ids).await?;
+
+        let mut presences = vec![];
+        for (id, state) in ids.iter().zip(states) {
+            let Some(row) = rows.iter().find(|row| &row.get(0) == id) else {
+                continue;
+            };
+            let visible =
+                id == caller_id || Audience::parse(&row.get(1)).allows(row.get(3) == "true");
+            let status = PresenceStatus::from(state);
+            let last_seen_at = row.get(2);
+            presences.push(if visible {
+                Presence {
+                    user_id: id.clone(),
+                    status,
+                    last_seen_at: (status == PresenceStatus::Offline && !last_seen_at.is_empty())
+                        .then_some(last_seen_at),
+                }
+            } else {
+                Presence {
+                    user_id: id.clone(),
+                    status: PresenceStatus::Offline,
+                    last_seen_at: None,
+                }
+            });
+        }
+        Ok(presences)
+    }
+
+    async fn record_offline(&self) -> Result<usize, Error> {
+        let mut recorded = 0;
+        loop {
+            let expired = self.store.claim_expired(OFFLINE_BATCH).await?;
+            if expired.is_empty() {
+                return Ok(recorded);
+            }
+            let (ids, times): (Vec<String>, Vec<String>) = expired
+                .iter()
+                .map(|(id, at)| (id.clone(), at.to_string()))
+                .unzip();
+            // An explicit sign off may have written a later time already
+            let result = self
+                .db
+                .execute(
+                    "UPDATE users SET last_seen_at = GREATEST(users.last_seen_at, to_timestamp(v.at::BIGINT)) \
+                     FROM unnest(string_to_array($1, ','), string_to_array($2, ',')) AS v(id, at) \
+                     WHERE users.id = v.id",
+                    &[&ids.join(","), &times.join(",")],
+                )
+                .await;
+            if let Err(e) = result {
+                let message = format!("failed to record {} users going offline: {}", ids.len(), e);
+                self.logger
+                    .error("presence_service::record_offline", &message);
+                return Err(e);
+            }
+            recorded += expired.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::db::MockDatabase;
+    use logger::log::Log;
+    use presence::store::MockPresenceStore;
+
+    use super::*;
+
+    fn row(id: &str, visibility: &str, last_seen_at: &str, friends: bool) -> PgRow {
+        PgRow::from(vec![
+            id.to_string(),
+            visibility.to_string(),
+            last_seen_at.to_string(),
+            friends.to_string(),
+        ])
+    }
+
+    #[test]
+    fn test_touches_are_throttled_per_user() {
+        let service = PresenceServiceImpl::new(MockDatabase::new(), Log, MockPresenceStore::new());
+        assert!(service.claim_touch("alice"));
+        assert!(!service.claim_touch("alice"));
+        assert!(service.claim_touch("bob"));
+
+        let long_ago = Instant::now() - TOUCH_INTERVAL;
+        service
+            .touched
+            .lock()
+            .unwrap()
+            .insert("alice".to_string(), long_ago);
+        assert!(service.claim_touch("alice"));
+    }
+
+    #[tokio::test]
+    async fn test_presence_respects_last_seen_visibility() {
+        let mut db = MockDatabase::new();
+        db.expect_query().returning(|_, _| {
+            let rows = vec![
+                row("bob", "friends", "2026-01-01T00:00:00+00:00", false),
+                row("carol", "friends", "2026-01-01T00:00:00+00:00", true),
+                row("dave", "everyone", "", false),
+            ];
+            Box::pin(async move { Ok(rows) })
+        });
+        let mut store = MockPresenceStore::new();
+        store
+            .expect_states()
+            .returning(|_| Ok(vec![Some(State::Online), None, Some(State::Idle), None]));
+        let service = PresenceServiceImpl::new(db, Log, store);
+        let query = QueryPresence {
+            ids: "bob,carol,dave,unknown".to_string(),
+        };
+
+        let presences = service.get_presence("alice", &query).await.unwrap();
+        assert_eq!(
+            presences,
+            vec![
+                Presence {
+                    user_id: "bob".to_string(),
+                    status: PresenceStatus::Offline,
+                    last_seen_at: None,
+                },
+                Presence {
+                    user_id: "carol".to_string(),
+                    status: PresenceStatus::Offline,
+                    last_seen_at: Some("2026-01-01T00:00:00+00:00".to_string()),
+                },
+                Presence {
+                    user_id: "dave".to_string(),
+                    status: PresenceStatus::Idle,
+                    last_seen_at: None,
+                },
+            ]
+        );
+    }
+}
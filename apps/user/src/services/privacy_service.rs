@@ -0,0 +1,355 @@
+use async_trait::async_trait;
+use database::{db::Database, pgx::PgRow};
+use errors::error::Error;
+use events::{channel::USER_RELATIONSHIPS, event::Event, publisher::Publisher};
+use logger::logger::Logger;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::services::{
+    relationship_service::RelationshipEvent,
+    user_service::{user_from_row, User, USER_COLUMNS},
+};
+
+pub const USER_BLOCKED: &str = "user.blocked";
+pub const USER_UNBLOCKED: &str = "user.unblocked";
+
+/// Who a privacy setting lets through. Blocked users are always left out.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Audience {
+    #[default]
+    Everyone,
+    /// Accepted friends only
+    Friends,
+    Nobody,
+}
+
+impl Audience {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Audience::Everyone => "everyone",
+            Audience::Friends => "friends",
+            Audience::Nobody => "nobody",
+        }
+    }
+
+    /// Parses the value stored in the database, unknown values let nobody in.
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "everyone" => Audience::Everyone,
+            "friends" => Audience::Friends,
+            _ => Audience::Nobody,
+        }
+    }
+
+    pub fn allows(&self, friends: bool) -> bool {
+        match self {
+            Audience::Everyone => true,
+            Audience::Friends => friends,
+            Audience::Nobody => false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Validate, ToSchema)]
+pub struct PrivacySettings {
+    /// Who can start a direct conversation with the user
+    dm_policy: Audience,
+    /// Who can find the user in listings and search
+    search_visibility: Audience,
+    /// Who can see when the user was last online
+    last_seen_visibility: Audience,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct BlockedUser {
+    user: User,
+    created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
+pub struct BlockUser {
+    #[validate(length(min = 1, max = 64))]
+    user_id: String,
+}
+
+/// How two users relate, as seen by `user_id`. Meant for other services
+/// deciding whether one user may reach another.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct RelationshipStatus {
+    user_id: String,
+    other_id: String,
+    /// `user_id` blocked `other_id`
+    blocked_by_user: bool,
+    /// `other_id` blocked `user_id`
+    blocked_by_other: bool,
+    friends: bool,
+    /// `user_id` may send direct messages to `other_id`
+    can_message: bool,
+}
+
+#[async_trait]
+pub trait PrivacyService {
+    async fn get_settings(&self, user_id: &str) -> Result<PrivacySettings, Error>;
+    async fn update_settings(
+        &self,
+        user_id: &str,
+        data: &PrivacySettings,
+    ) -> Result<PrivacySettings, Error>;
+    async fn get_blocked(&self, user_id: &str) -> Result<Vec<BlockedUser>, Error>;
+    /// Blocks a user and ends any friendship or pending request with them.
+    async fn block_user(&self, user_id: &str, data: &BlockUser) -> Result<(), Error>;
+    async fn unblock_user(&self, user_id: &str, blocked_id: &str) -> Result<(), Error>;
+    async fn get_relationship(
+        &self,
+        user_id: &str,
+        other_id: &str,
+    ) -> Result<RelationshipStatus, Error>;
+}
+
+pub struct PrivacyServiceImpl<D: Database<PgRow>, L: Logger, P: Publisher> {
+    db: D,
+    logger: L,
+    publisher: P,
+}
+
+impl<D: Database<PgRow>, L: Logger, P: Publisher> PrivacyServiceImpl<D, L, P> {
+    pub fn new(db: D, logger: L, publisher: P) -> Self {
+        Self {
+            db,
+            logger,
+            publisher,
+        }
+    }
+
+    /// Publishes a block change with the blocker as the requester.
+    async fn emit(&self, kind: &str, blocker_id: &str, blocked_id: &str) {
+        let event = Event::new(
+            kind,
+            RelationshipEvent {
+                requester_id: blocker_id.to_string(),
+                addressee_id: blocked_id.to_string(),
+            },
+        );
+        if let Err(e) = self.publisher.publish(USER_RELATIONSHIPS, &event).await {
+            let message = format!("failed to publish {}: {}", kind, e);
+            self.logger.error("privacy_service::emit", &message);
+        }
+    }
+}
+
+#[async_trait]
+impl<D: Database<PgRow> + Send + Sync, L: Logger + Send + Sync, P: Publisher + Send + Sync>
+    PrivacyService for PrivacyServiceImpl<D, L, P>
+{
+    async fn get_settings(&self, user_id: &str) -> Result<PrivacySettings, Error> {
+        let row = self
+            .db
+            .query_one(
+                "SELECT dm_policy, search_visibility, last_seen_visibility FROM users WHERE id = $1",
+                &[&user_id.to_string()],
+            )
+            .await?;
+        Ok(PrivacySettings {
+            dm_policy: Audience::parse(&row.get(0)),
+            search_visibility: Audience::parse(&row.get(1)),
+            last_seen_visibility: Audience::parse(&row.get(2)),
+        })
+    }
+
+    async fn update_settings(
+        &self,
+        user_id: &str,
+        data: &PrivacySettings,
+    ) -> Result<PrivacySettings, Error> {
+        let params = [
+            &data.dm_policy.as_str().to_string(),
+            &data.search_visibility.as_str().to_string(),
+            &data.last_seen_visibility.as_str().to_string(),
+            &user_id.to_string(),
+        ];
+        let result = self
+            .db
+            .execute(
+                "UPDATE users SET dm_policy = $1, search_visibility = $2, last_seen_visibility = $3, updated_at = NOW() WHERE id = $4",
+                &params,
+            )
+            .await;
+        match result {
+            Ok(0) => Err(Error::NotFound("User not found".to_string())),
+            Ok(_) => Ok(*data),
+            Err(e) => {
+                let message = format!("failed to update privacy of user {}: {}", user_id, e);
+                self.logger
+                    .error("privacy_service::update_settings", &message);
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_blocked(&self, user_id: &str) -> Result<Vec<BlockedUser>, Error> {
+        let sql = format!(
+            "SELECT {}, to_json(b.created_at) #>> '{{}}' FROM users \
+             JOIN user_blocks b ON b.blocked_id = users.id \
+             WHERE b.blocker_id = $1 ORDER BY b.created_at DESC",
+            USER_COLUMNS
+        );
+        match self.db.query(&sql, &[&user_id.to_string()]).await {
+            Ok(rows) => Ok(rows
+                .iter()
+                .map(|row| BlockedUser {
+                    user: user_from_row(row),
+                    created_at: row.get(10),
+                })
+                .collect()),
+            Err(e) => {
+                let message = format!("failed to query blocks of user {}: {}", user_id, e);
+                self.logger.error("privacy_service::get_blocked", &message);
+                Err(e)
+            }
+        }
+    }
+
+    async fn block_user(&self, user_id: &str, data: &BlockUser) -> Result<(), Error> {
+        let blocked_id = data.user_id.as_str();
+        if user_id == blocked_id {
+            return Err(Error::BadRequest("Cannot block yourself".to_string()));
+        }
+        let message = format!("user {} blocks {}", user_id, blocked_id);
+        self.logger.info("privacy_service::block_user", &message);
+        let params = [&user_id.to_string(), &blocked_id.to_string()];
+        let result = self
+            .db
+            .execute(
+                "INSERT INTO user_blocks (blocker_id, blocked_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                &params,
+            )
+            .await;
+        match result {
+            // Already blocked
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(Error::NotFound(_)) => return Err(Error::NotFound("User not found".to_string())),
+            Err(e) => {
+                let message = format!("failed to block user: {}", e);
+                self.logger.error("privacy_service::block_user", &message);
+                return Err(e);
+            }
+        }
+        self.db
+            .execute(
+                "DELETE FROM user_relationships WHERE (requester_id = $1 AND addressee_id = $2) OR (requester_id = $2 AND addressee_id = $1)",
+                &params,
+            )
+            .await?;
+        self.emit(USER_BLOCKED, user_id, blocked_id).await;
+        Ok(())
+    }
+
+    async fn unblock_user(&self, user_id: &str, blocked_id: &str) -> Result<(), Error> {
+        let deleted = self
+            .db
+            .execute(
+                "DELETE FROM user_blocks WHERE blocker_id = $1 AND blocked_id = $2",
+                &[&user_id.to_string(), &blocked_id.to_string()],
+            )
+            .await?;
+        if deleted == 0 {
+            return Err(Error::NotFound("Blocked user not found".to_string()));
+        }
+        self.emit(USER_UNBLOCKED, user_id, blocked_id).await;
+        Ok(())
+    }
+
+    async fn get_relationship(
+        &self,
+        user_id: &str,
+        other_id: &str,
+    ) -> Result<RelationshipStatus, Error> {
+        let row = self
+            .db
+            .query_one(
+                "SELECT \
+                   EXISTS (SELECT 1 FROM user_blocks WHERE blocker_id = $1 AND blocked_id = $2)::TEXT, \
+                   EXISTS (SELECT 1 FROM user_blocks WHERE blocker_id = $2 AND blocked_id = $1)::TEXT, \
+                   EXISTS (SELECT 1 FROM user_relationships WHERE status = 'accepted' \
+                     AND ((requester_id = $1 AND addressee_id = $2) \
+                       OR (requester_id = $2 AND addressee_id = $1)))::TEXT, \
+                   dm_policy \
+                 FROM users WHERE id = $2",
+                &[&user_id.to_string(), &other_id.to_string()],
+            )
+            .await?;
+        let blocked_by_user = row.get(0) == "true";
+        let blocked_by_other = row.get(1) == "true";
+        let friends = row.get(2) == "true";
+        let can_message = user_id != other_id
+            && !blocked_by_user
+            && !blocked_by_other
+            && Audience::parse(&row.get(3)).allows(friends);
+        Ok(RelationshipStatus {
+            user_id: user_id.to_string(),
+            other_id: other_id.to_string(),
+            blocked_by_user,
+            blocked_by_other,
+            friends,
+            can_message,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::db::MockDatabase;
+    use events::publisher::MockPublisher;
+    use logger::log::Log;
+
+    use super::*;
+
+    fn service(
+        db: MockDatabase<PgRow>,
+        publisher: MockPublisher,
+    ) -> PrivacyServiceImpl<MockDatabase<PgRow>, Log, MockPublisher> {
+        PrivacyServiceImpl::new(db, Log, publisher)
+    }
+
+    #[test]
+    fn test_audience_allows() {
+        assert!(Audience::Everyone.allows(false));
+        assert!(Audience::Friends.allows(true));
+        assert!(!Audience::Friends.allows(false));
+        assert!(!Audience::Nobody.allows(true));
+        assert_eq!(Audience::parse("unknown"), Audience::Nobody);
+    }
+
+    #[tokio::test]
+    async fn test_cannot_block_yourself() {
+        let data = BlockUser {
+            user_id: "alice".to_string(),
+        };
+        let result = service(MockDatabase::new(), MockPublisher::new())
+            .block_user("alice", &data)
+            .await;
+        assert!(matches!(result, Err(Error::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn test_repeated_block_publishes_nothing() {
+        let mut db = MockDatabase::new();
+        db.expect_execute()
+            .times(1)
+            .returning(|_, _| Box::pin(async { Ok(0) }));
+        let mut publisher = MockPublisher::new();
+        publisher.expect_publish().never();
+
+        let data = BlockUser {
+            user_id: "bob".to_string(),
+        };
+        service(db, publisher)
+            .block_user("alice", &data)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,392 @@
+use async_trait::async_trait;
+use database::{db::Database, pgx::PgRow};
+use errors::error::Error;
+use events::{channel::USER_RELATIONSHIPS, event::Event, publisher::Publisher};
+use logger::logger::Logger;
+use pagination::{
+    cursor::CursorCodec,
+    page::{Page, PageRequest},
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::services::user_service::{user_from_row, User, USER_COLUMNS};
+
+pub const FRIEND_REQUEST_SENT: &str = "friend_request.sent";
+pub const FRIEND_REQUEST_ACCEPTED: &str = "friend_request.accepted";
+pub const FRIEND_REQUEST_DECLINED: &str = "friend_request.declined";
+pub const FRIEND_REQUEST_CANCELLED: &str = "friend_request.cancelled";
+pub const FRIEND_REMOVED: &str = "friend.removed";
+
+/// Payload of the events published on [`USER_RELATIONSHIPS`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RelationshipEvent {
+    pub requester_id: String,
+    pub addressee_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestDirection {
+    /// Sent to the caller
+    #[default]
+    Incoming,
+    /// Sent by the caller
+    Outgoing,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct FriendRequest {
+    /// The other side of the request
+    user: User,
+    direction: RequestDirection,
+    created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate, ToSchema)]
+pub struct SendFriendRequest {
+    #[validate(length(min = 1, max = 64))]
+    user_id: String,
+}
+
+#[derive(Deserialize, Debug, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryFriends {
+    #[validate(range(min = 1, max = 100))]
+    limit: Option<u32>,
+    /// `next_cursor` or `prev_cursor` of the previous page
+    #[validate(length(max = 1024))]
+    cursor: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QueryFriendRequests {
+    /// Defaults to `incoming`
+    direction: Option<RequestDirection>,
+}
+
+impl QueryFriendRequests {
+    pub fn direction(&self) -> RequestDirection {
+        self.direction.unwrap_or_default()
+    }
+}
+
+#[async_trait]
+pub trait RelationshipService {
+    async fn get_friends(&self, user_id: &str, query: &QueryFriends) -> Result<Page<User>, Error>;
+    async fn get_friend_requests(
+        &self,
+        user_id: &str,
+        direction: RequestDirection,
+    ) -> Result<Vec<FriendRequest>, Error>;
+    /// Sends a request, or accepts the one the other user already sent.
+    async fn send_request(&self, user_id: &str, data: &SendFriendRequest) -> Result<(), Error>;
+    async fn accept_request(&self, user_id: &str, requester_id: &str) -> Result<(), Error>;
+    async fn decline_request(&self, user_id: &str, requester_id: &str) -> Result<(), Error>;
+    async fn cancel_request(&self, user_id: &str, addressee_id: &str) -> Result<(), Error>;
+    async fn remove_friend(&self, user_id: &str, friend_id: &str) -> Result<(), Error>;
+}
+
+pub struct RelationshipServiceImpl<D: Database<PgRow>, L: Logger, P: Publisher> {
+    db: D,
+    logger: L,
+    publisher: P,
+    cursors: CursorCodec,
+}
+
+impl<D: Database<PgRow>, L: Logger, P: Publisher> RelationshipServiceImpl<D, L, P> {
+    pub fn new(db: D, logger: L, publisher: P, cursors: CursorCodec) -> Self {
+        Self {
+            db,
+            logger,
+            publisher,
+            cursors,
+        }
+    }
+
+    /// Publishes a relationship change.
+    async fn emit(&self, kind: &str, requester_id: &str, addressee_id: &str) {
+        let event = Event::new(
+            kind,
+            RelationshipEvent {
+                requester_id: requester_id.to_string(),
+                addressee_id: addressee_id.to_string(),
+            },
+        );
+        if let Err(e) = self.publisher.publish(USER_RELATIONSHIPS, &event).await {
+            let message = format!("failed to publish {}: {}", kind, e);
+            self.logger.error("relationship_service::emit", &message);
+        }
+    }
+
+    async fn is_blocked(&self, user_id: &str, other_id: &str) -> Result<bool, Error> {
+        let rows = self
+            .db
+            .query(
+                "SELECT 1::TEXT FROM user_blocks WHERE (blocker_id = $1 AND blocked_id = $2) OR (blocker_id = $2 AND blocked_id = $1)",
+                &[&user_id.to_string(), &other_id.to_string()],
+            )
+            .await?;
+        Ok(!rows.is_empty())
+    }
+
+    /// Deletes the pending request from `requester_id` to `addressee_id`.
+    async fn delete_request(
+        &self,
+        requester_id: &str,
+        addressee_id: &str,
+        kind: &str,
+    ) -> Result<(), Error> {
+        let deleted = self
+            .db
+            .execute(
+                "DELETE FROM user_relationships WHERE requester_id = $1 AND addressee_id = $2 AND status = 'pending'",
+                &[&requester_id.to_string(), &addressee_id.to_string()],
+            )
+            .await?;
+        if deleted == 0 {
+            return Err(Error::NotFound("Friend request not found".to_string()));
+        }
+        self.emit(kind, requester_id, addressee_id).await;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<D: Database<PgRow> + Send + Sync, L: Logger + Send + Sync, P: Publisher + Send + Sync>
+    RelationshipService for RelationshipServiceImpl<D, L, P>
+{
+    async fn get_friends(&self, user_id: &str, query: &QueryFriends) -> Result<Page<User>, Error> {
+        let request = PageRequest::new(
+            &self.cursors,
+            &format!("friends:{}", user_id),
+            query.limit.unwrap_or(20),
+            query.cursor.as_deref(),
+        )?;
+        let after = request.key().first().cloned().unwrap_or_default();
+        let sql = format!(
+            "SELECT {} FROM users JOIN user_relationships r \
+             ON (r.requester_id = $1 AND r.addressee_id = users.id) \
+             OR (r.addressee_id = $1 AND r.requester_id = users.id) \
+             WHERE r.status = 'accepted' AND ($2 = '' OR users.username {} $2) \
+             ORDER BY users.username {} LIMIT $3::TEXT::INT",
+            USER_COLUMNS,
+            request.comparator(),
+            request.order()
+        );
+        let params = [&user_id.to_string(), &after, &request.fetch_limit()];
+        match self.db.query(&sql, &params).await {
+            Ok(rows) => {
+                let users = rows.iter().map(user_from_row).collect();
+                Ok(request.page(&self.cursors, users, |user: &User| {
+                    vec![user.username().to_string()]
+                }))
+            }
+            Err(e) => {
+                let message = format!("failed to query friends of user {}: {}", user_id, e);
+                self.logger
+                    .error("relationship_service::get_friends", &message);
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_friend_requests(
+        &self,
+        user_id: &str,
+        direction: RequestDirection,
+    ) -> Result<Vec<FriendRequest>, Error> {
+        // The listed user is the other side of the request
+        let (mine, theirs) = match direction {
+            RequestDirection::Incoming => ("addressee_id", "requester_id"),
+            RequestDirection::Outgoing => ("requester_id", "addressee_id"),
+        };
+        let sql = format!(
+            "SELECT {}, to_json(r.created_at) #>> '{{}}' FROM users \
+             JOIN user_relationships r ON r.{} = users.id \
+             WHERE r.{} = $1 AND r.status = 'pending' ORDER BY r.created_at DESC",
+            USER_COLUMNS, theirs, mine
+        );
+        match self.db.query(&sql, &[&user_id.to_string()]).await {
+            Ok(rows) => Ok(rows
+                .iter()
+                .map(|row| FriendRequest {
+                    user: user_from_row(row),
+                    direction,
+                    created_at: row.get(10),
+                })
+                .collect()),
+            Err(e) => {
+                let message = format!("failed to query requests of user {}: {}", user_id, e);
+                self.logger
+                    .error("relationship_service::get_friend_requests", &message);
+                Err(e)
+            }
+        }
+    }
+
+    async fn send_request(&self, user_id: &str, data: &SendFriendRequest) -> Result<(), Error> {
+        let addressee_id = data.user_id.as_str();
+        if user_id == addressee_id {
+            return Err(Error::BadRequest(
+                "Cannot send a friend request to yourself".to_string(),
+            ));
+        }
+        if self.is_blocked(user_id, addressee_id).await? {
+            return Err(Error::Forbidden(
+                "Cannot send a friend request to this user".to_string(),
+            ));
+        }
+        let existing = self
+            .db
+            .query(
+                "SELECT requester_id, status FROM user_relationships WHERE (requester_id = $1 AND addressee_id = $2) OR (requester_id = $2 AND addressee_id = $1)",
+                &[&user_id.to_string(), &addressee_id.to_string()],
+            )
+            .await?;
+        if let Some(row) = existing.first() {
+            return match (row.get(0) == user_id, row.get(1).as_str()) {
+                (_, "accepted") => Err(Error::Conflict("Already friends".to_string())),
+                (true, _) => Err(Error::Conflict("Friend request already sent".to_string())),
+                (false, _) => self.accept_request(user_id, addressee_id).await,
+            };
+        }
+
+        let message = format!(
+            "user {} sends a friend request to {}",
+            user_id, addressee_id
+        );
+        self.logger
+            .info("relationship_service::send_request", &message);
+        let result = self
+            .db
+            .execute(
+                "INSERT INTO user_relationships (requester_id, addressee_id, status) VALUES ($1, $2, 'pending')",
+                &[&user_id.to_string(), &addressee_id.to_string()],
+            )
+            .await;
+        match result {
+            Ok(_) => {
+                self.emit(FRIEND_REQUEST_SENT, user_id, addressee_id).await;
+                Ok(())
+            }
+            Err(Error::NotFound(_)) => Err(Error::NotFound("User not found".to_string())),
+            // Both sides sent a request at the same time
+            Err(Error::Conflict(_)) => {
+                Err(Error::Conflict("Friend request already exists".to_string()))
+            }
+            Err(e) => {
+                let message = format!("failed to send friend request: {}", e);
+                self.logger
+                    .error("relationship_service::send_request", &message);
+                Err(e)
+            }
+        }
+    }
+
+    async fn accept_request(&self, user_id: &str, requester_id: &str) -> Result<(), Error> {
+        let updated = self
+            .db
+            .execute(
+                "UPDATE user_relationships SET status = 'accepted', updated_at = NOW() WHERE requester_id = $1 AND addressee_id = $2 AND status = 'pending'",
+                &[&requester_id.to_string(), &user_id.to_string()],
+            )
+            .await?;
+        if updated == 0 {
+            return Err(Error::NotFound("Friend request not found".to_string()));
+        }
+        self.emit(FRIEND_REQUEST_ACCEPTED, requester_id, user_id)
+            .await;
+        Ok(())
+    }
+
+    async fn decline_request(&self, user_id: &str, requester_id: &str) -> Result<(), Error> {
+        self.delete_request(requester_id, user_id, FRIEND_REQUEST_DECLINED)
+            .await
+    }
+
+    async fn cancel_request(&self, user_id: &str, addressee_id: &str) -> Result<(), Error> {
+        self.delete_request(user_id, addressee_id, FRIEND_REQUEST_CANCELLED)
+            .await
+    }
+
+    async fn remove_friend(&self, user_id: &str, friend_id: &str) -> Result<(), Error> {
+        let rows = self
+            .db
+            .query(
+                "DELETE FROM user_relationships WHERE status = 'accepted' AND ((requester_id = $1 AND addressee_id = $2) OR (requester_id = $2 AND addressee_id = $1)) RETURNING requester_id, addressee_id",
+                &[&user_id.to_string(), &friend_id.to_string()],
+            )
+            .await?;
+        let row = rows
+            .first()
+            .ok_or_else(|| Error::NotFound("Friend not found".to_string()))?;
+        self.emit(FRIEND_REMOVED, &row.get(0), &row.get(1)).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::db::MockDatabase;
+    use events::publisher::MockPublisher;
+    use logger::log::Log;
+
+    use super::*;
+
+    fn service(
+        db: MockDatabase<PgRow>,
+        publisher: MockPublisher,
+    ) -> RelationshipServiceImpl<MockDatabase<PgRow>, Log, MockPublisher> {
+        RelationshipServiceImpl::new(db, Log, publisher, CursorCodec::new(b"secret"))
+    }
+
+    #[tokio::test]
+    async fn test_accept_publishes_event() {
+        let mut db = MockDatabase::new();
+        // Mocked before async_trait expands, so it returns the boxed future
+        db.expect_execute()
+            .returning(|_, _| Box::pin(async { Ok(1) }));
+        let mut publisher = MockPublisher::new();
+        publisher
+            .expect_publish()
+            .withf(|channel, event| {
+                channel == USER_RELATIONSHIPS
+                    && event.kind == FRIEND_REQUEST_ACCEPTED
+                    && event.data["requester_id"] == "alice"
+                    && event.data["addressee_id"] == "bob"
+            })
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        service(db, publisher)
+            .accept_request("bob", "alice")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_missing_request_is_not_found() {
+        let mut db = MockDatabase::new();
+        db.expect_execute()
+            .returning(|_, _| Box::pin(async { Ok(0) }));
+        let mut publisher = MockPublisher::new();
+        publisher.expect_publish().never();
+
+        let result = service(db, publisher).decline_request("bob", "alice").await;
+        assert!(matches!(result, Err(Error::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_cannot_befriend_yourself() {
+        let data = SendFriendRequest {
+            user_id: "alice".to_string(),
+        };
+        let result = service(MockDatabase::new(), MockPublisher::new())
+            .send_request("alice", &data)
+            .await;
+        assert!(matches!(result, Err(Error::BadRequest(_))));
+    }
+}
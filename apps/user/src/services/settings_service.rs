@@ -0,0 +1,390 @@
+use async_trait::async_trait;
+use database::{db::Database, pgx::PgRow};
+use errors::error::{Error, FieldError};
+use logger::logger::Logger;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+use validation::extractor::field_errors;
+use validator::{Validate, ValidationErrors};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    /// Follows the device
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailDigest {
+    #[default]
+    Off,
+    Daily,
+    Weekly,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Validate, ToSchema)]
+#[serde(default)]
+pub struct NotificationSettings {
+    pub direct_messages: bool,
+    pub mentions: bool,
+    pub replies: bool,
+    pub friend_requests: bool,
+    pub sounds: bool,
+    pub email_digest: EmailDigest,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            direct_messages: true,
+            mentions: true,
+            replies: true,
+            friend_requests: true,
+            sounds: true,
+            email_digest: EmailDigest::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Validate, ToSchema)]
+#[serde(default)]
+pub struct AppearanceSettings {
+    pub theme: Theme,
+    /// Text size in percent of the default
+    #[validate(range(min = 80, max = 200))]
+    pub font_scale: u16,
+    pub reduced_motion: bool,
+}
+
+impl Default for AppearanceSettings {
+    fn default() -> Self {
+        Self {
+            theme: Theme::default(),
+            font_scale: 100,
+            reduced_motion: false,
+        }
+    }
+}
+
+/// Every setting with its current value. Keys the user never changed have
+/// their default, so new keys show up for everyone at once.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Validate, ToSchema)]
+#[serde(default)]
+pub struct Settings {
+    #[validate(nested)]
+    pub notifications: NotificationSettings,
+    #[validate(nested)]
+    pub appearance: AppearanceSettings,
+    /// Interface language as a BCP 47 tag such as `en` or `pt-BR`
+    #[validate(custom(function = "validation::rules::language"))]
+    pub language: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            notifications: NotificationSettings::default(),
+            appearance: AppearanceSettings::default(),
+            language: "en".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct UserSettings {
+    /// Bumped on every change, also sent as the `ETag`
+    version: u32,
+    settings: Settings,
+    updated_at: String,
+}
+
+impl UserSettings {
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+}
+
+/// JSON merge patch (RFC 7396) of [`Settings`]. Keys left out are kept,
+/// `null` resets a key to its default.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(transparent)]
+pub struct SettingsPatch(Map<String, Value>);
+
+impl Validate for SettingsPatch {
+    // Keys and values are checked against the merged document
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        Ok(())
+    }
+}
+
+/// Applies a merge patch to `target` in place.
+fn merge(target: &mut Map<String, Value>, patch: &Map<String, Value>) {
+    for (key, value) in patch {
+        match value {
+            Value::Null => {
+                target.remove(key);
+            }
+            Value::Object(patch) => {
+                let entry = target
+                    .entry(key.clone())
+                    .or_insert_with(|| Value::Object(Map::new()));
+                if !entry.is_object() {
+                    *entry = Value::Object(Map::new());
+                }
+                if let Value::Object(target) = entry {
+                    merge(target, patch);
+                }
+            }
+            value => {
+                target.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Reports keys of `data` missing from `schema` and values whose JSON type
+/// differs from the default's.
+fn check_keys(
+    data: &Map<String, Value>,
+    schema: &Map<String, Value>,
+    prefix: &str,
+    errors: &mut Vec<FieldError>,
+) {
+    for (key, value) in data {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        let expected = match schema.get(key) {
+            Some(expected) => expected,
+            None => {
+                errors.push(FieldError::new(&path, "unknown", "is not a known setting"));
+                continue;
+            }
+        };
+        match (expected, value) {
+            (Value::Object(schema), Value::Object(data)) => check_keys(data, schema, &path, errors),
+            (Value::Object(_), _) => {
+                errors.push(FieldError::new(&path, "type", "must be an object"));
+            }
+            (Value::Bool(_), Value::Bool(_))
+            | (Value::Number(_), Value::Number(_))
+            | (Value::String(_), Value::String(_)) => {}
+            (Value::Bool(_), _) => errors.push(FieldError::new(&path, "type", "must be a boolean")),
+            (Value::Number(_), _) => {
+                errors.push(FieldError::new(&path, "type", "must be a number"))
+            }
+            _ => errors.push(FieldError::new(&path, "type", "must be a string")),
+        }
+    }
+}
+
+/// Turns the stored keys into [`Settings`], reporting what doesn't fit.
+fn parse(data: &Map<String, Value>) -> Result<Settings, Error> {
+    let schema = match serde_json::to_value(Settings::default()) {
+        Ok(Value::Object(schema)) => schema,
+        _ => return Err(Error::Internal("Settings are not an object".to_string())),
+    };
+    let mut errors = vec![];
+    check_keys(data, &schema, "", &mut errors);
+    if !errors.is_empty() {
+        return Err(Error::Validation(errors));
+    }
+    let settings: Settings = serde_json::from_value(Value::Object(data.clone())).map_err(|e| {
+        Error::Validation(vec![FieldError::new("settings", "invalid", &e.to_string())])
+    })?;
+    settings
+        .validate()
+        .map_err(|errors| Error::Validation(field_errors(&errors)))?;
+    Ok(settings)
+}
+
+#[async_trait]
+pub trait SettingsService {
+    async fn get_settings(&self, user_id: &str) -> Result<UserSettings, Error>;
+    /// Applies the patch if the settings are still at `version`, any version
+    /// when `None`.
+    async fn update_settings(
+        &self,
+        user_id: &str,
+        version: Option<u32>,
+        patch: &SettingsPatch,
+    ) -> Result<UserSettings, Error>;
+}
+
+pub struct SettingsServiceImpl<D: Database<PgRow>, L: Logger> {
+    db: D,
+    logger: L,
+}
+
+impl<D: Database<PgRow>, L: Logger> SettingsServiceImpl<D, L> {
+    pub fn new(db: D, logger: L) -> Self {
+        Self { db, logger }
+    }
+
+    /// Version, changed keys and update time, creating the row on first use.
+    async fn load(&self, user_id: &str) -> Result<(u32, Map<String, Value>, String), Error> {
+        let user_id = user_id.to_string();
+        self.db
+            .execute(
+                "INSERT INTO user_settings (user_id) VALUES ($1) ON CONFLICT (user_id) DO NOTHING",
+                &[&user_id],
+            )
+            .await
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound("User not found".to_string()),
+                e => e,
+            })?;
+        let row = self
+            .db
+            .query_one(
+                "SELECT version::TEXT, data::TEXT, to_json(updated_at) #>> '{}' \
+                 FROM user_settings WHERE user_id = $1",
+                &[&user_id],
+            )
+            .await?;
+        let data = match serde_json::from_str(&row.get(1)) {
+            Ok(Value::Object(data)) => data,
+            _ => {
+                let message = format!("settings of user {} are not an object", user_id);
+                self.logger.error("settings_service::load", &message);
+                Map::new()
+            }
+        };
+        Ok((row.get(0).parse().unwrap_or_default(), data, row.get(2)))
+    }
+}
+
+#[async_trait]
+impl<D: Database<PgRow> + Send + Sync, L: Logger + Send + Sync> SettingsService
+    for SettingsServiceImpl<D, L>
+{
+    async fn get_settings(&self, user_id: &str) -> Result<UserSettings, Error> {
+        let (version, data, updated_at) = self.load(user_id).await?;
+        // Keys retired since they were stored are dropped rather than failing
+        let settings = parse(&data).unwrap_or_else(|e| {
+            let message = format!("stored settings of user {} are invalid: {}", user_id, e);
+            self.logger
+                .error("settings_service::get_settings", &message);
+            serde_json::from_value(Value::Object(data)).unwrap_or_default()
+        });
+        Ok(UserSettings {
+            version,
+            settings,
+            updated_at,
+        })
+    }
+
+    async fn update_settings(
+        &self,
+        user_id: &str,
+        version: Option<u32>,
+        patch: &SettingsPatch,
+    ) -> Result<UserSettings, Error> {
+        let stale = || {
+            Error::PreconditionFailed(
+                "Settings changed on another device, fetch them again".to_string(),
+            )
+        };
+        let (current, mut data, _) = self.load(user_id).await?;
+        if version.is_some_and(|version| version != current) {
+            return Err(stale());
+        }
+        merge(&mut data, &patch.0);
+        let settings = parse(&data)?;
+
+        let rows = self
+            .db
+            .query(
+                "UPDATE user_settings SET data = $1::JSONB, version = version + 1, updated_at = NOW() \
+                 WHERE user_id = $2 AND version = $3::TEXT::INT \
+                 RETURNING version::TEXT, to_json(updated_at) #>> '{}'",
+                &[
+                    &Value::Object(data).to_string(),
+                    &user_id.to_string(),
+                    &current.to_string(),
+                ],
+            )
+            .await?;
+        // Another device saved in between
+        let Some(row) = rows.first() else {
+            return Err(stale());
+        };
+        let message = format!("updated settings of user {}", user_id);
+        self.logger
+            .info("settings_service::update_settings", &message);
+        Ok(UserSettings {
+            version: row.get(0).parse().unwrap_or_default(),
+            settings,
+            updated_at: row.get(1),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn object(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let mut data = object(json!({
+            "language": "id",
+            "appearance": { "theme": "dark", "font_scale": 120 },
+        }));
+        merge(
+            &mut data,
+            &object(json!({
+                "language": null,
+                "appearance": { "font_scale": 110 },
+                "notifications": { "sounds": false },
+            })),
+        );
+
+        let settings = parse(&data).unwrap();
+        assert_eq!(settings.language, "en");
+        assert_eq!(settings.appearance.theme, Theme::Dark);
+        assert_eq!(settings.appearance.font_scale, 110);
+        assert!(!settings.notifications.sounds);
+        assert!(settings.notifications.mentions);
+    }
+
+    #[test]
+    fn test_unknown_and_invalid_settings() {
+        let data = object(json!({
+            "colour": "red",
+            "appearance": { "font_scale": "large", "reduced_motion": true },
+            "notifications": { "mentions": { "enabled": true } },
+        }));
+        let Err(Error::Validation(errors)) = parse(&data) else {
+            panic!("expected validation errors");
+        };
+        let fields: Vec<_> = errors
+            .iter()
+            .map(|error| (error.field.as_str(), error.code.as_str()))
+            .collect();
+        assert!(fields.contains(&("colour", "unknown")));
+        assert!(fields.contains(&("appearance.font_scale", "type")));
+        assert!(fields.contains(&("notifications.mentions", "type")));
+
+        let data = object(json!({ "appearance": { "font_scale": 500 }, "language": "english" }));
+        let Err(Error::Validation(errors)) = parse(&data) else {
+            panic!("expected validation errors");
+        };
+        assert_eq!(errors.len(), 2);
+    }
+}
@@ -1,160 +1,870 @@
 use async_trait::async_trait;
-use database::{db::Database, pgx::PgRow};
+use database::{
+    db::Database,
+    pgx::{escape_like, PgRow},
+};
+use errors::error::Error;
 use logger::logger::Logger;
+use pagination::{
+    cursor::CursorCodec,
+    page::{Page, PageRequest},
+};
+use security::{hasher::Hasher, uuid::uuid_v4};
 use serde::{Deserialize, Serialize};
+use storage::{blob::BlobStore, mime, thumbnail};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct UserResponse {
-    data: Option<Vec<User>>,
-    total: Option<i64>,
-}
+/// Largest avatar upload accepted, before resizing.
+pub const MAX_AVATAR_BYTES: usize = 5 * 1024 * 1024;
+
+/// Days between asking to delete an account and the hard delete.
+pub const DELETION_GRACE_DAYS: u32 = 30;
+
+/// Days a user waits after changing their username before changing it again.
+pub const USERNAME_COOLDOWN_DAYS: u32 = 30;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Columns selected for [`User`], in the order [`user_from_row`] reads them.
+/// Qualified so they can be selected from joins too.
+pub(crate) const USER_COLUMNS: &str = "users.id, users.name, users.username, \
+    users.display_name, users.bio, users.status_text, users.avatar, users.timezone, \
+    to_json(users.created_at) #>> '{}', to_json(users.updated_at) #>> '{}'";
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct User {
     id: String,
     name: String,
     username: String,
+    display_name: String,
+    bio: String,
+    status_text: String,
+    avatar: Option<Avatar>,
+    timezone: String,
+    created_at: String,
+    updated_at: String,
+}
+
+impl User {
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+}
+
+/// Thumbnail sizes generated for every avatar upload.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AvatarSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl AvatarSize {
+    pub const ALL: [AvatarSize; 3] = [AvatarSize::Small, AvatarSize::Medium, AvatarSize::Large];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AvatarSize::Small => "small",
+            AvatarSize::Medium => "medium",
+            AvatarSize::Large => "large",
+        }
+    }
+
+    pub fn pixels(&self) -> u32 {
+        match self {
+            AvatarSize::Small => 64,
+            AvatarSize::Medium => 128,
+            AvatarSize::Large => 256,
+        }
+    }
+}
+
+/// URLs of the avatar thumbnails, relative to the API root. The version
+/// query changes on every upload so the images can be cached for long.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct Avatar {
+    small: String,
+    medium: String,
+    large: String,
+}
+
+impl Avatar {
+    fn new(user_id: &str, version: &str) -> Self {
+        let url =
+            |size: AvatarSize| format!("/user/{}/avatar/{}?v={}", user_id, size.name(), version);
+        Self {
+            small: url(AvatarSize::Small),
+            medium: url(AvatarSize::Medium),
+            large: url(AvatarSize::Large),
+        }
+    }
+}
+
+fn avatar_prefix(user_id: &str, version: &str) -> String {
+    format!("avatars/{}/{}", user_id, version)
+}
+
+pub(crate) fn avatar_key(user_id: &str, version: &str, size: AvatarSize) -> String {
+    format!("{}/{}.png", avatar_prefix(user_id, version), size.pixels())
+}
+
+pub(crate) fn user_from_row(row: &PgRow) -> User {
+    let id = row.get(0);
+    let avatar = row.get(6);
+    User {
+        avatar: (!avatar.is_empty()).then(|| Avatar::new(&id, &avatar)),
+        id,
+        name: row.get(1),
+        username: row.get(2),
+        display_name: row.get(3),
+        bio: row.get(4),
+        status_text: row.get(5),
+        timezone: row.get(7),
+        created_at: row.get(8),
+        updated_at: row.get(9),
+    }
 }
 
-#[derive(Deserialize, Debug, Serialize)]
+#[derive(Deserialize, Debug, Serialize, Validate, ToSchema)]
+pub struct UpdateProfile {
+    #[validate(length(max = 64))]
+    display_name: String,
+    #[validate(length(max = 500))]
+    bio: String,
+    #[validate(length(max = 140))]
+    status_text: String,
+    #[validate(custom(function = "validation::rules::timezone"))]
+    timezone: String,
+}
+
+/// Partial update, fields left out keep their value.
+#[derive(Deserialize, Debug, Default, Serialize, Validate, ToSchema)]
 pub struct UpdateUser {
+    #[validate(
+        length(min = 1, max = 64),
+        custom(function = "validation::rules::not_blank")
+    )]
     name: Option<String>,
+    /// Can be changed once every 30 days
+    #[validate(custom(function = "validation::rules::username"))]
     username: Option<String>,
+    #[validate(length(max = 64))]
+    display_name: Option<String>,
+    #[validate(length(max = 500))]
+    bio: Option<String>,
+    #[validate(length(max = 140))]
+    status_text: Option<String>,
+    #[validate(custom(function = "validation::rules::timezone"))]
+    timezone: Option<String>,
+}
+
+impl UpdateUser {
+    /// Columns to set with their new values, skipping the fields left out.
+    fn changes(&self) -> Vec<(&'static str, &String)> {
+        [
+            ("name", &self.name),
+            ("username", &self.username),
+            ("display_name", &self.display_name),
+            ("bio", &self.bio),
+            ("status_text", &self.status_text),
+            ("timezone", &self.timezone),
+        ]
+        .into_iter()
+        .filter_map(|(column, value)| value.as_ref().map(|value| (column, value)))
+        .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct UsernameChange {
+    old_username: String,
+    new_username: String,
+    changed_at: String,
+}
+
+#[derive(Deserialize, Debug, Serialize, Validate, ToSchema)]
+pub struct DeleteAccount {
+    /// Current password, confirms the request
+    #[validate(length(min = 1, max = 128))]
+    password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct AccountDeletion {
+    /// When the account and everything held about it is deleted. Signing in
+    /// before then cancels the deletion.
+    scheduled_at: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct QueryUser {
+    /// Matches name or username
+    #[validate(length(max = 64))]
     q: Option<String>,
+    #[validate(range(min = 1, max = 100))]
     limit: Option<u32>,
-    offset: Option<u32>,
+    /// `next_cursor` or `prev_cursor` of the previous page
+    #[validate(length(max = 1024))]
+    cursor: Option<String>,
+    /// Also count all matching users, costs an extra query
+    include_total: Option<bool>,
+}
+
+#[derive(Deserialize, Debug, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchUser {
+    /// Username prefix, or words of the name or display name
+    #[validate(
+        length(min = 1, max = 64),
+        custom(function = "validation::rules::not_blank")
+    )]
+    q: String,
+    #[validate(range(min = 1, max = 50))]
+    limit: Option<u32>,
+    /// Also leave out users the caller blocked, defaults to `true`. Users who
+    /// blocked the caller are never returned.
+    exclude_blocked: Option<bool>,
+}
+
+/// Condition hiding users from the caller bound to `$param`: accounts
+/// waiting for deletion, users who blocked the caller, and users whose
+/// search visibility leaves the caller out. The caller always sees
+/// themselves.
+pub(crate) fn visible_to(param: usize) -> String {
+    format!(
+        "users.deletion_scheduled_at IS NULL \
+         AND users.id NOT IN (SELECT blocker_id FROM user_blocks WHERE blocked_id = ${p}) \
+         AND (users.id = ${p} OR users.search_visibility = 'everyone' \
+           OR (users.search_visibility = 'friends' AND EXISTS ( \
+             SELECT 1 FROM user_relationships r WHERE r.status = 'accepted' \
+             AND ((r.requester_id = users.id AND r.addressee_id = ${p}) \
+               OR (r.addressee_id = users.id AND r.requester_id = ${p})))))",
+        p = param
+    )
+}
+
+/// Condition hiding the users the caller bound to `$param` blocked.
+pub(crate) fn not_blocked_by(param: usize) -> String {
+    format!(
+        "users.id NOT IN (SELECT blocked_id FROM user_blocks WHERE blocker_id = ${})",
+        param
+    )
+}
+
+/// Ranked user search. Exact and prefix username matches come first, then
+/// whole word matches on the name, then fuzzy trigram matches. `$1` is the
+/// escaped username prefix, `$2` the raw query, `$3` the caller, `$4` the
+/// limit and `$5` whether to also hide users the caller blocked.
+fn search_sql() -> String {
+    format!(
+        "SELECT {} FROM users \
+         WHERE (username LIKE $1 ESCAPE '\\' \
+             OR search @@ plainto_tsquery('simple', $2) \
+             OR name % $2 OR display_name % $2) \
+           AND {} \
+           AND ($5 = 'false' OR {}) \
+         ORDER BY (username = lower($2)) DESC, \
+             (username LIKE $1 ESCAPE '\\') DESC, \
+             ts_rank(search, plainto_tsquery('simple', $2)) \
+                 + GREATEST(similarity(name, $2), similarity(display_name, $2)) DESC, \
+             username \
+         LIMIT $4::TEXT::INT",
+        USER_COLUMNS,
+        visible_to(3),
+        not_blocked_by(3)
+    )
 }
 
 #[async_trait]
 pub trait UserService {
-    async fn get_user_by_id(&self, id: &str) -> Result<Option<User>, String>;
-    async fn get_users(&self, query: &QueryUser) -> Result<UserResponse, String>;
-    async fn update_user(&self, id: &str, user: &UpdateUser) -> Result<String, String>;
+    async fn get_user_by_id(&self, id: &str) -> Result<User, Error>;
+    async fn get_users(&self, caller_id: &str, query: &QueryUser) -> Result<Page<User>, Error>;
+    async fn search_users(&self, caller_id: &str, query: &SearchUser) -> Result<Vec<User>, Error>;
+    /// Sets only the fields provided. Changing the username is recorded in
+    /// the history and allowed once every [`USERNAME_COOLDOWN_DAYS`].
+    async fn update_user(&self, id: &str, user: &UpdateUser) -> Result<User, Error>;
+    /// Usernames the user gave up, newest first.
+    async fn get_username_history(&self, id: &str) -> Result<Vec<UsernameChange>, Error>;
+    async fn update_profile(&self, id: &str, profile: &UpdateProfile) -> Result<User, Error>;
+    async fn update_avatar(&self, id: &str, bytes: Vec<u8>) -> Result<User, Error>;
+    async fn remove_avatar(&self, id: &str) -> Result<User, Error>;
+    async fn get_avatar(&self, id: &str, size: AvatarSize) -> Result<Vec<u8>, Error>;
+    /// Schedules the hard delete [`DELETION_GRACE_DAYS`] from now and signs
+    /// the user out everywhere.
+    async fn delete_account(
+        &self,
+        id: &str,
+        data: &DeleteAccount,
+    ) -> Result<AccountDeletion, Error>;
+    /// Hard deletes the accounts whose grace period is over, along with
+    /// their blobs. Rows referencing them go with them through the foreign
+    /// keys. Returns how many accounts were deleted.
+    async fn purge_deleted_accounts(&self) -> Result<usize, Error>;
 }
 
-pub struct UserServiceImpl<D: Database<PgRow>, L: Logger> {
+pub struct UserServiceImpl<D: Database<PgRow>, L: Logger, B: BlobStore, H: Hasher> {
     db: D,
     logger: L,
+    blobs: B,
+    hasher: H,
+    cursors: CursorCodec,
 }
 
-impl<D: Database<PgRow>, L: Logger> UserServiceImpl<D, L> {
-    pub fn new(db: D, logger: L) -> Self {
-        Self { db, logger }
+impl<D: Database<PgRow>, L: Logger, B: BlobStore, H: Hasher> UserServiceImpl<D, L, B, H> {
+    pub fn new(db: D, logger: L, blobs: B, hasher: H, cursors: CursorCodec) -> Self {
+        Self {
+            db,
+            logger,
+            blobs,
+            hasher,
+            cursors,
+        }
+    }
+
+    /// Errors when the user changed their username within the cooldown.
+    /// Returns whether `username` differs from the current one.
+    async fn check_username_change(&self, id: &str, username: &str) -> Result<bool, Error> {
+        let row = self
+            .db
+            .query_one(
+                "SELECT username, COALESCE(( \
+                   SELECT to_json(MAX(changed_at) + make_interval(days => $2::TEXT::INT)) #>> '{}' \
+                   FROM username_history WHERE user_id = users.id \
+                   AND changed_at > NOW() - make_interval(days => $2::TEXT::INT)), '') \
+                 FROM users WHERE id = $1",
+                &[&id.to_string(), &USERNAME_COOLDOWN_DAYS.to_string()],
+            )
+            .await
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound(format!("user with id: {} not found", id)),
+                e => e,
+            })?;
+        if row.get(0) == username {
+            return Ok(false);
+        }
+        let allowed_at = row.get(1);
+        if !allowed_at.is_empty() {
+            return Err(Error::TooManyRequests(format!(
+                "Username can be changed again at {}",
+                allowed_at
+            )));
+        }
+        Ok(true)
+    }
+
+    async fn avatar_version(&self, id: &str) -> Result<String, Error> {
+        self.db
+            .query_one("SELECT avatar FROM users WHERE id = $1", &[&id.to_string()])
+            .await
+            .map(|row| row.get(0))
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound(format!("user with id: {} not found", id)),
+                e => e,
+            })
+    }
+
+    /// Points the user at a new avatar version, `""` clears it, and removes
+    /// the blobs of the previous one.
+    async fn set_avatar(&self, id: &str, version: &str) -> Result<User, Error> {
+        let previous = self.avatar_version(id).await?;
+        let sql = format!(
+            "UPDATE users SET avatar = $1, updated_at = NOW() WHERE id = $2 RETURNING {}",
+            USER_COLUMNS
+        );
+        let row = self
+            .db
+            .query_one(&sql, &[&version.to_string(), &id.to_string()])
+            .await?;
+        if !previous.is_empty() {
+            // The new avatar is already live, a leftover blob is only wasted space
+            if let Err(e) = self
+                .blobs
+                .delete_prefix(&avatar_prefix(id, &previous))
+                .await
+            {
+                let message = format!("failed to delete avatar {} of user {}: {}", previous, id, e);
+                self.logger.error("user_service::set_avatar", &message);
+            }
+        }
+        Ok(user_from_row(&row))
     }
 }
 
 #[async_trait]
-impl<D: Database<PgRow> + Send + Sync, L: Logger + Send + Sync> UserService
-    for UserServiceImpl<D, L>
+impl<
+        D: Database<PgRow> + Send + Sync,
+        L: Logger + Send + Sync,
+        B: BlobStore + Send + Sync,
+        H: Hasher + Send + Sync,
+    > UserService for UserServiceImpl<D, L, B, H>
 {
-    async fn get_user_by_id(&self, id: &str) -> Result<Option<User>, String> {
+    async fn get_user_by_id(&self, id: &str) -> Result<User, Error> {
         let message = format!("querying user with id: {}", id);
         self.logger.info("user_service::get_user_by_id", &message);
-        let row = self
+        let sql = format!("SELECT {} FROM users WHERE id = $1", USER_COLUMNS);
+        let row = self.db.query_one(&sql, &[&id.to_string()]).await;
+        match row {
+            Ok(row) => Ok(user_from_row(&row)),
+            Err(Error::NotFound(_)) => {
+                let message = format!("user with id: {} not found", id);
+                self.logger.error("user_service::get_user_by_id", &message);
+                Err(Error::NotFound(message))
+            }
+            Err(e) => {
+                let message = format!("failed to query user with id: {}: {}", id, e);
+                self.logger.error("user_service::get_user_by_id", &message);
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_users(&self, caller_id: &str, query: &QueryUser) -> Result<Page<User>, Error> {
+        let q = query.q.clone().unwrap_or_default();
+        let request = PageRequest::new(
+            &self.cursors,
+            &format!("users:{}", q),
+            query.limit.unwrap_or(10),
+            query.cursor.as_deref(),
+        )?;
+        // An empty pattern matches everything, so the query text never changes
+        let pattern = format!("%{}%", escape_like(&q));
+        let after = request.key().first().cloned().unwrap_or_default();
+        // The caller is always `$2` so the filter is shared with the count
+        let filter = format!(
+            "WHERE (username ILIKE $1 ESCAPE '\\' OR name ILIKE $1 ESCAPE '\\') AND {} AND {}",
+            visible_to(2),
+            not_blocked_by(2)
+        );
+        let sql = format!(
+            "SELECT {} FROM users {} AND ($3 = '' OR username {} $3) \
+             ORDER BY username {} LIMIT $4::TEXT::INT",
+            USER_COLUMNS,
+            filter,
+            request.comparator(),
+            request.order()
+        );
+        let caller_id = caller_id.to_string();
+        let message = format!("querying users matching: {}", q);
+        self.logger.info("user_service::get_users", &message);
+        let rows = self
             .db
-            .query_one(
-                "SELECT id, name, username FROM users WHERE id = $1",
-                &vec![&id.to_string()],
+            .query(
+                &sql,
+                &[&pattern, &caller_id, &after, &request.fetch_limit()],
             )
             .await;
-        if row.is_ok() {
-            let row = row.unwrap();
-            let id = row.get(0);
-            let name = row.get(1);
-            let username = row.get(2);
-            return Ok(Some(User {
-                id: id.to_string(),
-                name: name.to_string(),
-                username: username.to_string(),
-            }));
+        let total = if query.include_total.unwrap_or(false) {
+            let total_sql = format!("SELECT COUNT(*)::TEXT as total FROM users {}", filter);
+            self.db
+                .query_one(&total_sql, &[&pattern, &caller_id])
+                .await
+                .map(|row| row.get(0).parse().ok())
         } else {
-            let message = format!("user with id: {} not found", id,);
-            self.logger.error("user_service::get_user_by_id", &message);
-            return Err(message);
-        }
-    }
-
-    async fn get_users(&self, query: &QueryUser) -> Result<UserResponse, String> {
-        let limit = query.limit.unwrap_or(10);
-        let offset = query.offset.unwrap_or(0);
-        let mut sql = "SELECT id, name, username FROM users".to_string();
-        let mut total_sql = "SELECT COUNT(*)::TEXT as total FROM users".to_string();
-        if let Some(username) = &query.q {
-            sql = format!(
-                "{} WHERE username ILIKE '%{}%' OR name ILIKE '%{}%'",
-                sql, username, username
-            );
-            total_sql = format!(
-                "{} WHERE username ILIKE '%{}%' OR name ILIKE '%{}%'",
-                total_sql, username, username
-            );
-        }
-        sql = format!("{} LIMIT {} OFFSET {}", sql, limit, offset);
-        let message = format!("querying users with sql: {}", sql);
-        self.logger.info("user_service::get_users", &message);
-        let rows = self.db.query(&sql, &vec![]).await;
-        let total_rows = self.db.query_one(&total_sql, &vec![]).await;
-        if rows.is_ok() && total_rows.is_ok() {
-            let rows = rows.unwrap();
-            let mut users: Vec<User> = Vec::new();
-            for row in rows {
-                let id = row.get(0);
-                let name = row.get(1);
-                let username = row.get(2);
-                users.push(User {
-                    id: id.to_string(),
-                    name: name.to_string(),
-                    username: username.to_string(),
+            Ok(None)
+        };
+        match (rows, total) {
+            (Ok(rows), Ok(total)) => {
+                let users = rows.iter().map(user_from_row).collect();
+                let page = request.page(&self.cursors, users, |user: &User| {
+                    vec![user.username.clone()]
                 });
+                Ok(page.with_total(total))
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                let message = format!("failed to query users: {}", e);
+                self.logger.error("user_service::get_users", &message);
+                Err(e)
+            }
+        }
+    }
+
+    async fn search_users(&self, caller_id: &str, query: &SearchUser) -> Result<Vec<User>, Error> {
+        let q = query.q.trim().to_string();
+        let prefix = format!("{}%", escape_like(&q.to_lowercase()));
+        let exclude_blocked = query.exclude_blocked.unwrap_or(true).to_string();
+        let limit = query.limit.unwrap_or(20).to_string();
+        let message = format!("searching users matching: {}", q);
+        self.logger.info("user_service::search_users", &message);
+        let params = [
+            &prefix,
+            &q,
+            &caller_id.to_string(),
+            &limit,
+            &exclude_blocked,
+        ];
+        match self.db.query(&search_sql(), &params).await {
+            Ok(rows) => Ok(rows.iter().map(user_from_row).collect()),
+            Err(e) => {
+                let message = format!("failed to search users: {}", e);
+                self.logger.error("user_service::search_users", &message);
+                Err(e)
             }
-            let total = total_rows.unwrap().get(0);
-            let result = UserResponse {
-                data: Some(users),
-                total: Some(total.parse().unwrap_or(0)),
-            };
-            return Ok(result);
-        } else {
-            let message = format!("users not found");
-            self.logger.error("user_service::get_users", &message);
-            let result = UserResponse {
-                data: Some(Vec::new()),
-                total: Some(0),
-            };
-            return Ok(result);
         }
     }
 
-    async fn update_user(&self, id: &str, user: &UpdateUser) -> Result<String, String> {
+    async fn update_user(&self, id: &str, user: &UpdateUser) -> Result<User, Error> {
         let message = format!("updating user with id: {}", id);
         self.logger.info("user_service::update_user", &message);
-        let mut sql = "UPDATE users".to_string();
-        if Some(&user.name) != None && &user.username.clone().unwrap() != "" {
-            sql = format!("{} SET name = $1,", sql);
+        let mut changes = user.changes();
+        if let Some(username) = &user.username {
+            if !self.check_username_change(id, username).await? {
+                changes.retain(|(column, _)| *column != "username");
+            }
         }
-        if Some(&user.username) != None && &user.username.clone().unwrap() != "" {
-            sql = format!("{} username = $2", sql);
+        if changes.is_empty() {
+            return self.get_user_by_id(id).await;
         }
-        sql = format!("{} WHERE id = $3", sql);
+
+        // `$1` is the id, the new values follow in order
+        let assignments = changes
+            .iter()
+            .enumerate()
+            .map(|(i, (column, _))| format!("{} = ${}", column, i + 2))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let sql = format!(
+            "WITH previous AS (SELECT username FROM users WHERE id = $1), \
+             updated AS (UPDATE users SET {}, updated_at = NOW() WHERE id = $1 RETURNING {}), \
+             history AS (INSERT INTO username_history (user_id, old_username, new_username) \
+               SELECT $1, previous.username, updated.username FROM previous, updated \
+               WHERE previous.username <> updated.username) \
+             SELECT * FROM updated",
+            assignments, USER_COLUMNS
+        );
         let id = id.to_string();
-        let name = user.name.clone().unwrap().to_string();
-        let username = user.username.clone().unwrap().to_string();
-        let params = vec![&name, &username, &id];
-        let affected_rows = self.db.execute(&sql, &params).await;
-        if affected_rows.is_ok() {
-            let affected_rows = affected_rows.unwrap();
-            if affected_rows > 0 {
-                return Ok(format!("Successfully updated user with id: {}", id));
-            } else {
-                return Err(format!("Failed to update user with id: {}", id));
+        let mut params = vec![&id];
+        params.extend(changes.iter().map(|(_, value)| *value));
+        match self.db.query_one(&sql, &params).await {
+            Ok(row) => Ok(user_from_row(&row)),
+            Err(Error::NotFound(_)) => {
+                Err(Error::NotFound(format!("user with id: {} not found", id)))
             }
-        } else {
-            let message = format!("Failed to update user with id: {}", id);
-            self.logger.error("user_service::update_user", &message);
-            return Err(message);
+            Err(Error::Conflict(_)) => {
+                Err(Error::Conflict("Username is already taken".to_string()))
+            }
+            Err(e) => {
+                let message = format!("Failed to update user with id: {}: {}", id, e);
+                self.logger.error("user_service::update_user", &message);
+                Err(e)
+            }
+        }
+    }
+
+    async fn get_username_history(&self, id: &str) -> Result<Vec<UsernameChange>, Error> {
+        let rows = self
+            .db
+            .query(
+                "SELECT old_username, new_username, to_json(changed_at) #>> '{}' \
+                 FROM username_history WHERE user_id = $1 ORDER BY changed_at DESC, id DESC",
+                &[&id.to_string()],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| UsernameChange {
+                old_username: row.get(0),
+                new_username: row.get(1),
+                changed_at: row.get(2),
+            })
+            .collect())
+    }
+
+    async fn update_profile(&self, id: &str, profile: &UpdateProfile) -> Result<User, Error> {
+        let message = format!("updating profile of user with id: {}", id);
+        self.logger.info("user_service::update_profile", &message);
+        let sql = format!(
+            "UPDATE users SET display_name = $1, bio = $2, status_text = $3, timezone = $4, \
+             updated_at = NOW() WHERE id = $5 RETURNING {}",
+            USER_COLUMNS
+        );
+        let params = [
+            &profile.display_name,
+            &profile.bio,
+            &profile.status_text,
+            &profile.timezone,
+            &id.to_string(),
+        ];
+        match self.db.query_one(&sql, &params).await {
+            Ok(row) => Ok(user_from_row(&row)),
+            Err(Error::NotFound(_)) => {
+                Err(Error::NotFound(format!("user with id: {} not found", id)))
+            }
+            Err(e) => {
+                let message = format!("failed to update profile of user with id: {}: {}", id, e);
+                self.logger.error("user_service::update_profile", &message);
+                Err(e)
+            }
+        }
+    }
+
+    async fn update_avatar(&self, id: &str, bytes: Vec<u8>) -> Result<User, Error> {
+        if bytes.len() > MAX_AVATAR_BYTES {
+            return Err(Error::PayloadTooLarge(format!(
+                "Avatar must be at most {} bytes",
+                MAX_AVATAR_BYTES
+            )));
+        }
+        if !mime::sniff(&bytes).is_some_and(|mime| mime::IMAGE_TYPES.contains(&mime)) {
+            return Err(Error::UnsupportedMediaType(
+                "Avatar must be a PNG, JPEG, GIF or WebP image".to_string(),
+            ));
+        }
+        // Make sure the user exists before doing the expensive part
+        self.avatar_version(id).await?;
+
+        let sizes = AvatarSize::ALL.map(|size| size.pixels());
+        let thumbnails = tokio::task::spawn_blocking(move || thumbnail::thumbnails(&bytes, &sizes))
+            .await
+            .map_err(|e| Error::Internal(format!("Thumbnail task failed: {}", e)))??;
+        let version = uuid_v4();
+        for (size, thumbnail) in AvatarSize::ALL.iter().zip(thumbnails) {
+            self.blobs
+                .put(&avatar_key(id, &version, *size), &thumbnail)
+                .await?;
+        }
+        let message = format!("stored avatar {} of user with id: {}", version, id);
+        self.logger.info("user_service::update_avatar", &message);
+        self.set_avatar(id, &version).await
+    }
+
+    async fn remove_avatar(&self, id: &str) -> Result<User, Error> {
+        self.set_avatar(id, "").await
+    }
+
+    async fn get_avatar(&self, id: &str, size: AvatarSize) -> Result<Vec<u8>, Error> {
+        let version = self.avatar_version(id).await?;
+        if version.is_empty() {
+            return Err(Error::NotFound(format!(
+                "user with id: {} has no avatar",
+                id
+            )));
+        }
+        self.blobs.get(&avatar_key(id, &version, size)).await
+    }
+
+    async fn delete_account(
+        &self,
+        id: &str,
+        data: &DeleteAccount,
+    ) -> Result<AccountDeletion, Error> {
+        let row = self
+            .db
+            .query_one(
+                "SELECT password, (deletion_scheduled_at IS NOT NULL)::TEXT FROM users WHERE id = $1",
+                &[&id.to_string()],
+            )
+            .await
+            .map_err(|e| match e {
+                Error::NotFound(_) => Error::NotFound(format!("user with id: {} not found", id)),
+                e => e,
+            })?;
+        if !self.hasher.verify(&data.password, &row.get(0)) {
+            return Err(Error::Forbidden("Password is incorrect".to_string()));
+        }
+        if row.get(1) == "true" {
+            return Err(Error::Conflict(
+                "Account deletion is already scheduled".to_string(),
+            ));
+        }
+
+        let row = self
+            .db
+            .query_one(
+                "UPDATE users SET deletion_scheduled_at = NOW() + make_interval(days => $1::TEXT::INT), \
+                 updated_at = NOW() WHERE id = $2 RETURNING to_json(deletion_scheduled_at) #>> '{}'",
+                &[&DELETION_GRACE_DAYS.to_string(), &id.to_string()],
+            )
+            .await?;
+        self.db
+            .execute(
+                "DELETE FROM sessions WHERE user_id = $1",
+                &[&id.to_string()],
+            )
+            .await?;
+        let message = format!("scheduled deletion of user with id: {}", id);
+        self.logger.info("user_service::delete_account", &message);
+        Ok(AccountDeletion {
+            scheduled_at: row.get(0),
+        })
+    }
+
+    async fn purge_deleted_accounts(&self) -> Result<usize, Error> {
+        let rows = self
+            .db
+            .query(
+                "DELETE FROM users WHERE deletion_scheduled_at <= NOW() RETURNING id",
+                &[],
+            )
+            .await
+            .inspect_err(|e| {
+                let message = format!("failed to delete accounts: {}", e);
+                self.logger
+                    .error("user_service::purge_deleted_accounts", &message);
+            })?;
+        for row in &rows {
+            let id = row.get(0);
+            let prefixes = [
+                format!("avatars/{}", id),
+                format!("exports/{}", id),
+                format!("attachments/{}", id),
+            ];
+            for prefix in prefixes {
+                // The account is gone already, a leftover blob is only wasted space
+                if let Err(e) = self.blobs.delete_prefix(&prefix).await {
+                    let message = format!("failed to delete {} of user {}: {}", prefix, id, e);
+                    self.logger
+                        .error("user_service::purge_deleted_accounts", &message);
+                }
+            }
+            let message = format!("deleted user with id: {}", id);
+            self.logger
+                .info("user_service::purge_deleted_accounts", &message);
+        }
+        Ok(rows.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::db::MockDatabase;
+    use logger::log::Log;
+    use security::hasher::MockHasher;
+    use storage::local::LocalBlobStore;
+
+    use super::*;
+
+    fn service(
+        db: MockDatabase<PgRow>,
+        hasher: MockHasher,
+    ) -> UserServiceImpl<MockDatabase<PgRow>, Log, LocalBlobStore, MockHasher> {
+        UserServiceImpl::new(
+            db,
+            Log,
+            LocalBlobStore::new("unused"),
+            hasher,
+            CursorCodec::new(b"secret"),
+        )
+    }
+
+    fn account(scheduled: &str) -> MockDatabase<PgRow> {
+        let row = vec!["hash".to_string(), scheduled.to_string()];
+        let mut db = MockDatabase::new();
+        db.expect_query_one().times(1).returning(move |_, _| {
+            let row = PgRow::from(row.clone());
+            Box::pin(async move { Ok(row) })
+        });
+        db.expect_execute().never();
+        db
+    }
+
+    fn delete_account(password: &str) -> DeleteAccount {
+        DeleteAccount {
+            password: password.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_only_name() {
+        let mut db = MockDatabase::new();
+        db.expect_query_one()
+            .withf(|sql, params| {
+                sql.contains("SET name = $2, updated_at")
+                    && !sql.contains("username =")
+                    && params.len() == 2
+            })
+            .times(1)
+            .returning(|_, _| {
+                let mut row = vec!["alice".to_string(); 10];
+                row[6] = String::new();
+                let row = PgRow::from(row);
+                Box::pin(async move { Ok(row) })
+            });
+        let user = UpdateUser {
+            name: Some("Alice".to_string()),
+            ..Default::default()
+        };
+
+        let result = service(db, MockHasher::new())
+            .update_user("alice", &user)
+            .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_username_cooldown() {
+        let mut db = MockDatabase::new();
+        db.expect_query_one().times(1).returning(|_, _| {
+            let row = PgRow::from(vec![
+                "alice".to_string(),
+                "2026-11-01T00:00:00+00:00".to_string(),
+            ]);
+            Box::pin(async move { Ok(row) })
+        });
+        let user = UpdateUser {
+            username: Some("alice2".to_string()),
+            ..Default::default()
+        };
+
+        let result = service(db, MockHasher::new())
+            .update_user("alice", &user)
+            .await;
+        assert!(matches!(result, Err(Error::TooManyRequests(_))));
+    }
+
+    #[tokio::test]
+    async fn test_delete_account_checks_password() {
+        let mut hasher = MockHasher::new();
+        hasher.expect_verify().return_const(false);
+
+        let result = service(account("false"), hasher)
+            .delete_account("alice", &delete_account("wrong"))
+            .await;
+        assert!(matches!(result, Err(Error::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn test_delete_account_once() {
+        let mut hasher = MockHasher::new();
+        hasher.expect_verify().return_const(true);
+
+        let result = service(account("true"), hasher)
+            .delete_account("alice", &delete_account("secret"))
+            .await;
+        assert!(matches!(result, Err(Error::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn test_purge_deleted_accounts_removes_their_blobs() {
+        let mut db = MockDatabase::new();
+        db.expect_query().times(1).returning(|_, _| {
+            let rows = vec![PgRow::from(vec!["alice".to_string()])];
+            Box::pin(async move { Ok(rows) })
+        });
+        let root = std::env::temp_dir().join(format!("blobs-{}", security::uuid::uuid_v4()));
+        let blobs = LocalBlobStore::new(root);
+        let keys = [
+            "avatars/alice/avatar.png",
+            "exports/alice/e1.zip",
+            "attachments/alice/a1/original",
+            "attachments/bob/a2/original",
+        ];
+        for key in keys {
+            blobs.put(key, b"bytes").await.unwrap();
+        }
+        let service = UserServiceImpl::new(
+            db,
+            Log,
+            blobs,
+            MockHasher::new(),
+            CursorCodec::new(b"secret"),
+        );
+
+        assert_eq!(service.purge_deleted_accounts().await.unwrap(), 1);
+        for key in &keys[..3] {
+            assert!(service.blobs.get(key).await.is_err(), "{}", key);
         }
+        assert!(service.blobs.get(keys[3]).await.is_ok());
+        service.blobs.delete_prefix("attachments").await.unwrap();
     }
 }
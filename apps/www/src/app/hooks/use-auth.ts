@@ -1,4 +1,5 @@
 import { useCallback, useEffect, useState } from 'react'
+import { Response } from '../types/response'
 import { User } from '../types/user'
 
 type ErrorValidation = {
@@ -26,8 +27,13 @@ export function useAuth() {
       method: 'GET',
       credentials: 'include',
     })
-      .then((res) => res.json() as Promise<User>)
-      .then((user) => setUser(user))
+      .then((res) => {
+        if (!res.ok) {
+          throw new Error('Failed to fetch user')
+        }
+        return res.json() as Promise<Response<User>>
+      })
+      .then(({ data }) => setUser(data))
       .catch(() => {
         const refresh = async () => {
           try {
@@ -50,9 +56,14 @@ export function useAuth() {
       method: 'GET',
       credentials: 'include',
     })
-      .then((res) => res.json() as Promise<User>)
-      .then((user) => {
-        setUser(user)
+      .then((res) => {
+        if (!res.ok) {
+          throw new Error('Failed to fetch user')
+        }
+        return res.json() as Promise<Response<User>>
+      })
+      .then(({ data }) => {
+        setUser(data)
         setLoading(false)
       })
       .catch(() => {
@@ -0,0 +1,25 @@
+export type Response<T> = {
+  data: T
+  message: string
+}
+
+export type FieldError = {
+  field: string
+  code: string
+  message: string
+}
+
+export type ErrorResponse = {
+  code: string
+  message: string
+  fields?: FieldError[]
+}
+
+export type Page<T> = {
+  data: T[]
+  next_cursor?: string | null
+  prev_cursor?: string | null
+  next?: string | null
+  prev?: string | null
+  total?: number | null
+}
@@ -1,6 +1,19 @@
+export type Avatar = {
+  small: string
+  medium: string
+  large: string
+}
+
 export type User = {
   id: string
   name?: string | null
   email?: string | null
   username?: string | null
+  display_name?: string
+  bio?: string
+  status_text?: string
+  avatar?: Avatar | null
+  timezone?: string
+  created_at?: string
+  updated_at?: string
 }
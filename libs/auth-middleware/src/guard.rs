@@ -0,0 +1,343 @@
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+};
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    Error, HttpMessage,
+};
+use errors::error::Error as ApiError;
+use futures::future::LocalBoxFuture;
+use logger::{log::Log, logger::Logger};
+use security::jwt::{Claims, Jwt};
+
+use crate::{
+    source::{extract_token, TokenSource},
+    user::AuthenticatedUser,
+};
+
+/// What a route demands from a token once it has been verified.
+///
+/// Empty lists are not checked. `kinds` and `roles` pass when the token has
+/// any one of the listed values, `scopes` pass only when every scope is held.
+#[derive(Debug, Clone, Default)]
+pub struct Requirements {
+    pub kinds: Vec<String>,
+    pub roles: Vec<String>,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Rejection {
+    InvalidKind,
+    MissingRole,
+    MissingScope(String),
+}
+
+impl Requirements {
+    pub fn check(&self, claims: &Claims) -> Result<(), Rejection> {
+        let additional = &claims.additional_claims;
+        if !self.kinds.is_empty() && !self.kinds.contains(&additional.kind) {
+            return Err(Rejection::InvalidKind);
+        }
+        if !self.roles.is_empty() && !self.roles.iter().any(|r| additional.roles.contains(r)) {
+            return Err(Rejection::MissingRole);
+        }
+        if let Some(scope) = self
+            .scopes
+            .iter()
+            .find(|scope| !additional.scopes.contains(scope))
+        {
+            return Err(Rejection::MissingScope(scope.clone()));
+        }
+        Ok(())
+    }
+}
+
+/// Authenticates requests with an injected [`Jwt`] and inserts an
+/// [`AuthenticatedUser`] into the request extensions.
+///
+/// ```ignore
+/// let guard = Guard::new(jwt)
+///     .sources(vec![TokenSource::authorization(), TokenSource::cookie("token")])
+///     .kinds(&["auth_token"])
+///     .scopes(&["users:write"]);
+/// ```
+pub struct Guard<J: Jwt> {
+    jwt: Rc<J>,
+    sources: Vec<TokenSource>,
+    requirements: Requirements,
+}
+
+impl<J: Jwt> Guard<J> {
+    /// Reads a bearer token from the `Authorization` header and accepts any
+    /// valid token until requirements are added.
+    pub fn new(jwt: J) -> Self {
+        Self {
+            jwt: Rc::new(jwt),
+            sources: vec![TokenSource::authorization()],
+            requirements: Requirements::default(),
+        }
+    }
+
+    pub fn sources(mut self, sources: Vec<TokenSource>) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    pub fn kinds(mut self, kinds: &[&str]) -> Self {
+        self.requirements.kinds = to_strings(kinds);
+        self
+    }
+
+    pub fn roles(mut self, roles: &[&str]) -> Self {
+        self.requirements.roles = to_strings(roles);
+        self
+    }
+
+    pub fn scopes(mut self, scopes: &[&str]) -> Self {
+        self.requirements.scopes = to_strings(scopes);
+        self
+    }
+}
+
+fn to_strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|v| v.to_string()).collect()
+}
+
+impl<S, B, J> Transform<S, ServiceRequest> for Guard<J>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+    J: Jwt + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = GuardMiddleware<S, J>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(GuardMiddleware {
+            service,
+            jwt: self.jwt.clone(),
+            sources: self.sources.clone(),
+            requirements: self.requirements.clone(),
+            logger: Log,
+        }))
+    }
+}
+
+pub struct GuardMiddleware<S, J: Jwt> {
+    service: S,
+    jwt: Rc<J>,
+    sources: Vec<TokenSource>,
+    requirements: Requirements,
+    logger: Log,
+}
+
+impl<S, B, J> Service<ServiceRequest> for GuardMiddleware<S, J>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+    J: Jwt + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        self.logger
+            .info("Guard::call", "Guard: executing middleware");
+
+        let token = match extract_token(&self.sources, &req) {
+            Some(token) => token,
+            None => {
+                self.logger.error("Guard::call", "Guard: missing token");
+                return Box::pin(ready(Err(ApiError::Unauthorized(
+                    "Missing token".to_string(),
+                )
+                .into())));
+            }
+        };
+
+        let claims = match self.jwt.extract(&token) {
+            Ok(claims) => claims,
+            Err(err) => {
+                self.logger.error("Guard::call", "Guard: invalid token");
+                return Box::pin(ready(Err(err.into())));
+            }
+        };
+
+        if let Err(rejection) = self.requirements.check(&claims) {
+            let message = format!("Guard: rejected token, {:?}", rejection);
+            self.logger.error("Guard::call", &message);
+            let error = match rejection {
+                Rejection::InvalidKind => ApiError::Unauthorized("Invalid token kind".to_string()),
+                Rejection::MissingRole => ApiError::Forbidden("Missing required role".to_string()),
+                Rejection::MissingScope(scope) => {
+                    ApiError::Forbidden(format!("Missing required scope {}", scope))
+                }
+            };
+            return Box::pin(ready(Err(error.into())));
+        }
+
+        self.logger.info("Guard::call", "Guard: valid token");
+        req.extensions_mut()
+            .insert(AuthenticatedUser::from_claims(claims, token));
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await?;
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::StatusCode,
+        test::{call_and_read_body, init_service, try_call_service, TestRequest},
+        web, App, HttpResponse,
+    };
+    use security::jwt::{AdditionalClaims, MockJwt};
+
+    use super::*;
+
+    fn claims(kind: &str, roles: &[&str], scopes: &[&str]) -> Claims {
+        Claims {
+            exp: 0,
+            iat: 0,
+            nbf: 0,
+            sub: "alice".to_string(),
+            jti: "jti".to_string(),
+            additional_claims: AdditionalClaims {
+                user_id: "user-1".to_string(),
+                kind: kind.to_string(),
+                roles: to_strings(roles),
+                scopes: to_strings(scopes),
+            },
+        }
+    }
+
+    fn jwt_returning(kind: &'static str, scopes: &'static [&'static str]) -> MockJwt {
+        let mut jwt = MockJwt::new();
+        jwt.expect_extract().returning(move |token| match token {
+            "valid" => Ok(claims(kind, &[], scopes)),
+            _ => Err(ApiError::Unauthorized("Invalid token".to_string())),
+        });
+        jwt
+    }
+
+    async fn whoami(user: AuthenticatedUser) -> HttpResponse {
+        HttpResponse::Ok().body(user.user_id)
+    }
+
+    #[test]
+    fn test_requirements_check() {
+        let requirements = Requirements {
+            kinds: to_strings(&["auth_token"]),
+            roles: to_strings(&["admin", "moderator"]),
+            scopes: to_strings(&["users:read", "users:write"]),
+        };
+        assert_eq!(
+            requirements.check(&claims("refresh_token", &["admin"], &[])),
+            Err(Rejection::InvalidKind)
+        );
+        assert_eq!(
+            requirements.check(&claims("auth_token", &["user"], &[])),
+            Err(Rejection::MissingRole)
+        );
+        assert_eq!(
+            requirements.check(&claims("auth_token", &["moderator"], &["users:read"])),
+            Err(Rejection::MissingScope("users:write".to_string()))
+        );
+        assert!(requirements
+            .check(&claims(
+                "auth_token",
+                &["moderator"],
+                &["users:read", "users:write"]
+            ))
+            .is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_header_without_bearer_is_unauthorized() {
+        let app = init_service(
+            App::new().service(
+                web::scope("")
+                    .wrap(Guard::new(jwt_returning("auth_token", &[])))
+                    .route("/", web::get().to(whoami)),
+            ),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri("/")
+            .insert_header(("Authorization", "valid"))
+            .to_request();
+        let res = try_call_service(&app, req).await;
+        assert_eq!(
+            res.err().map(|e| e.as_response_error().status_code()),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_token_sources_and_scopes() {
+        let app = init_service(
+            App::new().service(
+                web::scope("")
+                    .wrap(
+                        Guard::new(jwt_returning("auth_token", &["users:read"]))
+                            .sources(vec![
+                                TokenSource::authorization(),
+                                TokenSource::cookie("token"),
+                                TokenSource::query("access_token"),
+                            ])
+                            .kinds(&["auth_token"])
+                            .scopes(&["users:read"]),
+                    )
+                    .route("/", web::get().to(whoami)),
+            ),
+        )
+        .await;
+
+        let req = TestRequest::get().uri("/?access_token=valid").to_request();
+        let body = call_and_read_body(&app, req).await;
+        assert_eq!(body, "user-1");
+
+        let req = TestRequest::get()
+            .uri("/")
+            .cookie(actix_web::cookie::Cookie::new("token", "valid"))
+            .to_request();
+        let body = call_and_read_body(&app, req).await;
+        assert_eq!(body, "user-1");
+    }
+
+    #[actix_web::test]
+    async fn test_missing_scope_is_forbidden() {
+        let app = init_service(
+            App::new().service(
+                web::scope("")
+                    .wrap(Guard::new(jwt_returning("auth_token", &[])).scopes(&["users:write"]))
+                    .route("/", web::get().to(whoami)),
+            ),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri("/")
+            .insert_header(("Authorization", "Bearer valid"))
+            .to_request();
+        let res = try_call_service(&app, req).await;
+        assert_eq!(
+            res.err().map(|e| e.as_response_error().status_code()),
+            Some(StatusCode::FORBIDDEN)
+        );
+    }
+}
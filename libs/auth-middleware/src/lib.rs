@@ -0,0 +1,5 @@
+pub mod guard;
+pub mod openapi;
+pub mod rbac;
+pub mod source;
+pub mod user;
@@ -0,0 +1,48 @@
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify,
+};
+
+/// Security scheme for tokens sent as `Authorization: Bearer <token>`.
+pub const BEARER: &str = "bearer";
+/// Security scheme for the auth token cookie set on sign in.
+pub const COOKIE: &str = "cookie";
+/// Security scheme for refresh tokens sent as
+/// `Authorization-refresh: Bearer <token>`.
+pub const REFRESH_BEARER: &str = "refresh_bearer";
+/// Security scheme for the refresh token cookie set on sign in.
+pub const REFRESH_COOKIE: &str = "refresh_cookie";
+
+/// Registers the token sources [`crate::guard::Guard`] reads from as OpenAPI
+/// security schemes, so operations can reference them by name.
+pub struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            BEARER,
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            COOKIE,
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("token"))),
+        );
+        components.add_security_scheme(
+            REFRESH_BEARER,
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "Authorization-refresh",
+                "Refresh token prefixed with `Bearer `",
+            ))),
+        );
+        components.add_security_scheme(
+            REFRESH_COOKIE,
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("refresh_token"))),
+        );
+    }
+}
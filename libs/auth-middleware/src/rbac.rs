@@ -0,0 +1,15 @@
+//! Role and permission names seeded in `schema.sql`. Roles are granted to
+//! users, permissions are granted to roles and travel in tokens as scopes.
+
+pub const ROLE_ADMIN: &str = "admin";
+pub const ROLE_MODERATOR: &str = "moderator";
+pub const ROLE_USER: &str = "user";
+/// Held by other services only, never granted to people.
+pub const ROLE_SERVICE: &str = "service";
+
+pub const USERS_READ: &str = "users:read";
+pub const USERS_WRITE: &str = "users:write";
+pub const ROLES_READ: &str = "roles:read";
+pub const ROLES_WRITE: &str = "roles:write";
+/// Guards `/internal` routes, only [`ROLE_SERVICE`] holds it.
+pub const INTERNAL_READ: &str = "internal:read";
@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+
+use actix_web::{dev::ServiceRequest, web};
+
+/// Where a guard looks for the token. Sources are tried in the order they are
+/// configured and the first one that yields a non-empty token wins.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenSource {
+    /// `<header>: Bearer <token>`
+    Bearer(String),
+    /// Value of the named cookie.
+    Cookie(String),
+    /// Value of the named query-string parameter.
+    Query(String),
+}
+
+impl TokenSource {
+    pub fn authorization() -> Self {
+        TokenSource::Bearer("Authorization".to_string())
+    }
+
+    pub fn bearer(header: &str) -> Self {
+        TokenSource::Bearer(header.to_string())
+    }
+
+    pub fn cookie(name: &str) -> Self {
+        TokenSource::Cookie(name.to_string())
+    }
+
+    pub fn query(name: &str) -> Self {
+        TokenSource::Query(name.to_string())
+    }
+
+    pub fn extract(&self, req: &ServiceRequest) -> Option<String> {
+        let token = match self {
+            TokenSource::Bearer(header) => req
+                .headers()
+                .get(header.as_str())
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .map(|value| value.trim().to_string()),
+            TokenSource::Cookie(name) => req.cookie(name).map(|c| c.value().to_string()),
+            TokenSource::Query(name) => {
+                web::Query::<HashMap<String, String>>::from_query(req.query_string())
+                    .ok()
+                    .and_then(|query| query.get(name).cloned())
+            }
+        };
+        token.filter(|value| !value.is_empty())
+    }
+}
+
+pub fn extract_token(sources: &[TokenSource], req: &ServiceRequest) -> Option<String> {
+    sources.iter().find_map(|source| source.extract(req))
+}
@@ -0,0 +1,53 @@
+use std::future::{ready, Ready};
+
+use actix_web::{dev::Payload, FromRequest, HttpMessage, HttpRequest};
+use errors::error::Error;
+use security::jwt::Claims;
+
+/// The caller resolved by [`crate::guard::Guard`]. Handlers behind a guard
+/// take it as an argument instead of digging through request extensions.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub user_id: String,
+    pub username: String,
+    pub kind: String,
+    pub roles: Vec<String>,
+    pub scopes: Vec<String>,
+    pub jti: String,
+    /// Unix time the token expires
+    pub exp: usize,
+    pub token: String,
+}
+
+impl AuthenticatedUser {
+    pub fn from_claims(claims: Claims, token: String) -> Self {
+        Self {
+            user_id: claims.additional_claims.user_id,
+            username: claims.sub,
+            kind: claims.additional_claims.kind,
+            roles: claims.additional_claims.roles,
+            scopes: claims.additional_claims.scopes,
+            jti: claims.jti,
+            exp: claims.exp,
+            token,
+        }
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let user = req.extensions().get::<AuthenticatedUser>().cloned();
+        ready(user.ok_or_else(|| Error::Unauthorized("Missing authenticated user".to_string())))
+    }
+}
@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use errors::error::Error;
 use mockall::automock;
 
 #[automock]
@@ -10,9 +11,9 @@ pub trait Database<T>
 where
     T: Row,
 {
-    async fn query(&self, sql: &str, params: &Vec<&String>) -> Result<Vec<T>, String>;
+    async fn query(&self, sql: &str, params: &[&String]) -> Result<Vec<T>, Error>;
 
-    async fn query_one(&self, sql: &str, params: &Vec<&String>) -> Result<T, String>;
+    async fn query_one(&self, sql: &str, params: &[&String]) -> Result<T, Error>;
 
-    async fn execute(&self, sql: &str, params: &Vec<&String>) -> Result<u64, String>;
+    async fn execute(&self, sql: &str, params: &[&String]) -> Result<u64, Error>;
 }
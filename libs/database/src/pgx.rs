@@ -1,12 +1,14 @@
 use crate::db::{self, Database};
 use async_trait::async_trait;
+use errors::error::Error;
 use security::env::{Env, EnvImpl};
-use tokio_postgres::{types::ToSql, NoTls};
+use tokio_postgres::{error::SqlState, types::ToSql, NoTls};
 
 pub struct Postgresql {
     client: tokio_postgres::Client,
 }
 
+#[derive(Default)]
 pub struct PgRow {
     row: Vec<String>,
 }
@@ -23,6 +25,47 @@ impl PgRow {
 
 impl db::Row for PgRow {}
 
+/// Builds a row from its text columns, lets tests return rows from mocks.
+impl From<Vec<String>> for PgRow {
+    fn from(row: Vec<String>) -> Self {
+        PgRow { row }
+    }
+}
+
+/// Maps driver errors onto the shared error type so constraint violations
+/// reach clients as conflicts instead of opaque 500s.
+fn map_error(e: tokio_postgres::Error) -> Error {
+    match e.code() {
+        Some(code) if *code == SqlState::UNIQUE_VIOLATION => {
+            Error::Conflict("Record already exists".to_string())
+        }
+        Some(code) if *code == SqlState::FOREIGN_KEY_VIOLATION => {
+            Error::NotFound("Referenced record not found".to_string())
+        }
+        Some(code)
+            if *code == SqlState::INVALID_TEXT_REPRESENTATION
+                || *code == SqlState::CHECK_VIOLATION
+                || *code == SqlState::NOT_NULL_VIOLATION =>
+        {
+            Error::BadRequest("Invalid value".to_string())
+        }
+        _ => Error::Internal(e.to_string()),
+    }
+}
+
+/// Escapes `%`, `_` and `\` so user input can be embedded in a `LIKE`
+/// pattern. The query must use `ESCAPE '\'`.
+pub fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
 impl Postgresql {
     pub async fn new(env: EnvImpl) -> Self {
         let db_url = env.get(&security::env::EnvConfig::DatabaseUrl);
@@ -45,7 +88,7 @@ impl Postgresql {
 
 #[async_trait]
 impl Database<PgRow> for Postgresql {
-    async fn query(&self, sql: &str, params: &Vec<&String>) -> Result<Vec<PgRow>, String> {
+    async fn query(&self, sql: &str, params: &[&String]) -> Result<Vec<PgRow>, Error> {
         let param_refs: Vec<&(dyn ToSql + Sync)> =
             params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
         let rows = self.client.query(sql, &param_refs).await;
@@ -61,33 +104,46 @@ impl Database<PgRow> for Postgresql {
                 }
                 Ok(pg_rows)
             }
-            Err(e) => Err(e.to_string()),
+            Err(e) => Err(map_error(e)),
         }
     }
-    async fn query_one(&self, sql: &str, params: &Vec<&String>) -> Result<PgRow, String> {
+    async fn query_one(&self, sql: &str, params: &[&String]) -> Result<PgRow, Error> {
         let param_refs: Vec<&(dyn ToSql + Sync)> =
             params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
-        let row = self.client.query_one(sql, &param_refs).await;
+        let row = self.client.query_opt(sql, &param_refs).await;
         match row {
-            Ok(row) => {
+            Ok(Some(row)) => {
                 let mut rows = Vec::new();
                 for index in 0..row.len() {
                     rows.push(row.get(index));
                 }
                 Ok(PgRow { row: rows })
             }
-            Err(e) => Err(e.to_string()),
+            Ok(None) => Err(Error::NotFound("Record not found".to_string())),
+            Err(e) => Err(map_error(e)),
         }
     }
 
-    async fn execute(&self, sql: &str, params: &Vec<&String>) -> Result<u64, String> {
+    async fn execute(&self, sql: &str, params: &[&String]) -> Result<u64, Error> {
         let param_refs: Vec<&(dyn ToSql + Sync)> =
             params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
 
         let result = self.client.execute(sql, &param_refs).await;
         match result {
             Ok(count) => Ok(count),
-            Err(e) => Err(e.to_string()),
+            Err(e) => Err(map_error(e)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_like() {
+        assert_eq!(escape_like("alice"), "alice");
+        assert_eq!(escape_like("a_b%c"), "a\\_b\\%c");
+        assert_eq!(escape_like("a\\b"), "a\\\\b");
+    }
+}
@@ -1,6 +1,7 @@
 use std::vec;
 
 use async_trait::async_trait;
+use errors::error::Error;
 use redis::{Client, Commands};
 use security::env::{Env, EnvConfig, EnvImpl};
 
@@ -24,31 +25,31 @@ impl Row for RedisRow {}
 
 #[async_trait]
 impl Database<RedisRow> for RedisImpl {
-    async fn query(&self, _sql: &str, _params: &Vec<&String>) -> Result<Vec<RedisRow>, String> {
+    async fn query(&self, _sql: &str, _params: &[&String]) -> Result<Vec<RedisRow>, Error> {
         Ok(vec![])
     }
 
-    async fn query_one(&self, sql: &str, _params: &Vec<&String>) -> Result<RedisRow, String> {
+    async fn query_one(&self, sql: &str, _params: &[&String]) -> Result<RedisRow, Error> {
         let mut connection = self
             .client
             .get_connection()
-            .expect("Failed to get connection from client");
+            .map_err(|e| Error::Internal(e.to_string()))?;
         let value: Result<String, redis::RedisError> = connection.get(sql);
         match value {
             Ok(v) => Ok(RedisRow::new(vec![v])),
-            Err(_) => Err("no data in redis".to_string()),
+            Err(_) => Err(Error::NotFound("no data in redis".to_string())),
         }
         // Ok(RedisRow::new(vec![value]))
     }
 
-    async fn execute(&self, sql: &str, params: &Vec<&String>) -> Result<u64, String> {
+    async fn execute(&self, sql: &str, params: &[&String]) -> Result<u64, Error> {
         let mut connection = self
             .client
             .get_connection()
-            .expect("Failed to get connection from client");
+            .map_err(|e| Error::Internal(e.to_string()))?;
         let _: () = connection
-            .set(sql, &params[0])
-            .expect("Failed to set value");
+            .set(sql, params[0])
+            .map_err(|e| Error::Internal(e.to_string()))?;
         Ok(1)
     }
 }
@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Envelope of every successful JSON response, errors use
+/// [`crate::error::ErrorBody`] instead.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct Response<T> {
+    pub data: T,
+    pub message: String,
+}
+
+/// Data of responses that only carry a message, serialized as `null`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, ToSchema)]
+pub struct Empty;
+
+impl<T> Response<T> {
+    pub fn new(data: T, message: &str) -> Self {
+        Self {
+            data,
+            message: message.to_string(),
+        }
+    }
+}
@@ -0,0 +1,8 @@
+//! Pub/sub channels shared between publishers and subscribers.
+
+/// Friend request, friendship and block changes, published by `apps/user`.
+pub const USER_RELATIONSHIPS: &str = "user.relationships";
+
+/// Changes in conversations for their connected members, published by
+/// `apps/chat`. `data` holds the `recipients` and the `payload` to hand them.
+pub const CHAT_CONVERSATIONS: &str = "chat.conversations";
@@ -0,0 +1,38 @@
+use chrono::{SecondsFormat, Utc};
+use security::uuid::uuid_v4;
+use serde::{Deserialize, Serialize};
+
+/// Something that happened in one service that others may react to.
+/// `kind` is a dotted name such as `friend_request.accepted`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Event {
+    pub id: String,
+    pub kind: String,
+    pub occurred_at: String,
+    pub data: serde_json::Value,
+}
+
+impl Event {
+    pub fn new(kind: &str, data: impl Serialize) -> Self {
+        Self {
+            id: uuid_v4(),
+            kind: kind.to_string(),
+            occurred_at: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            data: serde_json::to_value(data).unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_serialization() {
+        let event = Event::new("friend.removed", serde_json::json!({ "user_id": "1" }));
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["kind"], "friend.removed");
+        assert_eq!(json["data"]["user_id"], "1");
+        assert!(json["occurred_at"].as_str().unwrap().ends_with('Z'));
+    }
+}
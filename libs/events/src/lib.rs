@@ -0,0 +1,4 @@
+pub mod channel;
+pub mod event;
+pub mod publisher;
+pub mod subscriber;
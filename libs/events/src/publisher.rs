@@ -0,0 +1,48 @@
+use async_trait::async_trait;
+use errors::error::Error;
+use mockall::automock;
+use redis::{Client, Commands};
+use security::env::{Env, EnvConfig, EnvImpl};
+
+use crate::event::Event;
+
+/// Events describe changes that are already committed. Services log a failed
+/// publish instead of returning it, the change stands either way and clients
+/// catch up on their next fetch.
+#[automock]
+#[async_trait]
+pub trait Publisher {
+    async fn publish(&self, channel: &str, event: &Event) -> Result<(), Error>;
+}
+
+/// Publishes events as JSON on Redis pub/sub channels. Delivery is at most
+/// once, subscribers that are down miss the event.
+pub struct RedisPublisher {
+    client: Client,
+}
+
+impl RedisPublisher {
+    pub fn new(env: EnvImpl) -> Self {
+        let url = env
+            .get(&EnvConfig::RedisUrl)
+            .expect("Failed to get redis url from env");
+
+        let client = Client::open(url).expect("Failed to connect to redis");
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl Publisher for RedisPublisher {
+    async fn publish(&self, channel: &str, event: &Event) -> Result<(), Error> {
+        let payload = serde_json::to_string(event).map_err(|e| Error::Internal(e.to_string()))?;
+        let mut connection = self
+            .client
+            .get_connection()
+            .map_err(|e| Error::Internal(e.to_string()))?;
+        let _: i64 = connection
+            .publish(channel, payload)
+            .map_err(|e| Error::Internal(e.to_string()))?;
+        Ok(())
+    }
+}
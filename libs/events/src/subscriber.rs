@@ -0,0 +1,58 @@
+use std::{thread, time::Duration};
+
+use logger::{log::Log, logger::Logger};
+use redis::Client;
+use security::env::{Env, EnvConfig, EnvImpl};
+
+use crate::event::Event;
+
+/// Wait before subscribing again after the connection dropped.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Receives events published with [`crate::publisher::RedisPublisher`].
+/// Events published while the connection is down are missed.
+pub struct RedisSubscriber {
+    client: Client,
+    logger: Log,
+}
+
+impl RedisSubscriber {
+    pub fn new(env: EnvImpl) -> Self {
+        let url = env
+            .get(&EnvConfig::RedisUrl)
+            .expect("Failed to get redis url from env");
+
+        let client = Client::open(url).expect("Failed to connect to redis");
+        Self {
+            client,
+            logger: Log,
+        }
+    }
+
+    /// Calls `handler` with every event on `channel` from a thread of its
+    /// own, reconnecting whenever the connection drops. Payloads that are not
+    /// events are skipped.
+    pub fn listen(self, channel: &str, handler: impl Fn(Event) + Send + 'static) {
+        let channel = channel.to_string();
+        thread::spawn(move || loop {
+            if let Err(e) = self.receive(&channel, &handler) {
+                let message = format!("subscription to {} dropped: {}", channel, e);
+                self.logger.error("RedisSubscriber::listen", &message);
+            }
+            thread::sleep(RECONNECT_DELAY);
+        });
+    }
+
+    fn receive(&self, channel: &str, handler: &impl Fn(Event)) -> redis::RedisResult<()> {
+        let mut connection = self.client.get_connection()?;
+        let mut pubsub = connection.as_pubsub();
+        pubsub.subscribe(channel)?;
+        loop {
+            let message = pubsub.get_message()?;
+            let payload: String = message.get_payload()?;
+            if let Ok(event) = serde_json::from_str(&payload) {
+                handler(event);
+            }
+        }
+    }
+}
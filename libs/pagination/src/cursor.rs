@@ -0,0 +1,132 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use errors::error::Error;
+use hmac::{Hmac, Mac};
+use security::env::{Env, EnvConfig};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+/// Which side of the cursor key the page is on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    After,
+    Before,
+}
+
+/// Position in a keyset ordered list: the sort key of the row the page
+/// starts after, or ends before.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub direction: Direction,
+    pub key: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Payload {
+    /// Endpoint and filters the cursor was issued for
+    context: String,
+    cursor: Cursor,
+}
+
+/// Turns cursors into opaque, signed tokens so clients can neither read nor
+/// forge positions, nor reuse a cursor with different filters.
+#[derive(Clone)]
+pub struct CursorCodec {
+    secret: Vec<u8>,
+}
+
+impl CursorCodec {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.to_vec(),
+        }
+    }
+
+    /// Signs with `CURSOR_SECRET`, falling back to `JWT_SECRET`.
+    pub fn from_env(env: impl Env) -> Self {
+        let secret = env
+            .get(&EnvConfig::CursorSecret)
+            .or_else(|| env.get(&EnvConfig::SecretKey))
+            .expect("CURSOR_SECRET or JWT_SECRET must be set");
+        Self::new(secret.as_bytes())
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any size")
+    }
+
+    pub fn encode(&self, context: &str, cursor: &Cursor) -> String {
+        let payload = Payload {
+            context: context.to_string(),
+            cursor: cursor.clone(),
+        };
+        let payload = serde_json::to_vec(&payload).expect("cursor payload is serializable");
+        let mut mac = self.mac();
+        mac.update(&payload);
+        let signature = mac.finalize().into_bytes();
+        format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(payload),
+            URL_SAFE_NO_PAD.encode(signature)
+        )
+    }
+
+    /// Fails with `400` if the token was tampered with or issued for another
+    /// context.
+    pub fn decode(&self, context: &str, token: &str) -> Result<Cursor, Error> {
+        let invalid = || Error::BadRequest("Invalid cursor".to_string());
+        let (payload, signature) = token.split_once('.').ok_or_else(invalid)?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| invalid())?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+        let mut mac = self.mac();
+        mac.update(&payload);
+        mac.verify_slice(&signature).map_err(|_| invalid())?;
+        let payload: Payload = serde_json::from_slice(&payload).map_err(|_| invalid())?;
+        if payload.context != context {
+            return Err(invalid());
+        }
+        Ok(payload.cursor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor() -> Cursor {
+        Cursor {
+            direction: Direction::After,
+            key: vec!["alice".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let codec = CursorCodec::new(b"secret");
+        let token = codec.encode("users", &cursor());
+        assert_eq!(codec.decode("users", &token).unwrap(), cursor());
+    }
+
+    #[test]
+    fn test_rejects_tampered_and_foreign_cursors() {
+        let codec = CursorCodec::new(b"secret");
+        let token = codec.encode("users", &cursor());
+
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = Payload {
+            context: "users".to_string(),
+            cursor: Cursor {
+                direction: Direction::After,
+                key: vec!["bob".to_string()],
+            },
+        };
+        let forged = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap()),
+            signature
+        );
+        assert!(codec.decode("users", &forged).is_err());
+        assert!(codec.decode("users:q=bob", &token).is_err());
+        assert!(CursorCodec::new(b"other").decode("users", &token).is_err());
+        assert!(codec.decode("users", "garbage").is_err());
+    }
+}
@@ -0,0 +1,2 @@
+pub mod cursor;
+pub mod page;
@@ -0,0 +1,236 @@
+use actix_web::HttpRequest;
+use errors::error::Error;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::cursor::{Cursor, CursorCodec, Direction};
+
+/// One page of a keyset paginated list. Cursors are opaque tokens to send
+/// back as `cursor`, links are the same request with the cursor applied.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+    pub next: Option<String>,
+    pub prev: Option<String>,
+    /// Only counted when asked for with `include_total=true`
+    pub total: Option<i64>,
+}
+
+/// Describes the page to fetch and builds the SQL fragments for it.
+///
+/// Queries compare their sort key against the cursor key with
+/// [`PageRequest::comparator`], sort with [`PageRequest::order`] and fetch
+/// [`PageRequest::fetch_limit`] rows, one more than the page holds so
+/// [`PageRequest::page`] can tell whether there is more.
+pub struct PageRequest {
+    context: String,
+    limit: usize,
+    cursor: Option<Cursor>,
+    descending: bool,
+}
+
+impl PageRequest {
+    /// `context` identifies the endpoint and its filters, cursors issued for
+    /// another context are rejected.
+    pub fn new(
+        codec: &CursorCodec,
+        context: &str,
+        limit: u32,
+        cursor: Option<&str>,
+    ) -> Result<Self, Error> {
+        let cursor = cursor
+            .map(|cursor| codec.decode(context, cursor))
+            .transpose()?;
+        Ok(Self {
+            context: context.to_string(),
+            limit: limit as usize,
+            cursor,
+            descending: false,
+        })
+    }
+
+    /// Lists newest first or otherwise from the largest key down.
+    pub fn descending(mut self) -> Self {
+        self.descending = true;
+        self
+    }
+
+    /// Key of the cursor, empty on the first page.
+    pub fn key(&self) -> &[String] {
+        self.cursor
+            .as_ref()
+            .map(|cursor| cursor.key.as_slice())
+            .unwrap_or_default()
+    }
+
+    fn is_backward(&self) -> bool {
+        self.cursor
+            .as_ref()
+            .is_some_and(|cursor| cursor.direction == Direction::Before)
+    }
+
+    /// `>` or `<`, for comparing the sort key with the cursor key.
+    pub fn comparator(&self) -> &'static str {
+        if self.is_backward() != self.descending {
+            "<"
+        } else {
+            ">"
+        }
+    }
+
+    /// `ASC` or `DESC`. Pages before the cursor are fetched in reverse and
+    /// flipped back by [`PageRequest::page`].
+    pub fn order(&self) -> &'static str {
+        if self.is_backward() != self.descending {
+            "DESC"
+        } else {
+            "ASC"
+        }
+    }
+
+    pub fn fetch_limit(&self) -> String {
+        (self.limit + 1).to_string()
+    }
+
+    /// Builds the page from the fetched rows. `key` returns the sort key of a
+    /// row, in the same order as the cursor key.
+    pub fn page<T>(
+        &self,
+        codec: &CursorCodec,
+        mut rows: Vec<T>,
+        key: impl Fn(&T) -> Vec<String>,
+    ) -> Page<T> {
+        let has_more = rows.len() > self.limit;
+        rows.truncate(self.limit);
+        let backward = self.is_backward();
+        if backward {
+            rows.reverse();
+        }
+        // Coming from a cursor means there is something on the other side
+        let has_next = if backward { true } else { has_more };
+        let has_prev = if backward {
+            has_more
+        } else {
+            self.cursor.is_some()
+        };
+        let encode = |direction: Direction, row: Option<&T>| {
+            row.map(|row| {
+                codec.encode(
+                    &self.context,
+                    &Cursor {
+                        direction,
+                        key: key(row),
+                    },
+                )
+            })
+        };
+        Page {
+            next_cursor: has_next
+                .then(|| encode(Direction::After, rows.last()))
+                .flatten(),
+            prev_cursor: has_prev
+                .then(|| encode(Direction::Before, rows.first()))
+                .flatten(),
+            data: rows,
+            next: None,
+            prev: None,
+            total: None,
+        }
+    }
+}
+
+impl<T> Page<T> {
+    pub fn with_total(mut self, total: Option<i64>) -> Self {
+        self.total = total;
+        self
+    }
+
+    /// Fills `next` and `prev` with the request path and query, the
+    /// `cursor` parameter replaced.
+    pub fn with_links(mut self, req: &HttpRequest) -> Self {
+        let link = |cursor: &String| {
+            let mut query: Vec<(String, String)> =
+                serde_urlencoded::from_str(req.query_string()).unwrap_or_default();
+            query.retain(|(name, _)| name != "cursor");
+            query.push(("cursor".to_string(), cursor.clone()));
+            let query = serde_urlencoded::to_string(query).unwrap_or_default();
+            format!("{}?{}", req.path(), query)
+        };
+        self.next = self.next_cursor.as_ref().map(link);
+        self.prev = self.prev_cursor.as_ref().map(link);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn key(row: &i32) -> Vec<String> {
+        vec![row.to_string()]
+    }
+
+    #[test]
+    fn test_forward_pages() {
+        let codec = CursorCodec::new(b"secret");
+        let first = PageRequest::new(&codec, "numbers", 2, None).unwrap();
+        assert_eq!((first.comparator(), first.order()), (">", "ASC"));
+        let page = first.page(&codec, vec![1, 2, 3], key);
+        assert_eq!(page.data, vec![1, 2]);
+        assert!(page.prev_cursor.is_none());
+
+        let next = page.next_cursor.unwrap();
+        let second = PageRequest::new(&codec, "numbers", 2, Some(&next)).unwrap();
+        assert_eq!(second.key(), ["2"]);
+        let page = second.page(&codec, vec![3], key);
+        assert_eq!(page.data, vec![3]);
+        assert!(page.next_cursor.is_none());
+
+        let prev = page.prev_cursor.unwrap();
+        let back = PageRequest::new(&codec, "numbers", 2, Some(&prev)).unwrap();
+        assert_eq!((back.comparator(), back.order()), ("<", "DESC"));
+        // Fetched in reverse, handed out in order
+        let page = back.page(&codec, vec![2, 1], key);
+        assert_eq!(page.data, vec![1, 2]);
+        assert!(page.prev_cursor.is_none());
+        assert!(page.next_cursor.is_some());
+    }
+
+    #[test]
+    fn test_descending_order() {
+        let codec = CursorCodec::new(b"secret");
+        let first = PageRequest::new(&codec, "messages", 2, None)
+            .unwrap()
+            .descending();
+        assert_eq!((first.comparator(), first.order()), ("<", "DESC"));
+        let page = first.page(&codec, vec![9, 8, 7], key);
+        let next = page.next_cursor.unwrap();
+        let older = PageRequest::new(&codec, "messages", 2, Some(&next))
+            .unwrap()
+            .descending();
+        assert_eq!(older.comparator(), "<");
+        let prev = older.page(&codec, vec![7], key).prev_cursor.unwrap();
+        let newer = PageRequest::new(&codec, "messages", 2, Some(&prev))
+            .unwrap()
+            .descending();
+        assert_eq!((newer.comparator(), newer.order()), (">", "ASC"));
+    }
+
+    #[test]
+    fn test_links_replace_cursor() {
+        let codec = CursorCodec::new(b"secret");
+        let req = TestRequest::get()
+            .uri("/user/?q=al&cursor=old")
+            .to_http_request();
+        let request = PageRequest::new(&codec, "users", 1, None).unwrap();
+        let page = request.page(&codec, vec![1, 2], key).with_links(&req);
+        let next = page.next.unwrap();
+        assert!(next.starts_with("/user/?q=al&cursor="));
+        assert!(!next.contains("old"));
+        assert!(page.prev.is_none());
+    }
+}
@@ -0,0 +1,163 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use errors::error::Error;
+use mockall::automock;
+use redis::{Client, Commands};
+use security::env::{Env, EnvConfig, EnvImpl};
+
+/// Seconds a heartbeat keeps a user present. Clients send one about every
+/// 30 seconds so a single lost heartbeat doesn't flicker them offline.
+pub const HEARTBEAT_TTL_SECONDS: u64 = 90;
+
+/// Sorted set of every user with a heartbeat, scored by when it was sent.
+const LAST_HEARTBEATS: &str = "presence:last_heartbeat";
+
+fn state_key(user_id: &str) -> String {
+    format!("presence:{}", user_id)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Online,
+    /// Connected but inactive, such as a backgrounded app
+    Idle,
+}
+
+impl State {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            State::Online => "online",
+            State::Idle => "idle",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "online" => Some(State::Online),
+            "idle" => Some(State::Idle),
+            _ => None,
+        }
+    }
+}
+
+/// Who is present right now. Users without a heartbeat within
+/// [`HEARTBEAT_TTL_SECONDS`] are offline.
+#[automock]
+#[async_trait]
+pub trait PresenceStore {
+    async fn heartbeat(&self, user_id: &str, state: State) -> Result<(), Error>;
+    /// States of the users in order, `None` for offline ones.
+    async fn states(&self, user_ids: &[String]) -> Result<Vec<Option<State>>, Error>;
+    /// Takes the user offline now.
+    async fn disconnect(&self, user_id: &str) -> Result<(), Error>;
+    /// Claims up to `limit` users whose last heartbeat ran out, with its unix
+    /// time. Each user is handed to one caller only, so several instances
+    /// can run this at once.
+    async fn claim_expired(&self, limit: usize) -> Result<Vec<(String, i64)>, Error>;
+}
+
+/// Keeps each state under its own key expiring with the heartbeat, and the
+/// heartbeat times in [`LAST_HEARTBEATS`] to notice who went offline.
+pub struct RedisPresence {
+    client: Client,
+}
+
+impl RedisPresence {
+    pub fn new(env: EnvImpl) -> Self {
+        let url = env
+            .get(&EnvConfig::RedisUrl)
+            .expect("Failed to get redis url from env");
+
+        let client = Client::open(url).expect("Failed to connect to redis");
+        Self { client }
+    }
+
+    fn connection(&self) -> Result<redis::Connection, Error> {
+        self.client
+            .get_connection()
+            .map_err(|e| Error::Internal(e.to_string()))
+    }
+}
+
+fn internal(e: redis::RedisError) -> Error {
+    Error::Internal(e.to_string())
+}
+
+#[async_trait]
+impl PresenceStore for RedisPresence {
+    async fn heartbeat(&self, user_id: &str, state: State) -> Result<(), Error> {
+        let mut connection = self.connection()?;
+        let _: () = redis::pipe()
+            .atomic()
+            .set_ex(state_key(user_id), state.as_str(), HEARTBEAT_TTL_SECONDS)
+            .ignore()
+            .zadd(LAST_HEARTBEATS, user_id, Utc::now().timestamp())
+            .ignore()
+            .query(&mut connection)
+            .map_err(internal)?;
+        Ok(())
+    }
+
+    async fn states(&self, user_ids: &[String]) -> Result<Vec<Option<State>>, Error> {
+        if user_ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let keys: Vec<String> = user_ids.iter().map(|id| state_key(id)).collect();
+        let values: Vec<Option<String>> = self.connection()?.mget(keys).map_err(internal)?;
+        Ok(values
+            .iter()
+            .map(|value| value.as_deref().and_then(State::parse))
+            .collect())
+    }
+
+    async fn disconnect(&self, user_id: &str) -> Result<(), Error> {
+        // The heartbeat time stays, so the user is claimed as expired soon
+        let _: () = self
+            .connection()?
+            .del(state_key(user_id))
+            .map_err(internal)?;
+        Ok(())
+    }
+
+    async fn claim_expired(&self, limit: usize) -> Result<Vec<(String, i64)>, Error> {
+        let mut connection = self.connection()?;
+        let deadline = Utc::now().timestamp() - HEARTBEAT_TTL_SECONDS as i64;
+        let candidates: Vec<(String, i64)> = connection
+            .zrangebyscore_limit_withscores(LAST_HEARTBEATS, "-inf", deadline, 0, limit as isize)
+            .map_err(internal)?;
+        let mut claimed = vec![];
+        for (user_id, at) in candidates {
+            // Only remove the entry if no heartbeat came in since it was read
+            let removed: i64 = redis::cmd("EVAL")
+                .arg(CLAIM_SCRIPT)
+                .arg(1)
+                .arg(LAST_HEARTBEATS)
+                .arg(&user_id)
+                .arg(at)
+                .query(&mut connection)
+                .map_err(internal)?;
+            if removed == 1 {
+                claimed.push((user_id, at));
+            }
+        }
+        Ok(claimed)
+    }
+}
+
+/// Removes member `ARGV[1]` from the sorted set `KEYS[1]` if its score is
+/// still `ARGV[2]`. Returns 1 when it did.
+const CLAIM_SCRIPT: &str = "if redis.call('ZSCORE', KEYS[1], ARGV[1]) == ARGV[2] then \
+    return redis.call('ZREM', KEYS[1], ARGV[1]) else return 0 end";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_round_trip() {
+        for state in [State::Online, State::Idle] {
+            assert_eq!(State::parse(state.as_str()), Some(state));
+        }
+        assert_eq!(State::parse("offline"), None);
+    }
+}
@@ -0,0 +1,57 @@
+use async_trait::async_trait;
+use errors::error::Error;
+use mockall::automock;
+use redis::Client;
+use security::env::{Env, EnvConfig, EnvImpl};
+
+/// Fixed window counters shared by every instance.
+#[automock]
+#[async_trait]
+pub trait RateLimiter {
+    /// Counts a hit on `key` and tells whether it is within `limit` hits per
+    /// window of `window_seconds`.
+    async fn allow(&self, key: &str, limit: u64, window_seconds: u64) -> Result<bool, Error>;
+}
+
+/// Counts hits under `ratelimit:{key}`, the first hit of a window starts its
+/// expiry.
+pub struct RedisRateLimiter {
+    client: Client,
+}
+
+impl RedisRateLimiter {
+    pub fn new(env: EnvImpl) -> Self {
+        let url = env
+            .get(&EnvConfig::RedisUrl)
+            .expect("Failed to get redis url from env");
+
+        let client = Client::open(url).expect("Failed to connect to redis");
+        Self { client }
+    }
+}
+
+fn internal(e: redis::RedisError) -> Error {
+    Error::Internal(e.to_string())
+}
+
+/// Increments `KEYS[1]` and starts its expiry of `ARGV[1]` seconds on the
+/// first hit, so later hits keep the window that already started. Runs on
+/// Redis 6, which has no `EXPIRE ... NX`.
+const HIT_SCRIPT: &str = "local hits = redis.call('INCR', KEYS[1]) \
+    if hits == 1 then redis.call('EXPIRE', KEYS[1], ARGV[1]) end \
+    return hits";
+
+#[async_trait]
+impl RateLimiter for RedisRateLimiter {
+    async fn allow(&self, key: &str, limit: u64, window_seconds: u64) -> Result<bool, Error> {
+        let mut connection = self.client.get_connection().map_err(internal)?;
+        let hits: u64 = redis::cmd("EVAL")
+            .arg(HIT_SCRIPT)
+            .arg(1)
+            .arg(format!("ratelimit:{}", key))
+            .arg(window_seconds)
+            .query(&mut connection)
+            .map_err(internal)?;
+        Ok(hits <= limit)
+    }
+}
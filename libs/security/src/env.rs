@@ -5,6 +5,14 @@ pub enum EnvConfig {
     SecretKey,
     DatabaseUrl,
     RedisUrl,
+    BlobStoreDir,
+    BlobUrlSecret,
+    S3Endpoint,
+    S3Bucket,
+    S3Region,
+    S3AccessKey,
+    S3SecretKey,
+    CursorSecret,
 }
 
 #[automock]
@@ -25,6 +33,14 @@ impl Env for EnvImpl {
             EnvConfig::SecretKey => std::env::var("JWT_SECRET").ok(),
             EnvConfig::DatabaseUrl => std::env::var("DATABASE_URL").ok(),
             EnvConfig::RedisUrl => std::env::var("REDIS_URL").ok(),
+            EnvConfig::BlobStoreDir => std::env::var("BLOB_STORE_DIR").ok(),
+            EnvConfig::BlobUrlSecret => std::env::var("BLOB_URL_SECRET").ok(),
+            EnvConfig::S3Endpoint => std::env::var("S3_ENDPOINT").ok(),
+            EnvConfig::S3Bucket => std::env::var("S3_BUCKET").ok(),
+            EnvConfig::S3Region => std::env::var("S3_REGION").ok(),
+            EnvConfig::S3AccessKey => std::env::var("S3_ACCESS_KEY").ok(),
+            EnvConfig::S3SecretKey => std::env::var("S3_SECRET_KEY").ok(),
+            EnvConfig::CursorSecret => std::env::var("CURSOR_SECRET").ok(),
         }
     }
 }
@@ -12,10 +12,10 @@ pub struct Bcrypt;
 
 impl Hasher for Bcrypt {
     fn hash(&self, password: &str) -> String {
-        return hash(password, DEFAULT_COST).unwrap();
+        hash(password, DEFAULT_COST).unwrap()
     }
 
     fn verify(&self, password: &str, hash: &str) -> bool {
-        return verify(password, hash).unwrap();
+        verify(password, hash).unwrap()
     }
 }
@@ -1,3 +1,4 @@
+use errors::error::Error;
 use jsonwebtoken::Header;
 use mockall::automock;
 use serde::{Deserialize, Serialize};
@@ -8,6 +9,10 @@ use crate::env::{Env, EnvImpl};
 pub struct AdditionalClaims {
     pub user_id: String,
     pub kind: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -22,9 +27,9 @@ pub struct Claims {
 
 #[automock]
 pub trait Jwt {
-    fn sign(&self, payload: &Claims) -> String;
+    fn sign(&self, payload: &Claims) -> Result<String, Error>;
     fn verify(&self, token: &str) -> bool;
-    fn extract(&self, token: &str) -> Option<Claims>;
+    fn extract(&self, token: &str) -> Result<Claims, Error>;
 }
 
 #[derive(Debug, Default, Clone)]
@@ -40,39 +45,46 @@ impl<T: Env> JwtImpl<T> {
 
 impl Clone for JwtImpl<EnvImpl> {
     fn clone(&self) -> Self {
-        Self {
-            env: EnvImpl::default(),
-        }
+        Self { env: EnvImpl }
+    }
+}
+
+impl<T: Env> JwtImpl<T> {
+    fn secret(&self) -> Result<String, Error> {
+        self.env
+            .get(&crate::env::EnvConfig::SecretKey)
+            .ok_or_else(|| Error::Internal("JWT_SECRET is not set".to_string()))
     }
 }
 
 impl<T: Env> Jwt for JwtImpl<T> {
-    fn sign(&self, payload: &Claims) -> String {
+    fn sign(&self, payload: &Claims) -> Result<String, Error> {
         let header = Header::new(jsonwebtoken::Algorithm::HS256);
-        let key = &self.env.get(&crate::env::EnvConfig::SecretKey).unwrap();
-        let token = jsonwebtoken::encode(
+        let key = &self.secret()?;
+        jsonwebtoken::encode(
             &header,
             &payload,
             &jsonwebtoken::EncodingKey::from_secret(key.as_bytes()),
         )
-        .unwrap();
-
-        return token.to_string();
+        .map_err(|e| Error::Internal(e.to_string()))
     }
 
     fn verify(&self, token: &str) -> bool {
-        let key = &self.env.get(&crate::env::EnvConfig::SecretKey).unwrap();
+        let key = match self.secret() {
+            Ok(key) => key,
+            Err(_) => return false,
+        };
 
         let token = jsonwebtoken::decode::<Claims>(
             token,
             &jsonwebtoken::DecodingKey::from_secret(key.as_bytes()),
             &jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256),
         );
-        return token.is_ok();
+        token.is_ok()
     }
 
-    fn extract(&self, token: &str) -> Option<Claims> {
-        let key = &self.env.get(&crate::env::EnvConfig::SecretKey).unwrap();
+    fn extract(&self, token: &str) -> Result<Claims, Error> {
+        let key = &self.secret()?;
         let token = jsonwebtoken::decode::<Claims>(
             token,
             &jsonwebtoken::DecodingKey::from_secret(key.as_bytes()),
@@ -81,11 +93,9 @@ impl<T: Env> Jwt for JwtImpl<T> {
         match token {
             Err(err) => {
                 println!("Error: {}", err);
-                return None;
-            }
-            Ok(token) => {
-                return Some(token.claims);
+                Err(Error::Unauthorized("Invalid or expired token".to_string()))
             }
+            Ok(token) => Ok(token.claims),
         }
     }
 }
@@ -0,0 +1,50 @@
+use std::io::{Cursor, Write};
+
+use errors::error::Error;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+pub const ZIP: &str = "application/zip";
+
+/// Packs `(name, bytes)` entries into a deflated ZIP archive, in order.
+pub fn zip(entries: &[(String, Vec<u8>)]) -> Result<Vec<u8>, Error> {
+    let failed =
+        |e: &dyn std::fmt::Display| Error::Internal(format!("Failed to write archive: {}", e));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, bytes) in entries {
+        writer
+            .start_file(name.as_str(), options)
+            .map_err(|e| failed(&e))?;
+        writer.write_all(bytes).map_err(|e| failed(&e))?;
+    }
+    let cursor = writer.finish().map_err(|e| failed(&e))?;
+    Ok(cursor.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use zip::ZipArchive;
+
+    use super::*;
+
+    #[test]
+    fn test_zip_round_trip() {
+        let entries = vec![
+            ("data.json".to_string(), b"{\"id\":\"1\"}".to_vec()),
+            ("avatar/256.png".to_string(), vec![0u8; 1024]),
+        ];
+        let bytes = zip(&entries).unwrap();
+
+        let mut archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(archive.len(), 2);
+        let mut data = String::new();
+        archive
+            .by_name("data.json")
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "{\"id\":\"1\"}");
+    }
+}
@@ -0,0 +1,79 @@
+use async_trait::async_trait;
+use errors::error::Error;
+use security::env::Env;
+
+use crate::{local::LocalBlobStore, s3::S3BlobStore};
+
+/// Stores opaque bytes under slash separated keys such as
+/// `avatars/<user_id>/<version>/64.png`. Services depend on the trait so the
+/// backend can be swapped without touching them.
+#[async_trait]
+pub trait BlobStore {
+    async fn put(&self, key: &str, bytes: &[u8]) -> Result<(), Error>;
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, Error>;
+
+    /// Removes every blob whose key starts with `prefix`. Missing keys are
+    /// not an error.
+    async fn delete_prefix(&self, prefix: &str) -> Result<(), Error>;
+}
+
+/// A backend picked at startup, see [`from_env`].
+pub type AnyBlobStore = Box<dyn BlobStore + Send + Sync>;
+
+/// Lets services hold a backend picked at startup.
+#[async_trait]
+impl<B: BlobStore + Send + Sync + ?Sized> BlobStore for Box<B> {
+    async fn put(&self, key: &str, bytes: &[u8]) -> Result<(), Error> {
+        (**self).put(key, bytes).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        (**self).get(key).await
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<(), Error> {
+        (**self).delete_prefix(prefix).await
+    }
+}
+
+/// An S3 bucket when `S3_BUCKET` is set, the local filesystem otherwise.
+pub fn from_env<E: Env>(env: E) -> AnyBlobStore {
+    match S3BlobStore::from_env(&env) {
+        Some(store) => Box::new(store),
+        None => Box::new(LocalBlobStore::from_env(env)),
+    }
+}
+
+/// Rejects keys that could escape the store, such as absolute paths or `..`
+/// segments.
+pub fn check_key(key: &str) -> Result<(), Error> {
+    let valid = !key.is_empty()
+        && !key.starts_with('/')
+        && key
+            .split('/')
+            .all(|segment| !segment.is_empty() && segment != "." && segment != "..")
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::BadRequest(format!("Invalid blob key: {}", key)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_key() {
+        assert!(check_key("avatars/1/abc/64.png").is_ok());
+        assert!(check_key("").is_err());
+        assert!(check_key("/etc/passwd").is_err());
+        assert!(check_key("avatars/../secret").is_err());
+        assert!(check_key("avatars//64.png").is_err());
+        assert!(check_key("avatars/a b.png").is_err());
+    }
+}
@@ -0,0 +1,7 @@
+pub mod archive;
+pub mod blob;
+pub mod local;
+pub mod mime;
+pub mod s3;
+pub mod signed;
+pub mod thumbnail;
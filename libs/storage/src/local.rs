@@ -0,0 +1,108 @@
+use std::{io::ErrorKind, path::PathBuf};
+
+use async_trait::async_trait;
+use errors::error::Error;
+use security::env::{Env, EnvConfig};
+
+use crate::blob::{check_key, BlobStore};
+
+/// [`BlobStore`] backed by a directory on the local filesystem, one file per
+/// key. Used in development and in tests.
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Reads the root directory from `BLOB_STORE_DIR`, defaulting to `./blobs`.
+    pub fn from_env(env: impl Env) -> Self {
+        let root = env
+            .get(&EnvConfig::BlobStoreDir)
+            .unwrap_or_else(|| "blobs".to_string());
+        Self::new(root)
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, Error> {
+        check_key(key)?;
+        Ok(self.root.join(key))
+    }
+}
+
+fn map_error(key: &str, e: std::io::Error) -> Error {
+    match e.kind() {
+        ErrorKind::NotFound => Error::NotFound(format!("Blob {} not found", key)),
+        _ => Error::Internal(format!("Blob {}: {}", key, e)),
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put(&self, key: &str, bytes: &[u8]) -> Result<(), Error> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| map_error(key, e))?;
+        }
+        tokio::fs::write(&path, bytes)
+            .await
+            .map_err(|e| map_error(key, e))
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let path = self.path(key)?;
+        tokio::fs::read(&path).await.map_err(|e| map_error(key, e))
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<(), Error> {
+        let path = self.path(prefix.trim_end_matches('/'))?;
+        let result = match tokio::fs::metadata(&path).await {
+            Ok(metadata) if metadata.is_dir() => tokio::fs::remove_dir_all(&path).await,
+            Ok(_) => tokio::fs::remove_file(&path).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(map_error(prefix, e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> LocalBlobStore {
+        let root = std::env::temp_dir().join(format!("blobs-{}", security::uuid::uuid_v4()));
+        LocalBlobStore::new(root)
+    }
+
+    #[tokio::test]
+    async fn test_put_get_delete() {
+        let store = store();
+        store.put("avatars/1/v1/64.png", b"small").await.unwrap();
+        store.put("avatars/1/v1/128.png", b"large").await.unwrap();
+        assert_eq!(store.get("avatars/1/v1/64.png").await.unwrap(), b"small");
+
+        store.delete_prefix("avatars/1/v1/").await.unwrap();
+        assert!(matches!(
+            store.get("avatars/1/v1/128.png").await,
+            Err(Error::NotFound(_))
+        ));
+        // Deleting again is a no-op
+        store.delete_prefix("avatars/1/v1").await.unwrap();
+        std::fs::remove_dir_all(&store.root).ok();
+    }
+
+    #[tokio::test]
+    async fn test_rejects_escaping_keys() {
+        let store = store();
+        assert!(matches!(
+            store.put("../outside", b"x").await,
+            Err(Error::BadRequest(_))
+        ));
+    }
+}
@@ -0,0 +1,51 @@
+use crate::archive::ZIP;
+
+pub const PNG: &str = "image/png";
+pub const JPEG: &str = "image/jpeg";
+pub const GIF: &str = "image/gif";
+pub const WEBP: &str = "image/webp";
+pub const PDF: &str = "application/pdf";
+pub const MP4: &str = "video/mp4";
+
+/// Image types that can be decoded into thumbnails.
+pub const IMAGE_TYPES: [&str; 4] = [PNG, JPEG, GIF, WEBP];
+
+/// Detects the MIME type from the leading magic bytes. Clients can claim any
+/// `Content-Type`, so uploads are checked against this instead.
+pub fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(PNG)
+    } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some(JPEG)
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some(GIF)
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some(WEBP)
+    } else if bytes.starts_with(b"%PDF-") {
+        Some(PDF)
+    } else if bytes.starts_with(b"PK\x03\x04") {
+        Some(ZIP)
+    } else if bytes.len() >= 12 && &bytes[4..8] == b"ftyp" && !bytes[8..12].starts_with(b"qt") {
+        Some(MP4)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n...."), Some(PNG));
+        assert_eq!(sniff(&[0xff, 0xd8, 0xff, 0xe0]), Some(JPEG));
+        assert_eq!(sniff(b"GIF89a...."), Some(GIF));
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some(WEBP));
+        assert_eq!(sniff(b"%PDF-1.7\n"), Some(PDF));
+        assert_eq!(sniff(b"PK\x03\x04\x14\0"), Some(ZIP));
+        assert_eq!(sniff(b"\0\0\0\x18ftypmp42"), Some(MP4));
+        assert_eq!(sniff(b"<svg xmlns="), None);
+        assert_eq!(sniff(b""), None);
+    }
+}
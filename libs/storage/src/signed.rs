@@ -0,0 +1,105 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use errors::error::Error;
+use hmac::{Hmac, Mac};
+use security::env::{Env, EnvConfig};
+use sha2::Sha256;
+
+/// Seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Signs download URLs so they work without an auth token, such as in an
+/// `<img src>`, but only for the path they were issued for and only until
+/// they expire.
+#[derive(Clone)]
+pub struct UrlSigner {
+    secret: Vec<u8>,
+}
+
+impl UrlSigner {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.to_vec(),
+        }
+    }
+
+    /// Signs with `BLOB_URL_SECRET`, falling back to `JWT_SECRET`.
+    pub fn from_env(env: impl Env) -> Self {
+        let secret = env
+            .get(&EnvConfig::BlobUrlSecret)
+            .or_else(|| env.get(&EnvConfig::SecretKey))
+            .expect("BLOB_URL_SECRET or JWT_SECRET must be set");
+        Self::new(secret.as_bytes())
+    }
+
+    fn signature(&self, path: &str, expires: u64) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        mac.update(format!("{}\n{}", path, expires).as_bytes());
+        mac
+    }
+
+    /// Returns `path` with `expires` and `signature` query parameters, valid
+    /// until `expires` in seconds since the Unix epoch.
+    pub fn sign(&self, path: &str, expires: u64) -> String {
+        let signature = self.signature(path, expires).finalize().into_bytes();
+        format!(
+            "{}?expires={}&signature={}",
+            path,
+            expires,
+            URL_SAFE_NO_PAD.encode(signature)
+        )
+    }
+
+    /// Fails with `403` if the signature doesn't match the path or has
+    /// expired.
+    pub fn verify(&self, path: &str, expires: u64, signature: &str) -> Result<(), Error> {
+        let invalid = || Error::Forbidden("Link is invalid or has expired".to_string());
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+        self.signature(path, expires)
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+        if expires < now() {
+            return Err(invalid());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signature(url: &str) -> &str {
+        url.split("signature=").nth(1).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let signer = UrlSigner::new(b"secret");
+        let expires = now() + 60;
+        let url = signer.sign("/files/1", expires);
+        assert!(signer.verify("/files/1", expires, signature(&url)).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_other_paths_and_expired_links() {
+        let signer = UrlSigner::new(b"secret");
+        let expires = now() + 60;
+        let url = signer.sign("/files/1", expires);
+        assert!(signer.verify("/files/2", expires, signature(&url)).is_err());
+        assert!(signer
+            .verify("/files/1", expires + 1, signature(&url))
+            .is_err());
+
+        let expired = now() - 1;
+        let url = signer.sign("/files/1", expired);
+        assert!(signer.verify("/files/1", expired, signature(&url)).is_err());
+    }
+}
@@ -0,0 +1,102 @@
+use std::io::Cursor;
+
+use errors::error::Error;
+use image::{
+    imageops::FilterType, DynamicImage, GenericImageView, ImageFormat, ImageReader, Limits,
+};
+
+/// Largest width or height accepted before decoding, guards against images
+/// that are small on the wire but huge once decoded.
+pub const MAX_DIMENSION: u32 = 8192;
+
+/// A thumbnail scaled to fit a box, with the size of the image it was made
+/// from.
+pub struct Preview {
+    pub png: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+fn decode(bytes: &[u8]) -> Result<DynamicImage, Error> {
+    let invalid = |e: image::ImageError| Error::BadRequest(format!("Invalid image: {}", e));
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    let mut reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| Error::BadRequest(format!("Invalid image: {}", e)))?;
+    reader.limits(limits);
+    reader.decode().map_err(invalid)
+}
+
+fn encode(image: &DynamicImage) -> Result<Vec<u8>, Error> {
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| Error::Internal(format!("Failed to encode thumbnail: {}", e)))?;
+    Ok(png)
+}
+
+/// Decodes an image and returns square PNG thumbnails, one per requested
+/// size, cropped to the center.
+pub fn thumbnails(bytes: &[u8], sizes: &[u32]) -> Result<Vec<Vec<u8>>, Error> {
+    let image = decode(bytes)?;
+    sizes
+        .iter()
+        .map(|size| encode(&image.resize_to_fill(*size, *size, FilterType::Lanczos3)))
+        .collect()
+}
+
+/// Decodes an image and returns a PNG thumbnail that fits in a `size` square,
+/// keeping the aspect ratio. Images that already fit aren't scaled up.
+pub fn preview(bytes: &[u8], size: u32) -> Result<Preview, Error> {
+    let image = decode(bytes)?;
+    let (width, height) = image.dimensions();
+    let png = if width <= size && height <= size {
+        encode(&image)?
+    } else {
+        encode(&image.thumbnail(size, size))?
+    };
+    Ok(Preview { png, width, height })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thumbnails() {
+        let mut source = Vec::new();
+        DynamicImage::new_rgb8(300, 200)
+            .write_to(&mut Cursor::new(&mut source), ImageFormat::Jpeg)
+            .unwrap();
+
+        let thumbnails = thumbnails(&source, &[64, 128]).unwrap();
+        assert_eq!(thumbnails.len(), 2);
+        let small = image::load_from_memory(&thumbnails[0]).unwrap();
+        assert_eq!(small.dimensions(), (64, 64));
+        let large = image::load_from_memory(&thumbnails[1]).unwrap();
+        assert_eq!(large.dimensions(), (128, 128));
+    }
+
+    #[test]
+    fn test_preview_keeps_aspect_ratio() {
+        let mut source = Vec::new();
+        DynamicImage::new_rgb8(1000, 500)
+            .write_to(&mut Cursor::new(&mut source), ImageFormat::Png)
+            .unwrap();
+
+        let preview = preview(&source, 320).unwrap();
+        assert_eq!((preview.width, preview.height), (1000, 500));
+        let thumbnail = image::load_from_memory(&preview.png).unwrap();
+        assert_eq!(thumbnail.dimensions(), (320, 160));
+    }
+
+    #[test]
+    fn test_rejects_garbage() {
+        assert!(matches!(
+            thumbnails(b"\x89PNG\r\n\x1a\nnot really", &[64]),
+            Err(Error::BadRequest(_))
+        ));
+    }
+}
@@ -0,0 +1,177 @@
+use async_trait::async_trait;
+use errors::error::Error;
+use mockall::automock;
+use redis::Client;
+use security::env::{Env, EnvConfig, EnvImpl};
+
+/// Seconds a count is kept after it was last computed. Postgres holds the
+/// truth, so a count that drifted heals once it expires.
+pub const COUNTER_TTL_SECONDS: i64 = 24 * 60 * 60;
+
+fn unread_key(user_id: &str, conversation_id: &str) -> String {
+    format!("unread:{}:{}", user_id, conversation_id)
+}
+
+/// A cached count with the version it was read at. Every message counted or
+/// forgotten moves the version on, whether the count is known or not.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Count {
+    /// `None` when unknown
+    pub value: Option<i64>,
+    pub version: i64,
+}
+
+/// Cached unread counts of each user by conversation. A missing count is
+/// unknown rather than zero and has to be computed.
+#[automock]
+#[async_trait]
+pub trait UnreadCounter {
+    /// Counts of the user's conversations in order.
+    async fn counts(&self, user_id: &str, conversation_ids: &[String])
+        -> Result<Vec<Count>, Error>;
+    /// Stores a count computed after `version` was read. If a message was
+    /// counted or forgotten since, the count may miss it and is dropped
+    /// instead, to be computed again.
+    async fn set(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        count: i64,
+        version: i64,
+    ) -> Result<(), Error>;
+    /// Counts a new message for each of the users whose count is known.
+    async fn increment(&self, conversation_id: &str, user_ids: &[String]) -> Result<(), Error>;
+    /// Drops the counts of the users so they are computed again.
+    async fn forget(&self, conversation_id: &str, user_ids: &[String]) -> Result<(), Error>;
+}
+
+/// Keeps each count in the hash `unread:{user_id}:{conversation_id}`, with
+/// fields `count` and `version` and its own expiry.
+pub struct RedisUnreadCounter {
+    client: Client,
+}
+
+impl RedisUnreadCounter {
+    pub fn new(env: EnvImpl) -> Self {
+        let url = env
+            .get(&EnvConfig::RedisUrl)
+            .expect("Failed to get redis url from env");
+
+        let client = Client::open(url).expect("Failed to connect to redis");
+        Self { client }
+    }
+
+    fn connection(&self) -> Result<redis::Connection, Error> {
+        self.client
+            .get_connection()
+            .map_err(|e| Error::Internal(e.to_string()))
+    }
+
+    /// Runs `script` on the count of each user in the conversation.
+    fn run_for_each(
+        &self,
+        script: &str,
+        conversation_id: &str,
+        user_ids: &[String],
+    ) -> Result<(), Error> {
+        if user_ids.is_empty() {
+            return Ok(());
+        }
+        let mut pipe = redis::pipe();
+        for user_id in user_ids {
+            pipe.cmd("EVAL")
+                .arg(script)
+                .arg(1)
+                .arg(unread_key(user_id, conversation_id))
+                .arg(COUNTER_TTL_SECONDS)
+                .ignore();
+        }
+        let _: () = pipe.query(&mut self.connection()?).map_err(internal)?;
+        Ok(())
+    }
+}
+
+fn internal(e: redis::RedisError) -> Error {
+    Error::Internal(e.to_string())
+}
+
+/// Moves the version of the hash `KEYS[1]` on and increments its count only
+/// if known, so an unknown count doesn't start from zero. A new hash expires
+/// after `ARGV[1]` seconds, existing ones keep the expiry of their count.
+const INCREMENT_SCRIPT: &str = "redis.call('HINCRBY', KEYS[1], 'version', 1) \
+    if redis.call('HEXISTS', KEYS[1], 'count') == 1 then \
+    redis.call('HINCRBY', KEYS[1], 'count', 1) end \
+    if redis.call('TTL', KEYS[1]) < 0 then redis.call('EXPIRE', KEYS[1], ARGV[1]) end \
+    return 1";
+
+/// Moves the version of the hash `KEYS[1]` on and drops its count, expiring
+/// like [`INCREMENT_SCRIPT`].
+const FORGET_SCRIPT: &str = "redis.call('HINCRBY', KEYS[1], 'version', 1) \
+    redis.call('HDEL', KEYS[1], 'count') \
+    if redis.call('TTL', KEYS[1]) < 0 then redis.call('EXPIRE', KEYS[1], ARGV[1]) end \
+    return 1";
+
+/// Stores count `ARGV[1]` in the hash `KEYS[1]` for `ARGV[3]` seconds if its
+/// version is still `ARGV[2]`, drops the count otherwise. Returns 1 when it
+/// stored it.
+const SET_SCRIPT: &str = "if tonumber(redis.call('HGET', KEYS[1], 'version') or '0') \
+    ~= tonumber(ARGV[2]) then redis.call('HDEL', KEYS[1], 'count') return 0 end \
+    redis.call('HSET', KEYS[1], 'count', ARGV[1]) \
+    redis.call('EXPIRE', KEYS[1], ARGV[3]) \
+    return 1";
+
+#[async_trait]
+impl UnreadCounter for RedisUnreadCounter {
+    async fn counts(
+        &self,
+        user_id: &str,
+        conversation_ids: &[String],
+    ) -> Result<Vec<Count>, Error> {
+        if conversation_ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut pipe = redis::pipe();
+        for conversation_id in conversation_ids {
+            pipe.cmd("HMGET")
+                .arg(unread_key(user_id, conversation_id))
+                .arg("count")
+                .arg("version");
+        }
+        let values: Vec<(Option<i64>, Option<i64>)> =
+            pipe.query(&mut self.connection()?).map_err(internal)?;
+        Ok(values
+            .into_iter()
+            .map(|(value, version)| Count {
+                value,
+                version: version.unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    async fn set(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+        count: i64,
+        version: i64,
+    ) -> Result<(), Error> {
+        let _: i64 = redis::cmd("EVAL")
+            .arg(SET_SCRIPT)
+            .arg(1)
+            .arg(unread_key(user_id, conversation_id))
+            .arg(count)
+            .arg(version)
+            .arg(COUNTER_TTL_SECONDS)
+            .query(&mut self.connection()?)
+            .map_err(internal)?;
+        Ok(())
+    }
+
+    async fn increment(&self, conversation_id: &str, user_ids: &[String]) -> Result<(), Error> {
+        self.run_for_each(INCREMENT_SCRIPT, conversation_id, user_ids)
+    }
+
+    async fn forget(&self, conversation_id: &str, user_ids: &[String]) -> Result<(), Error> {
+        self.run_for_each(FORGET_SCRIPT, conversation_id, user_ids)
+    }
+}
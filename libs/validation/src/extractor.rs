@@ -0,0 +1,190 @@
+use std::{
+    future::{ready, Ready},
+    ops::Deref,
+};
+
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use errors::error::{Error, FieldError};
+use futures::future::LocalBoxFuture;
+use serde::de::DeserializeOwned;
+use validator::{Validate, ValidationErrors, ValidationErrorsKind};
+
+/// Flattens validator errors into one entry per failed rule. Nested structs
+/// and lists are reported with dotted and indexed field paths.
+pub fn field_errors(errors: &ValidationErrors) -> Vec<FieldError> {
+    let mut fields = Vec::new();
+    collect(errors, "", &mut fields);
+    fields.sort_by(|a, b| a.field.cmp(&b.field));
+    fields
+}
+
+fn collect(errors: &ValidationErrors, prefix: &str, fields: &mut Vec<FieldError>) {
+    for (field, kind) in errors.errors() {
+        let path = if prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", prefix, field)
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                for error in errors {
+                    let message = error
+                        .message
+                        .as_ref()
+                        .map(|message| message.to_string())
+                        .unwrap_or_else(|| describe(error));
+                    fields.push(FieldError::new(&path, &error.code, &message));
+                }
+            }
+            ValidationErrorsKind::Struct(errors) => collect(errors, &path, fields),
+            ValidationErrorsKind::List(errors) => {
+                for (index, errors) in errors {
+                    collect(errors, &format!("{}[{}]", path, index), fields);
+                }
+            }
+        }
+    }
+}
+
+fn describe(error: &validator::ValidationError) -> String {
+    let param = |name: &str| error.params.get(name).map(|value| value.to_string());
+    match (error.code.as_ref(), param("min"), param("max")) {
+        ("length", Some(min), Some(max)) => {
+            format!("must be between {} and {} characters", min, max)
+        }
+        ("length", Some(min), None) => format!("must be at least {} characters", min),
+        ("length", None, Some(max)) => format!("must be at most {} characters", max),
+        ("range", Some(min), Some(max)) => format!("must be between {} and {}", min, max),
+        ("range", Some(min), None) => format!("must be at least {}", min),
+        ("range", None, Some(max)) => format!("must be at most {}", max),
+        _ => "is invalid".to_string(),
+    }
+}
+
+fn validate<T: Validate>(value: &T) -> Result<(), Error> {
+    value
+        .validate()
+        .map_err(|errors| Error::Validation(field_errors(&errors)))
+}
+
+/// JSON body extractor that runs [`Validate`] before the handler is called.
+/// Malformed JSON is a `400`, a body that fails validation a `422`.
+#[derive(Debug)]
+pub struct ValidJson<T>(pub T);
+
+impl<T> ValidJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for ValidJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate + 'static> FromRequest for ValidJson<T> {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = web::Json::<T>::from_request(req, payload);
+        Box::pin(async move {
+            let value = json
+                .await
+                .map_err(|e| Error::BadRequest(e.to_string()))?
+                .into_inner();
+            validate(&value)?;
+            Ok(ValidJson(value))
+        })
+    }
+}
+
+/// Query string counterpart of [`ValidJson`].
+#[derive(Debug)]
+pub struct ValidQuery<T>(pub T);
+
+impl<T> ValidQuery<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for ValidQuery<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate> FromRequest for ValidQuery<T> {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let result = web::Query::<T>::from_query(req.query_string())
+            .map_err(|e| Error::BadRequest(e.to_string()))
+            .and_then(|query| {
+                let value = query.into_inner();
+                validate(&value)?;
+                Ok(ValidQuery(value))
+            });
+        ready(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, init_service, read_body_json, TestRequest},
+        web, App, HttpResponse,
+    };
+    use serde::Deserialize;
+    use validator::Validate;
+
+    use super::*;
+
+    #[derive(Deserialize, Validate)]
+    struct SignUp {
+        #[validate(custom(function = "crate::rules::username"))]
+        username: String,
+        #[validate(range(min = 1, max = 100))]
+        limit: u32,
+    }
+
+    async fn handler(body: ValidJson<SignUp>) -> HttpResponse {
+        HttpResponse::Ok().body(format!("{}:{}", body.username, body.limit))
+    }
+
+    #[actix_web::test]
+    async fn test_invalid_body_is_unprocessable() {
+        let app = init_service(App::new().route("/", web::post().to(handler))).await;
+        let req = TestRequest::post()
+            .uri("/")
+            .set_json(serde_json::json!({ "username": "A", "limit": 1000 }))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: serde_json::Value = read_body_json(res).await;
+        assert_eq!(body["fields"][0]["field"], "limit");
+        assert_eq!(body["fields"][0]["message"], "must be between 1 and 100");
+        assert_eq!(body["fields"][1]["field"], "username");
+    }
+
+    #[actix_web::test]
+    async fn test_malformed_body_is_bad_request() {
+        let app = init_service(App::new().route("/", web::post().to(handler))).await;
+        let req = TestRequest::post()
+            .uri("/")
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload("{")
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,283 @@
+use std::borrow::Cow;
+
+use validator::ValidationError;
+
+pub const USERNAME_MIN_LENGTH: usize = 3;
+pub const USERNAME_MAX_LENGTH: usize = 32;
+/// Taken by the `@room` and `@here` mentions.
+pub const RESERVED_USERNAMES: [&str; 2] = ["room", "here"];
+pub const PASSWORD_MIN_LENGTH: usize = 8;
+pub const PASSWORD_MAX_LENGTH: usize = 128;
+/// Code points of the longest emoji accepted, enough for ZWJ sequences
+/// with skin tones.
+pub const EMOJI_MAX_LENGTH: usize = 16;
+
+fn error(code: &'static str, message: String) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    error.message = Some(Cow::Owned(message));
+    error
+}
+
+/// Usernames are 3 to 32 characters of lowercase ASCII letters, digits, `_`
+/// and `.`, starting with a letter or a digit, and none of
+/// [`RESERVED_USERNAMES`].
+pub fn username(value: &str) -> Result<(), ValidationError> {
+    let length = value.chars().count();
+    if !(USERNAME_MIN_LENGTH..=USERNAME_MAX_LENGTH).contains(&length) {
+        return Err(error(
+            "length",
+            format!(
+                "must be between {} and {} characters",
+                USERNAME_MIN_LENGTH, USERNAME_MAX_LENGTH
+            ),
+        ));
+    }
+    if !value
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '.')
+    {
+        return Err(error(
+            "charset",
+            "may only contain lowercase letters, digits, '_' and '.'".to_string(),
+        ));
+    }
+    if !value
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphanumeric())
+    {
+        return Err(error(
+            "charset",
+            "must start with a letter or a digit".to_string(),
+        ));
+    }
+    if RESERVED_USERNAMES.contains(&value) {
+        return Err(error("reserved", "is reserved".to_string()));
+    }
+    Ok(())
+}
+
+/// Passwords are 8 to 128 characters with at least one lowercase letter, one
+/// uppercase letter and one digit.
+pub fn password(value: &str) -> Result<(), ValidationError> {
+    let length = value.chars().count();
+    if !(PASSWORD_MIN_LENGTH..=PASSWORD_MAX_LENGTH).contains(&length) {
+        return Err(error(
+            "length",
+            format!(
+                "must be between {} and {} characters",
+                PASSWORD_MIN_LENGTH, PASSWORD_MAX_LENGTH
+            ),
+        ));
+    }
+    let lower = value.chars().any(|c| c.is_lowercase());
+    let upper = value.chars().any(|c| c.is_uppercase());
+    let digit = value.chars().any(|c| c.is_ascii_digit());
+    if !(lower && upper && digit) {
+        return Err(error(
+            "strength",
+            "must contain a lowercase letter, an uppercase letter and a digit".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Rejects values that are empty once surrounding whitespace is removed.
+pub fn not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        return Err(error("blank", "must not be blank".to_string()));
+    }
+    Ok(())
+}
+
+/// IANA time zone names such as `UTC` or `America/Argentina/Buenos_Aires`.
+/// Only the shape is checked, the zone database is not consulted.
+pub fn timezone(value: &str) -> Result<(), ValidationError> {
+    let valid = !value.is_empty()
+        && value.len() <= 64
+        && value.split('/').all(|segment| {
+            segment.starts_with(|c: char| c.is_ascii_alphabetic())
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+'))
+        });
+    if !valid {
+        return Err(error(
+            "timezone",
+            "must be an IANA time zone such as Europe/Berlin".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// BCP 47 language tags made of a language and an optional region, such as
+/// `en`, `id` or `pt-BR`.
+pub fn language(value: &str) -> Result<(), ValidationError> {
+    let mut parts = value.split('-');
+    let language = parts.next().unwrap_or_default();
+    let region = parts.next();
+    let valid = (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_lowercase())
+        && region.is_none_or(|region| {
+            (region.len() == 2 && region.chars().all(|c| c.is_ascii_uppercase()))
+                || (region.len() == 3 && region.chars().all(|c| c.is_ascii_digit()))
+        })
+        && parts.next().is_none();
+    if !valid {
+        return Err(error(
+            "language",
+            "must be a language tag such as en or pt-BR".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// A Unicode emoji, possibly a sequence joined with ZWJ or carrying
+/// modifiers, or `custom:{id}` naming a custom emoji by an id of ASCII
+/// letters, digits, `_` and `-`. Only the shape of Unicode emoji is checked,
+/// not that they are assigned.
+pub fn emoji(value: &str) -> Result<(), ValidationError> {
+    if let Some(id) = value.strip_prefix("custom:") {
+        let valid = (1..=64).contains(&id.len())
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid {
+            return Err(error(
+                "emoji",
+                "custom emoji ids are 1 to 64 letters, digits, '_' and '-'".to_string(),
+            ));
+        }
+        return Ok(());
+    }
+    let length = value.chars().count();
+    // Keycaps such as 1️⃣ start with an ASCII digit, `#` or `*`
+    let valid = (1..=EMOJI_MAX_LENGTH).contains(&length)
+        && !value.is_ascii()
+        && value.chars().all(|c| {
+            if c.is_ascii() {
+                c.is_ascii_digit() || c == '#' || c == '*'
+            } else {
+                !c.is_whitespace() && !c.is_control() && !c.is_alphanumeric()
+            }
+        });
+    if !valid {
+        return Err(error(
+            "emoji",
+            "must be an emoji or custom:{id}".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn parse_timestamp(value: &str) -> Result<chrono::DateTime<chrono::FixedOffset>, ValidationError> {
+    chrono::DateTime::parse_from_rfc3339(value).map_err(|_| {
+        error(
+            "timestamp",
+            "must be an RFC 3339 timestamp such as 2024-05-01T12:00:00Z".to_string(),
+        )
+    })
+}
+
+/// RFC 3339 timestamps such as `2024-05-01T12:00:00Z`.
+pub fn timestamp(value: &str) -> Result<(), ValidationError> {
+    parse_timestamp(value).map(|_| ())
+}
+
+/// RFC 3339 timestamps such as `2024-05-01T12:00:00Z` that lie in the
+/// future.
+pub fn future_timestamp(value: &str) -> Result<(), ValidationError> {
+    if parse_timestamp(value)? <= chrono::Utc::now() {
+        return Err(error("future", "must be in the future".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_username() {
+        assert!(username("alice_01").is_ok());
+        assert!(username("a.b").is_ok());
+        assert_eq!(username("ab").unwrap_err().code, "length");
+        assert_eq!(username(&"a".repeat(33)).unwrap_err().code, "length");
+        assert_eq!(username("Alice").unwrap_err().code, "charset");
+        assert_eq!(username("al ice").unwrap_err().code, "charset");
+        assert_eq!(username("_alice").unwrap_err().code, "charset");
+        assert_eq!(username("here").unwrap_err().code, "reserved");
+    }
+
+    #[test]
+    fn test_password() {
+        assert!(password("Secret123").is_ok());
+        assert_eq!(password("S3cret").unwrap_err().code, "length");
+        assert_eq!(password("secret123").unwrap_err().code, "strength");
+        assert_eq!(password("SECRET123").unwrap_err().code, "strength");
+        assert_eq!(password("SecretPassword").unwrap_err().code, "strength");
+    }
+
+    #[test]
+    fn test_not_blank() {
+        assert!(not_blank("Alice").is_ok());
+        assert_eq!(not_blank("   ").unwrap_err().code, "blank");
+    }
+
+    #[test]
+    fn test_timezone() {
+        assert!(timezone("UTC").is_ok());
+        assert!(timezone("Asia/Jakarta").is_ok());
+        assert!(timezone("America/Argentina/Buenos_Aires").is_ok());
+        assert!(timezone("Etc/GMT+7").is_ok());
+        assert_eq!(timezone("").unwrap_err().code, "timezone");
+        assert_eq!(timezone("Europe/").unwrap_err().code, "timezone");
+        assert_eq!(timezone("../etc").unwrap_err().code, "timezone");
+    }
+
+    #[test]
+    fn test_language() {
+        assert!(language("en").is_ok());
+        assert!(language("pt-BR").is_ok());
+        assert!(language("es-419").is_ok());
+        assert_eq!(language("EN").unwrap_err().code, "language");
+        assert_eq!(language("en-").unwrap_err().code, "language");
+        assert_eq!(language("en-us").unwrap_err().code, "language");
+        assert_eq!(language("zh-Hant-TW").unwrap_err().code, "language");
+    }
+
+    #[test]
+    fn test_emoji() {
+        assert!(emoji("👍").is_ok());
+        assert!(emoji("👍🏽").is_ok());
+        assert!(emoji("👨‍👩‍👧‍👦").is_ok());
+        assert!(emoji("🇮🇩").is_ok());
+        assert!(emoji("1️⃣").is_ok());
+        assert!(emoji("custom:party_parrot").is_ok());
+        assert_eq!(emoji("").unwrap_err().code, "emoji");
+        assert_eq!(emoji("ok").unwrap_err().code, "emoji");
+        assert_eq!(emoji("é").unwrap_err().code, "emoji");
+        assert_eq!(emoji("👍 👍").unwrap_err().code, "emoji");
+        assert_eq!(emoji(&"👍".repeat(17)).unwrap_err().code, "emoji");
+        assert_eq!(emoji("custom:").unwrap_err().code, "emoji");
+        assert_eq!(emoji("custom:a/b").unwrap_err().code, "emoji");
+    }
+
+    #[test]
+    fn test_timestamp() {
+        assert!(timestamp("2000-01-01T00:00:00Z").is_ok());
+        assert!(timestamp("2000-01-01T07:00:00.5+07:00").is_ok());
+        assert_eq!(timestamp("2000-01-01").unwrap_err().code, "timestamp");
+    }
+
+    #[test]
+    fn test_future_timestamp() {
+        assert!(future_timestamp("2999-01-01T00:00:00Z").is_ok());
+        assert!(future_timestamp("2999-01-01T07:00:00+07:00").is_ok());
+        assert_eq!(
+            future_timestamp("2000-01-01T00:00:00Z").unwrap_err().code,
+            "future"
+        );
+        assert_eq!(future_timestamp("tomorrow").unwrap_err().code, "timestamp");
+    }
+}
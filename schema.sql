@@ -1,8 +1,396 @@
 -- Active: 1731069975349@@127.0.0.1@5432@postgres
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
 CREATE TABLE "users" (
     "id" TEXT DEFAULT gen_random_uuid (),
     "name" VARCHAR(255) NOT NULL,
     "username" VARCHAR(255) NOT NULL UNIQUE,
     "password" VARCHAR(255) NOT NULL,
+    "display_name" VARCHAR(64) NOT NULL DEFAULT '',
+    "bio" VARCHAR(500) NOT NULL DEFAULT '',
+    "status_text" VARCHAR(140) NOT NULL DEFAULT '',
+    -- Version of the current avatar thumbnails in the blob store, empty if none
+    "avatar" TEXT NOT NULL DEFAULT '',
+    "timezone" VARCHAR(64) NOT NULL DEFAULT 'UTC',
+    -- Privacy settings, each one of 'everyone', 'friends' or 'nobody'
+    "dm_policy" VARCHAR(16) NOT NULL DEFAULT 'everyone' CHECK ("dm_policy" IN ('everyone', 'friends', 'nobody')),
+    "search_visibility" VARCHAR(16) NOT NULL DEFAULT 'everyone' CHECK ("search_visibility" IN ('everyone', 'friends', 'nobody')),
+    "last_seen_visibility" VARCHAR(16) NOT NULL DEFAULT 'everyone' CHECK ("last_seen_visibility" IN ('everyone', 'friends', 'nobody')),
+    -- Set when the user asked to delete the account, it is hard deleted once
+    -- this passes. Signing in before then cancels the deletion.
+    "deletion_scheduled_at" TIMESTAMPTZ,
+    -- Set by an admin, the user has to choose a new password to sign in
+    "password_reset_required" BOOLEAN NOT NULL DEFAULT FALSE,
+    -- When the user was last present, written once their heartbeats stop
+    "last_seen_at" TIMESTAMPTZ,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    "updated_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    "search" TSVECTOR GENERATED ALWAYS AS (
+        to_tsvector('simple', "name" || ' ' || "display_name")
+    ) STORED,
     PRIMARY KEY ("id")
-);
\ No newline at end of file
+);
+
+-- Username prefix matches
+CREATE INDEX "users_username_prefix_idx" ON "users" ("username" text_pattern_ops);
+-- Whole word matches on name and display name
+CREATE INDEX "users_search_idx" ON "users" USING GIN ("search");
+-- Fuzzy matches on name and display name
+CREATE INDEX "users_name_trgm_idx" ON "users" USING GIN ("name" gin_trgm_ops);
+CREATE INDEX "users_display_name_trgm_idx" ON "users" USING GIN ("display_name" gin_trgm_ops);
+-- Accounts waiting for the hard delete
+CREATE INDEX "users_deletion_idx" ON "users" ("deletion_scheduled_at") WHERE "deletion_scheduled_at" IS NOT NULL;
+
+-- One row per signed in device, keyed by the jti of its refresh token.
+-- Refreshing requires the row, so deleting it revokes the session.
+CREATE TABLE "sessions" (
+    "id" TEXT NOT NULL,
+    "user_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    "expires_at" TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY ("id")
+);
+
+CREATE INDEX "sessions_user_idx" ON "sessions" ("user_id");
+
+-- Every username a user gave up, newest last. Also paces username changes.
+CREATE TABLE "username_history" (
+    "id" BIGSERIAL,
+    "user_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "old_username" VARCHAR(255) NOT NULL,
+    "new_username" VARCHAR(255) NOT NULL,
+    "changed_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY ("id")
+);
+
+CREATE INDEX "username_history_user_idx" ON "username_history" ("user_id", "changed_at");
+
+-- Client settings, only the keys the user changed. Every write bumps the
+-- version, which clients send back in If-Match.
+CREATE TABLE "user_settings" (
+    "user_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "version" INT NOT NULL DEFAULT 1,
+    "data" JSONB NOT NULL DEFAULT '{}',
+    "updated_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY ("user_id")
+);
+
+-- A suspension is active until it is lifted or expires
+CREATE TABLE "user_suspensions" (
+    "id" TEXT DEFAULT gen_random_uuid (),
+    "user_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "reason" VARCHAR(500) NOT NULL,
+    "suspended_by" TEXT NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- NULL suspends until lifted
+    "expires_at" TIMESTAMPTZ,
+    "lifted_at" TIMESTAMPTZ,
+    "lifted_by" TEXT,
+    PRIMARY KEY ("id")
+);
+
+CREATE INDEX "user_suspensions_user_idx" ON "user_suspensions" ("user_id", "created_at") WHERE "lifted_at" IS NULL;
+
+-- What admins did to which user. Actors are kept as plain ids so the
+-- history outlives them.
+CREATE TABLE "admin_audit_log" (
+    "id" BIGSERIAL,
+    "actor_id" TEXT NOT NULL,
+    "target_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "action" VARCHAR(64) NOT NULL,
+    "details" JSONB NOT NULL DEFAULT '{}',
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY ("id")
+);
+
+CREATE INDEX "admin_audit_log_target_idx" ON "admin_audit_log" ("target_id", "id");
+
+-- Archives of everything held about a user, built in the background
+CREATE TABLE "data_exports" (
+    "id" TEXT DEFAULT gen_random_uuid (),
+    "user_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "status" VARCHAR(16) NOT NULL DEFAULT 'pending' CHECK ("status" IN ('pending', 'running', 'ready', 'failed')),
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    "completed_at" TIMESTAMPTZ,
+    "expires_at" TIMESTAMPTZ,
+    PRIMARY KEY ("id")
+);
+
+CREATE INDEX "data_exports_user_idx" ON "data_exports" ("user_id", "created_at");
+-- One export in progress per user
+CREATE UNIQUE INDEX "data_exports_active_idx" ON "data_exports" ("user_id") WHERE "status" IN ('pending', 'running');
+
+CREATE TABLE "user_blocks" (
+    "blocker_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "blocked_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY ("blocker_id", "blocked_id")
+);
+
+CREATE INDEX "user_blocks_blocked_idx" ON "user_blocks" ("blocked_id");
+
+-- A pending request from requester to addressee, or a friendship once accepted
+CREATE TABLE "user_relationships" (
+    "requester_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "addressee_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "status" VARCHAR(16) NOT NULL CHECK ("status" IN ('pending', 'accepted')),
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    "updated_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY ("requester_id", "addressee_id"),
+    CHECK ("requester_id" <> "addressee_id")
+);
+
+-- At most one relationship per pair, whoever asked first
+CREATE UNIQUE INDEX "user_relationships_pair_idx" ON "user_relationships" (
+    LEAST("requester_id", "addressee_id"),
+    GREATEST("requester_id", "addressee_id")
+);
+CREATE INDEX "user_relationships_addressee_idx" ON "user_relationships" ("addressee_id", "status");
+
+-- Direct messages between two users, small group DMs and public rooms.
+-- Direct conversations are keyed by the sorted ids of both users, so the
+-- same pair always ends up in the same conversation.
+CREATE TABLE "conversations" (
+    "id" TEXT DEFAULT gen_random_uuid (),
+    "kind" VARCHAR(8) NOT NULL CHECK ("kind" IN ('direct', 'group', 'room')),
+    "name" VARCHAR(100) NOT NULL DEFAULT '',
+    "dm_key" TEXT UNIQUE,
+    "created_by" TEXT REFERENCES "users" ("id") ON DELETE SET NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- Bumped by every message, conversation lists are sorted by it
+    "last_activity_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY ("id"),
+    CHECK (("kind" = 'direct') = ("dm_key" IS NOT NULL))
+);
+
+CREATE INDEX "conversations_rooms_idx" ON "conversations" ("name", "id") WHERE "kind" = 'room';
+
+CREATE TABLE "conversation_members" (
+    "conversation_id" TEXT NOT NULL REFERENCES "conversations" ("id") ON DELETE CASCADE,
+    "user_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "joined_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- Messages up to this id are read
+    "last_read_message_id" BIGINT NOT NULL DEFAULT 0,
+    -- When the read position last moved, missing until it does
+    "last_read_at" TIMESTAMPTZ,
+    -- Messages after the read position mentioning the member
+    "mention_count" INT NOT NULL DEFAULT 0,
+    -- Muted conversations create no notifications for the member
+    "muted" BOOLEAN NOT NULL DEFAULT FALSE,
+    -- What the member may do in a room, members of other conversations
+    -- keep the default
+    "role" VARCHAR(16) NOT NULL DEFAULT 'member' CHECK ("role" IN ('owner', 'admin', 'moderator', 'member')),
+    PRIMARY KEY ("conversation_id", "user_id")
+);
+
+CREATE INDEX "conversation_members_user_idx" ON "conversation_members" ("user_id");
+
+CREATE TABLE "messages" (
+    "id" BIGSERIAL,
+    "conversation_id" TEXT NOT NULL REFERENCES "conversations" ("id") ON DELETE CASCADE,
+    "author_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    -- Kept after deletion for moderation, clients only see a tombstone
+    "body" VARCHAR(4000) NOT NULL,
+    "edited_at" TIMESTAMPTZ,
+    "deleted_at" TIMESTAMPTZ,
+    "deleted_by" TEXT REFERENCES "users" ("id") ON DELETE SET NULL,
+    -- First message of the thread this one replies in, threads don't nest
+    "thread_id" BIGINT REFERENCES "messages" ("id") ON DELETE CASCADE,
+    -- Kept on the first message of a thread, deleted replies aren't counted
+    "reply_count" INT NOT NULL DEFAULT 0,
+    "last_reply_at" TIMESTAMPTZ,
+    "last_reply_author_id" TEXT REFERENCES "users" ("id") ON DELETE SET NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    "search" TSVECTOR GENERATED ALWAYS AS (to_tsvector('simple', "body")) STORED,
+    PRIMARY KEY ("id")
+);
+
+-- Whole word matches on the body
+CREATE INDEX "messages_search_idx" ON "messages" USING GIN ("search");
+CREATE INDEX "messages_conversation_idx" ON "messages" ("conversation_id", "id") WHERE "thread_id" IS NULL;
+CREATE INDEX "messages_thread_idx" ON "messages" ("thread_id", "id") WHERE "thread_id" IS NOT NULL;
+
+-- Bodies replaced by edits
+CREATE TABLE "message_revisions" (
+    "id" BIGSERIAL,
+    "message_id" BIGINT NOT NULL REFERENCES "messages" ("id") ON DELETE CASCADE,
+    "body" VARCHAR(4000) NOT NULL,
+    "replaced_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY ("id")
+);
+
+CREATE INDEX "message_revisions_message_idx" ON "message_revisions" ("message_id", "id");
+
+-- A Unicode emoji or `custom:{id}` for a custom one
+CREATE TABLE "message_reactions" (
+    "id" BIGSERIAL,
+    "message_id" BIGINT NOT NULL REFERENCES "messages" ("id") ON DELETE CASCADE,
+    "user_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "emoji" VARCHAR(72) NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY ("id"),
+    UNIQUE ("message_id", "user_id", "emoji")
+);
+
+CREATE INDEX "message_reactions_message_idx" ON "message_reactions" ("message_id", "emoji", "id");
+
+-- Files uploaded for messages. A row is created with the declared size when
+-- an upload is requested, and stays pending until the bytes arrive. Pending
+-- rows reserve quota until they expire.
+CREATE TABLE "attachments" (
+    "id" TEXT DEFAULT gen_random_uuid (),
+    "user_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    -- Set once the attachment is sent with a message
+    "message_id" BIGINT REFERENCES "messages" ("id") ON DELETE CASCADE,
+    "file_name" VARCHAR(255) NOT NULL,
+    -- Sniffed from the bytes once uploaded, declared by the client until then
+    "content_type" VARCHAR(127) NOT NULL,
+    "size" BIGINT NOT NULL,
+    -- Pixels of images
+    "width" INT,
+    "height" INT,
+    "has_thumbnail" BOOLEAN NOT NULL DEFAULT FALSE,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    "uploaded_at" TIMESTAMPTZ,
+    PRIMARY KEY ("id")
+);
+
+CREATE INDEX "attachments_user_idx" ON "attachments" ("user_id");
+CREATE INDEX "attachments_message_idx" ON "attachments" ("message_id") WHERE "message_id" IS NOT NULL;
+
+-- Users notified about replies in a thread. Authors of the first message
+-- and of replies are subscribed when they take part.
+CREATE TABLE "thread_subscriptions" (
+    "thread_id" BIGINT NOT NULL REFERENCES "messages" ("id") ON DELETE CASCADE,
+    "user_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY ("thread_id", "user_id")
+);
+
+CREATE TABLE "notifications" (
+    "id" BIGSERIAL,
+    "user_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "kind" VARCHAR(16) NOT NULL CHECK ("kind" IN ('reply', 'mention')),
+    "conversation_id" TEXT NOT NULL REFERENCES "conversations" ("id") ON DELETE CASCADE,
+    "message_id" BIGINT NOT NULL REFERENCES "messages" ("id") ON DELETE CASCADE,
+    -- Thread the message is in, if any
+    "thread_id" BIGINT REFERENCES "messages" ("id") ON DELETE CASCADE,
+    "actor_id" TEXT REFERENCES "users" ("id") ON DELETE CASCADE,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    "read_at" TIMESTAMPTZ,
+    PRIMARY KEY ("id")
+);
+
+CREATE INDEX "notifications_user_idx" ON "notifications" ("user_id", "id");
+CREATE INDEX "notifications_unread_idx" ON "notifications" ("user_id", "id") WHERE "read_at" IS NULL;
+
+-- Members mentioned by a message, once each however they were mentioned
+CREATE TABLE "message_mentions" (
+    "message_id" BIGINT NOT NULL REFERENCES "messages" ("id") ON DELETE CASCADE,
+    "user_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    -- 'user' for @username, otherwise 'room' or 'here'
+    "kind" VARCHAR(8) NOT NULL CHECK ("kind" IN ('user', 'room', 'here')),
+    PRIMARY KEY ("message_id", "user_id")
+);
+
+CREATE INDEX "message_mentions_user_idx" ON "message_mentions" ("user_id", "message_id");
+
+-- Messages pinned to the top of a conversation, at most 50 each
+CREATE TABLE "message_pins" (
+    "message_id" BIGINT NOT NULL REFERENCES "messages" ("id") ON DELETE CASCADE,
+    "conversation_id" TEXT NOT NULL REFERENCES "conversations" ("id") ON DELETE CASCADE,
+    "pinned_by" TEXT REFERENCES "users" ("id") ON DELETE SET NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY ("message_id")
+);
+
+CREATE INDEX "message_pins_conversation_idx" ON "message_pins" ("conversation_id", "created_at");
+
+-- Users kept out of a room, whether or not they were in it when banned
+CREATE TABLE "room_bans" (
+    "conversation_id" TEXT NOT NULL REFERENCES "conversations" ("id") ON DELETE CASCADE,
+    "user_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "reason" VARCHAR(500) NOT NULL,
+    "banned_by" TEXT NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- NULL bans until lifted
+    "expires_at" TIMESTAMPTZ,
+    PRIMARY KEY ("conversation_id", "user_id")
+);
+
+CREATE INDEX "room_bans_recent_idx" ON "room_bans" ("conversation_id", "created_at", "user_id");
+
+-- Members of a room who can't send until the timeout expires. Kept when
+-- they leave, so joining again doesn't lift it.
+CREATE TABLE "room_timeouts" (
+    "conversation_id" TEXT NOT NULL REFERENCES "conversations" ("id") ON DELETE CASCADE,
+    "user_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "reason" VARCHAR(500) NOT NULL,
+    "timed_out_by" TEXT NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    "expires_at" TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY ("conversation_id", "user_id")
+);
+
+-- What owners, admins and moderators did in their room. Actors and
+-- targets are kept as plain ids so the history outlives them.
+CREATE TABLE "room_audit_log" (
+    "id" BIGSERIAL,
+    "conversation_id" TEXT NOT NULL REFERENCES "conversations" ("id") ON DELETE CASCADE,
+    "actor_id" TEXT NOT NULL,
+    -- NULL for changes to the room itself
+    "target_id" TEXT,
+    "action" VARCHAR(64) NOT NULL,
+    "details" JSONB NOT NULL DEFAULT '{}',
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY ("id")
+);
+
+CREATE INDEX "room_audit_log_conversation_idx" ON "room_audit_log" ("conversation_id", "id");
+
+CREATE TABLE "roles" (
+    "id" TEXT DEFAULT gen_random_uuid (),
+    "name" VARCHAR(64) NOT NULL UNIQUE,
+    "description" VARCHAR(255) NOT NULL DEFAULT '',
+    PRIMARY KEY ("id")
+);
+
+CREATE TABLE "permissions" (
+    "id" TEXT DEFAULT gen_random_uuid (),
+    "name" VARCHAR(64) NOT NULL UNIQUE,
+    "description" VARCHAR(255) NOT NULL DEFAULT '',
+    PRIMARY KEY ("id")
+);
+
+CREATE TABLE "role_permissions" (
+    "role_id" TEXT NOT NULL REFERENCES "roles" ("id") ON DELETE CASCADE,
+    "permission_id" TEXT NOT NULL REFERENCES "permissions" ("id") ON DELETE CASCADE,
+    PRIMARY KEY ("role_id", "permission_id")
+);
+
+CREATE TABLE "user_roles" (
+    "user_id" TEXT NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+    "role_id" TEXT NOT NULL REFERENCES "roles" ("id") ON DELETE CASCADE,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY ("user_id", "role_id")
+);
+
+INSERT INTO "roles" ("name", "description") VALUES
+    ('admin', 'Full access to users and roles'),
+    ('moderator', 'Can moderate users and content'),
+    ('user', 'Default role for every account'),
+    ('service', 'Other services calling internal routes, never granted to people');
+
+INSERT INTO "permissions" ("name", "description") VALUES
+    ('users:read', 'Read any user account'),
+    ('users:write', 'Update any user account'),
+    ('roles:read', 'List roles and role assignments'),
+    ('roles:write', 'Assign and revoke roles'),
+    ('internal:read', 'Call internal routes of other services');
+
+INSERT INTO "role_permissions" ("role_id", "permission_id")
+SELECT r.id, p.id FROM "roles" r, "permissions" p
+WHERE (r.name = 'admin' AND p.name <> 'internal:read')
+   OR (r.name = 'moderator' AND p.name IN ('users:read', 'users:write', 'roles:read'))
+   OR (r.name = 'user' AND p.name = 'users:read')
+   OR (r.name = 'service' AND p.name = 'internal:read');